@@ -0,0 +1,12 @@
+//! An MCP server giving clients tools to read, search and change files in a
+//! set of allowed directories. The `rs_filesystem` binary runs it over stdio
+//! or a socket; applications embed it with [`Server::builder`], adding tools
+//! of their own next to the built-in ones.
+
+pub mod mcp;
+pub mod replay;
+mod server;
+
+pub use server::Server;
+pub use server::ServerBuilder;
+pub use server::Transport;
@@ -1,210 +1,110 @@
-mod mcp;
-
-use crate::mcp::prompts::prompts_get;
-use crate::mcp::prompts::prompts_list;
-use crate::mcp::resources::resource_read;
-use crate::mcp::resources::resources_list;
-use crate::mcp::resources::{allowed_directories};
-use crate::mcp::tools::register_tools;
-use crate::mcp::tools::tools_list;
-use crate::mcp::types::CancelledNotification;
-use crate::mcp::types::JsonRpcError;
-use crate::mcp::types::JsonRpcResponse;
-use crate::mcp::types::ToolCallRequestParams;
-use crate::mcp::utilities::*;
 use clap::Parser;
-use dirs::data_local_dir;
-use dirs::home_dir;
-use dirs::state_dir;
-use rpc_router::Error;
-use rpc_router::Handler;
-use rpc_router::Request;
-use rpc_router::Router;
-use rpc_router::RouterBuilder;
-use serde_json::json;
-use serde_json::Value;
-use std::env;
-use std::fs::OpenOptions;
-use std::io::Write;
+use rs_filesystem::mcp::config;
+use rs_filesystem::mcp::prompt_library;
+use rs_filesystem::mcp::prompts::prompts_list;
+use rs_filesystem::mcp::resources::resources_list;
+use rs_filesystem::mcp::state;
+use rs_filesystem::mcp::tools::tools_list;
+use rs_filesystem::replay;
+use rs_filesystem::Server;
+use rs_filesystem::Transport;
+use std::ffi::OsString;
 use std::path::PathBuf;
-use tokio::io::AsyncBufReadExt;
-use tokio::signal;
-
-fn build_rpc_router() -> Router {
-    let builder = RouterBuilder::default()
-        // append resources here
-        .append_dyn("initialize", initialize.into_dyn())
-        .append_dyn("ping", ping.into_dyn())
-        .append_dyn("logging/setLevel", logging_set_level.into_dyn())
-        .append_dyn("roots/list", roots_list.into_dyn())
-        .append_dyn("prompts/list", prompts_list.into_dyn())
-        .append_dyn("prompts/get", prompts_get.into_dyn())
-        .append_dyn("resources/list", resources_list.into_dyn())
-        .append_dyn("resources/read", resource_read.into_dyn())
-        .append_dyn("resources/allowed_directories", allowed_directories.into_dyn());
-    let builder = register_tools(builder);
-    builder.build()
-}
-
-fn get_log_directory() -> PathBuf {
-    if cfg!(target_os = "macos") {
-        // macOS: ~/Library/Logs/Claude
-        home_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("Library/Logs/Claude")
-    } else if cfg!(target_os = "windows") {
-        // Windows: %LOCALAPPDATA%\Claude\logs
-        data_local_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("Claude")
-            .join("logs")
-    } else {
-        // Linux: ~/.local/state/claude/logs
-        state_dir()
-            .unwrap_or_else(|| {
-                home_dir()
-                    .unwrap_or_else(|| PathBuf::from("."))
-                    .join(".local/state")
-            })
-            .join("claude")
-            .join("logs")
-    }
-}
 
 #[tokio::main]
 async fn main() {
     // Parse command-line arguments
     let args = Args::parse();
-    if !args.mcp {
-        display_info(&args).await;
+    if let Some(bundle) = &args.export_state {
+        match state::export_state(bundle) {
+            Ok(count) => println!("Exported {} state document(s) to {}", count, bundle.display()),
+            Err(e) => eprintln!("Failed to export state: {}", e),
+        }
+        return;
+    }
+    if let Some(bundle) = &args.import_state {
+        match state::import_state(bundle, true, false) {
+            Ok(count) => println!("Imported {} state document(s) from {}", count, bundle.display()),
+            Err(e) => eprintln!("Failed to import state: {}", e),
+        }
         return;
     }
 
-    // Clone necessary variables for the shutdown task
-    let shutdown_handle = tokio::spawn(async {
-        // Create a shutdown signal future
-        #[cfg(unix)]
-        let shutdown = async {
-            // Listen for SIGINT and SIGTERM on Unix
-            let mut sigint = signal::unix::signal(signal::unix::SignalKind::interrupt())
-                .expect("Failed to set up SIGINT handler");
-            let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
-                .expect("Failed to set up SIGTERM handler");
-
-            tokio::select! {
-                _ = sigint.recv() => {},
-                _ = sigterm.recv() => {},
-            }
-        };
-
-        #[cfg(windows)]
-        let shutdown = async {
-            // Listen for Ctrl+C on Windows
-            signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
-        };
-
-        shutdown.await;
-        graceful_shutdown();
-        std::process::exit(0);
-    });
-
-    // Process JSON-RPC from MCP client
-    let router = build_rpc_router();
-    let log_path = env::var("MCP_LOG_FILE_PATH").map(PathBuf::from).unwrap_or_else(|_| {
-        get_log_directory().join("rs_filesystem.logs.jsonl")
-    });
-
-    let mut logging_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(&log_path)
-        .unwrap();
-
-    // Spawn a task to read lines from stdin
-    let rpc_handle = tokio::spawn(async move {
-        let mut reader = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut builder = Server::builder()
+        .allow_permission_changes(args.allow_permission_changes)
+        .allow_secrets(args.allow_secrets)
+        .allow_xattrs(args.allow_xattrs)
+        .dry_run(args.dry_run)
+        // Before a client is through its handshake, log messages go to stderr
+        .log_hook(|level, logger, message| eprintln!("{} {}: {}", level, logger, message));
+    if let Some(path) = &args.config {
+        builder = builder.config_file(path);
+    }
+    if let Some(tools) = &args.disabled_tools {
+        builder = builder.disabled_tools(tools.split(',').map(str::trim));
+    }
+    if let Some(socket) = &args.socket {
+        builder = builder.transport(Transport::Socket(socket.clone()));
+    }
+    let flag = |on: bool| on.then(|| OsString::from("1"));
+    let number = |value: Option<u64>| value.map(|value| OsString::from(value.to_string()));
+    for (name, value) in [
+        ("MCP_RS_FILESYSTEM_PROMPTS_DIR", args.prompts_dir.clone().map(OsString::from)),
+        ("MCP_RS_FILESYSTEM_VERIFY_WRITES", flag(args.verify_writes)),
+        ("MCP_RS_FILESYSTEM_INDEX", flag(args.index)),
+        ("MCP_RS_FILESYSTEM_RESOURCE_NOTIFICATIONS", flag(args.resource_notifications)),
+        ("MCP_RS_FILESYSTEM_RESPONSE_TIMING", flag(args.response_timing)),
+        ("MCP_RS_FILESYSTEM_LOGGING", args.no_logging.then(|| OsString::from("0"))),
+        ("MCP_RS_FILESYSTEM_TOOL_OUTPUT", args.tool_output.clone().map(OsString::from)),
+        ("MCP_RS_FILESYSTEM_PATH_EXPANSION", args.path_expansion.clone().map(OsString::from)),
+        ("MCP_RS_FILESYSTEM_BACKEND", args.backend.clone().map(OsString::from)),
+        ("MCP_RS_FILESYSTEM_IGNORE_PATTERNS", args.ignore_patterns.clone().map(OsString::from)),
+        ("MCP_RS_FILESYSTEM_PROTECTED_PATHS", args.protected_paths.clone().map(OsString::from)),
+        ("MCP_RS_FILESYSTEM_TOOL_TIMEOUT_MS", number(args.tool_timeout)),
+        ("MCP_RS_FILESYSTEM_TOOL_TIMEOUTS", args.tool_timeouts.clone().map(OsString::from)),
+        ("MCP_RS_FILESYSTEM_VERIFY_MAX_SHRINK", number(args.verify_max_shrink)),
+        ("MCP_RS_FILESYSTEM_MAX_CALLS_PER_MINUTE", number(args.max_calls_per_minute)),
+        ("MCP_RS_FILESYSTEM_MAX_BYTES_READ_PER_MINUTE", number(args.max_bytes_read_per_minute)),
+        ("MCP_RS_FILESYSTEM_MAX_BYTES_WRITTEN_PER_MINUTE", number(args.max_bytes_written_per_minute)),
+        ("MCP_RS_FILESYSTEM_MAX_FILES_PER_REQUEST", number(args.max_files_per_request)),
+        ("MCP_RS_FILESYSTEM_MAX_READ_BYTES", number(args.max_read_bytes)),
+        ("MCP_RS_FILESYSTEM_MAX_RESULT_ENTRIES", number(args.max_result_entries)),
+    ] {
+        if let Some(value) = value {
+            builder = builder.setting(name, value);
+        }
+    }
 
-        while let Ok(Some(line)) = reader.next_line().await {
-            writeln!(logging_file, "{}", line).unwrap();
-            if !line.is_empty() {
-                if let Ok(json_value) = serde_json::from_str::<Value>(&line) {
-                    // Notifications, no response required
-                    if json_value.is_object() && json_value.get("id").is_none() {
-                        if let Some(method) = json_value.get("method") {
-                            if method == "notifications/initialized" {
-                                notifications_initialized();
-                            } else if method == "notifications/cancelled" {
-                                let params_value = json_value.get("params").unwrap();
-                                let cancel_params: CancelledNotification =
-                                    serde_json::from_value(params_value.clone()).unwrap();
-                                notifications_cancelled(cancel_params);
-                            }
-                        }
-                    } else if let Ok(mut rpc_request) = Request::from_value(json_value) {
-                        // Normal JSON-RPC message, and response expected
-                        let id = rpc_request.id.clone();
-                        if rpc_request.method == "tools/call" {
-                            let params = serde_json::from_value::<ToolCallRequestParams>(
-                                rpc_request.params.unwrap(),
-                            )
-                            .unwrap();
-                            rpc_request = Request {
-                                id: id.clone(),
-                                method: params.name,
-                                params: params.arguments,
-                            }
-                        }
-                        match router.call(rpc_request).await {
-                            Ok(call_response) => {
-                                if !call_response.value.is_null() {
-                                    let response =
-                                        JsonRpcResponse::new(id, call_response.value.clone());
-                                    let response_json = serde_json::to_string(&response).unwrap();
-                                    writeln!(logging_file, "{}\n", response_json).unwrap();
-                                    println!("{}", response_json);
-                                }
-                            }
-                            Err(error) => match &error.error {
-                                // Error from JSON-RPC call
-                                Error::Handler(handler) => {
-                                    if let Some(error_value) = handler.get::<Value>() {
-                                        let json_error = json!({
-                                            "jsonrpc": "2.0",
-                                            "error": error_value,
-                                            "id": id
-                                        });
-                                        let response = serde_json::to_string(&json_error).unwrap();
-                                        writeln!(logging_file, "{}\n", response).unwrap();
-                                        println!("{}", response);
-                                    }
-                                }
-                                _ => {
-                                    let json_error = JsonRpcError::new(
-                                        id,
-                                        -1,
-                                        format!(
-                                            "Invalid json-rpc call, error: {}",
-                                            error.error.to_string()
-                                        )
-                                        .as_str(),
-                                    );
-                                    let response = serde_json::to_string(&json_error).unwrap();
-                                    writeln!(logging_file, "{}\n", response).unwrap();
-                                    println!("{}", response);
-                                }
-                            },
-                        }
-                    }
-                }
+    if !args.mcp || args.replay.is_some() {
+        if let Err(e) = builder.configure() {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+    if let Some(transcript) = &args.replay {
+        // Unless the environment or the configuration file allow directories,
+        // transcripts work under a root of their own
+        if config::var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES").is_err() {
+            builder = builder.allowed_directories([replay::REPLAY_ROOT]);
+            if let Err(e) = builder.configure() {
+                eprintln!("{}", e);
+                return;
             }
         }
-    });
+        if !replay::run(transcript, args.update_transcript).await {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if !args.mcp {
+        prompt_library::reload();
+        display_info(&args).await;
+        return;
+    }
 
-    // Wait for either the RPC handling or shutdown to complete
-    tokio::select! {
-        _ = rpc_handle => {},
-        _ = shutdown_handle => {},
+    match builder.build() {
+        Ok(server) => server.run().await,
+        Err(e) => eprintln!("{}", e),
     }
 }
 
@@ -223,6 +123,105 @@ struct Args {
     /// Start MCP server
     #[arg(long, default_value = "false")]
     mcp: bool,
+    /// Export the server state to a bundle file
+    #[arg(long, value_name = "FILE")]
+    export_state: Option<PathBuf>,
+    /// Import the server state from a bundle file
+    #[arg(long, value_name = "FILE")]
+    import_state: Option<PathBuf>,
+    /// TOML file with settings that can be changed while the server runs, reloaded on SIGHUP or `config/reload`
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Comma-separated tools to leave out of the tool list and refuse
+    #[arg(long, value_name = "TOOLS")]
+    disabled_tools: Option<String>,
+    /// Allow tools to change file permissions and ownership
+    #[arg(long, default_value = "false")]
+    allow_permission_changes: bool,
+    /// Return secrets such as private keys and access tokens in file contents as they are instead of masking them
+    #[arg(long, default_value = "false")]
+    allow_secrets: bool,
+    /// Allow tools to list, read and write extended attributes (alternate data streams on Windows)
+    #[arg(long, default_value = "false")]
+    allow_xattrs: bool,
+    /// Directory with user-defined prompt files
+    #[arg(long, value_name = "DIR")]
+    prompts_dir: Option<PathBuf>,
+    /// Apply writes to a shadow copy and validate it before touching the real file
+    #[arg(long, default_value = "false")]
+    verify_writes: bool,
+    /// How much smaller, in percent, a verified write may make a file
+    #[arg(long, value_name = "PERCENT")]
+    verify_max_shrink: Option<u64>,
+    /// Have mutating tools report what they would change, with diffs and sizes, without touching the disk
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+    /// Maximum number of tool calls per minute
+    #[arg(long, value_name = "N")]
+    max_calls_per_minute: Option<u64>,
+    /// Maximum number of bytes tools may read per minute
+    #[arg(long, value_name = "BYTES")]
+    max_bytes_read_per_minute: Option<u64>,
+    /// Maximum number of bytes tools may write per minute
+    #[arg(long, value_name = "BYTES")]
+    max_bytes_written_per_minute: Option<u64>,
+    /// Maximum number of files a single tool call may touch
+    #[arg(long, value_name = "N")]
+    max_files_per_request: Option<u64>,
+    /// Largest response of a single read, longer files are returned in parts
+    #[arg(long, value_name = "BYTES")]
+    max_read_bytes: Option<u64>,
+    /// Most entries of a single listing, longer ones are returned in pages
+    #[arg(long, value_name = "N")]
+    max_result_entries: Option<u64>,
+    /// Keep an index of the allowed directories, updated as files change, for fast repeated searches
+    #[arg(long, default_value = "false")]
+    index: bool,
+    /// Watch the allowed directories and tell clients when files are created or deleted, so they refresh their
+    /// resource lists
+    #[arg(long, default_value = "false")]
+    resource_notifications: bool,
+    /// Serve clients connecting to this Unix domain socket (a named pipe on Windows) instead of stdio
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+    /// Add the correlation id and duration of each request to the `_meta` of its result
+    #[arg(long, default_value = "false")]
+    response_timing: bool,
+    /// Send no log messages to clients and leave `logging` out of the announced capabilities
+    #[arg(long, default_value = "false")]
+    no_logging: bool,
+    /// Format of tool results: `structured` adds typed JSON next to the text, `text` returns text only
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "structured"])]
+    tool_output: Option<String>,
+    /// What path arguments expand before they are checked: `tilde` (default) turns a leading `~` or `~user` into a
+    /// home directory, `all` also expands `$VAR`, `${VAR}` and `%VAR%`, `off` takes paths literally
+    #[arg(long, value_name = "MODE", value_parser = ["off", "tilde", "all"])]
+    path_expansion: Option<String>,
+    /// Where files live: `local` (default) is the disk, `memory` keeps them in memory, starting from empty allowed
+    /// directories, and loses them on exit
+    #[arg(long, value_name = "BACKEND", value_parser = ["local", "memory"])]
+    backend: Option<String>,
+    /// Comma-separated patterns, in .gitignore syntax, that listings, searches and scans leave out
+    /// (default `.git,node_modules,target`, empty for none)
+    #[arg(long, value_name = "PATTERNS")]
+    ignore_patterns: Option<String>,
+    /// Comma-separated patterns, in .gitignore syntax and relative to each allowed directory, of paths that tools may
+    /// read but never change, e.g. `**/.git/**,Cargo.lock,*.pem`
+    #[arg(long, value_name = "PATTERNS")]
+    protected_paths: Option<String>,
+    /// Milliseconds a tool call may run before it is aborted with a timeout error (default 30000, 0 for no limit)
+    #[arg(long, value_name = "MS")]
+    tool_timeout: Option<u64>,
+    /// Timeouts of single tools, e.g. `find_file=60000,grep_search=10000`, overriding --tool-timeout
+    #[arg(long, value_name = "TOOL=MS,...")]
+    tool_timeouts: Option<String>,
+    /// Replay a JSONL transcript of `{"send": message, "expect": [messages]}` lines against an in-memory backend and
+    /// report where the server's answers differ, exiting with status 1 if any do
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// With --replay, rewrite the expectations of the transcript that no longer match instead of reporting them
+    #[arg(long, default_value = "false", requires = "replay")]
+    update_transcript: bool,
 }
 
 impl Args {
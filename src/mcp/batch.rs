@@ -0,0 +1,740 @@
+use crate::mcp::budget;
+use crate::mcp::checkpoint;
+use crate::mcp::dry_run;
+use crate::mcp::dry_run::ExistingText;
+use crate::mcp::encoding::read_text_file;
+use crate::mcp::encoding::write_text_preserving;
+use crate::mcp::encoding::TextEncoding;
+use crate::mcp::sandbox;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::scratch::scratch_directory;
+use crate::mcp::shadow;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::validate_write_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// One filesystem action inside a `batch` call
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Read {
+        path: String,
+        encoding: Option<String>,
+    },
+    Write {
+        path: String,
+        content: String,
+    },
+    Move {
+        source_path: String,
+        target_path: String,
+    },
+    Mkdir {
+        path: String,
+    },
+    Delete {
+        path: String,
+        #[serde(default)]
+        recursive: bool,
+    },
+}
+
+impl BatchOperation {
+    fn name(&self) -> &'static str {
+        match self {
+            BatchOperation::Read { .. } => "read",
+            BatchOperation::Write { .. } => "write",
+            BatchOperation::Move { .. } => "move",
+            BatchOperation::Mkdir { .. } => "mkdir",
+            BatchOperation::Delete { .. } => "delete",
+        }
+    }
+
+    fn paths(&self) -> Vec<&str> {
+        match self {
+            BatchOperation::Read { path, .. }
+            | BatchOperation::Write { path, .. }
+            | BatchOperation::Mkdir { path }
+            | BatchOperation::Delete { path, .. } => vec![path],
+            BatchOperation::Move {
+                source_path,
+                target_path,
+            } => vec![source_path, target_path],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct BatchRequest {
+    /// Operations to run. Each is an object with an `op` field and its arguments: {op: "read", path,
+    /// encoding?}, {op: "write", path, content}, {op: "move", source_path, target_path}, {op: "mkdir", path},
+    /// {op: "delete", path, recursive?}
+    pub operations: Vec<BatchOperation>,
+    /// Roll back completed operations when one fails. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub atomic: Option<bool>,
+    /// Report what each operation would change, running only the reads. Always the case when the server runs
+    /// with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+/// How to revert a completed operation
+enum Undo {
+    /// Put back the previous content of a file, or remove it if it did not exist
+    Restore { path: PathBuf, previous: Option<Vec<u8>> },
+    /// Rename `from` back to `to`
+    Rename { from: PathBuf, to: PathBuf },
+    /// Remove directories that the operation created, deepest first
+    RemoveDirs(Vec<PathBuf>),
+}
+
+impl Undo {
+    fn revert(self) -> Result<(), String> {
+        match self {
+            Undo::Restore { path, previous: Some(bytes) } => {
+                sandbox::write_with_mode(&path, &bytes, WriteMode::Overwrite)
+            }
+            Undo::Restore { path, previous: None } => sandbox::remove_file(&path),
+            Undo::Rename { from, to } => sandbox::rename(&from, &to),
+            Undo::RemoveDirs(dirs) => {
+                for dir in dirs {
+                    sandbox::remove_dir(&dir)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Outcome of a single operation
+struct Applied {
+    content: Option<String>,
+    undo: Option<Undo>,
+    /// deleted entries are parked here until the whole batch succeeds
+    trash: Option<PathBuf>,
+}
+
+static TRASH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Move a deleted entry out of the way, where it can be restored on rollback.
+/// It goes to the scratch directory, which is removed when the server exits
+/// or, after a crash, when the next one starts. Only when the rename cannot
+/// reach it, as from another filesystem, is the entry parked next to itself.
+fn park(path: &Path) -> Result<PathBuf, String> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let n = TRASH_COUNTER.fetch_add(1, Ordering::SeqCst);
+    if let Ok(scratch) = scratch_directory() {
+        let trash = scratch.join(format!("batch-deleted-{}-{}", n, name));
+        if sandbox::rename(path, &trash).is_ok() {
+            return Ok(trash);
+        }
+    }
+    let trash = path.with_file_name(format!(".{}.batch-deleted-{}-{}", name, std::process::id(), n));
+    sandbox::rename(path, &trash)?;
+    Ok(trash)
+}
+
+fn apply(operation: &BatchOperation) -> Result<Applied, String> {
+    for path in operation.paths() {
+        budget::touch(&resolve_path(Path::new(path)))?;
+    }
+    match operation {
+        BatchOperation::Read { path, encoding } => {
+            let encoding = TextEncoding::parse(encoding.as_deref().unwrap_or("auto"))?;
+            let decoded = read_text_file(&resolve_path(Path::new(path)), encoding)?;
+            Ok(Applied {
+                content: Some(decoded.text),
+                undo: None,
+                trash: None,
+            })
+        }
+        BatchOperation::Write { path, content } => {
+            let path = &resolve_path(Path::new(path));
+            let previous = if path.is_file() {
+                Some(sandbox::read(path)?)
+            } else {
+                None
+            };
+            shadow::write(path, |target| write_text_preserving(target, content, None, None))
+                .map_err(|e| e.into_message())?;
+            Ok(Applied {
+                content: None,
+                undo: Some(Undo::Restore {
+                    path: path.to_path_buf(),
+                    previous,
+                }),
+                trash: None,
+            })
+        }
+        BatchOperation::Move {
+            source_path,
+            target_path,
+        } => {
+            let (source, target) = (&resolve_path(Path::new(source_path)), &resolve_path(Path::new(target_path)));
+            if target.exists() {
+                return Err(format!("Target already exists: {}", target.display()));
+            }
+            shadow::verify_removal(source).map_err(|e| e.into_message())?;
+            checkpoint::preserve(source)?;
+            checkpoint::preserve(target)?;
+            sandbox::rename(source, target)?;
+            Ok(Applied {
+                content: None,
+                undo: Some(Undo::Rename {
+                    from: target.to_path_buf(),
+                    to: source.to_path_buf(),
+                }),
+                trash: None,
+            })
+        }
+        BatchOperation::Mkdir { path } => {
+            let path = &resolve_path(Path::new(path));
+            let mut created = Vec::new();
+            let mut missing = Some(path.as_path());
+            while let Some(dir) = missing.filter(|dir| !dir.exists()) {
+                created.push(dir.to_path_buf());
+                missing = dir.parent();
+            }
+            checkpoint::preserve(path)?;
+            sandbox::create_dir_all(path)?;
+            Ok(Applied {
+                content: None,
+                undo: Some(Undo::RemoveDirs(created)),
+                trash: None,
+            })
+        }
+        BatchOperation::Delete { path, recursive } => {
+            let path = &resolve_path(Path::new(path));
+            shadow::verify_removal(path).map_err(|e| e.into_message())?;
+            let metadata = fs::symlink_metadata(path).map_err(|e| e.to_string())?;
+            if metadata.is_dir() && !recursive && fs::read_dir(path).map_err(|e| e.to_string())?.next().is_some() {
+                return Err(format!(
+                    "Directory is not empty: {}. Set recursive to delete it with its contents",
+                    path.display()
+                ));
+            }
+            checkpoint::preserve(path)?;
+            let trash = park(path)?;
+            Ok(Applied {
+                content: None,
+                undo: Some(Undo::Rename {
+                    from: trash.clone(),
+                    to: path.to_path_buf(),
+                }),
+                trash: Some(trash),
+            })
+        }
+    }
+}
+
+/// An entry a dry run has pretended to change
+#[derive(Clone)]
+enum Pending {
+    /// A file holding this text
+    File(ExistingText),
+    /// A new, empty directory
+    Dir,
+    /// The entry that is on disk at this path, moved here
+    Moved(PathBuf),
+    Removed,
+}
+
+/// The entry at a path, as a dry run sees it
+enum Seen<'a> {
+    File(&'a ExistingText),
+    Dir,
+    /// The entry on disk at this path
+    Disk(PathBuf),
+}
+
+/// Entries a dry run has so far pretended to write, create, move or remove, so
+/// that later operations see what the earlier ones would have done
+#[derive(Default)]
+struct Planned {
+    entries: HashMap<PathBuf, Pending>,
+}
+
+impl Planned {
+    fn get(&self, path: &Path) -> Option<Seen<'_>> {
+        for ancestor in path.ancestors() {
+            let Some(pending) = self.entries.get(ancestor) else {
+                continue;
+            };
+            return match pending {
+                Pending::File(text) if ancestor == path => Some(Seen::File(text)),
+                Pending::Dir if ancestor == path => Some(Seen::Dir),
+                Pending::Moved(origin) => {
+                    let origin = match path.strip_prefix(ancestor) {
+                        Ok(rest) if !rest.as_os_str().is_empty() => origin.join(rest),
+                        _ => origin.clone(),
+                    };
+                    origin.exists().then_some(Seen::Disk(origin))
+                }
+                _ => None,
+            };
+        }
+        path.exists().then(|| Seen::Disk(path.to_path_buf()))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// Whether `path` is a directory with something in it
+    fn has_entries(&self, path: &Path) -> Result<bool, String> {
+        let origin = match self.get(path) {
+            Some(Seen::Disk(origin)) if origin.is_dir() => Some(origin),
+            Some(Seen::Dir) => None,
+            _ => return Ok(false),
+        };
+        let planned = self
+            .entries
+            .iter()
+            .any(|(entry, pending)| entry.parent() == Some(path) && !matches!(pending, Pending::Removed));
+        if planned {
+            return Ok(true);
+        }
+        let Some(origin) = origin else {
+            return Ok(false);
+        };
+        for entry in fs::read_dir(origin).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            if self.exists(&path.join(entry.file_name())) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn write(&mut self, path: &Path, text: ExistingText) {
+        self.entries.insert(path.to_path_buf(), Pending::File(text));
+    }
+
+    fn create_dir(&mut self, path: &Path) {
+        self.entries.insert(path.to_path_buf(), Pending::Dir);
+    }
+
+    fn remove(&mut self, path: &Path) {
+        self.entries.retain(|entry, _| !entry.starts_with(path));
+        self.entries.insert(path.to_path_buf(), Pending::Removed);
+    }
+
+    fn rename(&mut self, source: &Path, target: &Path) {
+        let moved = match self.get(source) {
+            Some(Seen::File(text)) => Pending::File(text.clone()),
+            Some(Seen::Dir) => Pending::Dir,
+            Some(Seen::Disk(origin)) => Pending::Moved(origin),
+            None => return,
+        };
+        // What was planned inside a moved directory moves with it
+        let inside: Vec<(PathBuf, Pending)> = self
+            .entries
+            .iter()
+            .filter_map(|(entry, pending)| {
+                let rest = entry.strip_prefix(source).ok().filter(|rest| !rest.as_os_str().is_empty())?;
+                Some((target.join(rest), pending.clone()))
+            })
+            .collect();
+        self.remove(source);
+        self.entries.retain(|entry, _| !entry.starts_with(target));
+        self.entries.insert(target.to_path_buf(), moved);
+        self.entries.extend(inside);
+    }
+}
+
+/// What an operation would change, without changing it. Reads run as usual,
+/// except that they return what earlier writes of the batch would have left.
+fn plan(operation: &BatchOperation, planned: &mut Planned) -> Result<(Option<String>, Vec<Value>), String> {
+    match operation {
+        BatchOperation::Read { path, encoding } => {
+            let resolved = &resolve_path(Path::new(path));
+            match planned.get(resolved) {
+                Some(Seen::File((text, ..))) => {
+                    budget::touch(resolved)?;
+                    Ok((Some(text.clone()), Vec::new()))
+                }
+                Some(Seen::Disk(origin)) => {
+                    budget::touch(resolved)?;
+                    let encoding = TextEncoding::parse(encoding.as_deref().unwrap_or("auto"))?;
+                    Ok((Some(read_text_file(&origin, encoding)?.text), Vec::new()))
+                }
+                Some(Seen::Dir) => Err(format!("Is a directory: {}", path)),
+                None => Err(format!("File does not exist: {}", path)),
+            }
+        }
+        BatchOperation::Write { path, content } => {
+            let path = &resolve_path(Path::new(path));
+            budget::touch(path)?;
+            let existing = match planned.get(path) {
+                Some(Seen::File(text)) => Some(text.clone()),
+                Some(Seen::Disk(origin)) => dry_run::existing_text(&origin)?,
+                Some(Seen::Dir) => return Err(format!("Is a directory: {}", path.display())),
+                None => None,
+            };
+            let (change, written) = dry_run::preserving_change_over(path, existing, content, None, None)?;
+            planned.write(path, written);
+            Ok((None, vec![change]))
+        }
+        BatchOperation::Move {
+            source_path,
+            target_path,
+        } => {
+            let (source, target) = (&resolve_path(Path::new(source_path)), &resolve_path(Path::new(target_path)));
+            budget::touch(source)?;
+            budget::touch(target)?;
+            if !planned.exists(source) {
+                return Err(format!("No such file or directory: {}", source.display()));
+            }
+            if planned.exists(target) {
+                return Err(format!("Target already exists: {}", target.display()));
+            }
+            shadow::verify_removal(source).map_err(|e| e.into_message())?;
+            planned.rename(source, target);
+            Ok((
+                None,
+                vec![json!({
+                    "action": "move",
+                    "from": source.display().to_string(),
+                    "to": target.display().to_string(),
+                })],
+            ))
+        }
+        BatchOperation::Mkdir { path } => {
+            let path = &resolve_path(Path::new(path));
+            budget::touch(path)?;
+            let mut missing: Vec<&Path> = path.ancestors().take_while(|dir| !planned.exists(dir)).collect();
+            missing.reverse();
+            let changes = missing
+                .iter()
+                .map(|dir| json!({ "action": "create_directory", "path": dir.display().to_string() }))
+                .collect();
+            for dir in missing {
+                planned.create_dir(dir);
+            }
+            Ok((None, changes))
+        }
+        BatchOperation::Delete { path, recursive } => {
+            let path = &resolve_path(Path::new(path));
+            budget::touch(path)?;
+            if !planned.exists(path) {
+                return Err(format!("No such file or directory: {}", path.display()));
+            }
+            shadow::verify_removal(path).map_err(|e| e.into_message())?;
+            if !recursive && planned.has_entries(path)? {
+                return Err(format!(
+                    "Directory is not empty: {}. Set recursive to delete it with its contents",
+                    path.display()
+                ));
+            }
+            planned.remove(path);
+            Ok((None, vec![json!({ "action": "delete", "path": path.display().to_string(), "recursive": recursive })]))
+        }
+    }
+}
+
+/// Run a batch as a dry run: each operation reports its changes, the first
+/// failure of an atomic batch skips the rest, and nothing is written
+fn dry_run_batch(request: &BatchRequest, atomic: bool) -> HandlerResult<CallToolResult> {
+    let mut planned = Planned::default();
+    let mut results: Vec<Value> = Vec::new();
+    let mut failed = false;
+    for (index, operation) in request.operations.iter().enumerate() {
+        let mut result = json!({
+            "index": index,
+            "op": operation.name(),
+            "paths": operation.paths(),
+        });
+        if failed && atomic {
+            result["status"] = json!("skipped");
+        } else {
+            match plan(operation, &mut planned) {
+                Ok((content, changes)) => {
+                    result["status"] = json!("planned");
+                    result["changes"] = json!(changes);
+                    if let Some(content) = content {
+                        result["content"] = json!(content);
+                    }
+                }
+                Err(error) => {
+                    failed = true;
+                    result["status"] = json!("error");
+                    result["error"] = json!(error);
+                }
+            }
+        }
+        results.push(result);
+    }
+    let summary = json!({
+        "dry_run": true,
+        "committed": false,
+        "results": results,
+    });
+    Ok(CallToolResult {
+        is_error: failed,
+        ..CallToolResult::text(serde_json::to_string_pretty(&summary).unwrap())
+    })
+}
+
+fn empty_trash(trash: &[PathBuf]) {
+    for path in trash {
+        let _ = if path.is_dir() {
+            sandbox::remove_dir_all(path)
+        } else {
+            sandbox::remove_file(path)
+        };
+    }
+}
+
+pub async fn batch(request: BatchRequest) -> HandlerResult<CallToolResult> {
+    let atomic = request.atomic.unwrap_or(true);
+
+    // Validate every path up front so nothing runs when part of the batch is out of bounds
+    for (index, operation) in request.operations.iter().enumerate() {
+        for path in operation.paths() {
+            let validation = match operation {
+                BatchOperation::Read { .. } => validate_path_or_error(&resolve_path(Path::new(path))),
+                _ => validate_write_path_or_error(&resolve_path(Path::new(path))),
+            };
+            if let Err(msg) = validation {
+                return Ok(CallToolResult::error(format!("Operation {} ({}): {}", index, operation.name(), msg)));
+            }
+        }
+    }
+
+    if dry_run::requested(request.dry_run) {
+        return dry_run_batch(&request, atomic);
+    }
+
+    let mut results: Vec<Value> = Vec::new();
+    let mut undo_log: Vec<(usize, Undo)> = Vec::new();
+    let mut trash: Vec<PathBuf> = Vec::new();
+    let mut failed = false;
+
+    for (index, operation) in request.operations.iter().enumerate() {
+        match apply(operation) {
+            Ok(applied) => {
+                let mut result = json!({
+                    "index": index,
+                    "op": operation.name(),
+                    "paths": operation.paths(),
+                    "status": "ok",
+                });
+                if let Some(content) = applied.content {
+                    result["content"] = json!(content);
+                }
+                results.push(result);
+                if let Some(undo) = applied.undo {
+                    undo_log.push((index, undo));
+                }
+                trash.extend(applied.trash);
+            }
+            Err(error) => {
+                failed = true;
+                results.push(json!({
+                    "index": index,
+                    "op": operation.name(),
+                    "paths": operation.paths(),
+                    "status": "error",
+                    "error": error,
+                }));
+                if atomic {
+                    break;
+                }
+            }
+        }
+    }
+
+    let rolled_back = failed && atomic;
+    if rolled_back {
+        // Revert in reverse order so later operations are undone before the ones they depend on
+        for (index, undo) in undo_log.into_iter().rev() {
+            let status = match undo.revert() {
+                Ok(_) => json!("rolled_back"),
+                Err(e) => json!(format!("rollback_failed: {}", e)),
+            };
+            results[index]["status"] = status;
+        }
+        // Operations after the failing one were never attempted
+        let attempted = results.len();
+        for (index, operation) in request.operations.iter().enumerate().skip(attempted) {
+            results.push(json!({
+                "index": index,
+                "op": operation.name(),
+                "paths": operation.paths(),
+                "status": "skipped",
+            }));
+        }
+    } else {
+        empty_trash(&trash);
+    }
+
+    let summary = json!({
+        "committed": !rolled_back,
+        "results": results,
+    });
+    Ok(CallToolResult {
+        is_error: failed,
+        ..CallToolResult::text(serde_json::to_string_pretty(&summary).unwrap())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+
+    #[tokio::test]
+    async fn test_batch_rolls_back_on_failure() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let existing = temp_dir.path().join("existing.txt");
+        fs::write(&existing, "original").unwrap();
+        let doomed = temp_dir.path().join("doomed.txt");
+        fs::write(&doomed, "delete me").unwrap();
+
+        let operations = json!([
+            {"op": "write", "path": existing.to_str().unwrap(), "content": "changed"},
+            {"op": "mkdir", "path": temp_dir.path().join("a/b").to_str().unwrap()},
+            {"op": "delete", "path": doomed.to_str().unwrap()},
+            {"op": "read", "path": temp_dir.path().join("missing.txt").to_str().unwrap()},
+            {"op": "write", "path": temp_dir.path().join("never.txt").to_str().unwrap(), "content": "x"}
+        ]);
+        let request = BatchRequest {
+            operations: serde_json::from_value(operations).unwrap(),
+            atomic: None,
+            dry_run: None,
+        };
+        let result = batch(request).await.unwrap();
+        assert!(result.is_error);
+
+        // Everything done before the failing read was undone
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "original");
+        assert!(!temp_dir.path().join("a").exists());
+        assert_eq!(fs::read_to_string(&doomed).unwrap(), "delete me");
+        assert!(!temp_dir.path().join("never.txt").exists());
+        let summary = result_json(&result);
+        assert_eq!(summary["committed"], false);
+        assert_eq!(summary["results"][0]["status"], "rolled_back");
+        assert_eq!(summary["results"][3]["status"], "error");
+        assert_eq!(summary["results"][4]["status"], "skipped");
+    }
+
+    #[tokio::test]
+    async fn test_park_in_scratch() {
+        let (_env, temp_dir, _) = test_env().await;
+        let doomed = temp_dir.path().canonicalize().unwrap().join("doomed.txt");
+        fs::write(&doomed, "delete me").unwrap();
+
+        let trash = park(&doomed).unwrap();
+        assert!(trash.starts_with(scratch_directory().unwrap()), "{}", trash.display());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        sandbox::rename(&trash, &doomed).unwrap();
+        assert_eq!(fs::read_to_string(&doomed).unwrap(), "delete me");
+    }
+
+    #[tokio::test]
+    async fn test_batch_dry_run() {
+        let (_env, temp_dir, _) = test_env().await;
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&notes, "one\ntwo\n").unwrap();
+        let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+
+        // Later operations of a batch see what the earlier ones would have done
+        let result = batch(BatchRequest {
+            operations: vec![
+                BatchOperation::Move {
+                    source_path: path("notes.txt"),
+                    target_path: path("moved.txt"),
+                },
+                BatchOperation::Delete {
+                    path: path("moved.txt"),
+                    recursive: false,
+                },
+                BatchOperation::Read {
+                    path: path("notes.txt"),
+                    encoding: None,
+                },
+            ],
+            atomic: Some(true),
+            dry_run: Some(true),
+        })
+        .await
+        .unwrap();
+        assert!(result.is_error);
+        let summary = result_json(&result);
+        assert_eq!(summary["dry_run"], true);
+        assert_eq!(summary["results"][0]["status"], "planned");
+        assert_eq!(summary["results"][1]["status"], "planned");
+        assert_eq!(summary["results"][2]["status"], "error");
+
+        assert_eq!(fs::read_to_string(&notes).unwrap(), "one\ntwo\n");
+        assert!(!temp_dir.path().join("moved.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_batch_dry_run_sees_planned_contents() {
+        let (_env, temp_dir, _) = test_env().await;
+        fs::write(temp_dir.path().join("notes.txt"), "one\ntwo\n").unwrap();
+        let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+        let write = |name: &str, content: &str| BatchOperation::Write {
+            path: path(name),
+            content: content.to_string(),
+        };
+        let read = |name: &str| BatchOperation::Read {
+            path: path(name),
+            encoding: None,
+        };
+
+        let result = batch(BatchRequest {
+            operations: vec![
+                write("draft.txt", "first\n"),
+                read("draft.txt"),
+                write("draft.txt", "second\n"),
+                BatchOperation::Move {
+                    source_path: path("notes.txt"),
+                    target_path: path("kept.txt"),
+                },
+                read("kept.txt"),
+                BatchOperation::Mkdir { path: path("dir") },
+                write("dir/file.txt", "x"),
+                BatchOperation::Delete {
+                    path: path("dir"),
+                    recursive: false,
+                },
+            ],
+            atomic: Some(false),
+            dry_run: Some(true),
+        })
+        .await
+        .unwrap();
+        let results = result_json(&result)["results"].clone();
+        assert_eq!(results[1]["content"], "first\n");
+        assert_eq!(results[2]["changes"][0]["action"], "modify");
+        assert_eq!(results[2]["changes"][0]["size_before"], 6);
+        assert_eq!(results[4]["content"], "one\ntwo\n");
+        assert_eq!(results[7]["status"], "error");
+        assert!(results[7]["error"].as_str().unwrap().contains("not empty"));
+
+        assert!(!temp_dir.path().join("draft.txt").exists());
+        assert!(temp_dir.path().join("notes.txt").exists());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::mcp::config;
+use crate::mcp::metrics;
+use crate::mcp::session;
+use crate::mcp::types::*;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Per-minute budgets are measured over this sliding window
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Session budgets, each unlimited unless its variable is set (by the matching
+/// `--max-*` command line flag)
+struct Limits {
+    calls_per_minute: Option<u64>,
+    bytes_read_per_minute: Option<u64>,
+    bytes_written_per_minute: Option<u64>,
+    files_per_request: Option<u64>,
+}
+
+fn limit(name: &str) -> Option<u64> {
+    config::var(name).ok().and_then(|value| value.parse().ok()).filter(|limit| *limit > 0)
+}
+
+fn limits() -> Limits {
+    Limits {
+        calls_per_minute: limit("MCP_RS_FILESYSTEM_MAX_CALLS_PER_MINUTE"),
+        bytes_read_per_minute: limit("MCP_RS_FILESYSTEM_MAX_BYTES_READ_PER_MINUTE"),
+        bytes_written_per_minute: limit("MCP_RS_FILESYSTEM_MAX_BYTES_WRITTEN_PER_MINUTE"),
+        files_per_request: limit("MCP_RS_FILESYSTEM_MAX_FILES_PER_REQUEST"),
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    calls: VecDeque<Instant>,
+    reads: VecDeque<(Instant, u64)>,
+    writes: VecDeque<(Instant, u64)>,
+}
+
+/// What a single tool call used. Each call has its own, so a call that keeps
+/// running after its timeout charges itself rather than the calls after it.
+#[derive(Default)]
+pub struct CallUsage {
+    /// Files touched by the call
+    files: HashSet<PathBuf>,
+    /// Set when the call ran over a budget
+    exceeded: Option<Value>,
+}
+
+tokio::task_local! {
+    static CALL: Arc<Mutex<CallUsage>>;
+}
+
+/// The usage of the tool call being handled. `None` outside a tool call.
+pub fn current_call() -> Option<Arc<Mutex<CallUsage>>> {
+    CALL.try_with(Arc::clone).ok()
+}
+
+/// Run `future`, the handling of a tool call, charging what it does to `call`
+pub async fn in_tool_call<F: Future>(call: Arc<Mutex<CallUsage>>, future: F) -> F::Output {
+    CALL.scope(call, future).await
+}
+
+impl Usage {
+    fn expire(&mut self, now: Instant) {
+        while self.calls.front().is_some_and(|t| now.duration_since(*t) >= WINDOW) {
+            self.calls.pop_front();
+        }
+        for log in [&mut self.reads, &mut self.writes] {
+            while log.front().is_some_and(|(t, _)| now.duration_since(*t) >= WINDOW) {
+                log.pop_front();
+            }
+        }
+    }
+}
+
+/// Usage of every session by its id, so one client running wild leaves the
+/// budgets of the others alone
+static USAGE: LazyLock<Mutex<HashMap<String, Usage>>> = LazyLock::new(Default::default);
+
+/// Run `f` on the usage of the current session
+fn with_usage<T>(f: impl FnOnce(&mut Usage) -> T) -> T {
+    let mut sessions = USAGE.lock().unwrap();
+    f(sessions.entry(session::current_id()).or_default())
+}
+
+/// Forget the usage of a session whose connection closed
+pub fn discard_session(session_id: &str) {
+    USAGE.lock().unwrap().remove(session_id);
+}
+
+/// Milliseconds until the oldest entry of a window expires
+fn retry_after(oldest: Option<Instant>, now: Instant) -> u128 {
+    oldest.map(|t| WINDOW.saturating_sub(now.duration_since(t)).as_millis()).unwrap_or(0)
+}
+
+fn exceeded(budget: &str, limit: u64, used: u64, retry_after_ms: Option<u128>) -> Value {
+    let mut report = json!({
+        "error": "budget_exceeded",
+        "budget": budget,
+        "limit": limit,
+        "used": used,
+    });
+    if let Some(retry_after_ms) = retry_after_ms {
+        report["retry_after_ms"] = json!(retry_after_ms);
+    }
+    report
+}
+
+/// Tool result reporting a budget that ran out
+pub fn exceeded_result(report: &Value) -> CallToolResult {
+    CallToolResult::error(serde_json::to_string_pretty(report).unwrap())
+}
+
+/// Add the report of a budget a call ran over to the result it returned. The
+/// result is kept, as it tells what the call did before it was stopped, and
+/// is marked as an error.
+pub fn add_exceeded_report(result: &mut Value, report: &Value) {
+    if let Some(content) = result.get_mut("content").and_then(Value::as_array_mut) {
+        content.push(json!({ "type": "text", "text": serde_json::to_string_pretty(report).unwrap() }));
+    }
+    result["isError"] = json!(true);
+}
+
+/// Admit a tool call, returning the usage to run it with in [`in_tool_call`].
+/// Fails when the call rate or a byte budget is used up.
+pub fn begin_tool_call() -> Result<Arc<Mutex<CallUsage>>, Value> {
+    let limits = limits();
+    let now = Instant::now();
+    with_usage(|usage| {
+        usage.expire(now);
+
+        if let Some(limit) = limits.calls_per_minute {
+            if usage.calls.len() as u64 >= limit {
+                let retry = retry_after(usage.calls.front().copied(), now);
+                return Err(exceeded("calls_per_minute", limit, usage.calls.len() as u64, Some(retry)));
+            }
+        }
+        for (budget, limit, log) in [
+            ("bytes_read_per_minute", limits.bytes_read_per_minute, &usage.reads),
+            ("bytes_written_per_minute", limits.bytes_written_per_minute, &usage.writes),
+        ] {
+            let used: u64 = log.iter().map(|(_, bytes)| bytes).sum();
+            if let Some(limit) = limit.filter(|limit| used >= *limit) {
+                let retry = retry_after(log.front().map(|(t, _)| *t), now);
+                return Err(exceeded(budget, limit, used, Some(retry)));
+            }
+        }
+        usage.calls.push_back(now);
+        Ok(Arc::new(Mutex::new(CallUsage::default())))
+    })
+}
+
+/// Finish a tool call, returning the budget report if it ran over a budget
+pub fn end_tool_call(call: &Mutex<CallUsage>) -> Option<Value> {
+    call.lock().unwrap().exceeded.take()
+}
+
+/// Account for a file the current call is about to touch. Only tool calls
+/// have a budget of files.
+pub fn touch(path: &Path) -> Result<(), String> {
+    let (Some(limit), Some(call)) = (limits().files_per_request, current_call()) else {
+        return Ok(());
+    };
+    let mut call = call.lock().unwrap();
+    call.files.insert(path.to_path_buf());
+    let used = call.files.len() as u64;
+    if used > limit {
+        call.exceeded = Some(exceeded("files_per_request", limit, used, None));
+        return Err(format!("Budget exceeded: the request touches more than {} files", limit));
+    }
+    Ok(())
+}
+
+/// Whether the files a directory walk reaches count against the files per
+/// request budget: in a tool call with that budget set. Walks in the
+/// background or for other requests, such as resource listings, are free.
+pub fn charges_walks() -> bool {
+    limits().files_per_request.is_some() && current_call().is_some()
+}
+
+fn charge(path: &Path, bytes: u64, write: bool) -> Result<(), String> {
+    touch(path)?;
+    let limits = limits();
+    let (budget, limit) = if write {
+        ("bytes_written_per_minute", limits.bytes_written_per_minute)
+    } else {
+        ("bytes_read_per_minute", limits.bytes_read_per_minute)
+    };
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    with_usage(|usage| {
+        usage.expire(now);
+        let log = if write { &mut usage.writes } else { &mut usage.reads };
+        let used: u64 = log.iter().map(|(_, bytes)| bytes).sum();
+        if used + bytes > limit {
+            let report = exceeded(budget, limit, used + bytes, Some(retry_after(log.front().map(|(t, _)| *t), now)));
+            if let Some(call) = current_call() {
+                call.lock().unwrap().exceeded = Some(report);
+            }
+            return Err(format!("Budget exceeded: {} of {} bytes per minute", budget, limit));
+        }
+        log.push_back((now, bytes));
+        Ok(())
+    })
+}
+
+/// Account for reading `bytes` from `path`. Reads through the backend of
+/// [`vfs::current`](crate::mcp::vfs::current) are charged already; this is
+/// for tools that go to the disk on their own.
+pub fn charge_read(path: &Path, bytes: u64) -> Result<(), String> {
+    charge(path, bytes, false)?;
+    metrics::add_bytes_read(bytes);
+    Ok(())
+}
+
+/// Account for writing `bytes` to `path`
+pub fn charge_write(path: &Path, bytes: u64) -> Result<(), String> {
+    charge(path, bytes, true)?;
+    metrics::add_bytes_written(bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::batch::batch;
+    use crate::mcp::batch::BatchOperation;
+    use crate::mcp::batch::BatchRequest;
+    use crate::mcp::ignore_rules::IgnoreRules;
+    use crate::mcp::session::Session;
+    use crate::mcp::testing::set_env;
+    use crate::mcp::testing::test_env;
+    use crate::mcp::vfs;
+    use std::fs;
+
+    /// Run `future` as a tool call, returning its output and budget report
+    async fn tool_call<F: Future>(future: F) -> (F::Output, Option<Value>) {
+        let call = begin_tool_call().unwrap();
+        let output = in_tool_call(Arc::clone(&call), future).await;
+        (output, end_tool_call(&call))
+    }
+
+    #[tokio::test]
+    async fn test_budget_limits() {
+        let (_env, temp_dir, _) = test_env().await;
+        let read_limit = set_env("MCP_RS_FILESYSTEM_MAX_BYTES_READ_PER_MINUTE", "150");
+        let _files_limit = set_env("MCP_RS_FILESYSTEM_MAX_FILES_PER_REQUEST", "2");
+
+        let file = temp_dir.path().join("data.txt");
+        fs::write(&file, "x".repeat(100)).unwrap();
+
+        // Reads through the backend are charged. They run in a session, as
+        // outside one the usage is that of the first session started.
+        let (sender, _output) = tokio::sync::mpsc::unbounded_channel();
+        let own = Session::start(sender);
+        let report = session::scope(Arc::clone(&own), async {
+            let (read, report) = tool_call(async { vfs::current().read(&file) }).await;
+            assert!(read.is_ok());
+            assert!(report.is_none());
+
+            // The second read would go over 150 bytes per minute
+            let (read, report) = tool_call(async { vfs::current().read(&file) }).await;
+            assert!(read.is_err());
+            report.unwrap()
+        })
+        .await;
+        own.end();
+        assert_eq!(report["error"], "budget_exceeded");
+        assert_eq!(report["budget"], "bytes_read_per_minute");
+
+        // Another session has a budget of its own
+        let (sender, _output) = tokio::sync::mpsc::unbounded_channel();
+        let other = Session::start(sender);
+        let (read, _) = session::scope(Arc::clone(&other), tool_call(async { vfs::current().read(&file) })).await;
+        assert!(read.is_ok());
+        other.end();
+        drop(read_limit);
+
+        // A batch touching three files is stopped at the third
+        let operations = (0..3)
+            .map(|n| BatchOperation::Mkdir {
+                path: temp_dir.path().join(format!("dir{}", n)).to_str().unwrap().to_string(),
+            })
+            .collect();
+        let (result, report) = tool_call(batch(BatchRequest {
+            operations,
+            atomic: Some(true),
+            dry_run: None,
+        }))
+        .await;
+        assert!(result.unwrap().is_error);
+        assert_eq!(report.unwrap()["budget"], "files_per_request");
+        assert!(!temp_dir.path().join("dir0").exists());
+
+        // The files a walk reaches in a tool call count too, and the walk stops at the budget
+        let tree = temp_dir.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        for n in 0..3 {
+            fs::write(tree.join(format!("file{}.txt", n)), "").unwrap();
+        }
+        let walk = || IgnoreRules::new(true, false).walker(&tree).build().flatten().count();
+        let (walked, report) = tool_call(async { walk() }).await;
+        assert_eq!(walked, 3);
+        let report = report.unwrap();
+        assert_eq!(report["budget"], "files_per_request");
+        // The report is added to what the call returned
+        let mut result = json!(CallToolResult::text("file0.txt".to_string()));
+        add_exceeded_report(&mut result, &report);
+        assert_eq!(result["isError"], true);
+        assert_eq!(result["content"][0]["text"], "file0.txt");
+        assert!(result["content"][1]["text"].as_str().unwrap().contains("budget_exceeded"));
+        // Outside a tool call walks are free
+        assert_eq!(walk(), 4);
+
+        // A call still running after its timeout charges itself, not the call after it
+        let first = begin_tool_call().unwrap();
+        let second = begin_tool_call().unwrap();
+        let touched = in_tool_call(Arc::clone(&first), async {
+            (0..3).filter(|n| touch(&tree.join(format!("file{}.txt", n))).is_ok()).count()
+        })
+        .await;
+        assert_eq!(touched, 2);
+        assert!(in_tool_call(Arc::clone(&second), async { touch(&tree.join("file0.txt")) }).await.is_ok());
+        assert!(end_tool_call(&second).is_none());
+        assert_eq!(end_tool_call(&first).unwrap()["budget"], "files_per_request");
+    }
+}
@@ -0,0 +1,431 @@
+use crate::mcp::dry_run;
+use crate::mcp::sandbox;
+use crate::mcp::sandbox::Access;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::scratch::existing_scratch_directory;
+use crate::mcp::scratch::scratch_directory;
+use crate::mcp::session;
+use crate::mcp::types::*;
+use crate::mcp::utilities::canonical_path;
+use chrono::DateTime;
+use chrono::Local;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+/// What a path looked like before the first change after a checkpoint
+enum Original {
+    /// Nothing existed there, so rolling back removes whatever is there now
+    Absent,
+    /// A file, copied to this location in the store
+    File(PathBuf),
+    /// A directory tree, copied to this location in the store
+    Tree(PathBuf),
+}
+
+struct Record {
+    path: PathBuf,
+    original: Original,
+}
+
+struct Checkpoint {
+    id: String,
+    name: Option<String>,
+    created_at: DateTime<Local>,
+    /// Where this checkpoint keeps its copies
+    store: PathBuf,
+    /// In the order the paths were first changed
+    records: Vec<Record>,
+}
+
+/// Checkpoints of each session, oldest first
+static CHECKPOINTS: LazyLock<Mutex<HashMap<String, Vec<Checkpoint>>>> = LazyLock::new(Default::default);
+static CHECKPOINT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static BLOB_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Copy a directory tree, recreating symlinks rather than following them
+fn copy_tree(source: &Path, target: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let destination = target.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&entry.path(), &destination)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &destination)?;
+        } else {
+            fs::copy(entry.path(), &destination)?;
+        }
+    }
+    Ok(())
+}
+
+/// Put back a file saved at `copy`, with its permissions, through the sandbox
+fn restore_file(copy: &Path, target: &Path) -> Result<(), String> {
+    let mut input = File::open(copy).map_err(|e| e.to_string())?;
+    let (dir, name) = sandbox::parent(target)?;
+    let mut output = dir.open(&name, Access::Write(WriteMode::Overwrite))?;
+    std::io::copy(&mut input, &mut output).map_err(|e| e.to_string())?;
+    let permissions = input.metadata().map_err(|e| e.to_string())?.permissions();
+    output.set_permissions(permissions).map_err(|e| e.to_string())
+}
+
+/// Put back a tree saved by `copy_tree`, through the sandbox
+fn restore_tree(copy: &Path, target: &Path) -> Result<(), String> {
+    sandbox::create_dir_all(target)?;
+    for entry in fs::read_dir(copy).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let destination = target.join(entry.file_name());
+        let file_type = entry.file_type().map_err(|e| e.to_string())?;
+        if file_type.is_dir() {
+            restore_tree(&entry.path(), &destination)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            {
+                let (dir, name) = sandbox::parent(&destination)?;
+                dir.symlink(&fs::read_link(entry.path()).map_err(|e| e.to_string())?, &name)?;
+            }
+        } else {
+            restore_file(&entry.path(), &destination)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_any(path: &Path) -> Result<(), String> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => sandbox::remove_dir_all(path),
+        Ok(_) => sandbox::remove_file(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Save the current state of `path` into the latest checkpoint before it is
+/// changed. Only the first change after a checkpoint is recorded, and nothing
+/// is recorded while there is no checkpoint. Mutating tools call this before
+/// touching a path, and fail if it fails.
+pub fn preserve(path: &Path) -> Result<(), String> {
+    let mut checkpoints = CHECKPOINTS.lock().unwrap();
+    let Some(checkpoint) = checkpoints.get_mut(&session::current_id()).and_then(|list| list.last_mut()) else {
+        return Ok(());
+    };
+    let path = canonical_path(path);
+    // Scratch files are temporary anyway, and the store lives there
+    if existing_scratch_directory().is_some_and(|scratch| path.starts_with(scratch)) {
+        return Ok(());
+    }
+    // A recorded ancestor already covers everything below it
+    if checkpoint.records.iter().any(|record| path.starts_with(&record.path)) {
+        return Ok(());
+    }
+
+    // For a path in directories that do not exist yet, record the topmost
+    // missing one so rolling back removes the directories created for it
+    let mut target = path.as_path();
+    while let Some(parent) = target.parent() {
+        if parent.symlink_metadata().is_ok() {
+            break;
+        }
+        target = parent;
+    }
+
+    let blob = || {
+        checkpoint
+            .store
+            .join(BLOB_COUNTER.fetch_add(1, Ordering::SeqCst).to_string())
+    };
+    let original = match fs::symlink_metadata(target) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Original::Absent,
+        Err(e) => return Err(format!("Failed to checkpoint {}: {}", target.display(), e)),
+        Ok(metadata) if metadata.is_dir() => {
+            let copy = blob();
+            copy_tree(target, &copy).map_err(|e| format!("Failed to checkpoint {}: {}", target.display(), e))?;
+            Original::Tree(copy)
+        }
+        Ok(_) => {
+            let copy = blob();
+            fs::create_dir_all(&checkpoint.store).map_err(|e| e.to_string())?;
+            fs::copy(target, &copy).map_err(|e| format!("Failed to checkpoint {}: {}", target.display(), e))?;
+            Original::File(copy)
+        }
+    };
+    checkpoint.records.push(Record {
+        path: target.to_path_buf(),
+        original,
+    });
+    Ok(())
+}
+
+/// Put a recorded path back as it was. What is written goes through the
+/// sandbox, like the changes of the tools it undoes.
+fn restore(record: &Record) -> Result<(), String> {
+    remove_any(&record.path)?;
+    match &record.original {
+        Original::Absent => Ok(()),
+        Original::File(copy) => {
+            if let Some(parent) = record.path.parent() {
+                sandbox::create_dir_all(parent)?;
+            }
+            restore_file(copy, &record.path)
+        }
+        Original::Tree(copy) => restore_tree(copy, &record.path),
+    }
+}
+
+fn json_result(value: serde_json::Value, is_error: bool) -> HandlerResult<CallToolResult> {
+    Ok(CallToolResult {
+        is_error,
+        ..CallToolResult::text(serde_json::to_string_pretty(&value).unwrap())
+    })
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct CreateCheckpointRequest {
+    /// Optional label for the checkpoint
+    pub name: Option<String>,
+}
+
+pub async fn create_checkpoint(request: CreateCheckpointRequest) -> HandlerResult<CallToolResult> {
+    let id = format!("checkpoint-{}", CHECKPOINT_COUNTER.fetch_add(1, Ordering::SeqCst) + 1);
+    let store = match scratch_directory() {
+        Ok(scratch) => scratch.join("checkpoints").join(&id),
+        Err(e) => {
+            return json_result(
+                json!({ "error": format!("Failed to create scratch directory: {}", e) }),
+                true,
+            )
+        }
+    };
+    let checkpoint = Checkpoint {
+        id: id.clone(),
+        name: request.name,
+        created_at: Local::now(),
+        store,
+        records: Vec::new(),
+    };
+    let info = json!({
+        "checkpoint_id": id,
+        "name": checkpoint.name,
+        "created_at": checkpoint.created_at.to_rfc3339(),
+    });
+    CHECKPOINTS.lock().unwrap().entry(session::current_id()).or_default().push(checkpoint);
+    json_result(info, false)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ListCheckpointsRequest {}
+
+pub async fn list_checkpoints(_request: ListCheckpointsRequest) -> HandlerResult<CallToolResult> {
+    let checkpoints = CHECKPOINTS.lock().unwrap();
+    let list: Vec<_> = checkpoints
+        .get(&session::current_id())
+        .into_iter()
+        .flatten()
+        .map(|checkpoint| {
+            json!({
+                "checkpoint_id": checkpoint.id,
+                "name": checkpoint.name,
+                "created_at": checkpoint.created_at.to_rfc3339(),
+                "paths_changed": checkpoint
+                    .records
+                    .iter()
+                    .map(|record| record.path.display().to_string())
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json_result(json!({ "checkpoints": list }), false)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct RollbackToCheckpointRequest {
+    /// ID returned by create_checkpoint
+    pub checkpoint_id: String,
+    /// List the paths that would be restored or removed and the checkpoints that would be discarded, without
+    /// rolling back. Always the case when the server runs with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+/// Put every path changed since the checkpoint back the way it was. Later
+/// checkpoints are discarded; the checkpoint itself stays and starts over.
+pub async fn rollback_to_checkpoint(request: RollbackToCheckpointRequest) -> HandlerResult<CallToolResult> {
+    let mut all = CHECKPOINTS.lock().unwrap();
+    let checkpoints = all.entry(session::current_id()).or_default();
+    let Some(position) = checkpoints.iter().position(|c| c.id == request.checkpoint_id) else {
+        return json_result(
+            json!({ "error": format!("Unknown checkpoint: {}", request.checkpoint_id) }),
+            true,
+        );
+    };
+
+    if dry_run::requested(request.dry_run) {
+        // The oldest record of a path holds what it goes back to
+        let mut seen = Vec::new();
+        let mut changes = Vec::new();
+        for record in checkpoints[position..].iter().flat_map(|checkpoint| &checkpoint.records) {
+            if seen.contains(&&record.path) {
+                continue;
+            }
+            seen.push(&record.path);
+            let path = record.path.display().to_string();
+            changes.push(match &record.original {
+                Original::Absent => json!({ "action": "remove", "path": path }),
+                Original::File(copy) => json!({
+                    "action": "restore_file",
+                    "path": path,
+                    "size_after": fs::metadata(copy).map(|metadata| metadata.len()).ok(),
+                }),
+                Original::Tree(_) => json!({ "action": "restore_directory", "path": path }),
+            });
+        }
+        let discarded: Vec<&str> = checkpoints[position + 1..].iter().map(|c| c.id.as_str()).collect();
+        return json_result(
+            json!({
+                "dry_run": true,
+                "checkpoint_id": request.checkpoint_id,
+                "changes": changes,
+                "discarded_checkpoints": discarded,
+            }),
+            false,
+        );
+    }
+
+    let mut restored = Vec::new();
+    let mut errors = Vec::new();
+    // Newest changes first, so the state at the checkpoint is what remains
+    for checkpoint in checkpoints[position..].iter().rev() {
+        for record in checkpoint.records.iter().rev() {
+            match restore(record) {
+                Ok(()) => restored.push(record.path.display().to_string()),
+                Err(e) => errors.push(json!({ "path": record.path.display().to_string(), "error": e })),
+            }
+        }
+    }
+    restored.sort();
+    restored.dedup();
+
+    let discarded: Vec<String> = checkpoints
+        .drain(position + 1..)
+        .map(|c| {
+            let _ = fs::remove_dir_all(&c.store);
+            c.id
+        })
+        .collect();
+    let checkpoint = &mut checkpoints[position];
+    checkpoint.records.clear();
+    let _ = fs::remove_dir_all(&checkpoint.store);
+
+    json_result(
+        json!({
+            "checkpoint_id": checkpoint.id,
+            "restored": restored,
+            "errors": errors,
+            "discarded_checkpoints": discarded,
+        }),
+        !errors.is_empty(),
+    )
+}
+
+/// Drop the checkpoints of a session that ended, along with their copies
+pub fn discard_session(session: &str) {
+    for checkpoint in CHECKPOINTS.lock().unwrap().remove(session).unwrap_or_default() {
+        let _ = fs::remove_dir_all(&checkpoint.store);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_text;
+    use crate::mcp::testing::test_env;
+    use crate::mcp::tools::create_directory;
+    use crate::mcp::tools::move_or_rename;
+    use crate::mcp::tools::overwrite_file;
+    use crate::mcp::tools::CreateDirectoryRequest;
+    use crate::mcp::tools::MoveOrRenameRequest;
+    use crate::mcp::tools::OverwriteFileRequest;
+
+    #[tokio::test]
+    async fn test_checkpoint_rollback() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let root = temp_dir.path();
+        fs::write(root.join("notes.txt"), "original").unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/guide.md"), "guide").unwrap();
+
+        let result = create_checkpoint(CreateCheckpointRequest { name: Some("before".to_string()) }).await.unwrap();
+        let text = result_text(&result);
+        let checkpoint_id = serde_json::from_str::<serde_json::Value>(text).unwrap()["checkpoint_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let overwrite = |path: &str, content: &str| OverwriteFileRequest {
+            path: root.join(path).to_string_lossy().into_owned(),
+            content: content.to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: None,
+            dry_run: None,
+        };
+        assert!(!overwrite_file(overwrite("notes.txt", "first")).await.unwrap().is_error);
+        assert!(!overwrite_file(overwrite("notes.txt", "second")).await.unwrap().is_error);
+        assert!(!overwrite_file(overwrite("docs/guide.md", "edited")).await.unwrap().is_error);
+        assert!(!move_or_rename(MoveOrRenameRequest {
+            source_path: root.join("docs").to_string_lossy().into_owned(),
+            target_path: root.join("moved").to_string_lossy().into_owned(),
+            commit_message: String::new(),
+            dry_run: None,
+        })
+        .await
+        .unwrap()
+        .is_error);
+        assert!(!create_directory(CreateDirectoryRequest {
+            path: root.join("new/deep").to_string_lossy().into_owned(),
+            commit_message: String::new(),
+            dry_run: None,
+        })
+        .await
+        .unwrap()
+        .is_error);
+        assert!(!overwrite_file(overwrite("created.txt", "new")).await.unwrap().is_error);
+
+        let result = rollback_to_checkpoint(RollbackToCheckpointRequest {
+            checkpoint_id,
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "original");
+        assert_eq!(fs::read_to_string(root.join("docs/guide.md")).unwrap(), "guide");
+        assert!(!root.join("moved").exists());
+        assert!(!root.join("new").exists());
+        assert!(!root.join("created.txt").exists());
+
+        let result = rollback_to_checkpoint(RollbackToCheckpointRequest {
+            checkpoint_id: "checkpoint-unknown".to_string(),
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(result.is_error);
+    }
+}
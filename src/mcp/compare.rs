@@ -0,0 +1,227 @@
+use crate::mcp::hashing::hash_file;
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::mime::looks_like_text;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::vfs;
+use git2::Patch;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const DEFAULT_MAX_FILES: usize = 100_000;
+const DEFAULT_MAX_DIFF_BYTES: u64 = 64 * 1024;
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct DiffDirectoriesRequest {
+    /// First directory, A
+    pub path_a: String,
+    /// Second directory, B
+    pub path_b: String,
+    /// Include a unified diff for text files that differ and are no larger than max_diff_bytes. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_diffs: Option<bool>,
+    /// Largest file, in bytes, to include a diff for. Defaults to 65536.
+    #[schemars(extend("default" = 65536))]
+    pub max_diff_bytes: Option<u64>,
+    /// Skip files excluded by .gitignore, .ignore and git excludes. Defaults to false, as backups and build outputs
+    /// are often ignored.
+    #[schemars(extend("default" = false))]
+    pub respect_gitignore: Option<bool>,
+    /// Include hidden files and directories. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub include_hidden: Option<bool>,
+    /// Stop scanning a directory after this many files. Defaults to 100000.
+    #[schemars(extend("default" = 100000))]
+    pub max_files: Option<usize>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+/// Files under `root` by their path relative to it, with their size. The
+/// bool tells whether the walk stopped at `max_files`.
+fn collect_files(root: &Path, rules: &IgnoreRules, max_files: usize) -> (BTreeMap<String, u64>, bool) {
+    let walker = rules.walker(root).build();
+    let mut files = BTreeMap::new();
+    for entry in walker.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        if files.len() >= max_files {
+            return (files, true);
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        // Forward slashes, so both sides of the comparison agree on every platform
+        let relative = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        files.insert(relative, metadata.len());
+    }
+    (files, false)
+}
+
+/// Unified diff of two text files, or why there is none
+fn unified_diff(a: &Path, b: &Path, relative: &str, max_bytes: u64) -> Result<String, String> {
+    let too_large = |size: u64| size > max_bytes;
+    let (size_a, size_b) = (
+        a.metadata().map_err(|e| e.to_string())?.len(),
+        b.metadata().map_err(|e| e.to_string())?.len(),
+    );
+    if too_large(size_a) || too_large(size_b) {
+        return Err("too large".to_string());
+    }
+    let fs = vfs::current();
+    let (bytes_a, bytes_b) = (fs.read(a)?, fs.read(b)?);
+    if !looks_like_text(&bytes_a) || !looks_like_text(&bytes_b) {
+        return Err("binary".to_string());
+    }
+    let path = Path::new(relative);
+    let mut patch = Patch::from_buffers(&bytes_a, Some(path), &bytes_b, Some(path), None).map_err(|e| e.to_string())?;
+    let text = patch.to_buf().map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+pub async fn diff_directories(request: DiffDirectoriesRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(diff_directories_blocking(request)).await
+}
+
+async fn diff_directories_blocking(request: DiffDirectoriesRequest) -> HandlerResult<CallToolResult> {
+    let path_a = &resolve_path(Path::new(&request.path_a));
+    let path_b = &resolve_path(Path::new(&request.path_b));
+    for path in [path_a, path_b] {
+        if let Err(msg) = validate_path_or_error(path) {
+            return Ok(CallToolResult::error(msg));
+        }
+        if !path.is_dir() {
+            return Ok(CallToolResult::error(format!("Not a directory: {}", path.display())));
+        }
+    }
+
+    let rules = IgnoreRules::new(request.respect_gitignore.unwrap_or(false), request.include_hidden.unwrap_or(true))
+        .with_overrides(&request.ignore);
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let max_files = request.max_files.unwrap_or(DEFAULT_MAX_FILES).max(1);
+    let (files_a, truncated_a) = collect_files(path_a, &rules, max_files);
+    let (files_b, truncated_b) = collect_files(path_b, &rules, max_files);
+    let include_diffs = request.include_diffs.unwrap_or(false);
+    let max_diff_bytes = request.max_diff_bytes.unwrap_or(DEFAULT_MAX_DIFF_BYTES);
+
+    let only_in_a: Vec<&String> = files_a.keys().filter(|path| !files_b.contains_key(*path)).collect();
+    let only_in_b: Vec<&String> = files_b.keys().filter(|path| !files_a.contains_key(*path)).collect();
+    let mut different = Vec::new();
+    let mut identical = 0;
+    let mut unreadable = Vec::new();
+    for (relative, &size_a) in &files_a {
+        let Some(&size_b) = files_b.get(relative) else {
+            continue;
+        };
+        let (file_a, file_b) = (path_a.join(relative), path_b.join(relative));
+        // Files of different sizes differ without reading them
+        let reason = if size_a != size_b {
+            "size"
+        } else {
+            match (hash_file(&file_a), hash_file(&file_b)) {
+                (Ok(hash_a), Ok(hash_b)) if hash_a == hash_b => {
+                    identical += 1;
+                    continue;
+                }
+                (Ok(_), Ok(_)) => "content",
+                (Err(e), _) | (_, Err(e)) => {
+                    unreadable.push(json!({ "path": relative, "error": e.to_string() }));
+                    continue;
+                }
+            }
+        };
+        let mut entry = json!({
+            "path": relative,
+            "reason": reason,
+            "size_a": size_a,
+            "size_b": size_b,
+        });
+        if include_diffs {
+            match unified_diff(&file_a, &file_b, relative, max_diff_bytes) {
+                Ok(diff) => entry["diff"] = json!(diff),
+                Err(why) => entry["diff_skipped"] = json!(why),
+            }
+        }
+        different.push(entry);
+    }
+
+    let report = json!({
+        "path_a": path_a.display().to_string(),
+        "path_b": path_b.display().to_string(),
+        "identical": identical,
+        "only_in_a": only_in_a,
+        "only_in_b": only_in_b,
+        "different": different,
+        "unreadable": unreadable,
+        "truncated": truncated_a || truncated_b,
+    });
+    Ok(CallToolResult::structured(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_diff_directories() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let (a, b) = (temp_dir.path().join("a"), temp_dir.path().join("b"));
+        for dir in [&a, &b] {
+            fs::create_dir_all(dir.join("sub")).unwrap();
+            fs::write(dir.join("same.txt"), "unchanged\n").unwrap();
+        }
+        fs::write(a.join("sub/old.txt"), "gone\n").unwrap();
+        fs::write(b.join("new.txt"), "added\n").unwrap();
+        fs::write(a.join("notes.txt"), "one\ntwo\n").unwrap();
+        fs::write(b.join("notes.txt"), "one\nTWO\n").unwrap();
+        fs::write(a.join("data.bin"), [0u8, 1, 2]).unwrap();
+        fs::write(b.join("data.bin"), [0u8, 1, 2, 3]).unwrap();
+
+        let result = diff_directories(DiffDirectoriesRequest {
+            path_a: a.to_str().unwrap().to_string(),
+            path_b: b.to_str().unwrap().to_string(),
+            include_diffs: Some(true),
+            max_diff_bytes: None,
+            respect_gitignore: None,
+            include_hidden: None,
+            max_files: None,
+            ignore: Default::default(),
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["identical"], 1);
+        assert_eq!(report["only_in_a"], json!(["sub/old.txt"]));
+        assert_eq!(report["only_in_b"], json!(["new.txt"]));
+        let different = report["different"].as_array().unwrap();
+        assert_eq!(different.len(), 2);
+        assert_eq!(different[0]["path"], "data.bin");
+        assert_eq!(different[0]["reason"], "size");
+        assert_eq!(different[0]["diff_skipped"], "binary");
+        // Same size, so told apart by their hashes
+        assert_eq!(different[1]["reason"], "content");
+        let diff = different[1]["diff"].as_str().unwrap();
+        assert!(diff.contains("--- a/notes.txt"));
+        assert!(diff.contains("-two\n+TWO\n"));
+    }
+}
@@ -0,0 +1,392 @@
+use crate::mcp::expansion::PathExpansion;
+use crate::mcp::ignore_rules;
+use crate::mcp::index;
+use crate::mcp::protection;
+use crate::mcp::resources;
+use crate::mcp::timeouts;
+use crate::mcp::tools;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::utilities::notify_all;
+use rpc_router::HandlerResult;
+use rpc_router::IntoHandlerError;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env::VarError;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use toml_edit::DocumentMut;
+use toml_edit::Item;
+
+/// How the value of a setting is written in the file
+enum Kind {
+    Flag,
+    Number,
+    /// One of these strings
+    Choice(&'static [&'static str]),
+    /// Array of strings, joined with commas
+    List,
+    /// Array of directories, joined like PATH
+    Paths,
+    /// Table of tool names to milliseconds
+    Timeouts,
+}
+
+/// Settings the file may hold: its key, the variable it stands in for and how
+/// it is written. Settings only read at startup, such as `index`, stay flags.
+const SETTINGS: &[(&str, &str, Kind)] = &[
+    ("allowed_directories", "MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", Kind::Paths),
+    ("disabled_tools", "MCP_RS_FILESYSTEM_DISABLED_TOOLS", Kind::List),
+    ("allow_permission_changes", "MCP_RS_FILESYSTEM_ALLOW_PERMISSION_CHANGES", Kind::Flag),
+    ("allow_xattrs", "MCP_RS_FILESYSTEM_ALLOW_XATTRS", Kind::Flag),
+    ("allow_secrets", "MCP_RS_FILESYSTEM_ALLOW_SECRETS", Kind::Flag),
+    ("verify_writes", "MCP_RS_FILESYSTEM_VERIFY_WRITES", Kind::Flag),
+    ("verify_max_shrink", "MCP_RS_FILESYSTEM_VERIFY_MAX_SHRINK", Kind::Number),
+    ("dry_run", "MCP_RS_FILESYSTEM_DRY_RUN", Kind::Flag),
+    ("max_calls_per_minute", "MCP_RS_FILESYSTEM_MAX_CALLS_PER_MINUTE", Kind::Number),
+    ("max_bytes_read_per_minute", "MCP_RS_FILESYSTEM_MAX_BYTES_READ_PER_MINUTE", Kind::Number),
+    ("max_bytes_written_per_minute", "MCP_RS_FILESYSTEM_MAX_BYTES_WRITTEN_PER_MINUTE", Kind::Number),
+    ("max_files_per_request", "MCP_RS_FILESYSTEM_MAX_FILES_PER_REQUEST", Kind::Number),
+    ("max_read_bytes", "MCP_RS_FILESYSTEM_MAX_READ_BYTES", Kind::Number),
+    ("max_result_entries", "MCP_RS_FILESYSTEM_MAX_RESULT_ENTRIES", Kind::Number),
+    ("response_timing", "MCP_RS_FILESYSTEM_RESPONSE_TIMING", Kind::Flag),
+    ("logging", "MCP_RS_FILESYSTEM_LOGGING", Kind::Flag),
+    ("tool_output", "MCP_RS_FILESYSTEM_TOOL_OUTPUT", Kind::Choice(&["text", "structured"])),
+    ("path_expansion", "MCP_RS_FILESYSTEM_PATH_EXPANSION", Kind::Choice(&["off", "tilde", "all"])),
+    ("ignore_patterns", "MCP_RS_FILESYSTEM_IGNORE_PATTERNS", Kind::List),
+    ("protected_paths", "MCP_RS_FILESYSTEM_PROTECTED_PATHS", Kind::List),
+    ("tool_timeout", "MCP_RS_FILESYSTEM_TOOL_TIMEOUT_MS", Kind::Number),
+    ("tool_timeouts", "MCP_RS_FILESYSTEM_TOOL_TIMEOUTS", Kind::Timeouts),
+];
+
+/// Values from the configuration file, by variable. Replaced as a whole on
+/// reload, so a request never sees half of an old file and half of a new one.
+static LOADED: RwLock<Option<HashMap<&'static str, String>>> = RwLock::new(None);
+
+/// The settings a server was built with, by the variable each stands for.
+/// They take precedence over the environment and the configuration file.
+#[derive(Debug, Default)]
+pub struct Settings {
+    values: HashMap<String, OsString>,
+}
+
+impl Settings {
+    pub fn new(values: impl IntoIterator<Item = (String, OsString)>) -> Settings {
+        Settings {
+            values: values.into_iter().collect(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OsString> {
+        self.values.get(name)
+    }
+}
+
+/// Settings of the server this process runs, put in place by its builder
+static SERVER: RwLock<Option<Arc<Settings>>> = RwLock::new(None);
+
+/// Make `settings` those every lookup of this process sees first
+pub fn install(settings: Arc<Settings>) {
+    *SERVER.write().unwrap() = Some(settings);
+}
+
+fn server_setting(name: &str) -> Option<OsString> {
+    SERVER.read().unwrap().as_ref().and_then(|settings| settings.get(name).cloned())
+}
+
+/// The configuration file the server was given, else `MCP_RS_FILESYSTEM_CONFIG`
+pub fn config_path() -> Option<PathBuf> {
+    server_setting("MCP_RS_FILESYSTEM_CONFIG")
+        .or_else(|| std::env::var_os("MCP_RS_FILESYSTEM_CONFIG"))
+        .map(PathBuf::from)
+}
+
+/// Whether settings can change while the server runs
+pub fn reloadable() -> bool {
+    config_path().is_some()
+}
+
+/// A setting: from the settings the server was built with, else from the
+/// environment, else from the configuration file. Read like `std::env::var`.
+pub fn var(name: &str) -> Result<String, VarError> {
+    if let Some(value) = server_setting(name) {
+        return value.into_string().map_err(VarError::NotUnicode);
+    }
+    std::env::var(name).or_else(|error| {
+        let loaded = LOADED.read().unwrap();
+        loaded.as_ref().and_then(|loaded| loaded.get(name).cloned()).ok_or(error)
+    })
+}
+
+/// As [`var`], for settings that may not be Unicode
+pub fn var_os(name: &str) -> Option<OsString> {
+    server_setting(name)
+        .or_else(|| std::env::var_os(name))
+        .or_else(|| var(name).ok().map(OsString::from))
+}
+
+fn strings(key: &str, item: &Item) -> Result<Vec<String>, String> {
+    let array = item.as_array().ok_or_else(|| format!("{} must be an array of strings", key))?;
+    array
+        .iter()
+        .map(|value| value.as_str().map(String::from).ok_or_else(|| format!("{} must be an array of strings", key)))
+        .collect()
+}
+
+fn convert(key: &str, kind: &Kind, item: &Item) -> Result<String, String> {
+    match kind {
+        Kind::Flag => match item.as_bool() {
+            Some(flag) => Ok(if flag { "1" } else { "0" }.to_string()),
+            None => Err(format!("{} must be true or false", key)),
+        },
+        Kind::Number => match item.as_integer() {
+            Some(number) if number >= 0 => Ok(number.to_string()),
+            _ => Err(format!("{} must be a whole number of 0 or more", key)),
+        },
+        Kind::Choice(choices) => match item.as_str() {
+            Some(choice) if choices.contains(&choice) => Ok(choice.to_string()),
+            _ => Err(format!("{} must be one of {}", key, choices.join(", "))),
+        },
+        Kind::List => Ok(strings(key, item)?.join(",")),
+        Kind::Paths => {
+            let paths = std::env::join_paths(strings(key, item)?).map_err(|e| format!("{}: {}", key, e))?;
+            paths.into_string().map_err(|_| format!("{} must be valid Unicode", key))
+        }
+        Kind::Timeouts => {
+            let table = item.as_table_like().ok_or_else(|| format!("{} must be a table of tool = milliseconds", key))?;
+            table
+                .iter()
+                .map(|(tool, ms)| match ms.as_integer() {
+                    Some(ms) if ms >= 0 => Ok(format!("{}={}", tool, ms)),
+                    _ => Err(format!("{}.{} must be a whole number of milliseconds", key, tool)),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(|pairs| pairs.join(","))
+        }
+    }
+}
+
+/// Check what the values mean, beyond their types
+fn check(settings: &HashMap<&'static str, String>) -> Result<(), String> {
+    if let Some(patterns) = settings.get("MCP_RS_FILESYSTEM_IGNORE_PATTERNS") {
+        let patterns: Vec<String> = patterns.split(',').map(String::from).collect();
+        ignore_rules::check_patterns(&patterns)?;
+    }
+    if let Some(patterns) = settings.get("MCP_RS_FILESYSTEM_PROTECTED_PATHS") {
+        let patterns: Vec<String> = patterns.split(',').map(String::from).collect();
+        protection::check_patterns(&patterns)?;
+    }
+    if let Some(overrides) = settings.get("MCP_RS_FILESYSTEM_TOOL_TIMEOUTS") {
+        timeouts::check_overrides(overrides)?;
+    }
+    if let Some(mode) = settings.get("MCP_RS_FILESYSTEM_PATH_EXPANSION") {
+        PathExpansion::parse(mode)?;
+    }
+    if let Some(disabled) = settings.get("MCP_RS_FILESYSTEM_DISABLED_TOOLS") {
+        if let Some(unknown) = disabled.split(',').find(|name| !name.is_empty() && !tools::is_tool(name)) {
+            return Err(format!("disabled_tools: unknown tool {}", unknown));
+        }
+    }
+    Ok(())
+}
+
+/// Settings in a configuration file, checked as a whole
+fn parse(text: &str) -> Result<HashMap<&'static str, String>, String> {
+    let document = text.parse::<DocumentMut>().map_err(|e| e.to_string())?;
+    let mut settings = HashMap::new();
+    for (key, item) in document.iter() {
+        let Some((_, name, kind)) = SETTINGS.iter().find(|(known, _, _)| *known == key) else {
+            return Err(format!("Unknown setting {}", key));
+        };
+        settings.insert(*name, convert(key, kind, item)?);
+    }
+    check(&settings)?;
+    Ok(settings)
+}
+
+fn load() -> Result<Option<HashMap<&'static str, String>>, String> {
+    let Some(path) = config_path() else {
+        return Ok(None);
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse(&text).map(Some).map_err(|e| format!("Invalid configuration {}: {}", path.display(), e))
+}
+
+/// Read the configuration file, if there is one, at startup
+pub fn init() -> Result<(), String> {
+    *LOADED.write().unwrap() = load()?;
+    Ok(())
+}
+
+/// Read the configuration file again and put it in place of the old one. An
+/// invalid file changes nothing. Clients are told when the tools or the
+/// allowed directories, and with them the resources, changed. The index and
+/// the resource watchers move to the new allowed directories.
+pub fn reload() -> Result<Value, String> {
+    let path = config_path().ok_or("No configuration file. Start the server with --config to use one")?;
+    let settings = load()?.unwrap_or_default();
+    let directories = get_allowed_directories();
+    let enabled_tools = tools::enabled_tools();
+
+    let previous = LOADED.write().unwrap().replace(settings.clone()).unwrap_or_default();
+    let changed: Vec<&str> = SETTINGS
+        .iter()
+        .filter(|(_, name, _)| previous.get(name) != settings.get(name))
+        .map(|(key, _, _)| *key)
+        .collect();
+    // The server's settings and the environment win over the file, so these keep their value
+    let overridden: Vec<&str> = SETTINGS
+        .iter()
+        .filter(|(_, name, _)| {
+            settings.contains_key(name) && (server_setting(name).is_some() || std::env::var_os(name).is_some())
+        })
+        .map(|(key, _, _)| *key)
+        .collect();
+
+    let directories_changed = get_allowed_directories() != directories;
+    let tools_changed = tools::enabled_tools() != enabled_tools;
+    if directories_changed {
+        // The scratch directory is apart from the allowed directories and stays where it is
+        index::init();
+        resources::init();
+        notify_all("notifications/resources/list_changed", None);
+    }
+    if tools_changed {
+        notify_all("notifications/tools/list_changed", None);
+    }
+    Ok(json!({
+        "path": path.display().to_string(),
+        "changed": changed,
+        "overridden": overridden,
+        "allowed_directories_changed": directories_changed,
+        "tools_changed": tools_changed,
+    }))
+}
+
+/// handler for the `config/reload` request
+pub async fn config_reload() -> HandlerResult<Value> {
+    reload().map_err(|message| json!({ "code": -32603, "message": message }).into_handler_error())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::limits::max_read_bytes;
+    use crate::mcp::testing::remove_env;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::set_env;
+    use crate::mcp::testing::test_env;
+    use crate::mcp::utilities::canonical_path;
+    use crate::mcp::utilities::validate_path_or_error;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse() {
+        let settings = parse(
+            r#"
+            allowed_directories = ["/srv/a", "/srv/b"]
+            allow_xattrs = true
+            max_read_bytes = 4096
+            ignore_patterns = [".git", "*.log"]
+            disabled_tools = ["overwrite_file"]
+            [tool_timeouts]
+            find_file = 60000
+            "#,
+        )
+        .unwrap();
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        assert_eq!(settings["MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES"], format!("/srv/a{}/srv/b", separator));
+        assert_eq!(settings["MCP_RS_FILESYSTEM_ALLOW_XATTRS"], "1");
+        assert_eq!(settings["MCP_RS_FILESYSTEM_MAX_READ_BYTES"], "4096");
+        assert_eq!(settings["MCP_RS_FILESYSTEM_IGNORE_PATTERNS"], ".git,*.log");
+        assert_eq!(settings["MCP_RS_FILESYSTEM_TOOL_TIMEOUTS"], "find_file=60000");
+
+        assert!(parse("max_read_bytes = \"big\"").unwrap_err().contains("max_read_bytes"));
+        assert!(parse("tool_output = \"xml\"").unwrap_err().contains("text, structured"));
+        assert!(parse("disabled_tools = [\"no_such_tool\"]").unwrap_err().contains("no_such_tool"));
+        assert!(parse("allowed_dirs = []").unwrap_err().contains("Unknown setting"));
+        assert!(parse("max_read_bytes = ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_server_settings_win_over_environment() {
+        let _guard = crate::mcp::testing::lock_env().await;
+        let name = "MCP_RS_FILESYSTEM_TEST_SETTING";
+        let _environment = crate::mcp::testing::set_env(name, "environment");
+        install(Arc::new(Settings::new([(name.to_string(), OsString::from("server"))])));
+        assert_eq!(var(name).as_deref(), Ok("server"));
+        assert_eq!(var_os(name), Some(OsString::from("server")));
+        // The environment itself is left alone
+        assert_eq!(std::env::var(name).as_deref(), Ok("environment"));
+
+        install(Arc::new(Settings::default()));
+        assert_eq!(var(name).as_deref(), Ok("environment"));
+    }
+
+    #[tokio::test]
+    async fn test_config_reload() {
+        let (_env, temp_dir, temp_path) = test_env().await;
+        let _directories = remove_env("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+        let _read_bytes = remove_env("MCP_RS_FILESYSTEM_MAX_READ_BYTES");
+        let _index = set_env("MCP_RS_FILESYSTEM_INDEX", "1");
+        let first = temp_dir.path().join("first");
+        let second = temp_dir.path().join("second");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        let write_config = |directory: &Path, extra: &str| {
+            let directory = directory.to_str().unwrap().replace('\\', "\\\\");
+            fs::write(&config_file, format!("allowed_directories = [\"{}\"]\n{}", directory, extra)).unwrap();
+        };
+        write_config(&first, "max_read_bytes = 4096\n");
+        let config_path = set_env("MCP_RS_FILESYSTEM_CONFIG", &config_file);
+        init().unwrap();
+        index::init();
+        let offered = |name: &str| tools::enabled_tools().iter().any(|tool| tool == name);
+        let indexed = || async {
+            let status = index::index_status(index::IndexStatusRequest {}).await.unwrap();
+            let status = result_json(&status);
+            status["roots"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|root| PathBuf::from(root["root"].as_str().unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(validate_path_or_error(&first.join("a.txt")).is_ok());
+        assert!(validate_path_or_error(&second.join("a.txt")).is_err());
+        assert_eq!(max_read_bytes(), 4096);
+        assert!(offered("overwrite_file"));
+        assert_eq!(indexed().await, [canonical_path(&first)]);
+
+        write_config(&second, "disabled_tools = [\"overwrite_file\"]\n");
+        let report = reload().unwrap();
+        assert_eq!(report["allowed_directories_changed"], true);
+        assert_eq!(report["tools_changed"], true);
+        assert!(validate_path_or_error(&second.join("a.txt")).is_ok());
+        assert!(validate_path_or_error(&first.join("a.txt")).is_err());
+        assert_eq!(max_read_bytes(), 1024 * 1024);
+        assert!(!offered("overwrite_file"));
+        assert!(tools::input_schema_of("overwrite_file").is_none());
+        // The index moved along
+        assert_eq!(indexed().await, [canonical_path(&second)]);
+
+        // A broken file leaves the settings in place
+        fs::write(&config_file, "max_read_bytes = \"lots\"").unwrap();
+        assert!(reload().is_err());
+        assert!(validate_path_or_error(&second.join("a.txt")).is_ok());
+
+        // The environment wins over the file
+        let _directories = set_env("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &temp_path);
+        write_config(&second, "");
+        assert_eq!(reload().unwrap()["overridden"], json!(["allowed_directories"]));
+        assert!(validate_path_or_error(&first.join("a.txt")).is_ok());
+
+        drop(config_path);
+        init().unwrap();
+    }
+}
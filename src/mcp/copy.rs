@@ -0,0 +1,422 @@
+use crate::mcp::budget::charge_read;
+use crate::mcp::budget::charge_write;
+use crate::mcp::checkpoint;
+use crate::mcp::dry_run;
+use crate::mcp::sandbox;
+use crate::mcp::sandbox::Access;
+use crate::mcp::sandbox::Directory;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::notify_progress;
+use crate::mcp::utilities::shutting_down;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::validate_write_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use tokio::sync::mpsc;
+
+/// Bytes read and written at a time
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// How much of a partial copy is compared with the source before resuming it
+const RESUME_CHECK_BYTES: u64 = 64 * 1024;
+
+/// Name of the file a copy to `name` is written to until it is complete. A
+/// copy that stops early leaves it behind, and the next copy to `name` resumes it.
+fn partial_name(name: &OsStr) -> OsString {
+    let mut partial = name.to_os_string();
+    partial.push(".partial");
+    partial
+}
+
+/// Name of the file keeping the size and modification time of the source next
+/// to the partial copy, so a source changed since is not resumed from
+fn stamp_name(name: &OsStr) -> OsString {
+    let mut stamp = partial_name(name);
+    stamp.push(".source");
+    stamp
+}
+
+/// Where a copy to `target` is written until it is complete
+pub fn partial_path(target: &Path) -> PathBuf {
+    target.with_file_name(partial_name(target.file_name().unwrap_or_default()))
+}
+
+/// Size and modification time of the source, as kept in the stamp file
+fn source_stamp(metadata: &fs::Metadata) -> String {
+    let modified = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+    json!({ "size": metadata.len(), "modified_ns": modified.map(|since| since.as_nanos() as u64) }).to_string()
+}
+
+/// Start of the next region at or after `offset` that holds data, skipping
+/// holes without reading them. `None` when only a hole follows.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd"))]
+fn next_data(file: &File, offset: u64) -> std::io::Result<Option<u64>> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: lseek on a file descriptor owned by `file`
+    let found = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, libc::SEEK_DATA) };
+    if found >= 0 {
+        return Ok(Some(found as u64));
+    }
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ENXIO) => Ok(None),
+        // Filesystems without hole reporting: everything is data
+        Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => Ok(Some(offset)),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd")))]
+fn next_data(_file: &File, offset: u64) -> std::io::Result<Option<u64>> {
+    Ok(Some(offset))
+}
+
+/// How far the partial copy to `name` in `dir` can be trusted: its length, if
+/// it was made from the source as it is now, is no longer than it and its last
+/// bytes match the source at the same place
+fn resumable_length(
+    source: &mut File,
+    dir: &Directory,
+    name: &OsStr,
+    stamp: &str,
+    source_len: u64,
+) -> std::io::Result<u64> {
+    let mut recorded = String::new();
+    let stamped = dir
+        .open(&stamp_name(name), Access::Read)
+        .is_ok_and(|mut file| file.read_to_string(&mut recorded).is_ok());
+    if !stamped || recorded != stamp {
+        return Ok(0);
+    }
+    let Ok(mut partial) = dir.open(&partial_name(name), Access::Read) else {
+        return Ok(0);
+    };
+    let len = partial.metadata()?.len();
+    if len > source_len {
+        return Ok(0);
+    }
+    let check = len.min(RESUME_CHECK_BYTES);
+    let (mut ours, mut theirs) = (vec![0; check as usize], vec![0; check as usize]);
+    partial.seek(SeekFrom::Start(len - check))?;
+    partial.read_exact(&mut ours)?;
+    source.seek(SeekFrom::Start(len - check))?;
+    source.read_exact(&mut theirs)?;
+    Ok(if ours == theirs { len } else { 0 })
+}
+
+/// What a copy did
+pub struct CopyOutcome {
+    pub size: u64,
+    /// Bytes of the source already in the partial copy, not copied again
+    pub resumed_from: u64,
+    /// Bytes written to the target, leaving out holes and zeros
+    pub written: u64,
+    /// Bytes of the source in holes or runs of zeros, left as holes in the target
+    pub sparse: u64,
+}
+
+/// Copy `source` to `target` in chunks, through the partial file of `target`
+/// which is renamed over it at the end. Holes of the source, and chunks of
+/// zeros, are skipped and stay holes in the target where the filesystem
+/// supports them. `progress` gets the bytes done so far, and stops the copy
+/// by returning false; the partial file is kept for a later resume. Both files
+/// are reached through the sandbox, and the target's directory is held open
+/// from the first write to the rename.
+pub fn copy_chunked(
+    source: &Path,
+    target: &Path,
+    resume: bool,
+    mut progress: impl FnMut(u64, u64) -> bool,
+) -> Result<CopyOutcome, String> {
+    let mut input = sandbox::open_read(source).map_err(|e| format!("Error opening {}: {}", source.display(), e))?;
+    let metadata = input.metadata().map_err(|e| e.to_string())?;
+    let size = metadata.len();
+    let (dir, name) = sandbox::parent(target)?;
+    let partial = partial_path(target);
+    let stamp = source_stamp(&metadata);
+    let resumed_from = if resume {
+        resumable_length(&mut input, &dir, &name, &stamp, size).map_err(|e| e.to_string())?
+    } else {
+        0
+    };
+    let mut output = dir
+        .open(&partial_name(&name), Access::Update)
+        .map_err(|e| format!("Error creating {}: {}", partial.display(), e))?;
+    output.set_len(resumed_from).map_err(|e| e.to_string())?;
+    if resumed_from == 0 {
+        dir.open(&stamp_name(&name), Access::Write(WriteMode::Overwrite))
+            .and_then(|mut file| file.write_all(stamp.as_bytes()).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Error creating the stamp of {}: {}", partial.display(), e))?;
+    }
+
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let (mut offset, mut written, mut sparse) = (resumed_from, 0, 0);
+    while offset < size {
+        if shutting_down() || !progress(offset, size) {
+            return Err(format!("Copy stopped at {} of {} bytes; copy again to resume", offset, size));
+        }
+        let data = next_data(&input, offset).map_err(|e| e.to_string())?.unwrap_or(size).min(size);
+        if data > offset {
+            sparse += data - offset;
+            offset = data;
+            continue;
+        }
+        input.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        let wanted = (size - offset).min(CHUNK_SIZE as u64) as usize;
+        let chunk = &mut buffer[..wanted];
+        input.read_exact(chunk).map_err(|e| format!("Error reading {}: {}", source.display(), e))?;
+        charge_read(source, wanted as u64)?;
+        if chunk.iter().all(|byte| *byte == 0) {
+            sparse += wanted as u64;
+        } else {
+            charge_write(target, wanted as u64)?;
+            output.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+            output.write_all(chunk).map_err(|e| format!("Error writing {}: {}", partial.display(), e))?;
+            written += wanted as u64;
+        }
+        offset += wanted as u64;
+    }
+    progress(size, size);
+
+    // Skipped holes at the end still count towards the length
+    output.set_len(size).map_err(|e| e.to_string())?;
+    output.set_permissions(metadata.permissions()).map_err(|e| e.to_string())?;
+    output.sync_all().map_err(|e| e.to_string())?;
+    drop(output);
+    dir.rename(&partial_name(&name), &dir, &name)
+        .map_err(|e| format!("Error moving the copy into place: {}", e))?;
+    let _ = dir.remove_file(&stamp_name(&name));
+    Ok(CopyOutcome {
+        size,
+        resumed_from,
+        written,
+        sparse,
+    })
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct CopyFileRequest {
+    /// File to copy
+    pub source_path: String,
+    /// Where to copy it, including the file name
+    pub target_path: String,
+    /// Replace the target if it exists. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub overwrite: Option<bool>,
+    /// Continue an earlier copy to the same target that stopped early, from the target's .partial file. Defaults
+    /// to true.
+    #[schemars(extend("default" = true))]
+    pub resume: Option<bool>,
+    /// Report the copy without making it. Always the case when the server runs with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub meta: Option<MetaParams>,
+}
+
+pub async fn copy_file(request: CopyFileRequest) -> HandlerResult<CallToolResult> {
+    let source = resolve_path(Path::new(&request.source_path));
+    let target = resolve_path(Path::new(&request.target_path));
+    if let Err(msg) = validate_path_or_error(&source).and_then(|_| validate_write_path_or_error(&target)) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if !source.is_file() {
+        return Ok(CallToolResult::error(format!("Not a file: {}", source.display())));
+    }
+    if target.is_dir() {
+        return Ok(CallToolResult::error(format!("Target is a directory: {}", target.display())));
+    }
+    if target.exists() && !request.overwrite.unwrap_or(false) {
+        return Ok(CallToolResult::error(format!("Target exists: {}. Pass overwrite to replace it", target.display())));
+    }
+    if dry_run::requested(request.dry_run) {
+        let size_before = fs::metadata(&target).ok().map(|metadata| metadata.len());
+        return dry_run::result(vec![json!({
+            "action": "copy",
+            "source": source.display().to_string(),
+            "path": target.display().to_string(),
+            "size_before": size_before,
+            "size_after": fs::metadata(&source).map(|metadata| metadata.len()).unwrap_or_default(),
+        })]);
+    }
+    if let Err(e) = checkpoint::preserve(&target) {
+        return Ok(CallToolResult::error(e));
+    }
+
+    // The copy blocks, so it runs on a thread of its own and reports back
+    // here, where progress notifications reach the calling client. Once this
+    // call goes away, as on a timeout, the channel closes and the copy stops.
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let resume = request.resume.unwrap_or(true);
+    // Like `timeouts::run_blocking`, the thread carries the call's session, budget and cancellation
+    let copy = {
+        let (source, target) = (source.clone(), target.clone());
+        let future = timeouts::in_call(async move {
+            let mut last_percent = None;
+            copy_chunked(&source, &target, resume, |done, total| {
+                let percent = (done * 100).checked_div(total).unwrap_or(100);
+                if last_percent == Some(percent) {
+                    return !sender.is_closed();
+                }
+                last_percent = Some(percent);
+                sender.send((percent, done, total)).is_ok()
+            })
+        });
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || runtime.block_on(future))
+    };
+    let progress_token = request.meta.as_ref().map(|meta| meta.progress_token.clone());
+    while let Some((percent, done, total)) = receiver.recv().await {
+        if let Some(token) = &progress_token {
+            notify_progress(token, percent as i32, Some(100), Some(format!("{} of {} bytes", done, total)));
+        }
+    }
+    let outcome = match copy.await.map_err(|e| e.to_string()).and_then(|result| result) {
+        Ok(outcome) => outcome,
+        Err(e) => return Ok(CallToolResult::error(format!("Failed to copy {}: {}", source.display(), e))),
+    };
+    let report = json!({
+        "source": source.display().to_string(),
+        "target": target.display().to_string(),
+        "size": outcome.size,
+        "resumed_from": outcome.resumed_from,
+        "written": outcome.written,
+        "sparse": outcome.sparse,
+    });
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&report).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+
+    #[tokio::test]
+    async fn test_copy_chunked_resumes() {
+        let (_env, temp_dir, _) = test_env().await;
+        let source = temp_dir.path().join("data.bin");
+        let target = temp_dir.path().join("copy.bin");
+        let content: Vec<u8> = (0..3 * CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &content).unwrap();
+
+        // Stopped after the first chunk, the partial copy stays behind
+        let mut calls = 0;
+        let stopped = copy_chunked(&source, &target, true, |_, _| {
+            calls += 1;
+            calls < 2
+        });
+        assert!(stopped.is_err());
+        assert!(!target.exists());
+        assert_eq!(fs::metadata(partial_path(&target)).unwrap().len(), CHUNK_SIZE as u64);
+
+        let outcome = copy_chunked(&source, &target, true, |_, _| true).unwrap();
+        assert_eq!(outcome.resumed_from, CHUNK_SIZE as u64);
+        assert_eq!(outcome.written, content.len() as u64 - CHUNK_SIZE as u64);
+        assert_eq!(fs::read(&target).unwrap(), content);
+        assert!(!partial_path(&target).exists());
+        assert!(!temp_dir.path().join("copy.bin.partial.source").exists());
+
+        // A partial copy of something else is started over
+        fs::write(partial_path(&target), vec![7u8; 1000]).unwrap();
+        let outcome = copy_chunked(&source, &target, true, |_, _| true).unwrap();
+        assert_eq!(outcome.resumed_from, 0);
+        assert_eq!(fs::read(&target).unwrap(), content);
+
+        // So is one of a source changed since, even where its last bytes still match
+        let mut calls = 0;
+        assert!(copy_chunked(&source, &target, true, |_, _| {
+            calls += 1;
+            calls < 2
+        })
+        .is_err());
+        let modified = fs::metadata(&source).unwrap().modified().unwrap() + std::time::Duration::from_secs(10);
+        File::options().write(true).open(&source).unwrap().set_modified(modified).unwrap();
+        let outcome = copy_chunked(&source, &target, true, |_, _| true).unwrap();
+        assert_eq!(outcome.resumed_from, 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_copy_chunked_does_not_follow_partial_symlink() {
+        let (_env, temp_dir, _) = test_env().await;
+        let source = temp_dir.path().join("data.bin");
+        let target = temp_dir.path().join("copy.bin");
+        let elsewhere = temp_dir.path().join("elsewhere.txt");
+        fs::write(&source, "data").unwrap();
+        fs::write(&elsewhere, "keep").unwrap();
+        std::os::unix::fs::symlink(&elsewhere, partial_path(&target)).unwrap();
+
+        assert!(copy_chunked(&source, &target, true, |_, _| true).is_err());
+        assert_eq!(fs::read_to_string(&elsewhere).unwrap(), "keep");
+        assert!(!target.exists());
+    }
+
+    #[tokio::test]
+    async fn test_copy_chunked_keeps_holes() {
+        let (_env, temp_dir, _) = test_env().await;
+        let source = temp_dir.path().join("sparse.img");
+        let target = temp_dir.path().join("copy.img");
+        let mut file = File::create(&source).unwrap();
+        file.write_all(b"header").unwrap();
+        file.seek(SeekFrom::Start(8 * CHUNK_SIZE as u64)).unwrap();
+        file.write_all(b"trailer").unwrap();
+        drop(file);
+
+        let outcome = copy_chunked(&source, &target, true, |_, _| true).unwrap();
+        assert_eq!(outcome.size, 8 * CHUNK_SIZE as u64 + 7);
+        assert!(outcome.sparse >= 6 * CHUNK_SIZE as u64);
+        assert_eq!(fs::read(&target).unwrap(), fs::read(&source).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_copy_file() {
+        let (_env, temp_dir, _) = test_env().await;
+        let source = temp_dir.path().join("data.bin");
+        let target = temp_dir.path().join("copy.bin");
+        fs::write(&source, "fresh").unwrap();
+        fs::write(&target, "stale").unwrap();
+        let request = |overwrite: bool, dry_run: bool| CopyFileRequest {
+            source_path: source.to_str().unwrap().to_string(),
+            target_path: target.to_str().unwrap().to_string(),
+            overwrite: Some(overwrite),
+            resume: None,
+            dry_run: Some(dry_run),
+            meta: None,
+        };
+
+        // An existing target is only replaced when asked to
+        assert!(copy_file(request(false, false)).await.unwrap().is_error);
+
+        let result = copy_file(request(true, true)).await.unwrap();
+        let report = result.structured_content.unwrap();
+        let change = &report["changes"][0];
+        assert_eq!((change["size_before"].as_u64(), change["size_after"].as_u64()), (Some(5), Some(5)));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "stale");
+
+        let result = copy_file(request(true, false)).await.unwrap();
+        assert!(!result.is_error);
+        let report = result_json(&result);
+        assert_eq!((report["size"].as_u64(), report["written"].as_u64()), (Some(5), Some(5)));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "fresh");
+        assert!(!partial_path(&target).exists());
+    }
+}
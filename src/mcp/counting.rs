@@ -0,0 +1,327 @@
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::limits::max_read_bytes;
+use crate::mcp::mime;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::vfs;
+use globset::Glob;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::io::Read;
+use std::path::Path;
+
+const DEFAULT_MAX_FILES: usize = 1000;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+/// Characters per token of common LLM tokenizers on English text and code
+const DEFAULT_CHARS_PER_TOKEN: f64 = 4.0;
+/// Tokens per word of the same tokenizers on English prose
+const TOKENS_PER_WORD: f64 = 4.0 / 3.0;
+
+/// Counts of one file, or of several added up
+#[derive(Default, Clone, Copy)]
+struct Counts {
+    bytes: u64,
+    lines: u64,
+    words: u64,
+    chars: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.bytes += other.bytes;
+        self.lines += other.lines;
+        self.words += other.words;
+        self.chars += other.chars;
+    }
+}
+
+/// Counter fed a file in pieces, so it never has to hold all of it
+#[derive(Default)]
+struct Counter {
+    counts: Counts,
+    in_word: bool,
+    last: Option<u8>,
+}
+
+impl Counter {
+    fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.counts.bytes += 1;
+            // UTF-8 continuation bytes belong to the character before them
+            if byte & 0xC0 != 0x80 {
+                self.counts.chars += 1;
+            }
+            if byte == b'\n' {
+                self.counts.lines += 1;
+            }
+            let space = byte.is_ascii_whitespace();
+            if !space && !self.in_word {
+                self.counts.words += 1;
+            }
+            self.in_word = !space;
+        }
+        if let Some(&byte) = bytes.last() {
+            self.last = Some(byte);
+        }
+    }
+
+    /// The counts, with a last line that has no line break counted too
+    fn finish(mut self) -> Counts {
+        if self.last.is_some_and(|byte| byte != b'\n') {
+            self.counts.lines += 1;
+        }
+        self.counts
+    }
+}
+
+/// How tokens are estimated
+#[derive(Clone, Copy)]
+enum Tokenizer {
+    Chars(f64),
+    Words,
+}
+
+impl Tokenizer {
+    fn estimate(self, counts: &Counts) -> u64 {
+        let tokens = match self {
+            Tokenizer::Chars(chars_per_token) => counts.chars as f64 / chars_per_token,
+            Tokenizer::Words => counts.words as f64 * TOKENS_PER_WORD,
+        };
+        tokens.ceil() as u64
+    }
+}
+
+fn count_file(path: &Path) -> Result<(Counts, bool), String> {
+    let mut file = vfs::current().open(path)?;
+    let mut counter = Counter::default();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut binary = None;
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        // Judged by the start of the file, as everywhere else
+        binary.get_or_insert_with(|| !mime::looks_like_text(&buffer[..read.min(mime::SNIFF_BYTES)]));
+        counter.feed(&buffer[..read]);
+    }
+    Ok((counter.finish(), binary.unwrap_or(false)))
+}
+
+fn describe(counts: &Counts, binary: bool, tokenizer: Tokenizer) -> Value {
+    json!({
+        "bytes": counts.bytes,
+        "lines": counts.lines,
+        "words": counts.words,
+        "chars": counts.chars,
+        // Tokens of a binary file mean nothing, it would not be read as text
+        "tokens": (!binary).then(|| tokenizer.estimate(counts)),
+        "binary": binary,
+        "fits_read_limit": counts.bytes <= max_read_bytes() as u64,
+    })
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct WordCountRequest {
+    /// File to count, or a directory to count the files of
+    pub path: String,
+    /// With a directory, only files whose path relative to it matches this glob, such as **/*.md. Defaults to all
+    /// files.
+    pub glob: Option<String>,
+    /// How to estimate tokens: chars (default) divides the characters by chars_per_token, which suits code and
+    /// mixed text; words counts 4/3 of a token per word, which suits prose.
+    #[schemars(extend("enum" = ["chars", "words"], "default" = "chars"))]
+    pub tokenizer: Option<String>,
+    /// Characters per token for the chars tokenizer. Defaults to 4.
+    #[schemars(extend("default" = 4.0))]
+    pub chars_per_token: Option<f64>,
+    /// With a directory, stop after this many files. Defaults to 1000.
+    #[schemars(extend("default" = 1000))]
+    pub max_files: Option<usize>,
+    /// Skip files excluded by .gitignore, .ignore and git excludes. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// Include hidden files and directories. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+fn json_result(value: &Value) -> HandlerResult<CallToolResult> {
+    Ok(CallToolResult::text(serde_json::to_string_pretty(value).unwrap()))
+}
+
+pub async fn word_count(request: WordCountRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(word_count_blocking(request)).await
+}
+
+async fn word_count_blocking(request: WordCountRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let tokenizer = match (request.tokenizer.as_deref().unwrap_or("chars"), request.chars_per_token) {
+        (_, Some(ratio)) if !ratio.is_finite() || ratio <= 0.0 => {
+            return Ok(CallToolResult::error("chars_per_token must be a positive number".to_string()))
+        }
+        ("chars", ratio) => Tokenizer::Chars(ratio.unwrap_or(DEFAULT_CHARS_PER_TOKEN)),
+        ("words", _) => Tokenizer::Words,
+        (other, _) => {
+            return Ok(CallToolResult::error(format!("Unknown tokenizer: {}, expected chars or words", other)))
+        }
+    };
+
+    if vfs::current().is_file(path) {
+        return match count_file(path) {
+            Ok((counts, binary)) => {
+                let mut report = describe(&counts, binary, tokenizer);
+                report["path"] = json!(path.display().to_string());
+                json_result(&report)
+            }
+            Err(e) => Ok(CallToolResult::error(format!("Error reading {}: {}", path.display(), e))),
+        };
+    }
+    if !vfs::current().stat(path).is_ok_and(|stat| stat.is_dir()) {
+        return Ok(CallToolResult::error(format!("Path not found: {}", path.display())));
+    }
+    // Directories are walked on the disk, with their ignore files
+    if !vfs::current().is_local() {
+        return Ok(CallToolResult::error("Counting a directory works on the local disk only".to_string()));
+    }
+
+    let glob = match request.glob.as_deref().map(Glob::new).transpose() {
+        Ok(glob) => glob.map(|glob| glob.compile_matcher()),
+        Err(e) => return Ok(CallToolResult::error(format!("Invalid glob: {}", e))),
+    };
+    let rules = IgnoreRules::new(request.respect_gitignore.unwrap_or(true), request.include_hidden.unwrap_or(false))
+        .with_overrides(&request.ignore);
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let max_files = request.max_files.unwrap_or(DEFAULT_MAX_FILES).max(1);
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    let mut total = Counts::default();
+    let mut truncated = false;
+    for entry in rules.walker(path).build().flatten() {
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(path) else {
+            continue;
+        };
+        let relative = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        if glob.as_ref().is_some_and(|glob| !glob.is_match(&relative)) {
+            continue;
+        }
+        if files.len() + errors.len() >= max_files {
+            truncated = true;
+            break;
+        }
+        match count_file(entry.path()) {
+            Ok((counts, binary)) => {
+                if !binary {
+                    total.add(&counts);
+                }
+                let mut file = describe(&counts, binary, tokenizer);
+                file["path"] = json!(relative);
+                files.push(file);
+            }
+            Err(e) => errors.push(json!({ "path": relative, "error": e })),
+        }
+    }
+    files.sort_by(|a, b| a["path"].as_str().cmp(&b["path"].as_str()));
+
+    let mut totals = describe(&total, false, tokenizer);
+    // The limit is per read, so it says nothing about a sum of files
+    totals.as_object_mut().unwrap().remove("fits_read_limit");
+    totals.as_object_mut().unwrap().remove("binary");
+    json_result(&json!({
+        "path": path.display().to_string(),
+        "files": files,
+        "total": totals,
+        "errors": errors,
+        "truncated": truncated,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    fn count(pieces: &[&str]) -> Counts {
+        let mut counter = Counter::default();
+        for piece in pieces {
+            counter.feed(piece.as_bytes());
+        }
+        counter.finish()
+    }
+
+    #[test]
+    fn test_counter() {
+        let counts = count(&["one two\nthree ", "fo", "ur\nfünf"]);
+        assert_eq!((counts.lines, counts.words), (3, 5));
+        assert_eq!(counts.chars, 23);
+        assert_eq!(counts.bytes, 24);
+        assert_eq!(count(&["a\n", "b\n"]).lines, 2);
+        assert_eq!(count(&[]).lines, 0);
+
+        let counts = Counts {
+            chars: 10,
+            words: 3,
+            ..Default::default()
+        };
+        assert_eq!(Tokenizer::Chars(4.0).estimate(&counts), 3);
+        assert_eq!(Tokenizer::Words.estimate(&counts), 4);
+    }
+
+    #[tokio::test]
+    async fn test_word_count() {
+        let (_env, temp_dir, _) = test_env().await;
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.md"), "one two three\nfour\n").unwrap();
+        fs::write(root.join("docs/b.md"), "five six").unwrap();
+        fs::write(root.join("docs/c.txt"), "not counted").unwrap();
+
+        let request = |path: &Path, glob: Option<&str>| WordCountRequest {
+            path: path.to_str().unwrap().to_string(),
+            glob: glob.map(String::from),
+            tokenizer: None,
+            chars_per_token: None,
+            max_files: None,
+            respect_gitignore: None,
+            include_hidden: None,
+            ignore: Default::default(),
+        };
+        let report = |result: CallToolResult| result_json(&result);
+
+        let file = report(word_count(request(&root.join("docs/a.md"), None)).await.unwrap());
+        assert_eq!((file["lines"].as_u64(), file["words"].as_u64(), file["bytes"].as_u64()), (Some(2), Some(4), Some(19)));
+        assert_eq!(file["tokens"], 5);
+        assert_eq!(file["fits_read_limit"], true);
+
+        let dir = report(word_count(request(root, Some("**/*.md"))).await.unwrap());
+        assert_eq!(dir["files"].as_array().unwrap().len(), 2);
+        assert_eq!(dir["total"]["words"], 6);
+        assert_eq!(dir["total"]["lines"], 3);
+
+        let mut words = request(root, Some("**/*.md"));
+        words.tokenizer = Some("words".to_string());
+        assert_eq!(report(word_count(words).await.unwrap())["total"]["tokens"], 8);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::mcp::config;
+use crate::mcp::encoding::apply_line_ending;
+use crate::mcp::encoding::decode;
+use crate::mcp::encoding::detect_encoding;
+use crate::mcp::encoding::detect_line_ending;
+use crate::mcp::encoding::encode;
+use crate::mcp::encoding::LineEnding;
+use crate::mcp::encoding::TextEncoding;
+use crate::mcp::types::*;
+use crate::mcp::vfs;
+use git2::Patch;
+use rpc_router::HandlerResult;
+use serde_json::json;
+use serde_json::Value;
+use std::path::Path;
+
+/// Diffs longer than this are left out of a dry run, which reports the sizes only
+const MAX_DIFF_BYTES: usize = 64 * 1024;
+
+/// Set by `--dry-run`: every mutating tool then reports what it would change
+/// instead of changing it, whatever the call asks for
+pub fn global() -> bool {
+    config::var("MCP_RS_FILESYSTEM_DRY_RUN")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Whether a call only reports what it would change, because it asked to or
+/// because the server runs with `--dry-run`
+pub fn requested(dry_run: Option<bool>) -> bool {
+    dry_run.unwrap_or(false) || global()
+}
+
+/// The answer of a dry run, listing the changes the call would have made
+pub fn result(changes: Vec<Value>) -> HandlerResult<CallToolResult> {
+    Ok(CallToolResult::structured(json!({ "dry_run": true, "changes": changes })))
+}
+
+/// Unified diff between two versions of the text at `path`
+pub fn unified_diff(path: &str, old: &str, new: &str) -> Result<String, String> {
+    let path = Path::new(path);
+    let mut patch =
+        Patch::from_buffers(old.as_bytes(), Some(path), new.as_bytes(), Some(path), None).map_err(|e| e.to_string())?;
+    let text = patch.to_buf().map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// The text of a file, its size in bytes, its encoding and its line ending style
+pub type ExistingText = (String, u64, TextEncoding, Option<LineEnding>);
+
+/// The text in the file now, its size and format, or `None` for a file that does not exist.
+/// Reading it for a dry run is not charged to the session's budget.
+pub fn existing_text(path: &Path) -> Result<Option<ExistingText>, String> {
+    let fs = vfs::backend();
+    if !fs.is_file(path) {
+        return Ok(None);
+    }
+    let bytes = fs.read(path)?;
+    let encoding = detect_encoding(&bytes);
+    // A binary file is diffed as if it were empty; its sizes still tell what changes
+    let text = decode(&bytes, encoding).unwrap_or_default();
+    let line_ending = detect_line_ending(&text);
+    Ok(Some((text, bytes.len() as u64, encoding, line_ending)))
+}
+
+fn change(path: &Path, old: Option<(&str, u64)>, new: &str, size_after: u64) -> Value {
+    let display = path.display().to_string();
+    let mut change = json!({
+        "action": if old.is_some() { "modify" } else { "create" },
+        "path": display,
+        "size_before": old.map(|(_, size)| size),
+        "size_after": size_after,
+    });
+    match unified_diff(&display, old.map(|(text, _)| text).unwrap_or(""), new) {
+        Ok(diff) if diff.len() <= MAX_DIFF_BYTES => change["diff"] = json!(diff),
+        Ok(_) => change["diff_omitted"] = json!("diff longer than 64 KiB"),
+        Err(e) => change["diff_omitted"] = json!(e),
+    }
+    change
+}
+
+/// What writing `text` to `path` in this encoding would change, converting
+/// line breaks when a style is given, like `write_text_file`
+pub fn text_change(
+    path: &Path,
+    text: &str,
+    encoding: TextEncoding,
+    line_ending: Option<LineEnding>,
+) -> Result<Value, String> {
+    let text = match line_ending {
+        Some(line_ending) => apply_line_ending(text, line_ending),
+        None => text.to_string(),
+    };
+    let size_after = encode(&text, encoding)?.len() as u64;
+    let existing = existing_text(path)?;
+    Ok(change(path, existing.as_ref().map(|(old, size, _, _)| (old.as_str(), *size)), &text, size_after))
+}
+
+/// What writing `text` to `path` would change, keeping the format of an
+/// existing file where none is given, like `write_text_preserving`
+pub fn preserving_change(
+    path: &Path,
+    text: &str,
+    encoding: Option<TextEncoding>,
+    line_ending: Option<LineEnding>,
+) -> Result<Value, String> {
+    preserving_change_over(path, existing_text(path)?, text, encoding, line_ending).map(|(change, _)| change)
+}
+
+/// Like [`preserving_change`], over `existing` rather than what is in the file
+/// now, such as the text an earlier step of a batch would have left there.
+/// Also returns the text the file would then hold.
+pub fn preserving_change_over(
+    path: &Path,
+    existing: Option<ExistingText>,
+    text: &str,
+    encoding: Option<TextEncoding>,
+    line_ending: Option<LineEnding>,
+) -> Result<(Value, ExistingText), String> {
+    let encoding = encoding
+        .or(existing.as_ref().map(|(_, _, encoding, _)| *encoding))
+        .unwrap_or(TextEncoding::Utf8);
+    let text = match line_ending.or(existing.as_ref().and_then(|(_, _, _, line_ending)| *line_ending)) {
+        Some(line_ending) => apply_line_ending(text, line_ending),
+        None => text.to_string(),
+    };
+    let size_after = encode(&text, encoding)?.len() as u64;
+    let change = change(path, existing.as_ref().map(|(old, size, _, _)| (old.as_str(), *size)), &text, size_after);
+    let line_ending = detect_line_ending(&text);
+    Ok((change, (text, size_after, encoding, line_ending)))
+}
+
+/// What appending `text` to `path` would change, continuing in the format the
+/// file already has, like `write_text_with_mode`
+pub fn append_change(
+    path: &Path,
+    text: &str,
+    encoding: Option<TextEncoding>,
+    line_ending: Option<LineEnding>,
+) -> Result<Value, String> {
+    let Some((old, size_before, existing_encoding, existing_line_ending)) = existing_text(path)? else {
+        return preserving_change(path, text, encoding, line_ending);
+    };
+    let encoding = encoding.unwrap_or(existing_encoding);
+    let text = match line_ending.or(existing_line_ending) {
+        Some(line_ending) => apply_line_ending(text, line_ending),
+        None => text.to_string(),
+    };
+    // The appended text carries no second byte order mark
+    let bom = match encoding {
+        TextEncoding::Utf8Bom => 3,
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => 2,
+        TextEncoding::Utf8 | TextEncoding::Latin1 => 0,
+    };
+    let appended = (encode(&text, encoding)?.len() as u64).saturating_sub(bom);
+    Ok(change(path, Some((&old, size_before)), &format!("{}{}", old, text), size_before + appended))
+}
+
+/// What writing these bytes to `path`, or appending them, would change.
+/// Binary content gets sizes but no diff.
+pub fn bytes_change(path: &Path, bytes: &[u8], append: bool) -> Result<Value, String> {
+    let fs = vfs::current();
+    let size_before = if fs.is_file(path) { Some(fs.stat(path)?.len) } else { None };
+    let kept = if append { size_before.unwrap_or(0) } else { 0 };
+    Ok(json!({
+        "action": if size_before.is_some() { "modify" } else { "create" },
+        "path": path.display().to_string(),
+        "size_before": size_before,
+        "size_after": kept + bytes.len() as u64,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::test_env;
+
+    #[tokio::test]
+    async fn test_text_change() {
+        let (_env, dir, _) = test_env().await;
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one\r\ntwo\r\n").unwrap();
+
+        let change = preserving_change(&path, "one\nthree\n", None, None).unwrap();
+        assert_eq!(change["action"], "modify");
+        assert_eq!(change["size_before"], 10);
+        assert_eq!(change["size_after"], 12);
+        assert!(change["diff"].as_str().unwrap().contains("+three\r\n"));
+
+        let change = append_change(&path, "four\n", None, None).unwrap();
+        assert_eq!(change["size_after"], 16);
+        assert!(change["diff"].as_str().unwrap().contains("+four\r\n"));
+
+        let change = text_change(&dir.path().join("new.txt"), "hello", TextEncoding::Utf8, None).unwrap();
+        assert_eq!(change["action"], "create");
+        assert!(change["size_before"].is_null());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\r\ntwo\r\n");
+        assert!(!dir.path().join("new.txt").exists());
+    }
+}
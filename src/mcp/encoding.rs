@@ -0,0 +1,282 @@
+use crate::mcp::mime::SNIFF_BYTES;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::vfs;
+use std::path::Path;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// Text encodings understood by the read/write tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    /// UTF-8 with a leading byte order mark
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+/// Values accepted for the `encoding` parameter of the read/write tools
+pub const ENCODING_NAMES: &[&str] = &["auto", "utf-8", "utf-8-bom", "utf-16le", "utf-16be", "latin1"];
+
+impl TextEncoding {
+    /// Parse an `encoding` parameter. `auto` (or no value) means detect.
+    pub fn parse(name: &str) -> Result<Option<TextEncoding>, String> {
+        match name.to_lowercase().replace('_', "-").as_str() {
+            "auto" | "" => Ok(None),
+            "utf-8" | "utf8" => Ok(Some(TextEncoding::Utf8)),
+            "utf-8-bom" | "utf8-bom" => Ok(Some(TextEncoding::Utf8Bom)),
+            "utf-16le" | "utf16le" | "utf-16" => Ok(Some(TextEncoding::Utf16Le)),
+            "utf-16be" | "utf16be" => Ok(Some(TextEncoding::Utf16Be)),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Some(TextEncoding::Latin1)),
+            other => Err(format!(
+                "Unsupported encoding: {}. Expected one of: {}",
+                other,
+                ENCODING_NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Line ending styles for written text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+/// Values accepted for the `line_ending` parameter of the write tools
+pub const LINE_ENDING_NAMES: &[&str] = &["preserve", "lf", "crlf"];
+
+impl LineEnding {
+    /// Parse a `line_ending` parameter. `preserve` (or no value) keeps the file's style.
+    pub fn parse(name: &str) -> Result<Option<LineEnding>, String> {
+        match name.to_lowercase().as_str() {
+            "preserve" | "" => Ok(None),
+            "lf" | "\n" => Ok(Some(LineEnding::Lf)),
+            "crlf" | "\r\n" => Ok(Some(LineEnding::CrLf)),
+            other => Err(format!(
+                "Unsupported line ending: {}. Expected one of: {}",
+                other,
+                LINE_ENDING_NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Detect the encoding of raw file content using the byte order mark,
+/// falling back to heuristics for BOM-less UTF-16 and Latin-1.
+pub fn detect_encoding(bytes: &[u8]) -> TextEncoding {
+    if bytes.starts_with(UTF8_BOM) {
+        return TextEncoding::Utf8Bom;
+    }
+    if bytes.starts_with(UTF16LE_BOM) {
+        return TextEncoding::Utf16Le;
+    }
+    if bytes.starts_with(UTF16BE_BOM) {
+        return TextEncoding::Utf16Be;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return TextEncoding::Utf8;
+    }
+
+    // ASCII-heavy UTF-16 text has a NUL in every other byte
+    if bytes.len() >= 2 && bytes.len().is_multiple_of(2) {
+        let pairs = bytes.len() / 2;
+        let even_nuls = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+        let odd_nuls = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+        if odd_nuls * 10 >= pairs * 3 && even_nuls * 10 < pairs {
+            return TextEncoding::Utf16Le;
+        }
+        if even_nuls * 10 >= pairs * 3 && odd_nuls * 10 < pairs {
+            return TextEncoding::Utf16Be;
+        }
+    }
+
+    TextEncoding::Latin1
+}
+
+/// Decode raw file content, stripping any byte order mark
+pub fn decode(bytes: &[u8], encoding: TextEncoding) -> Result<String, String> {
+    match encoding {
+        TextEncoding::Utf8 | TextEncoding::Utf8Bom => {
+            let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+            String::from_utf8(bytes.to_vec()).map_err(|e| format!("File is not valid UTF-8: {}", e))
+        }
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+            let little_endian = encoding == TextEncoding::Utf16Le;
+            let bom = if little_endian { UTF16LE_BOM } else { UTF16BE_BOM };
+            let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+            if !bytes.len().is_multiple_of(2) {
+                return Err("File is not valid UTF-16: odd number of bytes".to_string());
+            }
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|c| {
+                    if little_endian {
+                        u16::from_le_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_be_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16(&units).map_err(|e| format!("File is not valid UTF-16: {}", e))
+        }
+        TextEncoding::Latin1 => Ok(bytes.iter().map(|b| *b as char).collect()),
+    }
+}
+
+/// Encode text for writing, adding a byte order mark where the encoding implies one
+pub fn encode(text: &str, encoding: TextEncoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        TextEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        TextEncoding::Utf8Bom => {
+            let mut bytes = UTF8_BOM.to_vec();
+            bytes.extend_from_slice(text.as_bytes());
+            Ok(bytes)
+        }
+        TextEncoding::Utf16Le => {
+            let mut bytes = UTF16LE_BOM.to_vec();
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            Ok(bytes)
+        }
+        TextEncoding::Utf16Be => {
+            let mut bytes = UTF16BE_BOM.to_vec();
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+            Ok(bytes)
+        }
+        TextEncoding::Latin1 => text
+            .chars()
+            .map(|c| {
+                u8::try_from(u32::from(c))
+                    .map_err(|_| format!("Character {:?} cannot be encoded as latin1", c))
+            })
+            .collect(),
+    }
+}
+
+/// The dominant line ending of a text, or `None` when it has no line breaks
+pub fn detect_line_ending(text: &str) -> Option<LineEnding> {
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    if crlf == 0 && lf == 0 {
+        None
+    } else if crlf > lf {
+        Some(LineEnding::CrLf)
+    } else {
+        Some(LineEnding::Lf)
+    }
+}
+
+/// Rewrite every line break in `text` to the given style
+pub fn apply_line_ending(text: &str, line_ending: LineEnding) -> String {
+    let normalized = text.replace("\r\n", "\n");
+    match line_ending {
+        LineEnding::Lf => normalized,
+        LineEnding::CrLf => normalized.replace('\n', "\r\n"),
+    }
+}
+
+/// A decoded text file along with the format it was stored in
+pub struct DecodedText {
+    pub text: String,
+    pub encoding: TextEncoding,
+    pub line_ending: Option<LineEnding>,
+}
+
+/// Read a text file, detecting its encoding unless one is given
+pub fn read_text_file(path: &Path, encoding: Option<TextEncoding>) -> Result<DecodedText, String> {
+    let bytes = vfs::current().read(path)?;
+    let encoding = encoding.unwrap_or_else(|| detect_encoding(&bytes));
+    let text = decode(&bytes, encoding)?;
+    let line_ending = detect_line_ending(&text);
+    Ok(DecodedText {
+        text,
+        encoding,
+        line_ending,
+    })
+}
+
+/// Write a text file in the given encoding, converting line breaks when a style is given
+pub fn write_text_file(
+    path: &Path,
+    text: &str,
+    encoding: TextEncoding,
+    line_ending: Option<LineEnding>,
+) -> Result<(), String> {
+    let text = match line_ending {
+        Some(line_ending) => apply_line_ending(text, line_ending),
+        None => text.to_string(),
+    };
+    let bytes = encode(&text, encoding)?;
+    vfs::current().write(path, &bytes, WriteMode::Overwrite)
+}
+
+/// Write text according to `mode`. Replacing keeps the format of an existing
+/// file like `write_text_preserving`; appending continues in the encoding and
+/// line ending style the file already has, without a second byte order mark.
+pub fn write_text_with_mode(
+    path: &Path,
+    text: &str,
+    mode: WriteMode,
+    encoding: Option<TextEncoding>,
+    line_ending: Option<LineEnding>,
+) -> Result<(), String> {
+    if mode == WriteMode::Overwrite {
+        return write_text_preserving(path, text, encoding, line_ending);
+    }
+    // Only the start of the file is needed to tell its format, however long it grew
+    let fs = vfs::current();
+    let head = if mode.appends() && fs.is_file(path) {
+        let head = vfs::backend().read_prefix(path, SNIFF_BYTES)?;
+        (!head.is_empty()).then_some(head)
+    } else {
+        None
+    };
+    let existing = head.as_ref().map(|head| {
+        // The sample may end in the middle of a character
+        let valid = match std::str::from_utf8(head) {
+            Err(e) if e.error_len().is_none() => &head[..e.valid_up_to()],
+            _ => head,
+        };
+        let encoding = detect_encoding(valid);
+        (encoding, decode(valid, encoding).ok().and_then(|text| detect_line_ending(&text)))
+    });
+    let encoding = encoding
+        .or(existing.map(|(encoding, _)| encoding))
+        .unwrap_or(TextEncoding::Utf8);
+    let line_ending = line_ending.or(existing.and_then(|(_, line_ending)| line_ending));
+    let text = match line_ending {
+        Some(line_ending) => apply_line_ending(text, line_ending),
+        None => text.to_string(),
+    };
+    let mut bytes = encode(&text, encoding)?;
+    if existing.is_some() {
+        let bom = match encoding {
+            TextEncoding::Utf8Bom => UTF8_BOM,
+            TextEncoding::Utf16Le => UTF16LE_BOM,
+            TextEncoding::Utf16Be => UTF16BE_BOM,
+            TextEncoding::Utf8 | TextEncoding::Latin1 => &[],
+        };
+        bytes.drain(..bom.len());
+    }
+    fs.write(path, &bytes, mode)
+}
+
+/// Write a text file, keeping the encoding and line endings of an existing file
+/// for whichever of the two is not given explicitly. New files default to UTF-8.
+pub fn write_text_preserving(
+    path: &Path,
+    text: &str,
+    encoding: Option<TextEncoding>,
+    line_ending: Option<LineEnding>,
+) -> Result<(), String> {
+    let existing = if vfs::current().is_file(path) { read_text_file(path, None).ok() } else { None };
+    let encoding = encoding
+        .or(existing.as_ref().map(|decoded| decoded.encoding))
+        .unwrap_or(TextEncoding::Utf8);
+    let line_ending = line_ending.or(existing.and_then(|decoded| decoded.line_ending));
+    write_text_file(path, text, encoding, line_ending)
+}
@@ -0,0 +1,190 @@
+use crate::mcp::config;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// What is expanded in path arguments before they are resolved and checked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathExpansion {
+    /// Paths are taken literally
+    Off,
+    /// `~` and `~user` at the start become home directories
+    Tilde,
+    /// As `Tilde`, and `$VAR`, `${VAR}` and `%VAR%` become the values of
+    /// environment variables. Opt-in, as the values of the server's variables
+    /// can then show up in paths and error messages.
+    All,
+}
+
+impl PathExpansion {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode.to_lowercase().as_str() {
+            "off" => Ok(PathExpansion::Off),
+            "tilde" => Ok(PathExpansion::Tilde),
+            "all" => Ok(PathExpansion::All),
+            _ => Err(format!("Invalid path expansion {:?}, expected off, tilde or all", mode)),
+        }
+    }
+}
+
+/// `MCP_RS_FILESYSTEM_PATH_EXPANSION`, set by `--path-expansion`. Defaults to
+/// `tilde`; a value that does not parse was refused at startup.
+pub fn configured() -> PathExpansion {
+    config::var("MCP_RS_FILESYSTEM_PATH_EXPANSION")
+        .ok()
+        .and_then(|mode| PathExpansion::parse(&mode).ok())
+        .unwrap_or(PathExpansion::Tilde)
+}
+
+/// Home directory of `user`, or of the user running the server for `None`
+fn home_of(user: Option<&str>) -> Option<PathBuf> {
+    match user {
+        None => dirs::home_dir(),
+        Some(user) => home_of_user(user),
+    }
+}
+
+#[cfg(unix)]
+fn home_of_user(user: &str) -> Option<PathBuf> {
+    use std::ffi::CStr;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = CString::new(user).ok()?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut found: *mut libc::passwd = std::ptr::null_mut();
+    // SAFETY: every pointer refers to a live buffer of the given size
+    let status = unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if status != 0 || found.is_null() || entry.pw_dir.is_null() {
+        return None;
+    }
+    // SAFETY: getpwnam_r filled pw_dir with a NUL-terminated string inside `buffer`
+    let dir = unsafe { CStr::from_ptr(entry.pw_dir) };
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes())))
+}
+
+#[cfg(not(unix))]
+fn home_of_user(_user: &str) -> Option<PathBuf> {
+    None
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || (cfg!(windows) && c == '\\')
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Replace `$VAR`, `${VAR}` and `%VAR%` with the values `lookup` finds.
+/// References to variables it does not know are left as they are.
+fn expand_variables(text: &str, lookup: &impl Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let reference = if rest[start..].starts_with('%') {
+            after.find('%').map(|end| (&after[..end], end + 2))
+        } else if let Some(braced) = after.strip_prefix('{') {
+            braced.find('}').map(|end| (&braced[..end], end + 3))
+        } else {
+            let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            Some((&after[..end], end + 1))
+        };
+        match reference {
+            Some((name, len)) if name.starts_with(is_name_start) && name.chars().all(is_name_char) => {
+                match lookup(name) {
+                    Some(value) => expanded.push_str(&value),
+                    None => expanded.push_str(&rest[start..start + len]),
+                }
+                rest = &rest[start + len..];
+            }
+            _ => {
+                expanded.push_str(&rest[start..start + 1]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Replace a leading `~` or `~user` with the home directory `home` finds.
+/// Unknown users are left as they are.
+fn expand_tilde(text: &str, home: &impl Fn(Option<&str>) -> Option<PathBuf>) -> String {
+    let Some(after) = text.strip_prefix('~') else {
+        return text.to_string();
+    };
+    let end = after.find(is_separator).unwrap_or(after.len());
+    let user = (end > 0).then(|| &after[..end]);
+    match home(user) {
+        Some(dir) => format!("{}{}", dir.display(), &after[end..]),
+        None => text.to_string(),
+    }
+}
+
+fn expand_with(
+    text: &str,
+    mode: PathExpansion,
+    lookup: &impl Fn(&str) -> Option<String>,
+    home: &impl Fn(Option<&str>) -> Option<PathBuf>,
+) -> String {
+    match mode {
+        PathExpansion::Off => text.to_string(),
+        PathExpansion::Tilde => expand_tilde(text, home),
+        PathExpansion::All => expand_variables(&expand_tilde(text, home), lookup),
+    }
+}
+
+/// A path argument with `~` and, when enabled, environment variables
+/// expanded as configured. Paths that are not UTF-8 are left as they are.
+pub fn expand_path(path: &Path) -> PathBuf {
+    let mode = configured();
+    match path.to_str() {
+        Some(text) if mode != PathExpansion::Off => {
+            PathBuf::from(expand_with(text, mode, &|name| std::env::var(name).ok(), &home_of))
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_with() {
+        let lookup = |name: &str| match name {
+            "PROJECT" => Some("rs_filesystem".to_string()),
+            "ROOT" => Some("/srv".to_string()),
+            _ => None,
+        };
+        let home = |user: Option<&str>| match user {
+            None => Some(PathBuf::from("/home/me")),
+            Some("ann") => Some(PathBuf::from("/home/ann")),
+            Some(_) => None,
+        };
+        let expand = |text: &str, mode| expand_with(text, mode, &lookup, &home);
+
+        assert_eq!(expand("~/projects/foo", PathExpansion::Tilde), "/home/me/projects/foo");
+        assert_eq!(expand("~", PathExpansion::Tilde), "/home/me");
+        assert_eq!(expand("~ann/notes", PathExpansion::Tilde), "/home/ann/notes");
+        assert_eq!(expand("~nobody/notes", PathExpansion::Tilde), "~nobody/notes");
+        assert_eq!(expand("docs/~draft", PathExpansion::Tilde), "docs/~draft");
+        assert_eq!(expand("~/$PROJECT", PathExpansion::Tilde), "/home/me/$PROJECT");
+        assert_eq!(expand("~/x", PathExpansion::Off), "~/x");
+
+        assert_eq!(expand("~/$PROJECT/src", PathExpansion::All), "/home/me/rs_filesystem/src");
+        assert_eq!(expand("${ROOT}/a-${PROJECT}", PathExpansion::All), "/srv/a-rs_filesystem");
+        assert_eq!(expand("%ROOT%\\logs", PathExpansion::All), "/srv\\logs");
+        // Unknown variables and anything that is not a reference stay
+        assert_eq!(expand("$UNSET/a", PathExpansion::All), "$UNSET/a");
+        assert_eq!(expand("50% off $5 %20", PathExpansion::All), "50% off $5 %20");
+        assert_eq!(expand("a$", PathExpansion::All), "a$");
+    }
+}
@@ -0,0 +1,447 @@
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::index;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::nfc;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::unicode::strip_accents;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::utilities::validate_path_or_error;
+use chrono::DateTime;
+use chrono::Local;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+const DEFAULT_MAX_RESULTS: usize = 20;
+const MAX_MAX_RESULTS: usize = 1000;
+const DEFAULT_MAX_ENTRIES: usize = 200_000;
+const MAX_MAX_ENTRIES: usize = 1_000_000;
+const DEFAULT_MAX_DURATION_MS: u64 = 5_000;
+const MAX_MAX_DURATION_MS: u64 = 60_000;
+
+const SCORE_MATCH: i64 = 16;
+const GAP_START: i64 = 3;
+const GAP_EXTEND: i64 = 1;
+/// Extra for each character that directly follows the previous match
+const BONUS_CONSECUTIVE: i64 = 4;
+/// Extra for matching at the start of a path component
+const BONUS_SEPARATOR: i64 = 10;
+/// Extra for matching at the start of a word, after `_`, `-`, `.` or a space
+const BONUS_WORD: i64 = 8;
+/// Extra for matching an uppercase letter that follows a lowercase one
+const BONUS_CAMEL: i64 = 7;
+/// Extra when every query character is found in the file name itself
+const BONUS_NAME: i64 = 20;
+/// Extra when the query appears in the file name as a whole
+const BONUS_SUBSTRING: i64 = 10;
+const BONUS_EXACT: i64 = 30;
+
+/// Extra for a match at position `i` of `text`, based on the character before it
+fn position_bonus(text: &[char], i: usize) -> i64 {
+    let Some(&previous) = i.checked_sub(1).and_then(|p| text.get(p)) else {
+        return BONUS_SEPARATOR;
+    };
+    match previous {
+        '/' | '\\' => BONUS_SEPARATOR,
+        '_' | '-' | '.' | ' ' => BONUS_WORD,
+        _ if previous.is_lowercase() && text[i].is_uppercase() => BONUS_CAMEL,
+        _ => 0,
+    }
+}
+
+/// How a query is compared with names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Matching {
+    /// `None` for smart case: case matters only when the query has an uppercase letter
+    pub case_sensitive: Option<bool>,
+    /// Compare letters without their accents, so `cafe` finds `café`
+    pub ignore_accents: bool,
+    /// Compare NFC and NFD forms of the same text as equal, as macOS stores
+    /// names in NFD and Linux keeps them as written
+    pub normalize: bool,
+}
+
+impl Default for Matching {
+    fn default() -> Matching {
+        Matching {
+            case_sensitive: None,
+            ignore_accents: false,
+            normalize: true,
+        }
+    }
+}
+
+impl Matching {
+    /// `text` in the form it is compared in, apart from case
+    fn prepare(&self, text: &str) -> String {
+        if self.ignore_accents {
+            strip_accents(text)
+        } else if self.normalize {
+            nfc(text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn case_sensitive(&self, query: &str) -> bool {
+        self.case_sensitive.unwrap_or_else(|| query.chars().any(char::is_uppercase))
+    }
+}
+
+/// Score of the best alignment of `query` as a subsequence of `text`, like
+/// fzf: matches at word boundaries and runs of consecutive characters score
+/// higher, gaps between matches cost. `None` when `query` is not a
+/// subsequence. By default matching ignores case unless the query has an
+/// uppercase letter.
+pub fn fuzzy_score(query: &str, text: &str, matching: &Matching) -> Option<i64> {
+    let case_sensitive = matching.case_sensitive(query);
+    let fold = |c: char| if case_sensitive { c } else { c.to_lowercase().next().unwrap_or(c) };
+    let query: Vec<char> = matching.prepare(query).chars().map(fold).collect();
+    let original: Vec<char> = matching.prepare(text).chars().collect();
+    let folded: Vec<char> = original.iter().copied().map(fold).collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+    if query.len() > folded.len() {
+        return None;
+    }
+
+    // previous[j]: best score with the previous query character matched at j,
+    // and the bonus of the first character of the run of matches ending there
+    let n = folded.len();
+    let mut previous: Vec<Option<(i64, i64)>> = vec![None; n];
+    for (i, &q) in query.iter().enumerate() {
+        let mut current: Vec<Option<(i64, i64)>> = vec![None; n];
+        // Best score of the previous character followed by a gap of at least one
+        let mut gapped: Option<i64> = None;
+        for j in 0..n {
+            if j >= 2 {
+                let opened = previous[j - 2].map(|(s, _)| s - GAP_START);
+                gapped = gapped.map(|s| s - GAP_EXTEND).max(opened);
+            }
+            if folded[j] != q {
+                continue;
+            }
+            let bonus = position_bonus(&original, j);
+            let after_gap = if i == 0 { Some(0) } else { gapped };
+            // A run keeps the bonus of its first character, as fzf does, so a
+            // whole word outranks the same letters scattered over word starts
+            let consecutive = match j.checked_sub(1).and_then(|p| previous[p]) {
+                Some((s, run_bonus)) if i > 0 => {
+                    Some((s + SCORE_MATCH + bonus.max(run_bonus).max(BONUS_CONSECUTIVE), run_bonus.max(bonus)))
+                }
+                _ => None,
+            };
+            let gapped = after_gap.map(|s| (s + SCORE_MATCH + bonus, bonus));
+            current[j] = match (consecutive, gapped) {
+                (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+                (a, b) => a.or(b),
+            };
+        }
+        previous = current;
+    }
+    previous.into_iter().flatten().map(|(score, _)| score).max()
+}
+
+/// Score of a candidate found under a search root: the query is matched
+/// against the file name and against the path relative to the root, and
+/// matches confined to the name rank higher
+fn score_candidate(query: &str, name: &str, relative: &str, matching: &Matching) -> Option<i64> {
+    let by_path = fuzzy_score(query, relative, matching);
+    let by_name = fuzzy_score(query, name, matching).map(|score| {
+        let (query, name) = (
+            matching.prepare(query).to_lowercase(),
+            matching.prepare(name).to_lowercase(),
+        );
+        let bonus = if name == query {
+            BONUS_EXACT
+        } else if name.contains(&query) {
+            BONUS_SUBSTRING
+        } else {
+            0
+        };
+        score + BONUS_NAME + bonus
+    });
+    by_name.max(by_path)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct FindFileRequest {
+    /// Characters to look for in order
+    pub query: String,
+    /// Directory to search. Defaults to all allowed directories
+    pub path: Option<String>,
+    /// How many of the best matches to return. Defaults to 20
+    #[schemars(extend("default" = 20))]
+    pub max_results: Option<usize>,
+    /// Match directories as well as files. Defaults to false
+    #[schemars(extend("default" = false))]
+    pub include_directories: Option<bool>,
+    /// Skip files excluded by .gitignore, .ignore and global git excludes. Defaults to true
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// Search hidden files and directories. Defaults to false
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    /// Whether case matters. By default it does only when the query has an uppercase letter
+    pub case_sensitive: Option<bool>,
+    /// Match letters regardless of accents, so 'resume' finds 'résumé.pdf'. Defaults to false
+    #[schemars(extend("default" = false))]
+    pub ignore_accents: Option<bool>,
+    /// Treat the NFC and NFD forms of a name as the same, as macOS stores names in NFD. Defaults to true
+    #[schemars(extend("default" = true))]
+    pub normalize_unicode: Option<bool>,
+    /// Stop after visiting this many entries. Defaults to 200000
+    #[schemars(extend("default" = 200000))]
+    pub max_entries: Option<usize>,
+    /// Stop after this many milliseconds. Defaults to 5000
+    #[schemars(extend("default" = 5000))]
+    pub max_duration_ms: Option<u64>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+struct Candidate {
+    path: PathBuf,
+    is_dir: bool,
+    score: i64,
+    /// Size and modification time, when the index already knows them
+    details: Option<(u64, Option<SystemTime>)>,
+}
+
+pub async fn find_file(request: FindFileRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(find_file_blocking(request)).await
+}
+
+async fn find_file_blocking(request: FindFileRequest) -> HandlerResult<CallToolResult> {
+    let roots: Vec<PathBuf> = match &request.path {
+        Some(path) => {
+            let path = resolve_path(Path::new(path));
+            if let Err(msg) = validate_path_or_error(&path) {
+                return Ok(CallToolResult::error(msg));
+            }
+            vec![path]
+        }
+        None => get_allowed_directories()
+            .iter()
+            .map(|dir| canonical_path(Path::new(dir)))
+            .collect(),
+    };
+    let query = request.query.trim();
+    if query.is_empty() {
+        return Ok(CallToolResult::error("Query must not be empty".to_string()));
+    }
+
+    let max_results = request.max_results.unwrap_or(DEFAULT_MAX_RESULTS).clamp(1, MAX_MAX_RESULTS);
+    let max_entries = request.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES).clamp(1, MAX_MAX_ENTRIES);
+    let max_duration = Duration::from_millis(
+        request
+            .max_duration_ms
+            .unwrap_or(DEFAULT_MAX_DURATION_MS)
+            .clamp(1, MAX_MAX_DURATION_MS),
+    );
+    let include_directories = request.include_directories.unwrap_or(false);
+    let respect_gitignore = request.respect_gitignore.unwrap_or(true);
+    let include_hidden = request.include_hidden.unwrap_or(false);
+    let matching = Matching {
+        case_sensitive: request.case_sensitive,
+        ignore_accents: request.ignore_accents.unwrap_or(false),
+        normalize: request.normalize_unicode.unwrap_or(true),
+    };
+    let rules = match IgnoreRules::new(respect_gitignore, include_hidden).with_overrides(&request.ignore) {
+        Ok(rules) => rules,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    let started = Instant::now();
+    let mut scanned = 0;
+    let mut truncated: Option<&str> = None;
+    let mut candidates = Vec::new();
+    let mut consider = |root: &Path, path: &Path, is_dir: bool, details: Option<(u64, Option<SystemTime>)>| {
+        if is_dir && !include_directories {
+            return;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let relative = path.strip_prefix(root).unwrap_or(path).to_string_lossy();
+        if let Some(score) = score_candidate(query, &name, &relative, &matching) {
+            candidates.push(Candidate {
+                path: path.to_path_buf(),
+                is_dir,
+                score,
+                details,
+            });
+        }
+    };
+    // The index holds what a walk with the default options finds
+    let use_index = index::enabled() && respect_gitignore && !include_hidden && request.ignore.is_default();
+    let mut indexed = use_index;
+    'roots: for root in &roots {
+        if use_index {
+            let canonical_root = canonical_path(root);
+            let answered = index::for_each_entry(&canonical_root, |path, entry| {
+                scanned += 1;
+                consider(&canonical_root, path, entry.is_dir, Some((entry.size, entry.modified)));
+            });
+            if answered {
+                continue;
+            }
+            indexed = false;
+        }
+        for entry in rules.walker(root).build() {
+            if scanned >= max_entries {
+                truncated = Some("max_entries");
+                break 'roots;
+            }
+            if started.elapsed() >= max_duration {
+                truncated = Some("max_duration_ms");
+                break 'roots;
+            }
+            scanned += 1;
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if entry.depth() == 0 {
+                continue;
+            }
+            consider(root, entry.path(), entry.file_type().is_some_and(|t| t.is_dir()), None);
+        }
+    }
+
+    let total_matches = candidates.len();
+    // Best first; among equals the shorter path, which is usually the one meant
+    candidates.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| a.path.as_os_str().len().cmp(&b.path.as_os_str().len()))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    candidates.truncate(max_results);
+
+    let matches: Vec<_> = candidates
+        .iter()
+        .map(|c| {
+            let (size, modified) = c.details.or_else(|| {
+                let metadata = fs::metadata(&c.path).ok()?;
+                Some((metadata.len(), metadata.modified().ok()))
+            }).unzip();
+            json!({
+                "path": c.path.display().to_string(),
+                "type": if c.is_dir { "directory" } else { "file" },
+                "score": c.score,
+                "size": if c.is_dir { None } else { size },
+                "modified": modified.flatten().map(|time| DateTime::<Local>::from(time).to_rfc3339()),
+            })
+        })
+        .collect();
+    let summary = json!({
+        "query": query,
+        "matches": matches,
+        "total_matches": total_matches,
+        "scanned": scanned,
+        "indexed": indexed,
+        "truncated": truncated.is_some(),
+        "truncated_by": truncated,
+    });
+    Ok(CallToolResult::structured(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+
+    #[test]
+    fn test_fuzzy_score() {
+        let smart = Matching::default();
+        assert!(fuzzy_score("tlsrs", "tools.rs", &smart).is_some());
+        assert!(fuzzy_score("xyz", "tools.rs", &smart).is_none());
+        assert!(fuzzy_score("srt", "tools.rs", &smart).is_none());
+        // Consecutive and boundary matches beat scattered ones
+        assert!(fuzzy_score("tool", "tools.rs", &smart) > fuzzy_score("tool", "t_o_o_l.rs", &smart));
+        assert!(fuzzy_score("mr", "mod_router.rs", &smart) > fuzzy_score("mr", "summary.rs", &smart));
+        // Smart case
+        assert!(fuzzy_score("readme", "README.md", &smart).is_some());
+        assert!(fuzzy_score("Readme", "readme.md", &smart).is_none());
+        // Names beat directories that happen to contain the letters
+        assert!(
+            score_candidate("main", "main.rs", "src/main.rs", &smart)
+                > score_candidate("main", "lib.rs", "main/lib.rs", &smart)
+        );
+    }
+
+    #[test]
+    fn test_matching_options() {
+        let accents = Matching {
+            ignore_accents: true,
+            ..Matching::default()
+        };
+        assert_eq!(strip_accents("Crème brûlée"), "Creme brulee");
+        assert_eq!(strip_accents("Cre\u{300}me"), "Creme");
+        assert!(fuzzy_score("resume", "résumé.pdf", &Matching::default()).is_none());
+        assert!(fuzzy_score("resume", "résumé.pdf", &accents).is_some());
+        assert!(fuzzy_score("résumé", "resume.pdf", &accents).is_some());
+        // A name stored in NFD, as macOS does, matches a query in NFC
+        assert!(fuzzy_score("caf\u{e9}", "cafe\u{301}.txt", &Matching::default()).is_some());
+        let exact = Matching {
+            normalize: false,
+            ..Matching::default()
+        };
+        assert!(fuzzy_score("caf\u{e9}", "cafe\u{301}.txt", &exact).is_none());
+        let insensitive = Matching {
+            case_sensitive: Some(false),
+            ..Matching::default()
+        };
+        assert!(fuzzy_score("Readme", "readme.md", &insensitive).is_some());
+        let sensitive = Matching {
+            case_sensitive: Some(true),
+            ..Matching::default()
+        };
+        assert!(fuzzy_score("readme", "README.md", &sensitive).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_find_file_ranks_matches() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        fs::create_dir_all(temp_dir.path().join("src/mcp")).unwrap();
+        fs::write(temp_dir.path().join("src/mcp/tools.rs"), "").unwrap();
+        fs::write(temp_dir.path().join("src/mcp/types.rs"), "").unwrap();
+        fs::write(temp_dir.path().join("src/main.rs"), "").unwrap();
+        fs::write(temp_dir.path().join("README.md"), "").unwrap();
+
+        let result = find_file(FindFileRequest {
+            query: "tlsrs".to_string(),
+            path: None,
+            max_results: None,
+            include_directories: None,
+            respect_gitignore: None,
+            include_hidden: None,
+            case_sensitive: None,
+            ignore_accents: None,
+            normalize_unicode: None,
+            max_entries: None,
+            max_duration_ms: None,
+            ignore: Default::default(),
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let found = result_json(&result);
+        let matches = found["matches"].as_array().unwrap();
+        assert!(matches[0]["path"].as_str().unwrap().ends_with("tools.rs"));
+        assert!(matches.iter().all(|m| !m["path"].as_str().unwrap().ends_with("README.md")));
+    }
+}
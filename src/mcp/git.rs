@@ -0,0 +1,263 @@
+use crate::mcp::limits::max_result_entries;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::is_path_allowed;
+use crate::mcp::utilities::validate_path_or_error;
+use git2::Branch;
+use git2::Repository;
+use git2::Status;
+use git2::StatusOptions;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Repository containing `path`, with its working directory
+fn discover(path: &Path) -> Option<(Repository, PathBuf)> {
+    let repo = Repository::discover(path).ok()?;
+    let workdir = canonical_path(repo.workdir()?);
+    Some((repo, workdir))
+}
+
+/// Path of `path` inside the working directory, with `/` separators as git uses them
+fn repo_relative(workdir: &Path, path: &Path) -> Option<String> {
+    let relative = canonical_path(path).strip_prefix(workdir).ok()?.to_path_buf();
+    Some(
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Change staged in the index
+fn staged_change(status: Status) -> Option<&'static str> {
+    if status.is_index_new() {
+        Some("added")
+    } else if status.is_index_modified() {
+        Some("modified")
+    } else if status.is_index_deleted() {
+        Some("deleted")
+    } else if status.is_index_renamed() {
+        Some("renamed")
+    } else if status.is_index_typechange() {
+        Some("typechange")
+    } else {
+        None
+    }
+}
+
+/// Change in the working tree that is not staged
+fn unstaged_change(status: Status) -> Option<&'static str> {
+    if status.is_wt_new() {
+        Some("untracked")
+    } else if status.is_wt_modified() {
+        Some("modified")
+    } else if status.is_wt_deleted() {
+        Some("deleted")
+    } else if status.is_wt_renamed() {
+        Some("renamed")
+    } else if status.is_wt_typechange() {
+        Some("typechange")
+    } else {
+        None
+    }
+}
+
+/// One word for a listing entry. Directories take the label of their contents.
+fn summary_label(status: Status, is_content: bool) -> &'static str {
+    if status.is_conflicted() {
+        "conflicted"
+    } else if status.is_ignored() {
+        "ignored"
+    } else if status.is_wt_new() && !is_content {
+        "untracked"
+    } else if status.is_index_new() && !is_content {
+        "added"
+    } else {
+        "modified"
+    }
+}
+
+/// Rank of a label when a directory holds entries in several states
+fn label_priority(label: &str) -> u8 {
+    match label {
+        "conflicted" => 5,
+        "modified" | "added" => 4,
+        "untracked" => 3,
+        "tracked" => 2,
+        "ignored" => 1,
+        _ => 0,
+    }
+}
+
+/// Git state of each entry of `dir` by name: conflicted, modified, added,
+/// untracked, ignored or tracked. `None` when `dir` is not in a repository.
+pub fn directory_annotations(dir: &Path) -> Option<HashMap<String, &'static str>> {
+    let (repo, workdir) = discover(dir)?;
+    let relative = repo_relative(&workdir, dir)?;
+    let prefix = if relative.is_empty() { String::new() } else { format!("{}/", relative) };
+
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .include_ignored(true)
+        .recurse_untracked_dirs(false)
+        .recurse_ignored_dirs(false);
+    if !relative.is_empty() {
+        options.pathspec(&relative);
+    }
+    let statuses = repo.statuses(Some(&mut options)).ok()?;
+
+    let mut labels: HashMap<String, &'static str> = HashMap::new();
+    let mut mark = |name: &str, label: &'static str| {
+        let current = labels.entry(name.to_string()).or_insert(label);
+        if label_priority(label) > label_priority(current) {
+            *current = label;
+        }
+    };
+    for entry in statuses.iter() {
+        let Some(rest) = entry.path().and_then(|p| p.strip_prefix(prefix.as_str())) else {
+            continue;
+        };
+        let rest = rest.trim_end_matches('/');
+        let (name, is_content) = match rest.split_once('/') {
+            Some((name, _)) => (name, true),
+            None => (rest, false),
+        };
+        if !name.is_empty() {
+            mark(name, summary_label(entry.status(), is_content));
+        }
+    }
+    // Everything else in the index is tracked and unchanged
+    if let Ok(index) = repo.index() {
+        for entry in index.iter() {
+            let path = String::from_utf8_lossy(&entry.path).into_owned();
+            if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+                let name = rest.split('/').next().unwrap_or(rest);
+                mark(name, "tracked");
+            }
+        }
+    }
+    Some(labels)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct GitStatusRequest {
+    /// Directory inside the repository, e.g. an allowed root
+    pub path: String,
+    /// Include untracked files. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub include_untracked: Option<bool>,
+    /// Include ignored files. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_ignored: Option<bool>,
+}
+
+pub async fn git_status(request: GitStatusRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let Some((repo, workdir)) = discover(path) else {
+        return Ok(CallToolResult::error(format!("Not inside a git working tree: {}", path.display())));
+    };
+
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(request.include_untracked.unwrap_or(true))
+        .recurse_untracked_dirs(true)
+        .include_ignored(request.include_ignored.unwrap_or(false))
+        .renames_head_to_index(true);
+    // Only report on the part of the repository that was asked about
+    if let Some(relative) = repo_relative(&workdir, path).filter(|r| !r.is_empty()) {
+        options.pathspec(relative);
+    }
+    let statuses = match repo.statuses(Some(&mut options)) {
+        Ok(statuses) => statuses,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading git status: {}", e))),
+    };
+
+    let limit = max_result_entries();
+    let mut entries = Vec::new();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut total = 0;
+    for entry in statuses.iter() {
+        let Some(relative) = entry.path() else {
+            continue;
+        };
+        let full_path = workdir.join(relative);
+        // The repository may extend beyond the allowed directories
+        if !is_path_allowed(&full_path) {
+            continue;
+        }
+        total += 1;
+        let status = entry.status();
+        let (staged, unstaged) = if status.is_conflicted() {
+            (Some("conflicted"), Some("conflicted"))
+        } else if status.is_ignored() {
+            (None, Some("ignored"))
+        } else {
+            (staged_change(status), unstaged_change(status))
+        };
+        if status.is_conflicted() {
+            *counts.entry("conflicted").or_default() += 1;
+        } else {
+            if staged.is_some() {
+                *counts.entry("staged").or_default() += 1;
+            }
+            match unstaged {
+                Some("untracked") => *counts.entry("untracked").or_default() += 1,
+                Some("ignored") => *counts.entry("ignored").or_default() += 1,
+                Some(_) => *counts.entry("unstaged").or_default() += 1,
+                None => {}
+            }
+        }
+        if entries.len() < limit {
+            entries.push(json!({
+                "path": full_path.display().to_string(),
+                "staged": staged,
+                "unstaged": unstaged,
+            }));
+        }
+    }
+
+    let head = repo.head().ok();
+    let branch = match &head {
+        Some(head) if head.is_branch() => head.shorthand().map(str::to_string),
+        Some(_) => None,
+        // A repository without commits still names the branch HEAD points to
+        None => repo
+            .find_reference("HEAD")
+            .ok()
+            .and_then(|r| r.symbolic_target().map(|t| t.trim_start_matches("refs/heads/").to_string())),
+    };
+    let head_commit = head.as_ref().and_then(|h| h.target()).map(|oid| oid.to_string());
+    let upstream = head.filter(|h| h.is_branch()).and_then(|h| {
+        let upstream = Branch::wrap(h).upstream().ok()?;
+        let name = upstream.name().ok().flatten()?.to_string();
+        let (local, remote) = (repo.head().ok()?.target()?, upstream.get().target()?);
+        let (ahead, behind) = repo.graph_ahead_behind(local, remote).ok()?;
+        Some(json!({ "name": name, "ahead": ahead, "behind": behind }))
+    });
+
+    let summary = json!({
+        "repository": workdir.display().to_string(),
+        "branch": branch,
+        "detached": repo.head_detached().unwrap_or(false),
+        "head": head_commit,
+        "upstream": upstream,
+        "clean": total == counts.get("ignored").copied().unwrap_or(0),
+        "counts": counts,
+        "entries": entries,
+        "truncated": total > limit,
+    });
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&summary).unwrap()))
+}
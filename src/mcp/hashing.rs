@@ -0,0 +1,384 @@
+use crate::mcp::budget::charge_read;
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::sandbox;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+pub const DEFAULT_AVERAGE_CHUNK_SIZE: usize = 8 * 1024;
+const MIN_AVERAGE_CHUNK_SIZE: usize = 256;
+const MAX_AVERAGE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 100_000;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 of a file, streamed so large files are never held in memory
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    charge_read(path, file.metadata()?.len()).map_err(std::io::Error::other)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Random values the gear hash mixes in per byte. Generated with splitmix64 from a
+/// fixed seed so chunk boundaries are stable across runs and machines.
+fn gear_table() -> &'static [u64; 256] {
+    static GEAR: OnceLock<[u64; 256]> = OnceLock::new();
+    GEAR.get_or_init(|| {
+        let mut state: u64 = 0x5EED_F00D_CAFE_D00D;
+        let mut table = [0u64; 256];
+        for value in table.iter_mut() {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            *value = z ^ (z >> 31);
+        }
+        table
+    })
+}
+
+/// FastCDC parameters derived from the desired average chunk size
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkerConfig {
+    pub min_size: usize,
+    pub average_size: usize,
+    pub max_size: usize,
+    /// mask used before the average size is reached, harder to match
+    mask_small: u64,
+    /// mask used after the average size is reached, easier to match
+    mask_large: u64,
+}
+
+impl ChunkerConfig {
+    pub fn new(average_size: usize) -> Self {
+        let average_size = average_size
+            .clamp(MIN_AVERAGE_CHUNK_SIZE, MAX_AVERAGE_CHUNK_SIZE)
+            .next_power_of_two();
+        let bits = average_size.trailing_zeros();
+        // The gear hash shifts left per byte, so the high bits depend on the most
+        // input and make the best boundary condition
+        let top_bits = |n: u32| !0u64 << (64 - n);
+        ChunkerConfig {
+            min_size: average_size / 4,
+            average_size,
+            max_size: average_size * 8,
+            mask_small: top_bits(bits + 1),
+            mask_large: top_bits(bits - 1),
+        }
+    }
+
+    /// Length of the next chunk at the start of `data` (normalized chunking)
+    fn cut_point(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let gear = gear_table();
+        let end = data.len().min(self.max_size);
+        let normal = self.average_size.min(end);
+        let mut hash: u64 = 0;
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(gear[*byte as usize]);
+            let mask = if i < normal { self.mask_small } else { self.mask_large };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+/// One content-defined chunk of a file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Chunk {
+    pub offset: u64,
+    pub length: usize,
+    pub sha256: String,
+}
+
+/// Split a file into content-defined chunks with FastCDC and hash each one.
+/// Returns the chunks along with the SHA-256 of the whole file.
+pub fn chunk_file(path: &Path, config: ChunkerConfig) -> std::io::Result<(Vec<Chunk>, String)> {
+    let mut file = sandbox::open_read(path).map_err(std::io::Error::other)?;
+    charge_read(path, file.metadata()?.len()).map_err(std::io::Error::other)?;
+    let mut file_hasher = Sha256::new();
+    let mut chunks = Vec::new();
+    let mut buffer: Vec<u8> = Vec::with_capacity(config.max_size * 2);
+    let mut read_buffer = vec![0; READ_BUFFER_SIZE];
+    let mut offset: u64 = 0;
+    let mut eof = false;
+
+    loop {
+        // Keep at least one maximum-sized chunk buffered so cut points are found correctly
+        while !eof && buffer.len() < config.max_size {
+            let read = file.read(&mut read_buffer)?;
+            if read == 0 {
+                eof = true;
+            } else {
+                file_hasher.update(&read_buffer[..read]);
+                buffer.extend_from_slice(&read_buffer[..read]);
+            }
+        }
+        if buffer.is_empty() {
+            break;
+        }
+
+        let length = config.cut_point(&buffer);
+        chunks.push(Chunk {
+            offset,
+            length,
+            sha256: to_hex(&Sha256::digest(&buffer[..length])),
+        });
+        offset += length as u64;
+        buffer.drain(..length);
+    }
+
+    Ok((chunks, to_hex(&file_hasher.finalize())))
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ChunkSignatureRequest {
+    /// Path to the file
+    pub path: String,
+    /// Target average chunk size in bytes, rounded to a power of two. Defaults to 8192.
+    #[schemars(extend("default" = 8192))]
+    pub average_chunk_size: Option<usize>,
+}
+
+pub async fn chunk_signature(request: ChunkSignatureRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(chunk_signature_blocking(request)).await
+}
+
+async fn chunk_signature_blocking(request: ChunkSignatureRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+
+    let config = ChunkerConfig::new(request.average_chunk_size.unwrap_or(DEFAULT_AVERAGE_CHUNK_SIZE));
+    match chunk_file(path, config) {
+        Ok((chunks, sha256)) => {
+            let size: u64 = chunks.iter().map(|c| c.length as u64).sum();
+            let signature = serde_json::json!({
+                "path": path.display().to_string(),
+                "size": size,
+                "sha256": sha256,
+                "algorithm": "fastcdc-gear64/sha256",
+                "min_chunk_size": config.min_size,
+                "average_chunk_size": config.average_size,
+                "max_chunk_size": config.max_size,
+                "chunks": chunks,
+            });
+            Ok(CallToolResult::text(serde_json::to_string_pretty(&signature).unwrap()))
+        }
+        Err(e) => Ok(CallToolResult::error(format!("Error computing chunk signature: {}", e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct FindDuplicatesRequest {
+    /// Directory to scan
+    pub path: String,
+    /// Ignore files smaller than this many bytes. Defaults to 1, which skips empty files.
+    #[schemars(extend("default" = 1))]
+    pub min_size: Option<u64>,
+    /// Skip files excluded by .gitignore, .ignore and git excludes. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// Include hidden files and directories. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    /// Stop scanning after this many files. Defaults to 100000.
+    #[schemars(extend("default" = 100000))]
+    pub max_files: Option<usize>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+pub async fn find_duplicates(request: FindDuplicatesRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(find_duplicates_blocking(request)).await
+}
+
+async fn find_duplicates_blocking(request: FindDuplicatesRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if !path.is_dir() {
+        return Ok(CallToolResult::error(format!("Not a directory: {}", path.display())));
+    }
+
+    let min_size = request.min_size.unwrap_or(1);
+    let max_files = request.max_files.unwrap_or(DEFAULT_MAX_FILES).max(1);
+    let respect_gitignore = request.respect_gitignore.unwrap_or(true);
+    let rules = IgnoreRules::new(respect_gitignore, request.include_hidden.unwrap_or(false)).with_overrides(&request.ignore);
+    let walker = match rules {
+        Ok(rules) => rules.walker(path).build(),
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    // Only files sharing a size can be duplicates, so group by size before hashing anything
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut scanned = 0;
+    let mut truncated = false;
+    for entry in walker.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() || metadata.len() < min_size {
+            continue;
+        }
+        if scanned >= max_files {
+            truncated = true;
+            break;
+        }
+        scanned += 1;
+        by_size.entry(metadata.len()).or_default().push(entry.into_path());
+    }
+
+    let mut groups: Vec<(u64, String, Vec<PathBuf>)> = Vec::new();
+    let mut hashed = 0;
+    let mut unreadable = 0;
+    for (size, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            match hash_file(&path) {
+                Ok(hash) => {
+                    hashed += 1;
+                    by_hash.entry(hash).or_default().push(path);
+                }
+                Err(_) => unreadable += 1,
+            }
+        }
+        groups.extend(
+            by_hash
+                .into_iter()
+                .filter(|(_, paths)| paths.len() > 1)
+                .map(|(hash, mut paths)| {
+                    paths.sort();
+                    (size, hash, paths)
+                }),
+        );
+    }
+    // Largest savings first
+    groups.sort_by(|a, b| {
+        let wasted = |g: &(u64, String, Vec<PathBuf>)| g.0 * (g.2.len() as u64 - 1);
+        wasted(b).cmp(&wasted(a)).then_with(|| a.2.cmp(&b.2))
+    });
+
+    let wasted_bytes: u64 = groups.iter().map(|(size, _, paths)| size * (paths.len() as u64 - 1)).sum();
+    let summary = serde_json::json!({
+        "path": path.display().to_string(),
+        "files_scanned": scanned,
+        "files_hashed": hashed,
+        "unreadable": unreadable,
+        "truncated": truncated,
+        "duplicate_sets": groups.len(),
+        "wasted_bytes": wasted_bytes,
+        "groups": groups
+            .iter()
+            .map(|(size, hash, paths)| serde_json::json!({
+                "size": size,
+                "sha256": hash,
+                "paths": paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>(),
+    });
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&summary).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_insertion_keeps_most_chunks() {
+        let (_env, temp_dir, _) = test_env().await;
+        // Deterministic pseudo-random content
+        let mut state: u32 = 12345;
+        let data: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let original = temp_dir.path().join("original.bin");
+        fs::write(&original, &data).unwrap();
+        let mut edited_data = b"a small insertion".to_vec();
+        edited_data.extend_from_slice(&data);
+        let edited = temp_dir.path().join("edited.bin");
+        fs::write(&edited, &edited_data).unwrap();
+
+        let config = ChunkerConfig::new(4096);
+        let (before, _) = chunk_file(&original, config).unwrap();
+        let (after, _) = chunk_file(&edited, config).unwrap();
+
+        assert_eq!(before.iter().map(|c| c.length).sum::<usize>(), data.len());
+        assert!(before.iter().all(|c| c.length <= config.max_size));
+        let unchanged = after
+            .iter()
+            .filter(|c| before.iter().any(|b| b.sha256 == c.sha256))
+            .count();
+        assert!(unchanged + 2 >= before.len(), "{} of {} chunks survived", unchanged, before.len());
+    }
+
+    #[tokio::test]
+    async fn test_find_duplicates() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let root = temp_dir.path().join("photos");
+        fs::create_dir_all(root.join("backup")).unwrap();
+        fs::write(root.join("a.jpg"), "same bytes").unwrap();
+        fs::write(root.join("backup/a copy.jpg"), "same bytes").unwrap();
+        // Same size, different content
+        fs::write(root.join("b.jpg"), "diff bytes").unwrap();
+        fs::write(root.join("empty1"), "").unwrap();
+        fs::write(root.join("empty2"), "").unwrap();
+
+        let request = FindDuplicatesRequest {
+            path: root.to_str().unwrap().to_string(),
+            min_size: None,
+            respect_gitignore: None,
+            include_hidden: None,
+            max_files: None,
+            ignore: Default::default(),
+        };
+        let result = find_duplicates(request).await.unwrap();
+        assert!(!result.is_error);
+        let summary = result_json(&result);
+        assert_eq!(summary["duplicate_sets"], 1);
+        assert_eq!(summary["wasted_bytes"], 10);
+        let paths = summary["groups"][0]["paths"].as_array().unwrap();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].as_str().unwrap().ends_with("a.jpg"));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::mcp::budget;
+use crate::mcp::config;
+use crate::mcp::timeouts;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use ignore::Walk;
+use ignore::WalkBuilder;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+/// Left out of every traversal unless the server or the call says otherwise
+pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[".git", "node_modules", "target"];
+
+/// `MCP_RS_FILESYSTEM_IGNORE_PATTERNS`, set by `--ignore-patterns`: patterns in
+/// .gitignore syntax, separated by commas. Empty turns them all off.
+pub fn server_patterns() -> Vec<String> {
+    match config::var("MCP_RS_FILESYSTEM_IGNORE_PATTERNS") {
+        Ok(patterns) => patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => DEFAULT_IGNORE_PATTERNS.iter().map(|pattern| pattern.to_string()).collect(),
+    }
+}
+
+/// Check that every pattern parses
+pub fn check_patterns(patterns: &[String]) -> Result<(), String> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| format!("Invalid ignore pattern {:?}: {}", pattern, e))?;
+    }
+    Ok(())
+}
+
+/// Whether `matcher` leaves out `path`, which must be below its root
+pub fn is_ignored(matcher: &Gitignore, path: &Path, is_dir: bool) -> bool {
+    matcher.matched_path_or_any_parents(path, is_dir).is_ignore()
+}
+
+/// Changes a call makes to the server's ignore rules. Flattened into the
+/// requests of the traversal tools.
+#[derive(Default, Deserialize, Serialize, JsonSchema)]
+pub struct IgnoreOverrides {
+    /// Leave out paths matching the server's ignore patterns, by default .git, node_modules and target. Defaults
+    /// to true.
+    #[schemars(extend("default" = true))]
+    pub default_ignores: Option<bool>,
+    /// More patterns to leave out, in .gitignore syntax. A pattern starting with ! brings back paths an earlier
+    /// pattern left out.
+    pub ignore_patterns: Option<Vec<String>>,
+}
+
+impl IgnoreOverrides {
+    /// Whether the call keeps the server's rules as they are
+    pub fn is_default(&self) -> bool {
+        self.default_ignores != Some(false) && self.ignore_patterns.as_ref().is_none_or(Vec::is_empty)
+    }
+}
+
+/// What a traversal leaves out: ignore files, hidden entries and the ignore
+/// patterns of the server and the call
+pub struct IgnoreRules {
+    respect_gitignore: bool,
+    include_hidden: bool,
+    patterns: Vec<String>,
+}
+
+impl IgnoreRules {
+    /// The server's rules
+    pub fn new(respect_gitignore: bool, include_hidden: bool) -> Self {
+        IgnoreRules {
+            respect_gitignore,
+            include_hidden,
+            patterns: server_patterns(),
+        }
+    }
+
+    /// The server's rules with the changes of a call. Fails on patterns of
+    /// the call that do not parse.
+    pub fn with_overrides(mut self, overrides: &IgnoreOverrides) -> Result<Self, String> {
+        let added = overrides.ignore_patterns.as_deref().unwrap_or_default();
+        check_patterns(added)?;
+        if overrides.default_ignores == Some(false) {
+            self.patterns.clear();
+        }
+        self.patterns.extend(added.iter().cloned());
+        Ok(self)
+    }
+
+    /// Matcher of the ignore patterns, relative to the directory `root`. Ignore
+    /// files and hidden entries are left to [`IgnoreRules::walker`]. Server
+    /// patterns were checked at startup, so one that does not parse is skipped.
+    pub fn matcher(&self, root: &Path) -> Gitignore {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in &self.patterns {
+            let _ = builder.add_line(None, pattern);
+        }
+        builder.build().unwrap_or_else(|_| Gitignore::empty())
+    }
+
+    /// Walker of the tree below `root` applying all the rules. Ignore files
+    /// are honoured even when the directory is not inside a git repository.
+    /// The walk ends early once the call it runs for is cancelled, or once the
+    /// files it reached go over the files per request budget.
+    pub fn walker(&self, root: &Path) -> WalkBuilder {
+        let matcher = self.matcher(root);
+        let cancelled = timeouts::cancel_flag();
+        let charged = budget::charges_walks();
+        let respect_gitignore = self.respect_gitignore;
+        let mut builder = WalkBuilder::new(root);
+        builder
+            .hidden(!self.include_hidden)
+            .git_ignore(respect_gitignore)
+            .git_global(respect_gitignore)
+            .git_exclude(respect_gitignore)
+            .ignore(respect_gitignore)
+            .parents(respect_gitignore)
+            .require_git(false);
+        if !matcher.is_empty() || cancelled.is_some() || charged {
+            builder.filter_entry(move |entry| {
+                if cancelled.as_ref().is_some_and(|cancelled| cancelled.load(Ordering::Relaxed)) {
+                    return false;
+                }
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                // The root itself is what was asked for
+                let kept = entry.depth() == 0 || matcher.is_empty() || !is_ignored(&matcher, entry.path(), is_dir);
+                // Going over the budget fails the call, so the rest of the tree is not worth walking
+                kept && !(charged && !is_dir && budget::touch(entry.path()).is_err())
+            });
+        }
+        builder
+    }
+}
+
+/// Directory walker with the server's rules, for scans without overrides of their own
+pub fn build_walker(path: &Path, respect_gitignore: bool, include_hidden: bool) -> Walk {
+    walker_builder(path, respect_gitignore, include_hidden).build()
+}
+
+/// Configuration behind [`build_walker`], for callers that need to adjust it
+pub fn walker_builder(path: &Path, respect_gitignore: bool, include_hidden: bool) -> WalkBuilder {
+    IgnoreRules::new(respect_gitignore, include_hidden).walker(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn walk(rules: &IgnoreRules, root: &Path) -> Vec<String> {
+        let mut paths: Vec<String> = rules
+            .walker(root)
+            .build()
+            .flatten()
+            .filter(|entry| entry.depth() > 0)
+            .map(|entry| entry.path().strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join("web/node_modules/left-pad")).unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("target/debug/app"), "").unwrap();
+        fs::write(root.join("web/node_modules/left-pad/index.js"), "").unwrap();
+        fs::write(root.join("web/app.log"), "").unwrap();
+
+        let rules = IgnoreRules {
+            respect_gitignore: true,
+            include_hidden: false,
+            patterns: DEFAULT_IGNORE_PATTERNS.iter().map(|pattern| pattern.to_string()).collect(),
+        };
+        assert_eq!(walk(&rules, root), ["src", "src/main.rs", "web", "web/app.log"]);
+
+        // A call adds patterns, and brings back what the server leaves out
+        let overrides = IgnoreOverrides {
+            default_ignores: None,
+            ignore_patterns: Some(vec!["*.log".to_string(), "!target".to_string()]),
+        };
+        let rules = rules.with_overrides(&overrides).unwrap();
+        assert_eq!(walk(&rules, root), ["src", "src/main.rs", "target", "target/debug", "target/debug/app", "web"]);
+
+        let overrides = IgnoreOverrides {
+            default_ignores: Some(false),
+            ignore_patterns: None,
+        };
+        let rules = rules.with_overrides(&overrides).unwrap();
+        assert_eq!(walk(&rules, root).len(), 10);
+
+        let overrides = IgnoreOverrides {
+            default_ignores: None,
+            ignore_patterns: Some(vec!["src/[z-a]".to_string()]),
+        };
+        let error = IgnoreRules::new(true, false).with_overrides(&overrides).err().unwrap();
+        assert!(error.starts_with("Invalid ignore pattern \"src/[z-a]\""));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::mcp::limits::max_read_bytes;
+use crate::mcp::mime;
+use crate::mcp::types::*;
+use crate::mcp::vfs;
+use base64::Engine;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Images a read returns as image content, for clients that can look at them
+const IMAGE_FORMATS: &[(&str, ImageFormat)] = &[
+    ("image/png", ImageFormat::Png),
+    ("image/jpeg", ImageFormat::Jpeg),
+    ("image/webp", ImageFormat::WebP),
+];
+
+/// The image type of a file, if a read should return it as an image
+pub fn image_type(path: &Path) -> Option<&'static str> {
+    let head = vfs::backend().read_prefix(path, mime::SNIFF_BYTES).ok()?;
+    let mime_type = mime::detect(path, &head);
+    IMAGE_FORMATS.iter().any(|(name, _)| *name == mime_type).then_some(mime_type)
+}
+
+/// Read an image as image content, followed by a line giving its size. With
+/// `max_dimension` it is scaled down, keeping its aspect ratio, so that
+/// neither side is longer; smaller images are returned as they are.
+pub fn read_image(path: &Path, mime_type: &str, max_dimension: Option<u32>) -> Result<Vec<CallToolResultContent>, String> {
+    let format = IMAGE_FORMATS
+        .iter()
+        .find(|(name, _)| *name == mime_type)
+        .map(|(_, format)| *format)
+        .ok_or_else(|| format!("Not a supported image type: {}", mime_type))?;
+    let mut bytes = vfs::current().read(path)?;
+
+    let mut description = mime_type.to_string();
+    if let Some(max_dimension) = max_dimension {
+        if max_dimension == 0 {
+            return Err("max_dimension must be at least 1".to_string());
+        }
+        let image = image::load_from_memory_with_format(&bytes, format)
+            .map_err(|e| format!("Error decoding {}: {}", path.display(), e))?;
+        description = format!("{}, {}x{}", mime_type, image.width(), image.height());
+        if image.width().max(image.height()) > max_dimension {
+            let scaled = image.resize(max_dimension, max_dimension, FilterType::Triangle);
+            let mut encoded = Cursor::new(Vec::new());
+            scaled
+                .write_to(&mut encoded, format)
+                .map_err(|e| format!("Error encoding {}: {}", path.display(), e))?;
+            bytes = encoded.into_inner();
+            description = format!("{}, scaled to {}x{}", description, scaled.width(), scaled.height());
+        }
+    } else if let Some((width, height)) = mime::image_dimensions(mime_type, &bytes) {
+        description = format!("{}, {}x{}", mime_type, width, height);
+    }
+
+    let limit = max_read_bytes();
+    if bytes.len() > limit {
+        return Err(format!(
+            "Image is {} bytes, more than the read limit of {} bytes. Pass max_dimension to scale it down.",
+            bytes.len(),
+            limit
+        ));
+    }
+    Ok(vec![
+        CallToolResultContent::Image {
+            data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+            mime_type: mime_type.to_string(),
+        },
+        CallToolResultContent::Text { text: description },
+    ])
+}
@@ -0,0 +1,535 @@
+use crate::mcp::config;
+use crate::mcp::ignore_rules::build_walker;
+use crate::mcp::ignore_rules::server_patterns;
+use crate::mcp::ignore_rules::walker_builder;
+use crate::mcp::metrics;
+use crate::mcp::state;
+use crate::mcp::types::*;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::utilities::shutting_down;
+use crate::mcp::watch::watch_tree;
+use chrono::DateTime;
+use chrono::Local;
+use ignore::DirEntry;
+use notify::Event;
+use notify::EventKind;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Files whose change can include or exclude anything in their directory
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+/// Changes usually come in bursts; wait this long to handle them together
+const BATCH_DELAY: Duration = Duration::from_millis(200);
+/// Bump this whenever the layout of a saved index changes
+const SAVED_INDEX_VERSION: u32 = 1;
+
+/// What the index knows about a path
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Entry {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Index of one allowed directory. It holds what the scanning tools see with
+/// their default options: ignore files honoured, hidden entries skipped.
+struct RootIndex {
+    root: PathBuf,
+    /// Ordered so that everything below a directory follows it directly
+    entries: BTreeMap<PathBuf, Entry>,
+    /// Set once the first scan is done and the tree is being watched
+    ready: bool,
+    /// Whether the first scan started from the index a previous run saved
+    restored: bool,
+    backend: Option<&'static str>,
+    build_ms: Option<u128>,
+    updated: Option<DateTime<Local>>,
+    error: Option<String>,
+    /// Set when the directory is no longer allowed, to stop keeping it current
+    dropped: Arc<AtomicBool>,
+}
+
+static INDEX: Mutex<Vec<RootIndex>> = Mutex::new(Vec::new());
+
+/// The index is built when the server was started with `--index`, which sets
+/// this variable
+pub fn enabled() -> bool {
+    config::var("MCP_RS_FILESYSTEM_INDEX")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Start indexing every allowed directory in the background. Called again
+/// after a reload changed them, it indexes the directories not indexed yet and
+/// drops those no longer allowed.
+pub fn init() {
+    if !enabled() {
+        return;
+    }
+    let roots: Vec<PathBuf> = get_allowed_directories()
+        .iter()
+        .map(|dir| canonical_path(Path::new(dir)))
+        .filter(|root| root.is_dir())
+        .collect();
+    let mut index = INDEX.lock().unwrap();
+    index.retain(|root_index| {
+        let allowed = roots.contains(&root_index.root);
+        if !allowed {
+            root_index.dropped.store(true, Ordering::SeqCst);
+        }
+        allowed
+    });
+    for root in roots {
+        if index.iter().any(|root_index| root_index.root == root) {
+            continue;
+        }
+        let dropped = Arc::new(AtomicBool::new(false));
+        index.push(RootIndex {
+            root: root.clone(),
+            entries: BTreeMap::new(),
+            ready: false,
+            restored: false,
+            backend: None,
+            build_ms: None,
+            updated: None,
+            error: None,
+            dropped: Arc::clone(&dropped),
+        });
+        std::thread::spawn(move || maintain(root, dropped));
+    }
+}
+
+fn with_root<T>(root: &Path, f: impl FnOnce(&mut RootIndex) -> T) -> Option<T> {
+    INDEX.lock().unwrap().iter_mut().find(|index| index.root == root).map(f)
+}
+
+fn entry_of(dir_entry: &DirEntry) -> Option<(PathBuf, Entry)> {
+    let metadata = dir_entry.metadata().ok()?;
+    Some((
+        dir_entry.path().to_path_buf(),
+        Entry {
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        },
+    ))
+}
+
+/// Build the index of a root, then keep it current from change notifications
+/// until the server shuts down or the root is dropped
+fn maintain(root: PathBuf, dropped: Arc<AtomicBool>) {
+    let (sender, receiver) = mpsc::channel::<PathBuf>();
+    let handler = move |result: notify::Result<Event>| {
+        if let Ok(event) = result {
+            if !matches!(event.kind, EventKind::Access(_)) {
+                for path in event.paths {
+                    let _ = sender.send(path);
+                }
+            }
+        }
+    };
+    // Watch before scanning, so changes made during the scan are queued rather than lost
+    let (_watcher, polling) = match watch_tree(&root, handler) {
+        Ok(watching) => watching,
+        Err(e) => {
+            // An index nobody keeps current would return stale results, so it is not used at all
+            with_root(&root, |index| index.error = Some(format!("Failed to watch for changes: {}", e)));
+            return;
+        }
+    };
+
+    // Start from the index of a previous run when there is one, rescanning only what changed since
+    let started = Instant::now();
+    let restored = restore(&root);
+    let reused = restored.is_some();
+    let (entries, changed) = restored.unwrap_or_else(|| (scan_subtree(&root, &root).into_iter().collect(), Vec::new()));
+    with_root(&root, |index| index.entries = entries);
+    refresh(&root, changed);
+    with_root(&root, |index| {
+        index.ready = true;
+        index.restored = reused;
+        index.backend = Some(if polling { "poll" } else { "native" });
+        index.build_ms = Some(started.elapsed().as_millis());
+        index.updated = Some(Local::now());
+    });
+    save(&root);
+
+    while !shutting_down() && !dropped.load(Ordering::SeqCst) {
+        let first = match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(path) => path,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        std::thread::sleep(BATCH_DELAY);
+        let changed: Vec<PathBuf> = std::iter::once(first).chain(receiver.try_iter()).collect();
+        refresh(&root, changed);
+    }
+}
+
+/// The index of a root as saved in the state directory, for the next run to start from
+#[derive(Deserialize, Serialize)]
+struct SavedIndex {
+    version: u32,
+    root: PathBuf,
+    /// Ignore patterns of the server when the entries were scanned
+    patterns: Vec<String>,
+    /// Modification time of the root itself
+    root_modified: Option<SystemTime>,
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+/// Name of the state document holding the saved index of a root
+fn document_name(root: &Path) -> String {
+    let digest = Sha256::digest(root.to_string_lossy().as_bytes());
+    let hex: String = digest.iter().take(8).map(|byte| format!("{:02x}", byte)).collect();
+    format!("index-{}", hex)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::symlink_metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Save the index of a root once its first scan is done
+fn save(root: &Path) {
+    let saved = with_root(root, |index| {
+        index.ready.then(|| SavedIndex {
+            version: SAVED_INDEX_VERSION,
+            root: root.to_path_buf(),
+            patterns: server_patterns(),
+            root_modified: modified(root),
+            entries: index.entries.clone(),
+        })
+    });
+    if let Some(Some(saved)) = saved {
+        let _ = state::save_document(&document_name(root), &saved);
+    }
+}
+
+/// Save the index of every root, for the next run to start from. Called on shutdown.
+pub fn save_all() {
+    let roots: Vec<PathBuf> = INDEX.lock().unwrap().iter().map(|index| index.root.clone()).collect();
+    for root in roots {
+        save(&root);
+    }
+}
+
+/// The entries a previous run saved for `root`, with the paths that changed
+/// since and have to be rescanned. `None` when nothing was saved for this
+/// root, or it was saved in another layout or with other ignore patterns.
+fn restore(root: &Path) -> Option<(BTreeMap<PathBuf, Entry>, Vec<PathBuf>)> {
+    let persisted = state::load_document::<SavedIndex>(&document_name(root))?;
+    let saved_at = UNIX_EPOCH + Duration::from_secs(persisted.saved_at);
+    let mut saved = persisted.data;
+    if saved.version != SAVED_INDEX_VERSION || saved.root != root || saved.patterns != server_patterns() {
+        return None;
+    }
+    let changed = changed_since(root, saved.root_modified, &mut saved.entries, saved_at);
+    Some((saved.entries, changed))
+}
+
+/// Paths below `root` that changed since `saved_at`, when `entries` were
+/// saved: entries whose type, size or modification time differ, entries that
+/// are gone, paths new in a directory whose modification time differs, and
+/// ignore files written since. The modification times of directories are
+/// brought up to date on the way.
+fn changed_since(
+    root: &Path,
+    root_modified: Option<SystemTime>,
+    entries: &mut BTreeMap<PathBuf, Entry>,
+    saved_at: SystemTime,
+) -> Vec<PathBuf> {
+    let mut changed = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    let mut relisted = Vec::new();
+    if modified(root) != root_modified {
+        relisted.push(root.to_path_buf());
+    }
+    for (path, entry) in entries.iter_mut() {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if entry.is_dir && metadata.is_dir() => {
+                dirs.push(path.clone());
+                let modified = metadata.modified().ok();
+                if modified != entry.modified {
+                    entry.size = metadata.len();
+                    entry.modified = modified;
+                    relisted.push(path.clone());
+                }
+            }
+            Ok(metadata)
+                if !entry.is_dir
+                    && !metadata.is_dir()
+                    && metadata.len() == entry.size
+                    && metadata.modified().ok() == entry.modified => {}
+            _ => changed.push(path.clone()),
+        }
+    }
+    // Paths created while no server was running show in the directory that holds them
+    for dir in relisted {
+        let Ok(children) = fs::read_dir(&dir) else {
+            continue;
+        };
+        changed.extend(children.flatten().map(|child| child.path()).filter(|path| !entries.contains_key(path)));
+    }
+    // Ignore files are hidden, so never indexed themselves
+    for dir in dirs {
+        for ignore_file in IGNORE_FILES {
+            let path = dir.join(ignore_file);
+            if modified(&path).is_some_and(|modified| modified >= saved_at) {
+                changed.push(path);
+            }
+        }
+    }
+    changed
+}
+
+/// Rescan the changed paths of a root and replace what the index holds for them
+fn refresh(root: &Path, changed: Vec<PathBuf>) {
+    let mut targets: Vec<PathBuf> = changed
+        .into_iter()
+        .filter(|path| path.starts_with(root))
+        .map(|path| {
+            // A changed ignore file affects its whole directory
+            let is_ignore_file = path
+                .file_name()
+                .is_some_and(|name| IGNORE_FILES.iter().any(|ignore_file| name == *ignore_file));
+            match path.parent() {
+                Some(parent) if is_ignore_file => parent.to_path_buf(),
+                _ => path,
+            }
+        })
+        .collect();
+    targets.sort();
+    targets.dedup();
+    // Rescanning a directory covers everything below it
+    let targets: Vec<&PathBuf> = targets
+        .iter()
+        .filter(|target| !targets.iter().any(|other| other != *target && target.starts_with(other)))
+        .collect();
+
+    for target in targets {
+        // Nothing below a directory left out of the index, as ignored or hidden, is indexed either
+        let parent_indexed = with_root(root, |index| {
+            target == root
+                || target.parent().is_some_and(|parent| {
+                    parent == root || index.entries.get(parent).is_some_and(|entry| entry.is_dir)
+                })
+        })
+        .unwrap_or(false);
+        let fresh = if parent_indexed { scan_subtree(root, target) } else { Vec::new() };
+        with_root(root, |index| {
+            let stale: Vec<PathBuf> = index
+                .entries
+                .range(target.clone()..)
+                .take_while(|(path, _)| path.starts_with(target))
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in stale {
+                index.entries.remove(&path);
+            }
+            index.entries.extend(fresh);
+            index.updated = Some(Local::now());
+        });
+    }
+}
+
+/// Entries at and below `target` as a scan of the whole root would find them
+fn scan_subtree(root: &Path, target: &Path) -> Vec<(PathBuf, Entry)> {
+    if target == root {
+        return build_walker(root, true, false)
+            .flatten()
+            .filter(|dir_entry| dir_entry.depth() > 0)
+            .filter_map(|dir_entry| entry_of(&dir_entry))
+            .collect();
+    }
+    let Some(parent) = target.parent() else {
+        return Vec::new();
+    };
+    // Walk from the parent so the ignore rules apply to the target itself too
+    let wanted = target.to_path_buf();
+    walker_builder(parent, true, false)
+        .filter_entry(move |dir_entry| dir_entry.depth() == 0 || dir_entry.path().starts_with(&wanted))
+        .build()
+        .flatten()
+        .filter(|dir_entry| dir_entry.depth() > 0)
+        .filter_map(|dir_entry| entry_of(&dir_entry))
+        .collect()
+}
+
+/// Visit the indexed entries below `dir`. Returns false, without visiting
+/// anything, when the index cannot answer for `dir` and the caller has to walk
+/// the tree itself.
+pub fn for_each_entry(dir: &Path, visit: impl FnMut(&Path, &Entry)) -> bool {
+    let hit = visit_indexed(dir, visit);
+    metrics::record_index_lookup(hit);
+    hit
+}
+
+fn visit_indexed(dir: &Path, mut visit: impl FnMut(&Path, &Entry)) -> bool {
+    let dir = canonical_path(dir);
+    let index = INDEX.lock().unwrap();
+    let Some(root_index) = index.iter().find(|index| index.ready && dir.starts_with(&index.root)) else {
+        return false;
+    };
+    if dir != root_index.root && !root_index.entries.get(&dir).is_some_and(|entry| entry.is_dir) {
+        return false;
+    }
+    for (path, entry) in root_index
+        .entries
+        .range(dir.clone()..)
+        .take_while(|(path, _)| path.starts_with(&dir))
+    {
+        if *path != dir {
+            visit(path, entry);
+        }
+    }
+    true
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct IndexStatusRequest {}
+
+pub async fn index_status(_request: IndexStatusRequest) -> HandlerResult<CallToolResult> {
+    let roots: Vec<_> = INDEX
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|index| {
+            json!({
+                "root": index.root.display().to_string(),
+                "ready": index.ready,
+                "restored": index.restored,
+                "entries": index.entries.len(),
+                "backend": index.backend,
+                "build_ms": index.build_ms,
+                "updated": index.updated.map(|time| time.to_rfc3339()),
+                "error": index.error,
+            })
+        })
+        .collect();
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&json!({ "enabled": enabled(), "roots": roots })).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::find::find_file;
+    use crate::mcp::find::FindFileRequest;
+    use crate::mcp::testing::lock_env;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::set_env;
+    use crate::mcp::testing::test_env;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_changed_since() {
+        let _env = lock_env().await;
+        let temp_dir = TempDir::new().unwrap();
+        let root = canonical_path(temp_dir.path());
+        fs::create_dir(root.join("nested")).unwrap();
+        for name in ["kept.txt", "edited.txt", "removed.txt", "nested/inner.txt"] {
+            fs::write(root.join(name), "before").unwrap();
+        }
+        let mut entries: BTreeMap<PathBuf, Entry> = scan_subtree(&root, &root).into_iter().collect();
+        let root_modified = modified(&root);
+        let saved_at = SystemTime::now();
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Nothing changed, nothing to rescan
+        assert!(changed_since(&root, root_modified, &mut entries, saved_at).is_empty());
+
+        fs::write(root.join("edited.txt"), "after the restart").unwrap();
+        fs::remove_file(root.join("removed.txt")).unwrap();
+        fs::write(root.join("nested/added.txt"), "").unwrap();
+        fs::write(root.join("nested/.gitignore"), "*.log\n").unwrap();
+        let mut changed = changed_since(&root, root_modified, &mut entries, saved_at);
+        changed.sort();
+        let mut expected = vec![
+            root.join("edited.txt"),
+            root.join("removed.txt"),
+            root.join("nested/.gitignore"),
+            root.join("nested/added.txt"),
+        ];
+        expected.sort();
+        // The new ignore file is seen both as a new entry and as one written since
+        changed.dedup();
+        assert_eq!(changed, expected);
+    }
+
+    #[tokio::test]
+    async fn test_index_follows_changes() {
+        let (_env, temp_dir, _) = test_env().await;
+        let _index = set_env("MCP_RS_FILESYSTEM_INDEX", "1");
+
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        fs::write(root.join("first.txt"), "").unwrap();
+        init();
+
+        let indexed_names = || {
+            let mut names = Vec::new();
+            let ready = for_each_entry(&root, |path, _| {
+                names.push(path.file_name().unwrap().to_string_lossy().into_owned())
+            });
+            ready.then_some(names)
+        };
+        let wait_for = |condition: &dyn Fn(&[String]) -> bool| {
+            for _ in 0..100 {
+                if indexed_names().is_some_and(|names| condition(&names)) {
+                    return true;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            false
+        };
+        assert!(wait_for(&|names| names.iter().any(|n| n == "first.txt")));
+
+        fs::create_dir(root.join("nested")).unwrap();
+        fs::write(root.join("nested/second.txt"), "").unwrap();
+        fs::write(root.join("debug.log"), "").unwrap();
+        fs::remove_file(root.join("first.txt")).unwrap();
+        assert!(wait_for(&|names| {
+            names.iter().any(|n| n == "second.txt") && !names.iter().any(|n| n == "first.txt")
+        }));
+        assert!(!indexed_names().unwrap().iter().any(|n| n == "debug.log"));
+
+        let result = find_file(FindFileRequest {
+            query: "second".to_string(),
+            path: None,
+            max_results: None,
+            include_directories: None,
+            respect_gitignore: None,
+            include_hidden: None,
+            case_sensitive: None,
+            ignore_accents: None,
+            normalize_unicode: None,
+            max_entries: None,
+            max_duration_ms: None,
+            ignore: Default::default(),
+        })
+        .await
+        .unwrap();
+        let found = result_json(&result);
+        assert_eq!(found["indexed"], true);
+        assert!(found["matches"][0]["path"].as_str().unwrap().ends_with("second.txt"));
+    }
+}
@@ -0,0 +1,69 @@
+use crate::mcp::config;
+use crate::mcp::types::*;
+use serde_json::json;
+
+/// Largest response `read_file` returns in one call, unless configured otherwise
+const DEFAULT_MAX_READ_BYTES: usize = 1024 * 1024;
+/// Most entries a listing returns in one call, unless configured otherwise
+const DEFAULT_MAX_RESULT_ENTRIES: usize = 1000;
+
+fn configured(name: &str, default: usize) -> usize {
+    config::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|limit| *limit > 0)
+        .unwrap_or(default)
+}
+
+/// `MCP_RS_FILESYSTEM_MAX_READ_BYTES`, set by `--max-read-bytes`
+pub fn max_read_bytes() -> usize {
+    configured("MCP_RS_FILESYSTEM_MAX_READ_BYTES", DEFAULT_MAX_READ_BYTES)
+}
+
+/// `MCP_RS_FILESYSTEM_MAX_RESULT_ENTRIES`, set by `--max-result-entries`
+pub fn max_result_entries() -> usize {
+    configured("MCP_RS_FILESYSTEM_MAX_RESULT_ENTRIES", DEFAULT_MAX_RESULT_ENTRIES)
+}
+
+/// The configured limit, lowered to what the request asked for
+pub fn effective_limit(configured: usize, requested: Option<usize>) -> usize {
+    requested.map_or(configured, |requested| requested.min(configured)).max(1)
+}
+
+/// Largest char boundary of `text` at or below `index`
+pub fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Byte range of `text` to return, starting at `offset` and at most `limit`
+/// bytes long, never splitting a character
+pub fn text_window(text: &str, offset: usize, limit: usize) -> (usize, usize) {
+    let start = floor_char_boundary(text, offset);
+    let mut end = floor_char_boundary(text, start.saturating_add(limit));
+    if end == start && start < text.len() {
+        // A single character longer than the limit still has to make progress
+        end = start + text[start..].chars().next().map_or(0, char::len_utf8);
+    }
+    (start, end)
+}
+
+/// Content item describing a partial result. `unit` names what the offsets
+/// count, e.g. `bytes` or `entries`.
+pub fn truncation_metadata(unit: &str, total: usize, offset: usize, returned: usize) -> CallToolResultContent {
+    let end = offset + returned;
+    let total_key = if unit == "bytes" { "total_size".to_string() } else { format!("total_{}", unit) };
+    let mut metadata = json!({ "truncated": end < total });
+    metadata[total_key] = json!(total);
+    metadata["offset"] = json!(offset);
+    metadata[format!("returned_{}", unit)] = json!(returned);
+    if end < total {
+        metadata["next_offset"] = json!(end);
+    }
+    CallToolResultContent::Text {
+        text: serde_json::to_string_pretty(&metadata).unwrap(),
+    }
+}
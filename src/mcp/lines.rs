@@ -0,0 +1,317 @@
+use crate::mcp::dry_run;
+use crate::mcp::encoding::apply_line_ending;
+use crate::mcp::encoding::read_text_file;
+use crate::mcp::encoding::write_text_file;
+use crate::mcp::encoding::LineEnding;
+use crate::mcp::limits::max_read_bytes;
+use crate::mcp::shadow;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::validate_write_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::path::Path;
+
+/// Lines of a text, each with its own line break so the file's style,
+/// mixed or not, survives an edit
+pub fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// `content` as whole lines in the given style: line breaks converted and a
+/// final one added when missing. Empty content is no lines at all.
+fn as_lines(content: &str, line_ending: LineEnding) -> String {
+    if content.is_empty() {
+        return String::new();
+    }
+    let mut content = apply_line_ending(content, line_ending);
+    if !content.ends_with('\n') {
+        content.push_str(if line_ending == LineEnding::CrLf { "\r\n" } else { "\n" });
+    }
+    content
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReadLinesRequest {
+    /// Path of the file
+    pub path: String,
+    /// First line to return, counting from 1
+    pub start_line: usize,
+    /// Last line to return, inclusive. Defaults to the end of the file.
+    pub end_line: Option<usize>,
+    /// Prefix each line with its line number. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub line_numbers: Option<bool>,
+}
+
+pub async fn read_lines(request: ReadLinesRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let decoded = match read_text_file(path, None) {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading file: {}", e))),
+    };
+    let lines = split_lines(&decoded.text);
+    let total = lines.len();
+    let end_line = request.end_line.unwrap_or(total).min(total);
+    if request.start_line == 0 || request.start_line > end_line {
+        return Ok(CallToolResult::error(format!(
+            "Line range {}-{} is not within the file, which has {} lines",
+            request.start_line,
+            request.end_line.map_or("end".to_string(), |end| end.to_string()),
+            total
+        )));
+    }
+
+    // Whole lines only, as many as fit in the read limit, but always at least one
+    let budget = max_read_bytes();
+    let mut text = String::new();
+    let mut last = request.start_line - 1;
+    for (index, line) in lines.iter().enumerate().take(end_line).skip(request.start_line - 1) {
+        let line = if request.line_numbers.unwrap_or(false) {
+            format!("{:>6}\t{}", index + 1, line)
+        } else {
+            line.to_string()
+        };
+        if !text.is_empty() && text.len() + line.len() > budget {
+            break;
+        }
+        text.push_str(&line);
+        last = index + 1;
+    }
+
+    let mut range = json!({
+        "start_line": request.start_line,
+        "end_line": last,
+        "total_lines": total,
+    });
+    if last < end_line {
+        range["next_line"] = json!(last + 1);
+    }
+    Ok(CallToolResult::content(vec![
+        CallToolResultContent::Text { text },
+        CallToolResultContent::Text {
+            text: serde_json::to_string_pretty(&range).unwrap(),
+        },
+    ]))
+}
+
+/// Replace `remove` lines starting at the 0-based `index` with `content` and
+/// write the file back in its own encoding
+fn splice_lines(
+    path_arg: &str,
+    index: usize,
+    remove: usize,
+    content: &str,
+    done: &str,
+    dry_run: Option<bool>,
+) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(path_arg));
+    if let Err(msg) = validate_write_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let decoded = match read_text_file(path, None) {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading file: {}", e))),
+    };
+    let lines = split_lines(&decoded.text);
+    if index + remove > lines.len() {
+        return Ok(CallToolResult::error(format!(
+            "Line range is not within the file, which has {} lines",
+            lines.len()
+        )));
+    }
+
+    // New lines follow the style the file mostly uses
+    let line_ending = decoded.line_ending.unwrap_or(LineEnding::Lf);
+    let inserted = as_lines(content, line_ending);
+    let mut text = String::with_capacity(decoded.text.len() + inserted.len());
+    for line in &lines[..index] {
+        text.push_str(line);
+    }
+    // Appending after a last line that has no line break gives it one
+    if !inserted.is_empty() && !text.is_empty() && !text.ends_with('\n') {
+        text.push_str(if line_ending == LineEnding::CrLf { "\r\n" } else { "\n" });
+    }
+    text.push_str(&inserted);
+    for line in &lines[index + remove..] {
+        text.push_str(line);
+    }
+
+    if dry_run::requested(dry_run) {
+        return match dry_run::text_change(path, &text, decoded.encoding, None) {
+            Ok(change) => dry_run::result(vec![change]),
+            Err(e) => Ok(CallToolResult::error(format!("Error reading file: {}", e))),
+        };
+    }
+    if let Err(e) = shadow::write(path, |target| write_text_file(target, &text, decoded.encoding, None)) {
+        return Ok(e.into_result("Error writing file"));
+    }
+    Ok(CallToolResult::text(format!(
+        "{} in {}. The file now has {} lines",
+        done,
+        path.display(),
+        split_lines(&text).len()
+    )))
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct InsertLinesRequest {
+    /// Path of the file
+    pub path: String,
+    /// Insert after this line, counting from 1. 0 inserts at the start of the file.
+    pub after_line: usize,
+    /// Lines to insert. A final line break is added if missing.
+    pub content: String,
+    /// Return the diff without changing the file. Always the case when the server runs with --dry-run. Defaults
+    /// to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+pub async fn insert_lines(request: InsertLinesRequest) -> HandlerResult<CallToolResult> {
+    let count = split_lines(&request.content).len();
+    let done = format!("Inserted {} lines after line {}", count, request.after_line);
+    splice_lines(&request.path, request.after_line, 0, &request.content, &done, request.dry_run)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReplaceLinesRequest {
+    /// Path of the file
+    pub path: String,
+    /// First line to replace, counting from 1
+    pub start_line: usize,
+    /// Last line to replace, inclusive
+    pub end_line: usize,
+    /// Replacement lines. Empty content deletes the range.
+    pub content: String,
+    /// Return the diff without changing the file. Always the case when the server runs with --dry-run. Defaults
+    /// to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+pub async fn replace_lines(request: ReplaceLinesRequest) -> HandlerResult<CallToolResult> {
+    if request.start_line == 0 || request.end_line < request.start_line {
+        return Ok(CallToolResult::error(format!("Invalid line range {}-{}", request.start_line, request.end_line)));
+    }
+    let done = format!("Replaced lines {}-{}", request.start_line, request.end_line);
+    let remove = request.end_line - request.start_line + 1;
+    splice_lines(&request.path, request.start_line - 1, remove, &request.content, &done, request.dry_run)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct DeleteLinesRequest {
+    /// Path of the file
+    pub path: String,
+    /// First line to delete, counting from 1
+    pub start_line: usize,
+    /// Last line to delete, inclusive
+    pub end_line: usize,
+    /// Return the diff without changing the file. Always the case when the server runs with --dry-run. Defaults
+    /// to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+pub async fn delete_lines(request: DeleteLinesRequest) -> HandlerResult<CallToolResult> {
+    if request.start_line == 0 || request.end_line < request.start_line {
+        return Ok(CallToolResult::error(format!("Invalid line range {}-{}", request.start_line, request.end_line)));
+    }
+    let done = format!("Deleted lines {}-{}", request.start_line, request.end_line);
+    let remove = request.end_line - request.start_line + 1;
+    splice_lines(&request.path, request.start_line - 1, remove, "", &done, request.dry_run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_text;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_line_tools_preserve_line_endings() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let file = temp_dir.path().join("notes.txt");
+        let path = file.to_string_lossy().into_owned();
+        fs::write(&file, "one\r\ntwo\r\nthree\r\nfour").unwrap();
+
+        let result = read_lines(ReadLinesRequest {
+            path: path.clone(),
+            start_line: 2,
+            end_line: Some(3),
+            line_numbers: Some(true),
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let text = result_text(&result);
+        assert_eq!(text, "     2\ttwo\r\n     3\tthree\r\n");
+        let CallToolResultContent::Text { text } = &result.content[1] else { panic!() };
+        let range: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(range["total_lines"], 4);
+
+        let result = insert_lines(InsertLinesRequest {
+            path: path.clone(),
+            after_line: 1,
+            content: "one and a half\nalmost two".to_string(),
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "one\r\none and a half\r\nalmost two\r\ntwo\r\nthree\r\nfour"
+        );
+
+        let result = replace_lines(ReplaceLinesRequest {
+            path: path.clone(),
+            start_line: 2,
+            end_line: 3,
+            content: "1.5".to_string(),
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let result = delete_lines(DeleteLinesRequest {
+            path: path.clone(),
+            start_line: 4,
+            end_line: 4,
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let result = insert_lines(InsertLinesRequest {
+            path: path.clone(),
+            after_line: 4,
+            content: "five\r\n".to_string(),
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "one\r\n1.5\r\ntwo\r\nfour\r\nfive\r\n");
+
+        let result = delete_lines(DeleteLinesRequest {
+            path,
+            start_line: 5,
+            end_line: 9,
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(result.is_error);
+    }
+}
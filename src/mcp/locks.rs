@@ -0,0 +1,312 @@
+use crate::mcp::session;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::vfs;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::TryLockError;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+const DEFAULT_LEASE_SECONDS: u64 = 300;
+const MAX_LEASE_SECONDS: u64 = 3600;
+/// Longest a `lock_file` call waits for a lock held elsewhere
+const MAX_WAIT_MS: u64 = 60_000;
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A file locked by a session. The open handle holds the OS lock (flock on
+/// Unix, LockFileEx on Windows), so other processes honouring advisory locks
+/// see it too; dropping it unlocks the file.
+struct HeldLock {
+    session: String,
+    label: Option<String>,
+    expires_at: Instant,
+    file: File,
+}
+
+static LOCKS: Mutex<Option<HashMap<PathBuf, HeldLock>>> = Mutex::new(None);
+
+fn with_locks<T>(f: impl FnOnce(&mut HashMap<PathBuf, HeldLock>) -> T) -> T {
+    let mut locks = LOCKS.lock().unwrap();
+    let locks = locks.get_or_insert_with(HashMap::new);
+    let now = Instant::now();
+    locks.retain(|_, held| held.expires_at > now);
+    f(locks)
+}
+
+/// Whether the file at `path` is still the one `file` has open
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(held), Ok(current)) => held.dev() == current.dev() && held.ino() == current.ino(),
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+/// Writes replace files by renaming a new one into place, which leaves the OS
+/// lock on the old file. Move it to the file now at the path.
+fn reattach(path: &Path, held: &mut HeldLock) {
+    if same_file(&held.file, path) {
+        return;
+    }
+    if let Ok(file) = File::open(path) {
+        if file.try_lock().is_ok() {
+            held.file = file;
+        }
+    }
+}
+
+fn describe(path: &Path, held: &HeldLock) -> String {
+    let expires_in = held.expires_at.saturating_duration_since(Instant::now()).as_secs();
+    match &held.label {
+        Some(label) => format!(
+            "{} (locked by session {} for {}, expires in {}s)",
+            path.display(),
+            held.session,
+            label,
+            expires_in
+        ),
+        None => format!("{} (locked by session {}, expires in {}s)", path.display(), held.session, expires_in),
+    }
+}
+
+/// Error out when another session, or another process, holds a lock on
+/// `path` or on a file inside it
+pub fn check_not_locked(path: &Path) -> Result<(), String> {
+    let path = canonical_path(path);
+    let session = session::current_id();
+    let conflict = with_locks(|locks| {
+        let mut own = false;
+        for (locked, held) in locks.iter_mut() {
+            if !locked.starts_with(&path) {
+                continue;
+            }
+            if held.session != session {
+                return Some(describe(locked, held));
+            }
+            reattach(locked, held);
+            own |= *locked == path;
+        }
+        // Other processes only lock files on the local disk
+        if own || !vfs::current().is_local() || !path.is_file() {
+            return None;
+        }
+        // Not locked here, but maybe by another server or program. Locks are
+        // advisory, so a file that cannot be opened to find out is not blocked.
+        let file = File::open(&path).ok()?;
+        match file.try_lock() {
+            Err(TryLockError::WouldBlock) => Some(format!("{} (locked by another process)", path.display())),
+            _ => None,
+        }
+    });
+    match conflict {
+        Some(lock) => Err(format!("Access denied: {} is locked: {}", path.display(), lock)),
+        None => Ok(()),
+    }
+}
+
+/// Unlock everything a session holds, used when it ends
+pub fn release_session_locks(session: &str) {
+    with_locks(|locks| locks.retain(|_, held| held.session != session));
+}
+
+enum Attempt {
+    Locked { renewed: bool },
+    Busy(String),
+}
+
+fn try_lock(path: &Path, session: &str, lease: Duration, label: &Option<String>) -> Result<Attempt, String> {
+    with_locks(|locks| {
+        if let Some(held) = locks.get_mut(path) {
+            if held.session != session {
+                return Ok(Attempt::Busy(describe(path, held)));
+            }
+            reattach(path, held);
+            held.expires_at = Instant::now() + lease;
+            if label.is_some() {
+                held.label = label.clone();
+            }
+            return Ok(Attempt::Locked { renewed: true });
+        }
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        match file.try_lock() {
+            Ok(()) => {
+                locks.insert(
+                    path.to_path_buf(),
+                    HeldLock {
+                        session: session.to_string(),
+                        label: label.clone(),
+                        expires_at: Instant::now() + lease,
+                        file,
+                    },
+                );
+                Ok(Attempt::Locked { renewed: false })
+            }
+            Err(TryLockError::WouldBlock) => Ok(Attempt::Busy(format!("{} (locked by another process)", path.display()))),
+            Err(TryLockError::Error(e)) => Err(e.to_string()),
+        }
+    })
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct LockFileRequest {
+    /// File to lock
+    pub path: String,
+    /// How long the lock lasts unless released or renewed. Defaults to 300, capped at 3600.
+    #[schemars(extend("default" = 300))]
+    pub lease_seconds: Option<u64>,
+    /// How long to wait for a lock held by someone else before giving up. Defaults to 0, capped at 60000.
+    #[schemars(extend("default" = 0))]
+    pub wait_ms: Option<u64>,
+    /// What the file is locked for, shown to others who find it locked
+    pub label: Option<String>,
+}
+
+fn text_result(text: String) -> HandlerResult<CallToolResult> {
+    Ok(CallToolResult::text(text))
+}
+
+pub async fn lock_file(request: LockFileRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if !path.is_file() {
+        return Ok(CallToolResult::error(format!("Not a file: {}", path.display())));
+    }
+    let path = canonical_path(path);
+    let lease_seconds = request
+        .lease_seconds
+        .unwrap_or(DEFAULT_LEASE_SECONDS)
+        .clamp(1, MAX_LEASE_SECONDS);
+    let lease = Duration::from_secs(lease_seconds);
+    let deadline = Instant::now() + Duration::from_millis(request.wait_ms.unwrap_or(0).min(MAX_WAIT_MS));
+    let session = session::current_id();
+
+    loop {
+        match try_lock(&path, &session, lease, &request.label) {
+            Ok(Attempt::Locked { renewed }) => {
+                // Unlock as soon as the lease runs out rather than on the next lock call
+                tokio::spawn(async move {
+                    tokio::time::sleep(lease).await;
+                    with_locks(|_| ());
+                });
+                let action = if renewed { "Renewed the lock on" } else { "Locked" };
+                return text_result(format!("{} {} for {}s", action, path.display(), lease_seconds));
+            }
+            Ok(Attempt::Busy(holder)) if Instant::now() >= deadline => {
+                return Ok(CallToolResult::error(format!("{} is already locked: {}", path.display(), holder)));
+            }
+            Ok(Attempt::Busy(_)) => tokio::time::sleep(RETRY_INTERVAL).await,
+            Err(e) => return Ok(CallToolResult::error(format!("Failed to lock {}: {}", path.display(), e))),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct UnlockFileRequest {
+    /// File to unlock. Unlocks every file this session has locked when omitted.
+    pub path: Option<String>,
+}
+
+pub async fn unlock_file(request: UnlockFileRequest) -> HandlerResult<CallToolResult> {
+    let path = request.path.map(|path| canonical_path(&resolve_path(Path::new(&path))));
+    let session = session::current_id();
+    let unlocked = with_locks(|locks| {
+        let before = locks.len();
+        locks.retain(|locked, held| held.session != session || path.as_ref().is_some_and(|path| path != locked));
+        before - locks.len()
+    });
+    match path {
+        Some(path) if unlocked == 0 => {
+            Ok(CallToolResult::error(format!("{} is not locked by this session", path.display())))
+        }
+        Some(path) => text_result(format!("Unlocked {}", path.display())),
+        None => text_result(format!("Unlocked {} file(s)", unlocked)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::session::Session;
+    use crate::mcp::testing::test_env;
+    use crate::mcp::tools::overwrite_file;
+    use crate::mcp::tools::OverwriteFileRequest;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_file_locks() {
+        let (_env, temp_dir, _) = test_env().await;
+        let file = temp_dir.path().join("shared.txt");
+        fs::write(&file, "start").unwrap();
+
+        let (sender_a, _output_a) = tokio::sync::mpsc::unbounded_channel();
+        let (sender_b, _output_b) = tokio::sync::mpsc::unbounded_channel();
+        let a = Session::start(sender_a);
+        let b = Session::start(sender_b);
+        let lock = || LockFileRequest {
+            path: file.to_str().unwrap().to_string(),
+            lease_seconds: None,
+            wait_ms: None,
+            label: Some("refactor".to_string()),
+        };
+        let overwrite = |content: &str| OverwriteFileRequest {
+            path: file.to_str().unwrap().to_string(),
+            content: content.to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: None,
+            dry_run: None,
+        };
+
+        session::scope(a.clone(), async {
+            assert!(!lock_file(lock()).await.unwrap().is_error);
+            assert!(!overwrite_file(overwrite("from a")).await.unwrap().is_error);
+            // The OS lock follows the file the write put in place
+            assert!(check_not_locked(&file).is_ok());
+        })
+        .await;
+        assert!(matches!(fs::File::open(&file).unwrap().try_lock(), Err(fs::TryLockError::WouldBlock)));
+
+        session::scope(b.clone(), async {
+            assert!(overwrite_file(overwrite("from b")).await.unwrap().is_error);
+            let mut waiting = lock();
+            waiting.wait_ms = Some(200);
+            assert!(lock_file(waiting).await.unwrap().is_error);
+            let unlock = UnlockFileRequest {
+                path: Some(file.to_str().unwrap().to_string()),
+            };
+            assert!(unlock_file(unlock).await.unwrap().is_error);
+        })
+        .await;
+        assert_eq!(fs::read_to_string(&file).unwrap(), "from a");
+
+        // Locks of a session go away with it
+        a.end();
+        session::scope(b.clone(), async {
+            assert!(!lock_file(lock()).await.unwrap().is_error);
+            assert!(!overwrite_file(overwrite("from b")).await.unwrap().is_error);
+            assert!(!unlock_file(UnlockFileRequest { path: None }).await.unwrap().is_error);
+        })
+        .await;
+        b.end();
+        assert!(fs::File::open(&file).unwrap().try_lock().is_ok());
+    }
+}
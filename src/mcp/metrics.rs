@@ -0,0 +1,225 @@
+use crate::mcp::session;
+use crate::mcp::types::*;
+use crate::mcp::watch::active_watches;
+use chrono::DateTime;
+use chrono::Local;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+/// Counters of what clients had the server do since it started, over all sessions
+struct Metrics {
+    started: DateTime<Local>,
+    requests: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+    tool_calls: BTreeMap<String, u64>,
+    tool_errors: BTreeMap<String, u64>,
+    tool_timeouts: BTreeMap<String, u64>,
+    bytes_read: u64,
+    bytes_written: u64,
+    index_hits: u64,
+    index_misses: u64,
+}
+
+static METRICS: LazyLock<Mutex<Metrics>> = LazyLock::new(|| {
+    Mutex::new(Metrics {
+        started: Local::now(),
+        requests: BTreeMap::new(),
+        errors: BTreeMap::new(),
+        tool_calls: BTreeMap::new(),
+        tool_errors: BTreeMap::new(),
+        tool_timeouts: BTreeMap::new(),
+        bytes_read: 0,
+        bytes_written: 0,
+        index_hits: 0,
+        index_misses: 0,
+    })
+});
+
+fn increment(counters: &mut BTreeMap<String, u64>, name: &str) {
+    *counters.entry(name.to_string()).or_default() += 1;
+}
+
+/// A request for `method` arrived
+pub fn record_request(method: &str) {
+    increment(&mut METRICS.lock().unwrap().requests, method);
+}
+
+/// A request for `method` was answered with a JSON-RPC error
+pub fn record_error(method: &str) {
+    increment(&mut METRICS.lock().unwrap().errors, method);
+}
+
+/// `tool` was called. Failures are results of the call rather than JSON-RPC
+/// errors, and counted by `failed`; `timed_out` ones also on their own.
+pub fn record_tool_call(tool: &str, failed: bool, timed_out: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    increment(&mut metrics.tool_calls, tool);
+    if failed {
+        increment(&mut metrics.tool_errors, tool);
+    }
+    if timed_out {
+        increment(&mut metrics.tool_timeouts, tool);
+    }
+}
+
+pub fn add_bytes_read(bytes: u64) {
+    METRICS.lock().unwrap().bytes_read += bytes;
+}
+
+pub fn add_bytes_written(bytes: u64) {
+    METRICS.lock().unwrap().bytes_written += bytes;
+}
+
+/// A search asked the file index, which could answer or not
+pub fn record_index_lookup(hit: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    if hit {
+        metrics.index_hits += 1;
+    } else {
+        metrics.index_misses += 1;
+    }
+}
+
+/// The counters and the current gauges
+fn snapshot() -> Value {
+    let metrics = METRICS.lock().unwrap();
+    json!({
+        "started": metrics.started.to_rfc3339(),
+        "uptime_seconds": (Local::now() - metrics.started).num_seconds(),
+        "requests": metrics.requests,
+        "errors": metrics.errors,
+        "tool_calls": metrics.tool_calls,
+        "tool_errors": metrics.tool_errors,
+        "tool_timeouts": metrics.tool_timeouts,
+        "bytes_read": metrics.bytes_read,
+        "bytes_written": metrics.bytes_written,
+        "index_hits": metrics.index_hits,
+        "index_misses": metrics.index_misses,
+        "active_watches": active_watches(),
+        "active_sessions": session::all().len(),
+    })
+}
+
+/// The snapshot in the Prometheus text exposition format
+fn prometheus(snapshot: &Value) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, &Value)>| {
+        let _ = writeln!(text, "# HELP rs_filesystem_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE rs_filesystem_{} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(text, "rs_filesystem_{}{} {}", name, labels, value);
+        }
+    };
+    let labelled = |key: &str, label: &str| -> Vec<(String, &Value)> {
+        snapshot[key]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(name, value)| (format!("{{{}=\"{}\"}}", label, name.replace('\\', "\\\\").replace('"', "\\\"")), value))
+            .collect()
+    };
+    let single = |key: &str| vec![(String::new(), &snapshot[key])];
+    metric("requests_total", "counter", "JSON-RPC requests by method", labelled("requests", "method"));
+    metric("errors_total", "counter", "JSON-RPC error responses by method", labelled("errors", "method"));
+    metric("tool_calls_total", "counter", "Tool calls by tool", labelled("tool_calls", "tool"));
+    metric("tool_errors_total", "counter", "Tool calls that failed, by tool", labelled("tool_errors", "tool"));
+    metric("tool_timeouts_total", "counter", "Tool calls aborted by their timeout", labelled("tool_timeouts", "tool"));
+    metric("read_bytes_total", "counter", "Bytes read from files by tools", single("bytes_read"));
+    metric("written_bytes_total", "counter", "Bytes written to files by tools", single("bytes_written"));
+    metric("index_hits_total", "counter", "Searches answered from the file index", single("index_hits"));
+    metric("index_misses_total", "counter", "Searches the file index could not answer", single("index_misses"));
+    metric("active_watches", "gauge", "Paths watched for changes", single("active_watches"));
+    metric("active_sessions", "gauge", "Connected client sessions", single("active_sessions"));
+    metric("uptime_seconds", "gauge", "Seconds since the server started", single("uptime_seconds"));
+    text
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ServerStatsRequest {
+    /// json (default) for a JSON document, prometheus for the Prometheus text format
+    #[schemars(extend("enum" = ["json", "prometheus"], "default" = "json"))]
+    pub format: Option<String>,
+}
+
+pub async fn server_stats(request: ServerStatsRequest) -> HandlerResult<CallToolResult> {
+    let snapshot = snapshot();
+    match request.format.as_deref().unwrap_or("json").to_lowercase().as_str() {
+        "json" => Ok(CallToolResult::structured(snapshot)),
+        "prometheus" => Ok(CallToolResult::text(prometheus(&snapshot))),
+        other => Ok(CallToolResult::error(format!("Unknown format: {}, expected json or prometheus", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_text;
+    use crate::mcp::testing::test_env;
+    use crate::mcp::tools::read_file;
+    use crate::mcp::tools::ReadFileRequest;
+    use std::fs;
+
+    #[test]
+    fn test_prometheus() {
+        let snapshot = json!({
+            "requests": { "tools/call": 3, "ping": 1 },
+            "tool_calls": { "read_file": 3 },
+            "bytes_read": 120,
+            "active_watches": 0,
+        });
+        let text = prometheus(&snapshot);
+        assert!(text.contains("# TYPE rs_filesystem_requests_total counter\n"));
+        assert!(text.contains("rs_filesystem_requests_total{method=\"tools/call\"} 3\n"));
+        assert!(text.contains("rs_filesystem_tool_calls_total{tool=\"read_file\"} 3\n"));
+        assert!(text.contains("rs_filesystem_read_bytes_total 120\n"));
+        assert!(text.contains("rs_filesystem_active_watches 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_server_stats() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let stats = || async {
+            let result = server_stats(ServerStatsRequest { format: None }).await.unwrap();
+            result.structured_content.unwrap()
+        };
+        let before = stats().await;
+        let file = temp_dir.path().join("counted.txt");
+        fs::write(&file, "0123456789").unwrap();
+        let request = ReadFileRequest {
+            file_path: file.to_str().unwrap().to_string(),
+            encoding: None,
+            offset: None,
+            max_bytes: None,
+            max_dimension: None,
+        };
+        assert!(!read_file(request).await.unwrap().is_error);
+        // Other tests may read at the same time, so the count grows by at least as much
+        let after = stats().await;
+        assert!(after["bytes_read"].as_u64().unwrap() >= before["bytes_read"].as_u64().unwrap() + 10);
+
+        let result = server_stats(ServerStatsRequest {
+            format: Some("prometheus".to_string()),
+        })
+        .await
+        .unwrap();
+        let text = result_text(&result);
+        assert!(text.contains("# TYPE rs_filesystem_read_bytes_total counter"));
+
+        let result = server_stats(ServerStatsRequest {
+            format: Some("xml".to_string()),
+        })
+        .await
+        .unwrap();
+        assert!(result.is_error);
+    }
+}
@@ -0,0 +1,274 @@
+use std::path::Path;
+
+/// How much of a file content detection looks at
+pub const SNIFF_BYTES: usize = 8192;
+
+/// Signatures at the start of a file, longest and most specific first
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"II*\x00", "image/tiff"),
+    (b"MM\x00*", "image/tiff"),
+    (b"8BPS", "image/vnd.adobe.photoshop"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"\x00asm", "application/wasm"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+    (b"\xca\xfe\xba\xbe", "application/java-vm"),
+    (b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"\x00\x01\x00\x00\x00", "font/ttf"),
+    (b"OTTO", "font/otf"),
+];
+
+/// Formats whose signature is too short to tell them from text, such as a
+/// note starting with "BM", recognised by the header that follows it
+fn checked_signature(head: &[u8]) -> Option<&'static str> {
+    let u32_at = |offset: usize| Some(u32::from_le_bytes(head.get(offset..offset + 4)?.try_into().ok()?));
+    // The size of the DIB header that follows the file header tells its version
+    if head.starts_with(b"BM") && matches!(u32_at(14), Some(12 | 40 | 52 | 56 | 64 | 108 | 124)) {
+        return Some("image/bmp");
+    }
+    // A DOS stub pointing at the PE header
+    if head.starts_with(b"MZ") {
+        let pe_header = u32_at(0x3c).and_then(|offset| head.get(offset as usize..(offset as usize).saturating_add(4)));
+        if pe_header == Some(&b"PE\0\0"[..]) {
+            return Some("application/vnd.microsoft.portable-executable");
+        }
+    }
+    // Tag version 2 to 4, then a size of four 7-bit bytes
+    if let Some(header) = head.get(..10).filter(|header| header.starts_with(b"ID3")) {
+        if (2..=4).contains(&header[3]) && header[6..].iter().all(|b| *b < 0x80) {
+            return Some("audio/mpeg");
+        }
+    }
+    None
+}
+
+/// Text formats recognised by extension, once the content looks like text
+const TEXT_EXTENSIONS: &[(&str, &str)] = &[
+    ("json", "application/json"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("ts", "text/x-typescript"),
+    ("rs", "text/x-rust"),
+    ("py", "text/x-python"),
+    ("go", "text/x-go"),
+    ("java", "text/x-java"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("hpp", "text/x-c++"),
+    ("sh", "application/x-sh"),
+    ("toml", "application/toml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("ini", "text/plain"),
+    ("txt", "text/plain"),
+];
+
+/// Archives that are zip files underneath
+const ZIP_EXTENSIONS: &[(&str, &str)] = &[
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("jar", "application/java-archive"),
+    ("epub", "application/epub+zip"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+];
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|e| e.to_str()).map(str::to_lowercase)
+}
+
+/// Whether the start of a file looks like text: no NUL bytes and valid
+/// UTF-8, or UTF-16 with a byte order mark
+pub fn looks_like_text(head: &[u8]) -> bool {
+    if head.starts_with(b"\xff\xfe") || head.starts_with(b"\xfe\xff") {
+        return true;
+    }
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // The sample may end in the middle of a character
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// MIME type of a file from its first bytes, falling back to the extension
+/// for text formats that have no signature
+pub fn detect(path: &Path, head: &[u8]) -> &'static str {
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            b"AVI " => return "video/x-msvideo",
+            _ => {}
+        }
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return match &head[8..12] {
+            b"avif" | b"avis" => "image/avif",
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        };
+    }
+    if head.len() >= 262 && &head[257..262] == b"ustar" {
+        return "application/x-tar";
+    }
+    if let Some(mime) = checked_signature(head) {
+        return mime;
+    }
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        if *mime == "application/zip" {
+            let ext = extension(path);
+            if let Some((_, mime)) = ZIP_EXTENSIONS.iter().find(|(e, _)| ext.as_deref() == Some(e)) {
+                return mime;
+            }
+        }
+        return mime;
+    }
+    if !looks_like_text(head) {
+        return "application/octet-stream";
+    }
+    let ext = extension(path);
+    if let Some((_, mime)) = TEXT_EXTENSIONS.iter().find(|(e, _)| ext.as_deref() == Some(e)) {
+        return mime;
+    }
+    let start = String::from_utf8_lossy(&head[..head.len().min(256)]).trim_start().to_lowercase();
+    if start.starts_with("<svg") || (start.starts_with("<?xml") && start.contains("<svg")) {
+        "image/svg+xml"
+    } else if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html"
+    } else if start.starts_with("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Whether content of this type is returned as text rather than base64
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json" | "application/xml" | "image/svg+xml" | "application/toml" | "application/yaml"
+                | "application/x-sh"
+        )
+}
+
+fn u16_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u24_le(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Width and height of an image, read from its header
+pub fn image_dimensions(mime: &str, bytes: &[u8]) -> Option<(u32, u32)> {
+    match mime {
+        "image/png" => Some((u32_be(bytes, 16)?, u32_be(bytes, 20)?)),
+        "image/gif" => Some((u16_le(bytes, 6)?, u16_le(bytes, 8)?)),
+        // Rows are stored bottom-up when the height is positive, top-down otherwise
+        "image/bmp" => Some((u32_le(bytes, 18)?, (u32_le(bytes, 22)? as i32).unsigned_abs())),
+        "image/webp" => match bytes.get(12..16)? {
+            b"VP8 " => Some((u16_le(bytes, 26)? & 0x3fff, u16_le(bytes, 28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32_le(bytes, 21)?;
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((u24_le(bytes, 24)? + 1, u24_le(bytes, 27)? + 1)),
+            _ => None,
+        },
+        "image/jpeg" => {
+            // Walk the segments up to the start of frame, which holds the size
+            let mut at = 2;
+            loop {
+                if *bytes.get(at)? != 0xff {
+                    return None;
+                }
+                let marker = *bytes.get(at + 1)?;
+                let length = u16_be(bytes, at + 2)? as usize;
+                let is_start_of_frame = matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+                if is_start_of_frame {
+                    return Some((u16_be(bytes, at + 7)?, u16_be(bytes, at + 5)?));
+                }
+                at += 2 + length;
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00\x00\x20\x00\x00\x00\x10\x08\x06\x00\x00\x00";
+        assert_eq!(detect(Path::new("picture.dat"), png), "image/png");
+        assert_eq!(image_dimensions("image/png", png), Some((32, 16)));
+        assert_eq!(detect(Path::new("report.docx"), b"PK\x03\x04rest"), "application/vnd.openxmlformats-officedocument.wordprocessingml.document");
+        assert_eq!(detect(Path::new("data.json"), b"{\"a\": 1}"), "application/json");
+        assert_eq!(detect(Path::new("icon"), b"<svg xmlns=\"http://www.w3.org/2000/svg\">"), "image/svg+xml");
+        assert_eq!(detect(Path::new("notes"), "plain text, café".as_bytes()), "text/plain");
+        assert_eq!(detect(Path::new("blob.json"), b"\x00\x01\x02\x03"), "application/octet-stream");
+
+        // Short signatures need the header behind them
+        assert_eq!(detect(Path::new("notes.txt"), b"BM notes: call the plumber"), "text/plain");
+        assert_eq!(detect(Path::new("names.txt"), b"MZ Smith, Ann"), "text/plain");
+        assert_eq!(detect(Path::new("todo.md"), b"ID3 tags still missing"), "text/markdown");
+        let mut bmp = b"BM\x46\x00\x00\x00\x00\x00\x00\x00\x36\x00\x00\x00\x28\x00\x00\x00".to_vec();
+        bmp.extend_from_slice(&[0; 16]);
+        assert_eq!(detect(Path::new("image"), &bmp), "image/bmp");
+        let mut exe = vec![0u8; 0x84];
+        exe[..2].copy_from_slice(b"MZ");
+        exe[0x3c] = 0x80;
+        exe[0x80..].copy_from_slice(b"PE\0\0");
+        assert_eq!(detect(Path::new("tool"), &exe), "application/vnd.microsoft.portable-executable");
+        assert_eq!(detect(Path::new("song"), b"ID3\x04\x00\x00\x00\x00\x10\x00rest"), "audio/mpeg");
+    }
+}
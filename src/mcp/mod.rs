@@ -1,10 +1,63 @@
+pub mod batch;
+pub mod budget;
+pub mod checkpoint;
+pub mod compare;
+pub mod config;
+pub mod conversion;
+pub mod copy;
+pub mod counting;
+pub mod dry_run;
+pub mod encoding;
+pub mod expansion;
+pub mod find;
+pub mod git;
+pub mod hashing;
+pub mod ignore_rules;
+pub mod images;
+pub mod index;
+pub mod limits;
+pub mod lines;
+pub mod locks;
+pub mod metrics;
+pub mod mime;
+pub mod permissions;
+pub mod preview;
+pub mod prompt_library;
 pub mod prompts;
+pub mod protection;
+pub mod protocol;
+pub mod recent;
+pub mod replace;
+pub mod reservations;
 pub mod resources;
+pub mod sandbox;
+pub mod schema;
+pub mod scratch;
+pub mod secrets;
+pub mod session;
+pub mod shadow;
+pub mod sorting;
+pub mod state;
+pub mod structured;
+pub mod tabular;
+#[cfg(test)]
+pub mod testing;
+pub mod timeouts;
 pub mod tools;
+pub mod trace;
 pub mod types;
+pub mod unicode;
+pub mod usage;
 pub mod utilities;
+pub mod vfs;
+pub mod watch;
+pub mod workspace;
+pub mod xattrs;
 
 const JSONRPC_VERSION: &str = "2.0";
-const PROTOCOL_VERSION: &str = "2024-11-05";
+/// Protocol versions this server speaks, newest first
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+/// The first protocol version with structured tool output
+const STRUCTURED_OUTPUT_PROTOCOL_VERSION: &str = "2025-06-18";
 const SERVER_NAME: &str = "rs_filesystem";
 const SERVER_VERSION: &str = "0.1.0";
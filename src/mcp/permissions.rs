@@ -0,0 +1,327 @@
+use crate::mcp::config;
+use crate::mcp::dry_run;
+use crate::mcp::sandbox;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::validate_write_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+/// Permission changes are refused unless the server was started with
+/// `--allow-permission-changes`, which sets this variable
+pub fn permission_changes_allowed() -> bool {
+    config::var("MCP_RS_FILESYSTEM_ALLOW_PERMISSION_CHANGES")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+const SETUID: u32 = 0o4000;
+const SETGID: u32 = 0o2000;
+const STICKY: u32 = 0o1000;
+
+/// `rwxr-xr-x` style rendering of the permission bits
+fn symbolic(mode: u32) -> String {
+    let mut text = String::new();
+    for (shift, special, special_char) in [(6, SETUID, 's'), (3, SETGID, 's'), (0, STICKY, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
+
+/// Apply a chmod-style mode to `current`. Accepts octal (`755`, `0644`) or
+/// comma-separated symbolic clauses (`+x`, `u+x,go-w`, `a=rX`).
+pub fn parse_mode(spec: &str, current: u32, is_dir: bool) -> Result<u32, String> {
+    let spec = spec.trim();
+    if !spec.is_empty() && spec.chars().all(|c| c.is_digit(8)) {
+        if spec.len() > 4 {
+            return Err(format!("Invalid octal mode: {}", spec));
+        }
+        return u32::from_str_radix(spec, 8).map_err(|e| format!("Invalid octal mode {}: {}", spec, e));
+    }
+
+    let mut mode = current & 0o7777;
+    for clause in spec.split(',') {
+        let operator_at = clause
+            .find(['+', '-', '='])
+            .ok_or_else(|| format!("Invalid mode clause: {:?}", clause))?;
+        let (who, rest) = clause.split_at(operator_at);
+        let mut who_mask = 0;
+        for c in who.chars() {
+            who_mask |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return Err(format!("Invalid mode clause: {:?}", clause)),
+            };
+        }
+        if who_mask == 0 {
+            who_mask = 0o7777;
+        }
+
+        // Several operations may follow each other, as in `u+r-w`
+        let mut chars = rest.chars().peekable();
+        while let Some(operator) = chars.next() {
+            let mut perm = 0;
+            while let Some(c) = chars.next_if(|c| !matches!(c, '+' | '-' | '=')) {
+                perm |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    // Execute only for directories or files already executable by someone
+                    'X' if is_dir || current & 0o111 != 0 => 0o111,
+                    'X' => 0,
+                    's' => SETUID | SETGID,
+                    't' => STICKY,
+                    _ => return Err(format!("Invalid permission {:?} in {:?}", c, clause)),
+                };
+            }
+            let perm = perm & who_mask;
+            match operator {
+                '+' => mode |= perm,
+                '-' => mode &= !perm,
+                _ => mode = (mode & !who_mask) | perm,
+            }
+        }
+    }
+    Ok(mode)
+}
+
+fn describe(path: &Path) -> Result<serde_json::Value, String> {
+    let metadata = fs::symlink_metadata(path).map_err(|e| e.to_string())?;
+    let file_type = if metadata.is_dir() {
+        "directory"
+    } else if metadata.is_symlink() {
+        "symlink"
+    } else {
+        "file"
+    };
+    let mut info = json!({
+        "path": path.display().to_string(),
+        "type": file_type,
+        "readonly": metadata.permissions().readonly(),
+    });
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let mode = metadata.mode() & 0o7777;
+        info["mode"] = json!(format!("{:04o}", mode));
+        info["symbolic"] = json!(symbolic(mode));
+        info["uid"] = json!(metadata.uid());
+        info["gid"] = json!(metadata.gid());
+    }
+    Ok(info)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct GetPermissionsRequest {
+    /// Path to the file or directory
+    pub path: String,
+}
+
+pub async fn get_permissions(request: GetPermissionsRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    match describe(path) {
+        Ok(info) => Ok(CallToolResult::text(serde_json::to_string_pretty(&info).unwrap())),
+        Err(e) => Ok(CallToolResult::error(format!("Error reading permissions: {}", e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct SetPermissionsRequest {
+    /// Path to the file or directory
+    pub path: String,
+    /// chmod-style mode, either octal ("755") or symbolic ("+x", "u+x,go-w"). Unix only.
+    pub mode: Option<String>,
+    /// Set or clear the read-only attribute. On Unix this toggles all write bits.
+    pub readonly: Option<bool>,
+    /// New owner user id. Unix only.
+    pub uid: Option<u32>,
+    /// New owner group id. Unix only.
+    pub gid: Option<u32>,
+    /// Return the permissions before and after without changing them. Always the case when the server runs with
+    /// --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+fn apply_permissions(path: &Path, request: &SetPermissionsRequest) -> Result<(), String> {
+    // Changed through the sandbox rather than by name, so a symlink swapped in
+    // after the path was validated cannot redirect the change
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+
+    if let Some(spec) = &request.mode {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let current = metadata.permissions().mode();
+            let mode = parse_mode(spec, current, metadata.is_dir())?;
+            if mode & (SETUID | SETGID) != 0 {
+                return Err("Setting the setuid or setgid bit is not allowed".to_string());
+            }
+            sandbox::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = spec;
+            return Err("mode is only supported on Unix, use readonly instead".to_string());
+        }
+    }
+
+    if let Some(readonly) = request.readonly {
+        let mut permissions = fs::metadata(path).map_err(|e| e.to_string())?.permissions();
+        // On Unix this toggles the write bits of owner, group and others
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(readonly);
+        sandbox::set_permissions(path, permissions)?;
+    }
+
+    if request.uid.is_some() || request.gid.is_some() {
+        #[cfg(unix)]
+        sandbox::set_owner(path, request.uid, request.gid)?;
+        #[cfg(not(unix))]
+        return Err("Changing ownership is only supported on Unix".to_string());
+    }
+    Ok(())
+}
+
+/// What `apply_permissions` would change, as the permissions before and after
+fn plan_permissions(path: &Path, request: &SetPermissionsRequest) -> Result<serde_json::Value, String> {
+    let before = describe(path)?;
+    let mut after = before.clone();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+        let current = metadata.permissions().mode();
+        let mut mode = current & 0o7777;
+        if let Some(spec) = &request.mode {
+            mode = parse_mode(spec, current, metadata.is_dir())?;
+            if mode & (SETUID | SETGID) != 0 {
+                return Err("Setting the setuid or setgid bit is not allowed".to_string());
+            }
+        }
+        if let Some(readonly) = request.readonly {
+            mode = if readonly { mode & !0o222 } else { mode | 0o222 };
+        }
+        after["mode"] = json!(format!("{:04o}", mode));
+        after["symbolic"] = json!(symbolic(mode));
+        after["readonly"] = json!(mode & 0o222 == 0);
+        if let Some(uid) = request.uid {
+            after["uid"] = json!(uid);
+        }
+        if let Some(gid) = request.gid {
+            after["gid"] = json!(gid);
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        if request.mode.is_some() {
+            return Err("mode is only supported on Unix, use readonly instead".to_string());
+        }
+        if request.uid.is_some() || request.gid.is_some() {
+            return Err("Changing ownership is only supported on Unix".to_string());
+        }
+        if let Some(readonly) = request.readonly {
+            after["readonly"] = json!(readonly);
+        }
+    }
+    Ok(json!({
+        "action": "set_permissions",
+        "path": path.display().to_string(),
+        "before": before,
+        "after": after,
+    }))
+}
+
+pub async fn set_permissions(request: SetPermissionsRequest) -> HandlerResult<CallToolResult> {
+    if !permission_changes_allowed() {
+        return Ok(CallToolResult::error(
+            "Permission changes are disabled. Start the server with --allow-permission-changes to enable them"
+                .to_string(),
+        ));
+    }
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_write_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if request.mode.is_none() && request.readonly.is_none() && request.uid.is_none() && request.gid.is_none() {
+        return Ok(CallToolResult::error(
+            "Nothing to change: give at least one of mode, readonly, uid or gid".to_string(),
+        ));
+    }
+
+    if dry_run::requested(request.dry_run) {
+        return match plan_permissions(path, &request) {
+            Ok(change) => dry_run::result(vec![change]),
+            Err(e) => Ok(CallToolResult::error(format!("Error changing permissions: {}", e))),
+        };
+    }
+
+    match apply_permissions(path, &request).and_then(|_| describe(path)) {
+        Ok(info) => Ok(CallToolResult::text(serde_json::to_string_pretty(&info).unwrap())),
+        Err(e) => Ok(CallToolResult::error(format!("Error changing permissions: {}", e))),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::remove_env;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::set_env;
+    use crate::mcp::testing::test_env;
+
+    #[tokio::test]
+    async fn test_set_permissions_requires_flag() {
+        use std::os::unix::fs::PermissionsExt;
+        let (_env, temp_dir, _) = test_env().await;
+
+        let script = temp_dir.path().join("run.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+        let request = || SetPermissionsRequest {
+            path: script.to_str().unwrap().to_string(),
+            mode: Some("u+x,g+x".to_string()),
+            readonly: None,
+            uid: None,
+            gid: None,
+            dry_run: None,
+        };
+
+        let _allowed = remove_env("MCP_RS_FILESYSTEM_ALLOW_PERMISSION_CHANGES");
+        assert!(set_permissions(request()).await.unwrap().is_error);
+        assert_eq!(fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o644);
+
+        let _allowed = set_env("MCP_RS_FILESYSTEM_ALLOW_PERMISSION_CHANGES", "1");
+        let result = set_permissions(request()).await.unwrap();
+        assert!(!result.is_error);
+        assert_eq!(fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o754);
+
+        let request = GetPermissionsRequest {
+            path: script.to_str().unwrap().to_string(),
+        };
+        let result = get_permissions(request).await.unwrap();
+        let info = result_json(&result);
+        assert_eq!(info["mode"], "0754");
+        assert_eq!(info["symbolic"], "rwxr-xr--");
+    }
+}
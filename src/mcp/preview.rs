@@ -0,0 +1,184 @@
+use crate::mcp::encoding::decode;
+use crate::mcp::encoding::detect_encoding;
+use crate::mcp::limits::max_read_bytes;
+use crate::mcp::mime;
+use crate::mcp::tabular::records;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::vfs;
+use chrono::DateTime;
+use chrono::Local;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::path::Path;
+
+/// How much of a file is read for a preview, unless the whole file is needed
+const PREVIEW_BYTES: usize = 64 * 1024;
+const DEFAULT_MAX_ROWS: usize = 10;
+const DEFAULT_MAX_LINES: usize = 20;
+const MAX_KEYS: usize = 100;
+/// Longest line or cell shown, longer ones are cut
+const MAX_CELL_CHARS: usize = 200;
+
+fn shorten(text: &str) -> String {
+    match text.char_indices().nth(MAX_CELL_CHARS) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Shape of a JSON document: its top level type and keys, not the values
+fn json_preview(value: &Value) -> Value {
+    let describe = |value: &Value| {
+        let mut description = json!({ "type": json_type(value) });
+        match value {
+            Value::Array(items) => description["length"] = json!(items.len()),
+            Value::Object(map) => description["key_count"] = json!(map.len()),
+            _ => {}
+        }
+        description
+    };
+    match value {
+        Value::Object(map) => json!({
+            "type": "object",
+            "key_count": map.len(),
+            "keys": map
+                .iter()
+                .take(MAX_KEYS)
+                .map(|(key, value)| {
+                    let mut description = describe(value);
+                    description["key"] = json!(key);
+                    description
+                })
+                .collect::<Vec<_>>(),
+        }),
+        Value::Array(items) => {
+            let mut element_types: Vec<&str> = items.iter().map(json_type).collect();
+            element_types.sort();
+            element_types.dedup();
+            let mut preview = json!({ "type": "array", "length": items.len(), "element_types": element_types });
+            if let Some(Value::Object(first)) = items.first() {
+                preview["first_element_keys"] = json!(first.keys().take(MAX_KEYS).collect::<Vec<_>>());
+            }
+            preview
+        }
+        other => describe(other),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct PreviewFileRequest {
+    /// File to preview
+    pub path: String,
+    /// Rows of a CSV or TSV file to show. Defaults to 10
+    #[schemars(extend("default" = 10))]
+    pub max_rows: Option<usize>,
+    /// Lines of other text files to show. Defaults to 20
+    #[schemars(extend("default" = 20))]
+    pub max_lines: Option<usize>,
+}
+
+pub async fn preview_file(request: PreviewFileRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let fs = vfs::current();
+    let stat = match fs.stat(path) {
+        Ok(stat) if stat.is_file() => stat,
+        Ok(_) => return Ok(CallToolResult::error(format!("Not a file: {}", path.display()))),
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading {}: {}", path.display(), e))),
+    };
+    let size = stat.len;
+    let head = match vfs::backend().read_prefix(path, mime::SNIFF_BYTES) {
+        Ok(head) => head,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading {}: {}", path.display(), e))),
+    };
+    let mime_type = mime::detect(path, &head);
+
+    // JSON is only useful when parsed as a whole; everything else needs its start
+    let whole = mime_type == "application/json" && size <= max_read_bytes() as u64;
+    let limit = if whole { size as usize } else { PREVIEW_BYTES };
+    let bytes = match fs.read_prefix(path, limit) {
+        Ok(bytes) => bytes,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading {}: {}", path.display(), e))),
+    };
+    let complete = bytes.len() as u64 >= size;
+
+    let mut preview = json!({
+        "path": path.display().to_string(),
+        "size": size,
+        "mime_type": mime_type,
+        "modified": stat.modified.map(|time| DateTime::<Local>::from(time).to_rfc3339()),
+    });
+    let text = mime::is_text(mime_type)
+        .then(|| decode(&bytes, detect_encoding(&bytes)).ok())
+        .flatten();
+
+    match (mime_type, text) {
+        (mime_type, _) if mime_type.starts_with("image/") && mime_type != "image/svg+xml" => {
+            preview["kind"] = json!("image");
+            if let Some((width, height)) = mime::image_dimensions(mime_type, &bytes) {
+                preview["width"] = json!(width);
+                preview["height"] = json!(height);
+            }
+        }
+        ("application/json", Some(text)) if complete => match serde_json::from_str::<Value>(&text) {
+            Ok(value) => {
+                preview["kind"] = json!("json");
+                preview["structure"] = json_preview(&value);
+            }
+            Err(e) => {
+                preview["kind"] = json!("text");
+                preview["parse_error"] = json!(e.to_string());
+                preview["lines"] = json!(text.lines().take(DEFAULT_MAX_LINES).map(shorten).collect::<Vec<_>>());
+            }
+        },
+        ("text/csv" | "text/tab-separated-values", Some(text)) => {
+            let delimiter = if mime_type == "text/csv" { ',' } else { '\t' };
+            let max_rows = request.max_rows.unwrap_or(DEFAULT_MAX_ROWS);
+            let mut rows = records(&text, delimiter);
+            let columns = rows.next().unwrap_or_default();
+            let rows: Vec<Vec<String>> = rows.take(max_rows).map(|row| row.iter().map(|cell| shorten(cell)).collect()).collect();
+            preview["kind"] = json!("table");
+            preview["delimiter"] = json!(delimiter.to_string());
+            preview["columns"] = json!(columns);
+            preview["rows"] = json!(rows);
+            if complete {
+                preview["total_rows"] = json!(records(&text, delimiter).count().saturating_sub(1));
+            }
+        }
+        (_, Some(text)) => {
+            let max_lines = request.max_lines.unwrap_or(DEFAULT_MAX_LINES);
+            preview["kind"] = json!("text");
+            preview["lines"] = json!(text.lines().take(max_lines).map(shorten).collect::<Vec<_>>());
+            if complete {
+                preview["total_lines"] = json!(text.lines().count());
+            }
+        }
+        (_, None) => {
+            preview["kind"] = json!("binary");
+            preview["magic"] = json!(head.iter().take(16).map(|b| format!("{:02x}", b)).collect::<String>());
+        }
+    }
+    preview["complete"] = json!(complete);
+
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&preview).unwrap()))
+}
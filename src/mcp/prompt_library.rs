@@ -0,0 +1,295 @@
+use crate::mcp::config;
+use crate::mcp::session;
+use crate::mcp::types::*;
+use crate::mcp::utilities::log_message;
+use crate::mcp::utilities::notify;
+use crate::mcp::utilities::shutting_down;
+use dirs::config_dir;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// How often the prompts directory is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A prompt defined in a JSON file of the prompts directory. A file holds one
+/// prompt object or an array of them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PromptDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub arguments: Vec<PromptArgumentDefinition>,
+    /// Body of a single user message
+    pub template: Option<String>,
+    /// Several messages, used instead of `template`
+    pub messages: Option<Vec<PromptMessageDefinition>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PromptArgumentDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PromptMessageDefinition {
+    #[serde(default = "default_role")]
+    pub role: String,
+    pub template: String,
+}
+
+fn default_role() -> String {
+    "user".to_string()
+}
+
+static LIBRARY: RwLock<Vec<PromptDefinition>> = RwLock::new(Vec::new());
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Directory holding user-defined prompts. `MCP_RS_FILESYSTEM_PROMPTS_DIR`
+/// (set by `--prompts-dir`) overrides `<config dir>/rs_filesystem/prompts`.
+pub fn prompts_directory() -> Option<PathBuf> {
+    if let Ok(dir) = config::var("MCP_RS_FILESYSTEM_PROMPTS_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    config_dir().map(|dir| dir.join("rs_filesystem").join("prompts"))
+}
+
+/// Prompt files in the directory with their modification times, used to notice changes
+fn fingerprint() -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    let Some(entries) = prompts_directory().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+            let len = metadata.map(|m| m.len()).unwrap_or_default();
+            (path, modified, len)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Read every prompt definition from the prompts directory. Invalid files are
+/// reported through a log notification and skipped.
+fn load_definitions() -> Vec<PromptDefinition> {
+    let mut prompts: Vec<PromptDefinition> = Vec::new();
+    for (path, _, _) in fingerprint() {
+        let parsed = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str::<Value>(&text).map_err(|e| e.to_string()))
+            .and_then(|value| match value {
+                Value::Array(_) => serde_json::from_value::<Vec<PromptDefinition>>(value).map_err(|e| e.to_string()),
+                value => serde_json::from_value::<PromptDefinition>(value)
+                    .map(|prompt| vec![prompt])
+                    .map_err(|e| e.to_string()),
+            });
+        match parsed {
+            Ok(definitions) => {
+                for definition in definitions {
+                    if definition.template.is_none() && definition.messages.is_none() {
+                        log_warning(format!("Prompt {} in {} has no template", definition.name, path.display()));
+                    } else if prompts.iter().any(|p| p.name == definition.name) {
+                        log_warning(format!("Duplicate prompt {} in {}", definition.name, path.display()));
+                    } else {
+                        prompts.push(definition);
+                    }
+                }
+            }
+            Err(e) => log_warning(format!("Invalid prompt file {}: {}", path.display(), e)),
+        }
+    }
+    prompts.sort_by(|a, b| a.name.cmp(&b.name));
+    prompts
+}
+
+fn log_warning(message: String) {
+    log_message("warning", "prompts", &message);
+}
+
+/// Load the prompt library and watch the prompts directory, sending
+/// `notifications/prompts/list_changed` whenever its files change
+pub fn init() {
+    reload();
+    WATCHING.store(true, Ordering::SeqCst);
+    std::thread::spawn(|| {
+        let mut last = fingerprint();
+        while !shutting_down() {
+            std::thread::sleep(POLL_INTERVAL);
+            let current = fingerprint();
+            if current != last {
+                last = current;
+                reload();
+                // Without a client there is nobody to tell, and stdout is not ours yet
+                if !session::all().is_empty() {
+                    notify("notifications/prompts/list_changed", None);
+                }
+            }
+        }
+    });
+}
+
+/// Whether `init` started watching the prompts directory, so that
+/// `notifications/prompts/list_changed` will be sent
+pub fn watching() -> bool {
+    WATCHING.load(Ordering::SeqCst)
+}
+
+pub fn reload() {
+    let definitions = load_definitions();
+    *LIBRARY.write().unwrap() = definitions;
+}
+
+/// Prompts of the library in `prompts/list` form
+pub fn library_prompts() -> Vec<Prompt> {
+    prompts_of(&LIBRARY.read().unwrap())
+}
+
+fn prompts_of(library: &[PromptDefinition]) -> Vec<Prompt> {
+    library
+        .iter()
+        .map(|definition| Prompt {
+            name: definition.name.clone(),
+            description: definition.description.clone(),
+            arguments: (!definition.arguments.is_empty()).then(|| {
+                definition
+                    .arguments
+                    .iter()
+                    .map(|argument| PromptArgument {
+                        name: argument.name.clone(),
+                        description: argument.description.clone(),
+                        required: Some(argument.required),
+                    })
+                    .collect()
+            }),
+        })
+        .collect()
+}
+
+/// Replace every `{{name}}` in `template` with the value of that argument
+fn substitute(template: &str, arguments: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[..end].trim();
+                match arguments.get(name) {
+                    Some(value) => output.push_str(value),
+                    // Unknown placeholders are left untouched
+                    None => output.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Render a library prompt. Returns `None` when no prompt has this name.
+pub fn render(name: &str, arguments: Option<&HashMap<String, Value>>) -> Option<Result<PromptResult, String>> {
+    render_from(&LIBRARY.read().unwrap(), name, arguments)
+}
+
+fn render_from(
+    library: &[PromptDefinition],
+    name: &str,
+    arguments: Option<&HashMap<String, Value>>,
+) -> Option<Result<PromptResult, String>> {
+    let definition = library.iter().find(|definition| definition.name == name)?;
+
+    let mut values: HashMap<String, String> = HashMap::new();
+    for argument in &definition.arguments {
+        let value = arguments.and_then(|arguments| arguments.get(&argument.name));
+        match value {
+            Some(Value::String(text)) => values.insert(argument.name.clone(), text.clone()),
+            Some(Value::Null) | None if argument.required => {
+                return Some(Err(format!("Missing required argument: {}", argument.name)))
+            }
+            Some(Value::Null) | None => values.insert(argument.name.clone(), String::new()),
+            Some(other) => values.insert(argument.name.clone(), other.to_string()),
+        };
+    }
+
+    let messages: Vec<PromptMessageDefinition> = match (&definition.messages, &definition.template) {
+        (Some(messages), _) => messages.clone(),
+        (None, Some(template)) => vec![PromptMessageDefinition {
+            role: default_role(),
+            template: template.clone(),
+        }],
+        (None, None) => Vec::new(),
+    };
+    Some(Ok(PromptResult {
+        description: definition.description.clone().unwrap_or_else(|| definition.name.clone()),
+        messages: Some(
+            messages
+                .iter()
+                .map(|message| PromptMessage {
+                    role: message.role.clone(),
+                    content: PromptMessageContent::Text {
+                        text: substitute(&message.template, &values),
+                    },
+                })
+                .collect(),
+        ),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_library_prompt() {
+        // A library of its own, leaving the server's alone
+        let library: Vec<PromptDefinition> = vec![serde_json::from_value(serde_json::json!({
+            "name": "explain",
+            "description": "Explain a file",
+            "arguments": [
+                {"name": "path", "required": true},
+                {"name": "audience"}
+            ],
+            "template": "Explain {{path}} to {{audience}}"
+        }))
+        .unwrap()];
+
+        let arguments = HashMap::from([("path".to_string(), Value::from("lib.rs"))]);
+        let result = render_from(&library, "explain", Some(&arguments)).unwrap().unwrap();
+        let messages = result.messages.unwrap();
+        assert!(matches!(&messages[0].content, PromptMessageContent::Text { text } if text == "Explain lib.rs to "));
+        assert!(render_from(&library, "explain", None).unwrap().is_err());
+        assert!(render_from(&library, "unknown", None).is_none());
+        assert_eq!(prompts_of(&library)[0].arguments.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_substitute() {
+        let arguments = HashMap::from([("path".to_string(), "src/main.rs".to_string())]);
+        assert_eq!(
+            substitute("Review {{ path }} and {{path}}, not {{other}} {{", &arguments),
+            "Review src/main.rs and src/main.rs, not {{other}} {{"
+        );
+    }
+}
@@ -1,7 +1,18 @@
+use crate::mcp::prompt_library::library_prompts;
+use crate::mcp::prompt_library::render;
+use crate::mcp::encoding::read_text_file;
 use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::vfs;
 use rpc_router::HandlerResult;
 use rpc_router::IntoHandlerError;
 use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use url::Url;
 
 pub async fn prompts_list(
     _request: Option<ListPromptsRequest>,
@@ -108,8 +119,31 @@ pub async fn prompts_list(
                     },
                 ]),
             },
+            Prompt {
+                name: "review_file".to_string(),
+                description: Some("Review a file, with its contents embedded in the prompt".to_string()),
+                arguments: Some(vec![
+                    PromptArgument {
+                        name: "path".to_string(),
+                        description: Some("Path to the file to review".to_string()),
+                        required: Some(true),
+                    },
+                    PromptArgument {
+                        name: "focus".to_string(),
+                        description: Some("What the review should concentrate on, e.g. bugs or readability".to_string()),
+                        required: Some(false),
+                    },
+                ]),
+            },
         ],
     };
+    // User-defined prompts follow the built-in ones, which take precedence on name clashes
+    let mut response = response;
+    for prompt in library_prompts() {
+        if !response.prompts.iter().any(|p| p.name == prompt.name) {
+            response.prompts.push(prompt);
+        }
+    }
     Ok(response)
 }
 
@@ -119,8 +153,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Get the current time in city".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "What's the time of {}?",
                         request.arguments.as_ref().unwrap()["city"].as_str().unwrap()
@@ -132,8 +165,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Get the current local time".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: "What's the current local time?".to_string(),
                 },
             }]),
@@ -142,8 +174,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Edit a file".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "Edit file {} with message: {}",
                         request.arguments.as_ref().unwrap()["file_path"].as_str().unwrap(),
@@ -156,8 +187,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Read a file".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "Read file {}",
                         request.arguments.as_ref().unwrap()["file_path"].as_str().unwrap()
@@ -169,8 +199,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "List directory contents".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "List contents of directory {}",
                         request.arguments.as_ref().unwrap()["path"].as_str().unwrap()
@@ -182,8 +211,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Move or rename file/directory".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "Move/rename {} to {}",
                         request.arguments.as_ref().unwrap()["source_path"].as_str().unwrap(),
@@ -196,8 +224,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Get file metadata".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "Get info for {}",
                         request.arguments.as_ref().unwrap()["path"].as_str().unwrap()
@@ -209,8 +236,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Create a new directory".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "Create directory {}",
                         request.arguments.as_ref().unwrap()["path"].as_str().unwrap()
@@ -222,8 +248,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
             description: "Overwrite file contents".to_string(),
             messages: Some(vec![PromptMessage {
                 role: "user".to_string(),
-                content: PromptMessageContent {
-                    type_name: "text".to_string(),
+                content: PromptMessageContent::Text {
                     text: format!(
                         "Overwrite {} with new content",
                         request.arguments.as_ref().unwrap()["file_path"].as_str().unwrap()
@@ -231,9 +256,86 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                 },
             }]),
         },
-        _ => {
-            return Err(json!({"code": -32602, "message": "Prompt not found"}).into_handler_error())
-        }
+        "review_file" => match review_file(request.arguments.as_ref()) {
+            Ok(result) => result,
+            Err(message) => {
+                return Err(json!({"code": -32602, "message": message}).into_handler_error())
+            }
+        },
+        name => match render(name, request.arguments.as_ref()) {
+            Some(Ok(result)) => result,
+            Some(Err(message)) => {
+                return Err(json!({"code": -32602, "message": message}).into_handler_error())
+            }
+            None => {
+                return Err(json!({"code": -32602, "message": "Prompt not found"}).into_handler_error())
+            }
+        },
     };
     Ok(response)
 }
+
+/// Files larger than this are not embedded in a prompt
+const REVIEW_FILE_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Build the `review_file` prompt: the file as an embedded resource followed by the request
+fn review_file(arguments: Option<&HashMap<String, Value>>) -> Result<PromptResult, String> {
+    let path = arguments
+        .and_then(|arguments| arguments.get("path"))
+        .and_then(Value::as_str)
+        .ok_or_else(|| "Missing required argument: path".to_string())?;
+    let path = resolve_path(Path::new(path));
+    validate_path_or_error(&path)?;
+    let stat = vfs::current().stat(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if !stat.is_file() {
+        return Err(format!("Not a file: {}", path.display()));
+    }
+    if stat.len > REVIEW_FILE_MAX_BYTES {
+        return Err(format!(
+            "{} is too large to embed in a prompt ({} bytes, limit {})",
+            path.display(),
+            stat.len,
+            REVIEW_FILE_MAX_BYTES
+        ));
+    }
+    let decoded = read_text_file(&path, None)?;
+    let uri = Url::from_file_path(canonical_path(&path)).map_err(|_| format!("Invalid path: {}", path.display()))?;
+
+    let focus = arguments
+        .and_then(|arguments| arguments.get("focus"))
+        .and_then(Value::as_str)
+        .filter(|focus| !focus.trim().is_empty());
+    let request = match focus {
+        Some(focus) => format!(
+            "Please review the file {} above, focusing on {}. Point out concrete problems with line references and suggest fixes.",
+            path.display(),
+            focus
+        ),
+        None => format!(
+            "Please review the file {} above. Point out bugs, unclear code and risky patterns with line references, and suggest fixes.",
+            path.display()
+        ),
+    };
+
+    Ok(PromptResult {
+        description: format!("Review {}", path.display()),
+        messages: Some(vec![
+            PromptMessage {
+                role: "user".to_string(),
+                content: PromptMessageContent::Resource {
+                    resource: ResourceContent {
+                        uri,
+                        mime_type: Some("text/plain".to_string()),
+                        text: Some(decoded.text),
+                        blob: None,
+                    },
+                },
+            },
+            PromptMessage {
+                role: "user".to_string(),
+                content: PromptMessageContent::Text { text: request },
+            },
+        ]),
+    })
+}
+
@@ -0,0 +1,124 @@
+use crate::mcp::config;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::get_allowed_directories;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use ignore::WalkBuilder;
+use serde_json::json;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// `MCP_RS_FILESYSTEM_PROTECTED_PATHS`, set by `--protected-paths` or the
+/// configuration file: patterns in .gitignore syntax, separated by commas, of
+/// paths that tools may read but never change
+pub fn protected_patterns() -> Vec<String> {
+    config::var("MCP_RS_FILESYSTEM_PROTECTED_PATHS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// The patterns as rules for one allowed directory, which they are relative to
+fn rules(root: &Path, patterns: &[String]) -> Result<Gitignore, String> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| format!("Invalid protected path pattern {:?}: {}", pattern, e))?;
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Check that every pattern parses
+pub fn check_patterns(patterns: &[String]) -> Result<(), String> {
+    rules(Path::new(""), patterns).map(|_| ())
+}
+
+/// The pattern protecting `path`, which must be below the root of `rules`
+fn matching_rule(rules: &Gitignore, path: &Path, is_dir: bool) -> Option<String> {
+    let matched = rules.matched_path_or_any_parents(path, is_dir);
+    matched.is_ignore().then(|| matched.inner().map(|glob| glob.original().to_string()).unwrap_or_default())
+}
+
+/// A protected path at or below `path`, with the pattern that protects it
+fn find_protected(rules: &Gitignore, root: &Path, path: &Path) -> Option<(PathBuf, String)> {
+    let is_dir = path.is_dir();
+    if path != root {
+        if let Some(rule) = matching_rule(rules, path, is_dir) {
+            return Some((path.to_path_buf(), rule));
+        }
+    }
+    // Moving or deleting a directory takes what is inside along
+    if !is_dir {
+        return None;
+    }
+    WalkBuilder::new(path)
+        .standard_filters(false)
+        .build()
+        .flatten()
+        .filter(|entry| entry.path() != root)
+        .find_map(|entry| {
+            let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+            matching_rule(rules, entry.path(), is_dir).map(|rule| (entry.path().to_path_buf(), rule))
+        })
+}
+
+/// Error out when `path`, or anything inside it, matches a protected path
+/// pattern of the allowed directory it is in. The error is JSON naming the
+/// rule, so a client can tell it from other failures.
+pub fn check_not_protected(path: &Path) -> Result<(), String> {
+    let patterns = protected_patterns();
+    if patterns.is_empty() {
+        return Ok(());
+    }
+    let path = canonical_path(path);
+    for directory in get_allowed_directories() {
+        let root = canonical_path(Path::new(&directory));
+        if !path.starts_with(&root) {
+            continue;
+        }
+        let rules = rules(&root, &patterns)?;
+        if let Some((protected, rule)) = find_protected(&rules, &root, &path) {
+            let mut error = json!({
+                "error": "protected_path",
+                "message": format!("{} is protected by the rule {:?} and cannot be changed", protected.display(), rule),
+                "path": path.display().to_string(),
+                "rule": rule,
+                "allowed_directory": directory,
+            });
+            if protected != path {
+                error["protected_path"] = json!(protected.display().to_string());
+            }
+            return Err(serde_json::to_string_pretty(&error).unwrap());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_rule() {
+        let root = Path::new("/srv/project");
+        let patterns: Vec<String> = ["**/.git/**", "/Cargo.lock", "*.pem", "!public.pem"]
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect();
+        let rules = rules(root, &patterns).unwrap();
+        let rule = |path: &str| matching_rule(&rules, &root.join(path), false);
+
+        assert_eq!(rule(".git/config").as_deref(), Some("**/.git/**"));
+        assert_eq!(rule("vendor/lib/.git/HEAD").as_deref(), Some("**/.git/**"));
+        assert_eq!(rule("Cargo.lock").as_deref(), Some("/Cargo.lock"));
+        assert_eq!(rule("crates/a/Cargo.lock"), None);
+        assert_eq!(rule("keys/server.pem").as_deref(), Some("*.pem"));
+        assert_eq!(rule("keys/public.pem"), None);
+        assert_eq!(rule("src/main.rs"), None);
+        assert!(check_patterns(&["keys/[z-a]".to_string()]).is_err());
+    }
+}
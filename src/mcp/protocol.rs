@@ -0,0 +1,122 @@
+// Errors here are answered as they are, one per malformed message
+#![allow(clippy::result_large_err)]
+
+use crate::mcp::types::*;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Parse a line from the client. Text that is not JSON is answered with a
+/// parse error, as the id of the request cannot be known.
+pub fn parse_line(line: &str) -> Result<Value, JsonRpcError> {
+    serde_json::from_str(line)
+        .map_err(|e| JsonRpcError::new(Value::Null, ErrorCode::ParseError as i32, &format!("Parse error: {}", e)))
+}
+
+/// Whether a message is a notification, which never gets a response, not
+/// even an error
+pub fn is_notification(message: &Value) -> bool {
+    message.is_object() && message.get("id").is_none()
+}
+
+/// Check that a message is a JSON-RPC 2.0 request or notification
+pub fn check_message(message: &Value) -> Result<(), JsonRpcError> {
+    let invalid = |id: &Value, detail: String| {
+        JsonRpcError::new(id.clone(), ErrorCode::InvalidRequest as i32, &format!("Invalid Request: {}", detail))
+    };
+    let Value::Object(object) = message else {
+        return Err(invalid(&Value::Null, format!("expected an object, got {}", kind(message))));
+    };
+    // An id of the wrong type cannot be echoed back
+    let id = match object.get("id") {
+        None => &Value::Null,
+        Some(id @ (Value::String(_) | Value::Number(_) | Value::Null)) => id,
+        Some(other) => return Err(invalid(&Value::Null, format!("id must be a string or a number, got {}", kind(other)))),
+    };
+    match object.get("jsonrpc") {
+        Some(Value::String(version)) if version == "2.0" => {}
+        Some(other) => return Err(invalid(id, format!("jsonrpc must be \"2.0\", got {}", other))),
+        None => return Err(invalid(id, "missing jsonrpc version".to_string())),
+    }
+    let method = match object.get("method") {
+        Some(Value::String(method)) => method,
+        Some(other) => return Err(invalid(id, format!("method must be a string, got {}", kind(other)))),
+        None => return Err(invalid(id, "missing method".to_string())),
+    };
+    match object.get("params") {
+        None | Some(Value::Null | Value::Object(_) | Value::Array(_)) => Ok(()),
+        Some(other) => Err(JsonRpcError::new(
+            id.clone(),
+            ErrorCode::InvalidParams as i32,
+            &format!("Invalid params for {}: expected an object, got {}", method, kind(other)),
+        )),
+    }
+}
+
+/// The params of a message as `T`. Missing or mistyped params are an invalid
+/// params error naming what is wrong.
+pub fn params<T: DeserializeOwned>(id: &Value, method: &str, params: Option<Value>) -> Result<T, JsonRpcError> {
+    let invalid = |detail: String| {
+        JsonRpcError::new(id.clone(), ErrorCode::InvalidParams as i32, &format!("Invalid params for {}: {}", method, detail))
+    };
+    let params = params.filter(|params| !params.is_null()).ok_or_else(|| invalid("missing params".to_string()))?;
+    serde_json::from_value(params).map_err(|e| invalid(e.to_string()))
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rejected(message: Value) -> (Value, i32, String) {
+        let error = check_message(&message).unwrap_err();
+        (error.id, error.error.code, error.error.message)
+    }
+
+    #[test]
+    fn test_check_message() {
+        assert!(check_message(&json!({"jsonrpc": "2.0", "id": 1, "method": "ping"})).is_ok());
+        assert!(check_message(&json!({"jsonrpc": "2.0", "method": "notifications/initialized"})).is_ok());
+
+        let (id, code, message) = rejected(json!({"jsonrpc": "1.0", "id": 7, "method": "ping"}));
+        assert_eq!((id, code), (json!(7), -32600));
+        assert_eq!(message, "Invalid Request: jsonrpc must be \"2.0\", got \"1.0\"");
+        let (id, _, message) = rejected(json!({"jsonrpc": "2.0", "id": {"a": 1}, "method": "ping"}));
+        assert_eq!(id, Value::Null);
+        assert!(message.contains("id must be a string or a number"));
+        let (_, _, message) = rejected(json!({"jsonrpc": "2.0", "id": 1}));
+        assert_eq!(message, "Invalid Request: missing method");
+        let (_, code, message) = rejected(json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": 3}));
+        assert_eq!(code, -32602);
+        assert_eq!(message, "Invalid params for tools/call: expected an object, got a number");
+        let (_, code, _) = rejected(json!([1]));
+        assert_eq!(code, -32600);
+
+        let error = parse_line("{\"jsonrpc\": ").unwrap_err();
+        assert_eq!(error.error.code, -32700);
+        assert!(error.error.message.starts_with("Parse error: EOF"));
+    }
+
+    #[test]
+    fn test_params() {
+        let id = json!(1);
+        let cancelled: CancelledNotification =
+            params(&id, "notifications/cancelled", Some(json!({"requestId": 4, "reason": "late"}))).unwrap();
+        assert_eq!(cancelled.request_id, json!(4));
+        let error = params::<CancelledNotification>(&id, "notifications/cancelled", None).err().unwrap();
+        assert_eq!(error.error.message, "Invalid params for notifications/cancelled: missing params");
+        let error = params::<ToolCallRequestParams>(&id, "tools/call", Some(json!({"arguments": {}}))).err().unwrap();
+        assert_eq!(error.error.code, -32602);
+        assert!(error.error.message.contains("missing field `name`"));
+    }
+}
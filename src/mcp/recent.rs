@@ -0,0 +1,298 @@
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::index;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::utilities::validate_path_or_error;
+use chrono::DateTime;
+use chrono::Local;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+const DEFAULT_MAX_RESULTS: usize = 20;
+const MAX_MAX_RESULTS: usize = 1000;
+const DEFAULT_MAX_ENTRIES: usize = 200_000;
+const MAX_MAX_ENTRIES: usize = 1_000_000;
+const DEFAULT_MAX_DURATION_MS: u64 = 5_000;
+const MAX_MAX_DURATION_MS: u64 = 60_000;
+
+/// A point in time given as RFC 3339, e.g. `2024-05-01T12:00:00Z`, or as seconds
+/// since the Unix epoch
+fn parse_since(since: &str) -> Result<SystemTime, String> {
+    let since = since.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(since) {
+        return Ok(time.into());
+    }
+    match since.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(SystemTime::UNIX_EPOCH + Duration::from_secs_f64(seconds)),
+        _ => Err(format!(
+            "Invalid since: {}. Expected an RFC 3339 timestamp such as 2024-05-01T12:00:00Z or seconds since the Unix epoch",
+            since
+        )),
+    }
+}
+
+/// The most recently modified entries seen so far, holding no more than it returns
+struct Newest {
+    limit: usize,
+    since: Option<SystemTime>,
+    /// Oldest on top, so it is the one dropped when a newer entry comes
+    heap: BinaryHeap<Reverse<(SystemTime, PathBuf, bool, u64)>>,
+    total: usize,
+}
+
+impl Newest {
+    fn new(limit: usize, since: Option<SystemTime>) -> Newest {
+        Newest {
+            limit,
+            since,
+            heap: BinaryHeap::with_capacity(limit + 1),
+            total: 0,
+        }
+    }
+
+    fn offer(&mut self, path: &Path, is_dir: bool, size: u64, modified: Option<SystemTime>) {
+        let Some(modified) = modified else {
+            return;
+        };
+        if self.since.is_some_and(|since| modified <= since) {
+            return;
+        }
+        self.total += 1;
+        if self.heap.len() == self.limit && self.heap.peek().is_some_and(|Reverse(oldest)| oldest.0 >= modified) {
+            return;
+        }
+        self.heap.push(Reverse((modified, path.to_path_buf(), is_dir, size)));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    /// Newest first
+    fn into_sorted(self) -> Vec<(SystemTime, PathBuf, bool, u64)> {
+        self.heap.into_sorted_vec().into_iter().map(|Reverse(entry)| entry).collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct RecentChangesRequest {
+    /// Directory to look in. Defaults to all allowed directories
+    pub path: Option<String>,
+    /// Only return entries modified after this time: an RFC 3339 timestamp, such as the as_of of an earlier call, or
+    /// seconds since the Unix epoch
+    pub since: Option<String>,
+    /// How many of the most recently modified entries to return. Defaults to 20
+    #[schemars(extend("default" = 20))]
+    pub max_results: Option<usize>,
+    /// Return directories as well as files. Defaults to false
+    #[schemars(extend("default" = false))]
+    pub include_directories: Option<bool>,
+    /// Skip files excluded by .gitignore, .ignore and global git excludes. Defaults to true
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// Look at hidden files and directories. Defaults to false
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    /// Stop after visiting this many entries. Defaults to 200000
+    #[schemars(extend("default" = 200000))]
+    pub max_entries: Option<usize>,
+    /// Stop after this many milliseconds. Defaults to 5000
+    #[schemars(extend("default" = 5000))]
+    pub max_duration_ms: Option<u64>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+pub async fn recent_changes(request: RecentChangesRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(recent_changes_blocking(request)).await
+}
+
+async fn recent_changes_blocking(request: RecentChangesRequest) -> HandlerResult<CallToolResult> {
+    let error = |text: String| Ok(CallToolResult::error(text));
+    let roots: Vec<PathBuf> = match &request.path {
+        Some(path) => {
+            let path = resolve_path(Path::new(path));
+            if let Err(msg) = validate_path_or_error(&path) {
+                return error(msg);
+            }
+            vec![path]
+        }
+        None => get_allowed_directories()
+            .iter()
+            .map(|dir| canonical_path(Path::new(dir)))
+            .collect(),
+    };
+    let since = match request.since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(msg) => return error(msg),
+    };
+
+    let max_results = request.max_results.unwrap_or(DEFAULT_MAX_RESULTS).clamp(1, MAX_MAX_RESULTS);
+    let max_entries = request.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES).clamp(1, MAX_MAX_ENTRIES);
+    let max_duration = Duration::from_millis(
+        request
+            .max_duration_ms
+            .unwrap_or(DEFAULT_MAX_DURATION_MS)
+            .clamp(1, MAX_MAX_DURATION_MS),
+    );
+    let include_directories = request.include_directories.unwrap_or(false);
+    let respect_gitignore = request.respect_gitignore.unwrap_or(true);
+    let include_hidden = request.include_hidden.unwrap_or(false);
+    let rules = match IgnoreRules::new(respect_gitignore, include_hidden).with_overrides(&request.ignore) {
+        Ok(rules) => rules,
+        Err(msg) => return error(msg),
+    };
+
+    // Taken before looking, so a change made while this call runs shows up in
+    // the next call that passes it as since
+    let as_of = SystemTime::now();
+    let started = Instant::now();
+    let mut scanned = 0;
+    let mut truncated: Option<&str> = None;
+    let mut newest = Newest::new(max_results, since);
+    // The index holds what a walk with the default options finds, kept current by the watcher
+    let use_index = index::enabled() && respect_gitignore && !include_hidden && request.ignore.is_default();
+    let mut indexed = use_index;
+    'roots: for root in &roots {
+        if use_index {
+            let answered = index::for_each_entry(&canonical_path(root), |path, entry| {
+                scanned += 1;
+                if include_directories || !entry.is_dir {
+                    newest.offer(path, entry.is_dir, entry.size, entry.modified);
+                }
+            });
+            if answered {
+                continue;
+            }
+            indexed = false;
+        }
+        for entry in rules.walker(root).build() {
+            if scanned >= max_entries {
+                truncated = Some("max_entries");
+                break 'roots;
+            }
+            if started.elapsed() >= max_duration {
+                truncated = Some("max_duration_ms");
+                break 'roots;
+            }
+            scanned += 1;
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            if entry.depth() == 0 || (is_dir && !include_directories) {
+                continue;
+            }
+            if let Ok(metadata) = entry.metadata() {
+                newest.offer(entry.path(), is_dir, metadata.len(), metadata.modified().ok());
+            }
+        }
+    }
+
+    let total_matches = newest.total;
+    let changes: Vec<_> = newest
+        .into_sorted()
+        .into_iter()
+        .map(|(modified, path, is_dir, size)| {
+            json!({
+                "path": path.display().to_string(),
+                "type": if is_dir { "directory" } else { "file" },
+                "size": if is_dir { None } else { Some(size) },
+                "modified": DateTime::<Local>::from(modified).to_rfc3339(),
+            })
+        })
+        .collect();
+    let summary = json!({
+        "changes": changes,
+        "total_matches": total_matches,
+        "since": since.map(|since| DateTime::<Local>::from(since).to_rfc3339()),
+        "as_of": DateTime::<Local>::from(as_of).to_rfc3339(),
+        "scanned": scanned,
+        "indexed": indexed,
+        "truncated": truncated.is_some(),
+        "truncated_by": truncated,
+    });
+    Ok(CallToolResult::structured(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    #[test]
+    fn test_newest() {
+        let at = |seconds: u64| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+        let mut newest = Newest::new(2, at(10));
+        for (name, seconds) in [("a", 5), ("b", 30), ("c", 20), ("d", 40), ("e", 10)] {
+            newest.offer(Path::new(name), false, 0, at(seconds));
+        }
+        newest.offer(Path::new("f"), false, 0, None);
+        assert_eq!(newest.heap.len(), 2);
+        assert_eq!(newest.total, 3);
+        let sorted: Vec<_> = newest.into_sorted().into_iter().map(|entry| entry.1).collect();
+        assert_eq!(sorted, [PathBuf::from("d"), PathBuf::from("b")]);
+
+        assert_eq!(parse_since("1970-01-01T00:01:40Z").unwrap(), at(100).unwrap());
+        assert_eq!(parse_since("100").unwrap(), at(100).unwrap());
+        assert!(parse_since("yesterday").is_err());
+        assert!(parse_since("-5").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recent_changes() {
+        let (_env, temp_dir, _) = test_env().await;
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        let epoch = SystemTime::UNIX_EPOCH;
+        for (name, seconds) in [("old.txt", 1_000), ("src/lib.rs", 3_000), ("notes.md", 2_000)] {
+            fs::write(root.join(name), name).unwrap();
+            let file = fs::File::options().write(true).open(root.join(name)).unwrap();
+            file.set_modified(epoch + Duration::from_secs(seconds)).unwrap();
+        }
+
+        let request = |since: Option<&str>, max_results: Option<usize>| RecentChangesRequest {
+            path: None,
+            since: since.map(String::from),
+            max_results,
+            include_directories: None,
+            respect_gitignore: None,
+            include_hidden: None,
+            max_entries: None,
+            max_duration_ms: None,
+            ignore: Default::default(),
+        };
+        let names = |result: CallToolResult| {
+            assert!(!result.is_error);
+            let found = result.structured_content.unwrap();
+            let names: Vec<String> = found["changes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|change| Path::new(change["path"].as_str().unwrap()).file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            (names, found["total_matches"].as_u64().unwrap())
+        };
+
+        assert_eq!(names(recent_changes(request(None, None)).await.unwrap()), (vec!["lib.rs".to_string(), "notes.md".to_string(), "old.txt".to_string()], 3));
+        assert_eq!(names(recent_changes(request(None, Some(1))).await.unwrap()), (vec!["lib.rs".to_string()], 3));
+        assert_eq!(names(recent_changes(request(Some("1970-01-01T00:25:00Z"), None)).await.unwrap()), (vec!["lib.rs".to_string(), "notes.md".to_string()], 2));
+        assert_eq!(names(recent_changes(request(Some("2500"), None)).await.unwrap()).1, 1);
+        assert!(recent_changes(request(Some("last tuesday"), None)).await.unwrap().is_error);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::mcp::dry_run;
+use crate::mcp::dry_run::unified_diff;
+use crate::mcp::encoding::read_text_file;
+use crate::mcp::encoding::write_text_file;
+use crate::mcp::encoding::TextEncoding;
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::limits::max_read_bytes;
+use crate::mcp::mime;
+use crate::mcp::sandbox;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::shadow;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::validate_write_path_or_error;
+use crate::mcp::vfs;
+use globset::Glob;
+use regex::NoExpand;
+use regex::Regex;
+use regex::RegexBuilder;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+const DEFAULT_MAX_FILES: usize = 10_000;
+const DEFAULT_MAX_DIFF_BYTES: usize = 64 * 1024;
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReplaceInFilesRequest {
+    /// Directory to search
+    pub path: String,
+    /// Files to change, as a glob against their path relative to `path`, such as **/*.rs or src/*.{ts,tsx}. A *
+    /// also matches across directories.
+    pub glob: String,
+    /// Text to look for, or a regular expression when `regex` is true
+    pub pattern: String,
+    /// Text to put in its place. With `regex`, $1 or ${name} insert capture groups and $$ a dollar sign.
+    pub replacement: String,
+    /// Read `pattern` as a regular expression. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub regex: Option<bool>,
+    /// Ignore case when matching. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub case_insensitive: Option<bool>,
+    /// Write the changes. Defaults to false, a dry run reporting the matches and diffs per file without changing
+    /// anything; run it first and check the result.
+    #[schemars(extend("default" = false))]
+    pub apply: Option<bool>,
+    /// Report the changes without writing them, even with `apply`. Always the case when the server runs with
+    /// --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+    /// Largest total size, in bytes, of the diffs returned. Files past it are listed without one. Defaults to
+    /// 65536.
+    #[schemars(extend("default" = 65536))]
+    pub max_diff_bytes: Option<usize>,
+    /// Stop after looking at this many files. Defaults to 10000.
+    #[schemars(extend("default" = 10000))]
+    pub max_files: Option<usize>,
+    /// Skip files excluded by .gitignore, .ignore and git excludes. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// Include hidden files and directories. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+/// The matcher of a request; a literal pattern is matched as it is
+fn build_matcher(request: &ReplaceInFilesRequest) -> Result<Regex, String> {
+    if request.pattern.is_empty() {
+        return Err("pattern must not be empty".to_string());
+    }
+    let pattern = if request.regex.unwrap_or(false) {
+        request.pattern.clone()
+    } else {
+        regex::escape(&request.pattern)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(request.case_insensitive.unwrap_or(false))
+        .multi_line(true)
+        .build()
+        .map_err(|e| format!("Invalid pattern: {}", e))
+}
+
+/// `text` with every match replaced, and the number of matches
+fn replace_all(matcher: &Regex, text: &str, replacement: &str, expand: bool) -> (String, usize) {
+    let matches = matcher.find_iter(text).count();
+    if matches == 0 {
+        return (text.to_string(), 0);
+    }
+    let replaced = if expand {
+        matcher.replace_all(text, replacement)
+    } else {
+        matcher.replace_all(text, NoExpand(replacement))
+    };
+    (replaced.into_owned(), matches)
+}
+
+/// A file the replacement changes
+struct Change {
+    path: PathBuf,
+    relative: String,
+    matches: usize,
+    old: String,
+    new: String,
+    encoding: TextEncoding,
+}
+
+/// Why a matching file is left alone, or `None` to look inside it
+fn skip_reason(path: &Path) -> Option<String> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Some(e.to_string()),
+    };
+    if size > max_read_bytes() as u64 {
+        return Some(format!("larger than the read limit of {} bytes", max_read_bytes()));
+    }
+    match vfs::backend().read_prefix(path, mime::SNIFF_BYTES) {
+        Ok(head) if !mime::looks_like_text(&head) => Some("binary".to_string()),
+        Ok(_) => None,
+        Err(e) => Some(e),
+    }
+}
+
+pub async fn replace_in_files(request: ReplaceInFilesRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(replace_in_files_blocking(request)).await
+}
+
+async fn replace_in_files_blocking(request: ReplaceInFilesRequest) -> HandlerResult<CallToolResult> {
+    let root = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(root) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if !root.is_dir() {
+        return Ok(CallToolResult::error(format!("Not a directory: {}", root.display())));
+    }
+    let glob = match Glob::new(&request.glob) {
+        Ok(glob) => glob.compile_matcher(),
+        Err(e) => return Ok(CallToolResult::error(format!("Invalid glob {:?}: {}", request.glob, e))),
+    };
+    let matcher = match build_matcher(&request) {
+        Ok(matcher) => matcher,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let rules = IgnoreRules::new(request.respect_gitignore.unwrap_or(true), request.include_hidden.unwrap_or(false))
+        .with_overrides(&request.ignore);
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let apply = request.apply.unwrap_or(false) && !dry_run::requested(request.dry_run);
+    let expand = request.regex.unwrap_or(false);
+    let max_files = request.max_files.unwrap_or(DEFAULT_MAX_FILES).max(1);
+
+    let mut changes = Vec::new();
+    let mut skipped = Vec::new();
+    let mut scanned = 0;
+    let mut truncated = false;
+    for entry in rules.walker(root).build().flatten() {
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        // Forward slashes, so globs read the same on every platform
+        let relative = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        if !glob.is_match(&relative) {
+            continue;
+        }
+        if scanned >= max_files {
+            truncated = true;
+            break;
+        }
+        scanned += 1;
+        let path = entry.path().to_path_buf();
+        if let Some(reason) = skip_reason(&path) {
+            skipped.push(json!({ "path": relative, "reason": reason }));
+            continue;
+        }
+        if apply {
+            if let Err(msg) = validate_write_path_or_error(&path) {
+                skipped.push(json!({ "path": relative, "reason": msg }));
+                continue;
+            }
+        }
+        let decoded = match read_text_file(&path, None) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                skipped.push(json!({ "path": relative, "reason": e }));
+                continue;
+            }
+        };
+        let (new, matches) = replace_all(&matcher, &decoded.text, &request.replacement, expand);
+        if matches > 0 && new != decoded.text {
+            changes.push(Change {
+                path,
+                relative,
+                matches,
+                old: decoded.text,
+                new,
+                encoding: decoded.encoding,
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| a.relative.cmp(&b.relative));
+
+    // Write every file or none: a failure puts back the files written before it
+    let mut failure = None;
+    let mut not_restored = Vec::new();
+    if apply {
+        let mut written: Vec<(&Change, Vec<u8>)> = Vec::new();
+        for change in &changes {
+            let previous = match sandbox::read(&change.path) {
+                Ok(previous) => previous,
+                Err(e) => {
+                    failure = Some(format!("{}: {}", change.relative, e));
+                    break;
+                }
+            };
+            let result = shadow::write(&change.path, |target| write_text_file(target, &change.new, change.encoding, None));
+            if let Err(e) = result {
+                failure = Some(format!("{}: {}", change.relative, e.into_message()));
+                break;
+            }
+            written.push((change, previous));
+        }
+        if failure.is_some() {
+            for (change, previous) in written {
+                if let Err(e) = sandbox::write_with_mode(&change.path, &previous, WriteMode::Overwrite) {
+                    not_restored.push(json!({ "path": change.relative, "reason": e }));
+                }
+            }
+        }
+    }
+
+    let max_diff_bytes = request.max_diff_bytes.unwrap_or(DEFAULT_MAX_DIFF_BYTES);
+    let mut diff_bytes = 0;
+    let files: Vec<Value> = changes
+        .iter()
+        .map(|change| {
+            let mut file = json!({ "path": change.relative, "matches": change.matches });
+            match unified_diff(&change.relative, &change.old, &change.new) {
+                Ok(diff) if diff_bytes + diff.len() <= max_diff_bytes => {
+                    diff_bytes += diff.len();
+                    file["diff"] = json!(diff);
+                }
+                Ok(_) => file["diff_omitted"] = json!("max_diff_bytes reached"),
+                Err(e) => file["diff_omitted"] = json!(e),
+            }
+            file
+        })
+        .collect();
+    let mut report = json!({
+        "applied": apply && failure.is_none(),
+        "dry_run": !apply,
+        "files_scanned": scanned,
+        "files_changed": changes.len(),
+        "replacements": changes.iter().map(|change| change.matches).sum::<usize>(),
+        "files": files,
+        "skipped": skipped,
+        "truncated": truncated,
+    });
+    if let Some(failure) = &failure {
+        report["error"] = if not_restored.is_empty() {
+            json!(format!("Rolled back, nothing was changed. Failed to write {}", failure))
+        } else {
+            json!(format!(
+                "Failed to write {}. Rolling back failed for {} file(s), listed in not_restored, which keep the replacement",
+                failure,
+                not_restored.len()
+            ))
+        };
+        if !not_restored.is_empty() {
+            report["not_restored"] = json!(not_restored);
+        }
+    }
+    Ok(CallToolResult {
+        is_error: failure.is_some(),
+        ..CallToolResult::text(serde_json::to_string_pretty(&report).unwrap())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_text;
+    use crate::mcp::testing::test_env;
+
+    #[test]
+    fn test_replace_all() {
+        let literal = Regex::new(&regex::escape("a.b")).unwrap();
+        assert_eq!(replace_all(&literal, "a.b axb a.b", "$1", false), ("$1 axb $1".to_string(), 2));
+        let pattern = Regex::new(r"fn (\w+)_old").unwrap();
+        assert_eq!(
+            replace_all(&pattern, "fn load_old() {}\nfn save_old() {}", "fn ${1}_new", true),
+            ("fn load_new() {}\nfn save_new() {}".to_string(), 2)
+        );
+        assert_eq!(replace_all(&pattern, "nothing here", "x", true), ("nothing here".to_string(), 0));
+    }
+
+    #[tokio::test]
+    async fn test_replace_in_files() {
+        let (_env, temp_dir, _) = test_env().await;
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::write(root.join("src/a.rs"), "fn load_config() {}\nload_config();\n").unwrap();
+        fs::write(root.join("src/nested/b.rs"), "// calls load_config\r\n").unwrap();
+        fs::write(root.join("notes.md"), "load_config stays\n").unwrap();
+        fs::write(root.join("src/blob.rs"), b"load_config\x00\x01\x02").unwrap();
+
+        let request = |apply: bool| ReplaceInFilesRequest {
+            path: root.to_str().unwrap().to_string(),
+            glob: "src/**/*.rs".to_string(),
+            pattern: r"load_(\w+)".to_string(),
+            replacement: "read_$1".to_string(),
+            regex: Some(true),
+            case_insensitive: None,
+            apply: Some(apply),
+            max_diff_bytes: None,
+            max_files: None,
+            respect_gitignore: None,
+            include_hidden: None,
+            ignore: Default::default(),
+            dry_run: None,
+        };
+        let report = |result: CallToolResult| -> serde_json::Value {
+            let text = result_text(&result);
+            serde_json::from_str(text).unwrap()
+        };
+
+        // A dry run reports without writing
+        let dry_run = report(replace_in_files(request(false)).await.unwrap());
+        assert_eq!(dry_run["applied"], false);
+        assert_eq!(dry_run["files_changed"], 2);
+        assert_eq!(dry_run["replacements"], 3);
+        assert!(dry_run["files"][0]["diff"].as_str().unwrap().contains("+fn read_config() {}"));
+        assert_eq!(dry_run["skipped"][0]["reason"], "binary");
+        assert_eq!(fs::read_to_string(root.join("src/a.rs")).unwrap(), "fn load_config() {}\nload_config();\n");
+
+        let applied = report(replace_in_files(request(true)).await.unwrap());
+        assert_eq!(applied["applied"], true);
+        assert_eq!(fs::read_to_string(root.join("src/a.rs")).unwrap(), "fn read_config() {}\nread_config();\n");
+        // Line endings are kept, and files outside the glob left alone
+        assert_eq!(fs::read_to_string(root.join("src/nested/b.rs")).unwrap(), "// calls read_config\r\n");
+        assert_eq!(fs::read_to_string(root.join("notes.md")).unwrap(), "load_config stays\n");
+
+        let mut invalid = request(false);
+        invalid.pattern = "(".to_string();
+        assert!(replace_in_files(invalid).await.unwrap().is_error);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::mcp::session;
+use crate::mcp::state::unix_now;
+use crate::mcp::state::update_shared_document;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::validate_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Name of the state document holding reservations of every server process
+const RESERVATIONS_DOCUMENT: &str = "reservations";
+const DEFAULT_LEASE_SECONDS: u64 = 300;
+const MAX_LEASE_SECONDS: u64 = 3600;
+
+/// A lease on a file or directory tree held by one session
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Reservation {
+    /// canonical path of the reserved file or directory
+    pub path: String,
+    pub session: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// seconds since the unix epoch
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ReservationTable {
+    pub reservations: Vec<Reservation>,
+}
+
+impl ReservationTable {
+    fn drop_expired(&mut self, now: u64) {
+        self.reservations.retain(|r| r.expires_at > now);
+    }
+
+    /// Unexpired reservation by another session covering or inside `path`
+    fn conflict(&self, path: &Path, session: &str) -> Option<&Reservation> {
+        self.reservations.iter().find(|r| {
+            let reserved = Path::new(&r.path);
+            r.session != session && (path.starts_with(reserved) || reserved.starts_with(path))
+        })
+    }
+}
+
+fn describe(reservation: &Reservation) -> String {
+    match &reservation.label {
+        Some(label) => format!(
+            "{} (reserved by session {} for {}, expires in {}s)",
+            reservation.path,
+            reservation.session,
+            label,
+            reservation.expires_at.saturating_sub(unix_now())
+        ),
+        None => format!(
+            "{} (reserved by session {}, expires in {}s)",
+            reservation.path,
+            reservation.session,
+            reservation.expires_at.saturating_sub(unix_now())
+        ),
+    }
+}
+
+/// Error out when another session holds a reservation covering `path`
+pub fn check_not_reserved(path: &Path) -> Result<(), String> {
+    let path = canonical_path(path);
+    let conflict = update_shared_document(RESERVATIONS_DOCUMENT, |table: &mut ReservationTable| {
+        table.drop_expired(unix_now());
+        table.conflict(&path, &session::current_id()).map(describe)
+    });
+    match conflict {
+        Ok(Some(reservation)) => Err(format!(
+            "Access denied: {} is reserved by another session: {}",
+            path.display(),
+            reservation
+        )),
+        // Reservations are advisory, so an unreadable state directory does not block writes
+        Ok(None) | Err(_) => Ok(()),
+    }
+}
+
+/// Release everything a session holds, used when it ends
+pub fn release_session_reservations(session: &str) {
+    let _ = update_shared_document(RESERVATIONS_DOCUMENT, |table: &mut ReservationTable| {
+        table.reservations.retain(|r| r.session != session);
+    });
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReservePathsRequest {
+    /// Files or directories to reserve
+    pub paths: Vec<String>,
+    /// How long the reservation lasts. Defaults to 300, capped at 3600.
+    #[schemars(extend("default" = 300))]
+    pub lease_seconds: Option<u64>,
+    /// What the paths are reserved for, shown to other sessions
+    pub label: Option<String>,
+}
+
+pub async fn reserve_paths(request: ReservePathsRequest) -> HandlerResult<CallToolResult> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for path in &request.paths {
+        let path = &resolve_path(Path::new(path));
+        if let Err(msg) = validate_path_or_error(path) {
+            return Ok(CallToolResult::error(msg));
+        }
+        paths.push(canonical_path(path));
+    }
+
+    let lease = request
+        .lease_seconds
+        .unwrap_or(DEFAULT_LEASE_SECONDS)
+        .clamp(1, MAX_LEASE_SECONDS);
+    let session_id = session::current_id();
+    let result = update_shared_document(RESERVATIONS_DOCUMENT, |table: &mut ReservationTable| {
+        let now = unix_now();
+        table.drop_expired(now);
+
+        // All or nothing: refuse the whole request if any path conflicts
+        let conflicts: Vec<String> = paths
+            .iter()
+            .filter_map(|path| table.conflict(path, &session_id).map(describe))
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+
+        for path in &paths {
+            let path = path.to_string_lossy().into_owned();
+            // Renewing a path this session already holds extends the lease
+            table
+                .reservations
+                .retain(|r| !(r.session == session_id && r.path == path));
+            table.reservations.push(Reservation {
+                path,
+                session: session_id.clone(),
+                label: request.label.clone(),
+                expires_at: now + lease,
+            });
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(Ok(())) => Ok(CallToolResult::text(format!(
+            "Reserved {} path(s) for {}s:\n{}",
+            paths.len(),
+            lease,
+            paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join("\n")
+        ))),
+        Ok(Err(conflicts)) => Ok(CallToolResult::error(format!(
+            "No paths were reserved, some are held by another session:\n{}",
+            conflicts.join("\n")
+        ))),
+        Err(e) => Ok(CallToolResult::error(format!("Failed to update reservations: {}", e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReleasePathsRequest {
+    /// Paths to release. Releases all of this session's reservations when omitted.
+    pub paths: Option<Vec<String>>,
+}
+
+pub async fn release_paths(request: ReleasePathsRequest) -> HandlerResult<CallToolResult> {
+    let paths: Option<Vec<String>> = request.paths.map(|paths| {
+        paths
+            .iter()
+            .map(|p| canonical_path(&resolve_path(Path::new(p))).to_string_lossy().into_owned())
+            .collect()
+    });
+    let session_id = session::current_id();
+    let result = update_shared_document(RESERVATIONS_DOCUMENT, |table: &mut ReservationTable| {
+        let before = table.reservations.len();
+        table.reservations.retain(|r| {
+            r.session != session_id
+                || paths.as_ref().is_some_and(|paths| !paths.contains(&r.path))
+        });
+        before - table.reservations.len()
+    });
+
+    match result {
+        Ok(released) => Ok(CallToolResult::text(format!("Released {} reservation(s)", released))),
+        Err(e) => Ok(CallToolResult::error(format!("Failed to update reservations: {}", e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ListReservationsRequest {}
+
+pub async fn list_reservations(_request: ListReservationsRequest) -> HandlerResult<CallToolResult> {
+    let session_id = session::current_id();
+    let result = update_shared_document(RESERVATIONS_DOCUMENT, |table: &mut ReservationTable| {
+        table.drop_expired(unix_now());
+        table
+            .reservations
+            .iter()
+            .map(|r| {
+                let owner = if r.session == session_id { "this session" } else { "another session" };
+                format!("{} [{}]", describe(r), owner)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    match result {
+        Ok(lines) if lines.is_empty() => Ok(CallToolResult::text("No active reservations".to_string())),
+        Ok(lines) => Ok(CallToolResult::text(lines.join("\n"))),
+        Err(e) => Ok(CallToolResult::error(format!("Failed to read reservations: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::session::Session;
+    use crate::mcp::testing::result_text;
+    use crate::mcp::testing::test_env;
+    use crate::mcp::tools::overwrite_file;
+    use crate::mcp::tools::OverwriteFileRequest;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_reserved_paths_block_other_sessions() {
+        let (_env, temp_dir, _) = test_env().await;
+        let shared = temp_dir.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        let file = shared.join("notes.txt");
+
+        let (sender_a, _output_a) = tokio::sync::mpsc::unbounded_channel();
+        let (sender_b, _output_b) = tokio::sync::mpsc::unbounded_channel();
+        let a = Session::start(sender_a);
+        let b = Session::start(sender_b);
+        let reserve = |path: &Path| ReservePathsRequest {
+            paths: vec![path.to_str().unwrap().to_string()],
+            lease_seconds: Some(60),
+            label: Some("refactor".to_string()),
+        };
+        let overwrite = || OverwriteFileRequest {
+            path: file.to_str().unwrap().to_string(),
+            content: "mine".to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: None,
+            dry_run: None,
+        };
+
+        // One session holds the directory
+        session::scope(a.clone(), async {
+            assert!(!reserve_paths(reserve(&shared)).await.unwrap().is_error);
+            let listed = list_reservations(ListReservationsRequest {}).await.unwrap();
+            let text = result_text(&listed);
+            assert!(text.contains("refactor") && text.contains("[this session]"), "{}", text);
+        })
+        .await;
+
+        // The other can neither write below it nor reserve a path inside it
+        session::scope(b.clone(), async {
+            assert!(overwrite_file(overwrite()).await.unwrap().is_error);
+            assert!(reserve_paths(reserve(&file)).await.unwrap().is_error);
+        })
+        .await;
+        assert!(!file.exists());
+
+        // Once released, the other session can reserve and write it
+        session::scope(a.clone(), async {
+            assert!(!release_paths(ReleasePathsRequest { paths: None }).await.unwrap().is_error);
+        })
+        .await;
+        session::scope(b.clone(), async {
+            assert!(!reserve_paths(reserve(&file)).await.unwrap().is_error);
+            assert!(!overwrite_file(overwrite()).await.unwrap().is_error);
+        })
+        .await;
+        assert_eq!(fs::read_to_string(&file).unwrap(), "mine");
+
+        // Reservations of a session go away with it
+        b.end();
+        session::scope(a.clone(), async {
+            assert!(!reserve_paths(reserve(&file)).await.unwrap().is_error);
+        })
+        .await;
+        a.end();
+    }
+}
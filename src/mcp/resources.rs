@@ -6,40 +6,230 @@ use url::Url;
 use serde_json::json;
 use serde::{Deserialize, Serialize};
 use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::scratch::existing_scratch_directory;
+use crate::mcp::workspace::token_budget;
+use crate::mcp::workspace::workspace_summary;
+use crate::mcp::workspace::SUMMARY_URI;
+use crate::mcp::encoding::decode;
+use crate::mcp::encoding::detect_encoding;
+use crate::mcp::limits::max_read_bytes;
+use crate::mcp::mime;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::is_path_allowed;
+use crate::mcp::utilities::log_message;
+use crate::mcp::utilities::notify;
+use crate::mcp::utilities::shutting_down;
+use crate::mcp::ignore_rules::is_ignored;
+use crate::mcp::ignore_rules::walker_builder;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::vfs;
+use crate::mcp::vfs::FileKind;
+use crate::mcp::vfs::FileSystem;
+use crate::mcp::watch::watch_tree;
+use crate::mcp::config;
+use base64::Engine;
+use notify::event::ModifyKind;
+use notify::Event;
+use notify::EventKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::Duration;
+use rpc_router::HandlerError;
 
+/// Configured allowed directories plus the session's scratch directory
+fn allowed_directories_with_scratch() -> Vec<String> {
+    let mut allowed_dirs = get_allowed_directories();
+    if let Some(scratch) = existing_scratch_directory() {
+        allowed_dirs.push(scratch.display().to_string());
+    }
+    allowed_dirs
+}
+
+/// Resources per page of `resources/list`; `nextCursor` leads to the next one
+const RESOURCES_PAGE_SIZE: usize = 200;
+
+/// Files of the allowed directories, in a stable order, leaving out what the
+/// tools that walk trees leave out. Stops after `limit` files.
+fn file_paths(limit: usize) -> Vec<PathBuf> {
+    let fs = vfs::current();
+    let mut files = Vec::new();
+    for dir in get_allowed_directories() {
+        let room = limit.saturating_sub(files.len());
+        if room == 0 {
+            break;
+        }
+        let dir = PathBuf::from(dir);
+        if !fs.is_local() {
+            backend_files(fs.as_ref(), &dir, limit, &mut files);
+            continue;
+        }
+        let walker = walker_builder(&dir, true, false).sort_by_file_name(|a, b| a.cmp(b)).build();
+        files.extend(
+            walker
+                .flatten()
+                .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+                .map(|entry| entry.into_path())
+                .take(room),
+        );
+    }
+    files
+}
+
+/// [`file_paths`] on a backend other than the disk, which has no ignore files
+fn backend_files(fs: &dyn FileSystem, dir: &Path, limit: usize, files: &mut Vec<PathBuf>) {
+    let Ok(mut entries) = fs.list(dir) else {
+        return;
+    };
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for entry in entries {
+        if files.len() >= limit {
+            return;
+        }
+        let path = dir.join(&entry.name);
+        match entry.kind {
+            FileKind::File => files.push(path),
+            FileKind::Directory => backend_files(fs, &path, limit, files),
+            FileKind::Other => {}
+        }
+    }
+}
+
+fn file_resource(path: &Path) -> Option<Resource> {
+    Some(Resource {
+        uri: Url::from_file_path(path).ok()?,
+        name: path.display().to_string(),
+        description: None,
+        mime_type: None,
+    })
+}
 
+/// The fixed resources, then every file of the allowed directories, a page at
+/// a time. The cursor is the position of the first resource of the page.
 pub async fn resources_list(
-    _request: Option<ListResourcesRequest>,
+    request: Option<ListResourcesRequest>,
 ) -> HandlerResult<ListResourcesResult> {
-    let mut resources = Vec::new();
-    
+    let offset = match request.and_then(|request| request.cursor) {
+        Some(cursor) => cursor.parse::<usize>().map_err(|_| invalid_params(format!("Invalid cursor: {}", cursor)))?,
+        None => 0,
+    };
     // Always include the allowed_directories resource
-    resources.push(Resource {
+    let mut fixed = vec![Resource {
         uri: Url::parse("file:///api/allowed_directories").unwrap(),
         name: "Allowed Directories".to_string(),
         description: Some("List of directories that can be accessed".to_string()),
         mime_type: Some("application/json".to_string()),
-    }); 
-    
+    }];
+    // The summary walks the local disk, so other backends go without it
+    if vfs::current().is_local() {
+        fixed.push(Resource {
+            uri: Url::parse(SUMMARY_URI).unwrap(),
+            name: "Workspace Summary".to_string(),
+            description: Some("Overview of the allowed directories: project types, code stats, key files, recently modified files and a directory skeleton. Append ?budget=N to size it to about N tokens.".to_string()),
+            mime_type: Some("text/markdown".to_string()),
+        });
+    }
+    // One more than the page, to tell whether another follows
+    let files = file_paths((offset + RESOURCES_PAGE_SIZE + 1).saturating_sub(fixed.len()));
+    let mut resources: Vec<Resource> = fixed
+        .into_iter()
+        .chain(files.iter().filter_map(|path| file_resource(path)))
+        .skip(offset)
+        .take(RESOURCES_PAGE_SIZE + 1)
+        .collect();
+    let next_cursor = (resources.len() > RESOURCES_PAGE_SIZE).then(|| {
+        resources.truncate(RESOURCES_PAGE_SIZE);
+        (offset + RESOURCES_PAGE_SIZE).to_string()
+    });
+
     let response = ListResourcesResult {
         resources,
-        next_cursor: None,
+        next_cursor,
     };
     Ok(response)
 }
 
+fn invalid_params(message: String) -> HandlerError {
+    json!({"code": -32602, "message": message}).into_handler_error()
+}
+
+/// Contents of a file inside the allowed directories, typed by its first bytes.
+/// Text comes back as text, anything else base64 encoded.
+fn read_file_resource(uri: &Url) -> HandlerResult<ReadResourceResult> {
+    let path = uri
+        .to_file_path()
+        .map_err(|_| invalid_params(format!("Not a file path: {}", uri)))?;
+    let path = resolve_path(&path);
+    validate_path_or_error(&path).map_err(invalid_params)?;
+    let fs = vfs::current();
+    let stat = fs.stat(&path).map_err(|e| invalid_params(format!("Resource not found: {}", e)))?;
+    if !stat.is_file() {
+        return Err(invalid_params(format!("Not a file: {}", path.display())));
+    }
+    let limit = max_read_bytes();
+    if stat.len > limit as u64 {
+        return Err(invalid_params(format!(
+            "{} is {} bytes, more than the {} bytes a resource read returns. Use read_file or preview_file instead",
+            path.display(),
+            stat.len,
+            limit
+        )));
+    }
+    let bytes = fs.read(&path).map_err(invalid_params)?;
+    let mime_type = mime::detect(&path, &bytes[..bytes.len().min(mime::SNIFF_BYTES)]);
+    let text = if mime::is_text(mime_type) {
+        decode(&bytes, detect_encoding(&bytes)).ok()
+    } else {
+        None
+    };
+    let blob = match text {
+        Some(_) => None,
+        None => Some(base64::engine::general_purpose::STANDARD.encode(&bytes)),
+    };
+    Ok(ReadResourceResult {
+        contents: vec![ResourceContent {
+            uri: uri.clone(),
+            mime_type: Some(mime_type.to_string()),
+            text,
+            blob,
+        }],
+    })
+}
+
 pub async fn resource_read(request: ReadResourceRequest) -> HandlerResult<ReadResourceResult> {
+    if request.uri.scheme() == "workspace" && request.uri.host_str() == Some("summary") {
+        if !vfs::current().is_local() {
+            return Err(invalid_params(
+                "The workspace summary works on the local disk only and is not available with this backend".to_string(),
+            ));
+        }
+        let budget = token_budget(&request.uri);
+        return Ok(ReadResourceResult {
+            contents: vec![ResourceContent {
+                uri: request.uri.clone(),
+                mime_type: Some("text/markdown".to_string()),
+                text: Some(workspace_summary(budget)),
+                blob: None,
+            }],
+        });
+    }
     let response = match request.uri.path() {
         "/api/allowed_directories" => {
-            let allowed_dirs = get_allowed_directories();
+            let allowed_dirs = allowed_directories_with_scratch();
             ReadResourceResult {
-                contents: vec![TextResourceContents {
+                contents: vec![ResourceContent {
                     uri: request.uri.clone(),
                     mime_type: Some("application/json".to_string()),
-                    text: serde_json::to_string_pretty(&allowed_dirs).unwrap(),
+                    text: Some(serde_json::to_string_pretty(&allowed_dirs).unwrap()),
+                    blob: None,
                 }],
             }
         },
+        _ if request.uri.scheme() == "file" => return read_file_resource(&request.uri),
         _ => return Err(json!({"code": -32602, "message": "Resource not found"}).into_handler_error()),
     };
     Ok(response)
@@ -50,12 +240,110 @@ pub struct GetAllowedDirectoriesRequest {
 }
 
 pub async fn allowed_directories(_request: GetAllowedDirectoriesRequest) -> HandlerResult<ReadResourceResult> {
-    let allowed_dirs = get_allowed_directories();
+    let allowed_dirs = allowed_directories_with_scratch();
     Ok(ReadResourceResult {
-        contents: vec![TextResourceContents {
+        contents: vec![ResourceContent {
             uri: Url::parse("file:///api/allowed_directories").unwrap(),
             mime_type: Some("application/json".to_string()),
-            text: serde_json::to_string_pretty(&allowed_dirs).unwrap(),
+            text: Some(serde_json::to_string_pretty(&allowed_dirs).unwrap()),
+            blob: None,
         }],
     })
 }
+
+/// Files come and go in bursts, as on a checkout or a build; one notification
+/// is sent per burst
+const LIST_CHANGED_DELAY: Duration = Duration::from_millis(500);
+
+static WATCHING: AtomicBool = AtomicBool::new(false);
+/// Bumped by every `init`, ending the watching of the one before
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether `MCP_RS_FILESYSTEM_RESOURCE_NOTIFICATIONS`, set by
+/// `--resource-notifications`, asks to watch the allowed directories
+pub fn notifications_enabled() -> bool {
+    config::var("MCP_RS_FILESYSTEM_RESOURCE_NOTIFICATIONS")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Whether a change adds or removes a resource. Edits leave the list as it is.
+fn changes_resource_list(kind: &EventKind) -> bool {
+    matches!(kind, EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)))
+}
+
+/// Watch the allowed directories, sending `notifications/resources/list_changed`
+/// to every client when files are created, deleted or renamed in them, unless
+/// the ignore patterns leave them out of the list. Called again after a reload
+/// changed the allowed directories, it watches those in place of the old ones.
+pub fn init() {
+    if !notifications_enabled() {
+        return;
+    }
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let (sender, receiver) = mpsc::channel();
+    let mut watchers = Vec::new();
+    for dir in get_allowed_directories() {
+        let sender = sender.clone();
+        let root = PathBuf::from(&dir);
+        let matcher = IgnoreRules::new(true, false).matcher(&root);
+        let listed = move |path: &Path| {
+            is_path_allowed(path) && path.starts_with(&root) && !is_ignored(&matcher, path, path.is_dir())
+        };
+        let handler = move |result: notify::Result<Event>| {
+            if let Ok(event) = result {
+                if changes_resource_list(&event.kind) && event.paths.iter().any(|path| listed(path)) {
+                    let _ = sender.send(());
+                }
+            }
+        };
+        match watch_tree(Path::new(&dir), handler) {
+            Ok((watcher, _)) => watchers.push(watcher),
+            Err(e) => {
+                let message = format!("Not watching {} for resource changes: {}", dir, e);
+                log_message("warning", "resources", &message);
+            }
+        }
+    }
+    WATCHING.store(!watchers.is_empty(), Ordering::SeqCst);
+    if watchers.is_empty() {
+        return;
+    }
+    std::thread::spawn(move || {
+        // Kept alive for as long as the thread runs
+        let _watchers = watchers;
+        while !shutting_down() && GENERATION.load(Ordering::SeqCst) == generation {
+            match receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(()) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            std::thread::sleep(LIST_CHANGED_DELAY);
+            receiver.try_iter().for_each(drop);
+            notify("notifications/resources/list_changed", None);
+        }
+    });
+}
+
+/// Whether `init` started watching, so that
+/// `notifications/resources/list_changed` will be sent
+pub fn watching() -> bool {
+    WATCHING.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::CreateKind;
+    use notify::event::DataChange;
+    use notify::event::RenameMode;
+
+    #[test]
+    fn test_changes_resource_list() {
+        assert!(changes_resource_list(&EventKind::Create(CreateKind::File)));
+        assert!(changes_resource_list(&EventKind::Remove(notify::event::RemoveKind::Any)));
+        assert!(changes_resource_list(&EventKind::Modify(ModifyKind::Name(RenameMode::Both))));
+        assert!(!changes_resource_list(&EventKind::Modify(ModifyKind::Data(DataChange::Content))));
+        assert!(!changes_resource_list(&EventKind::Access(notify::event::AccessKind::Any)));
+    }
+}
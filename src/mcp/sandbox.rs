@@ -0,0 +1,807 @@
+use crate::mcp::scratch::existing_scratch_directory;
+use crate::mcp::unicode::nfc_path;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::vfs::DirEntry;
+use crate::mcp::vfs::FileKind;
+use crate::mcp::vfs::Stat;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::time::Duration;
+#[cfg(unix)]
+use std::time::UNIX_EPOCH;
+
+/// Symlinks followed while resolving one path before giving up, as the kernel does
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// How a write treats a file that already exists, or does not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Replace the file, creating it if needed
+    Overwrite,
+    /// Add to the end of an existing file
+    Append,
+    /// Create the file, failing if it already exists
+    CreateNew,
+    /// Add to the end of the file, creating it if needed
+    CreateOrAppend,
+}
+
+/// Values accepted for the `mode` parameter of the write tool
+pub const WRITE_MODE_NAMES: &[&str] = &["overwrite", "append", "create_new", "create_or_append"];
+
+impl WriteMode {
+    /// Parse a `mode` parameter. No value means overwrite.
+    pub fn parse(name: Option<&str>) -> Result<WriteMode, String> {
+        match name.map(|name| name.to_lowercase().replace('-', "_")).as_deref() {
+            None | Some("overwrite") => Ok(WriteMode::Overwrite),
+            Some("append") => Ok(WriteMode::Append),
+            Some("create_new") => Ok(WriteMode::CreateNew),
+            Some("create_or_append") => Ok(WriteMode::CreateOrAppend),
+            Some(other) => Err(format!(
+                "Unsupported write mode: {}. Expected one of: {}",
+                other,
+                WRITE_MODE_NAMES.join(", ")
+            )),
+        }
+    }
+
+    /// Whether the write adds to the file rather than replacing it
+    pub fn appends(self) -> bool {
+        matches!(self, WriteMode::Append | WriteMode::CreateOrAppend)
+    }
+}
+
+/// Resolve `path` the way the kernel walks it: each symlink is followed when it
+/// is met, so a later `..` leaves the real parent directory rather than the
+/// spelled one. Components that do not exist are kept as given, and a `..`
+/// back out of them looks at the disk again, since a tool may create the
+/// missing directories and then follow the rest of the path from there. The
+/// result is absolute and free of `.`, `..` and symlinks.
+///
+/// Paths are checked here. Tools then reach what they read or change from
+/// its root: files are opened with [`open_read`] and the like, and entries are
+/// created, renamed and removed in a [`Directory`] held open. On Unix that
+/// goes one component at a time without following symlinks, so a symlink
+/// swapped in after the check makes them fail rather than leave the allowed
+/// directories. Elsewhere the path is joined to the root and opened as is,
+/// with no such protection against reparse points swapped in meanwhile.
+pub fn resolve(path: &Path) -> Result<PathBuf, String> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().map_err(|e| e.to_string())?.join(path)
+    };
+    let mut pending: VecDeque<OsString> = absolute.components().map(|c| c.as_os_str().to_os_string()).collect();
+    let mut resolved = PathBuf::new();
+    let mut hops = 0;
+    let mut missing = false;
+
+    while let Some(part) = pending.pop_front() {
+        match Path::new(&part).components().next() {
+            Some(Component::Prefix(_)) | Some(Component::RootDir) => resolved.push(&part),
+            Some(Component::CurDir) | None => {}
+            Some(Component::ParentDir) => {
+                resolved.pop();
+                // What remains may exist, holding symlinks that must be followed
+                missing = false;
+            }
+            Some(Component::Normal(name)) => {
+                let candidate = resolved.join(name);
+                let metadata = if missing { None } else { candidate.symlink_metadata().ok() };
+                match metadata {
+                    Some(metadata) if metadata.is_symlink() => {
+                        hops += 1;
+                        if hops > MAX_SYMLINK_HOPS {
+                            return Err(format!("Too many levels of symbolic links: {}", path.display()));
+                        }
+                        let target = std::fs::read_link(&candidate).map_err(|e| e.to_string())?;
+                        if target.is_absolute() {
+                            resolved = PathBuf::new();
+                        }
+                        for component in target.components().rev() {
+                            pending.push_front(component.as_os_str().to_os_string());
+                        }
+                    }
+                    Some(_) => resolved = candidate,
+                    None => {
+                        // Nothing below a missing entry exists either, so the rest is lexical
+                        missing = true;
+                        resolved = candidate;
+                    }
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Device names Windows reserves in every directory
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The short spelling of a Windows `\\?\` long path: `\\?\C:\dir` becomes
+/// `C:\dir` and `\\?\UNC\server\share` becomes `\\server\share`. `None` when
+/// the path is not a verbatim one, or when dropping the prefix would change its
+/// meaning, as for names with trailing dots or reserved device names.
+pub fn strip_verbatim_prefix(path: &str) -> Option<String> {
+    let rest = path.strip_prefix(r"\\?\")?;
+    let short = if let Some(unc) = rest.strip_prefix(r"UNC\") {
+        format!(r"\\{}", unc)
+    } else {
+        let bytes = rest.as_bytes();
+        if bytes.len() < 2 || !bytes[0].is_ascii_alphabetic() || bytes[1] != b':' {
+            return None;
+        }
+        rest.to_string()
+    };
+    // Verbatim paths are taken literally, the short form would be reinterpreted
+    let literal_only = short.contains('/')
+        || short.trim_start_matches('\\').split('\\').skip(1).any(|name| {
+            let stem = name.split('.').next().unwrap_or_default();
+            name == "."
+                || name == ".."
+                || name.ends_with('.')
+                || name.ends_with(' ')
+                || RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        });
+    (!literal_only).then_some(short)
+}
+
+/// Drop the `\\?\` prefix `canonicalize` puts on Windows paths where it is not
+/// needed, so they compare equal to and read like what users configure. The
+/// standard library adds it back for long paths when calling the OS.
+pub fn simplify(path: PathBuf) -> PathBuf {
+    if cfg!(windows) {
+        if let Some(short) = path.to_str().and_then(strip_verbatim_prefix) {
+            return PathBuf::from(short);
+        }
+    }
+    path
+}
+
+/// Form of a path used for containment checks: NFC normalized, and on Windows
+/// also without the long path prefix and case folded, since its filesystems
+/// ignore case
+fn comparable(path: &Path) -> PathBuf {
+    let path = nfc_path(&simplify(path.to_path_buf()));
+    if cfg!(windows) {
+        PathBuf::from(path.to_string_lossy().to_lowercase())
+    } else {
+        path
+    }
+}
+
+/// Directories the server may touch: the allowed directories and this
+/// session's scratch directory, canonicalized
+fn roots() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = get_allowed_directories()
+        .iter()
+        .filter_map(|dir| Path::new(dir).canonicalize().ok())
+        .map(simplify)
+        .collect();
+    roots.extend(existing_scratch_directory().map(Path::to_path_buf));
+    roots
+}
+
+/// The root containing an already resolved path
+fn root_of(resolved: &Path) -> Option<PathBuf> {
+    let normalized = comparable(resolved);
+    roots().into_iter().find(|root| normalized.starts_with(comparable(root)))
+}
+
+/// Whether `path`, once resolved, lies inside an allowed directory
+pub fn is_contained(path: &Path) -> bool {
+    resolve(path).ok().and_then(|resolved| root_of(&resolved)).is_some()
+}
+
+/// How an entry is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write(WriteMode),
+    /// Read and write anywhere in the file, creating it if needed and keeping what it holds
+    Update,
+    /// Read or change the attributes of the entry, not its contents. The open never blocks, even on a FIFO.
+    Attributes,
+}
+
+/// The root holding `path`, once resolved and checked, and the rest of the way from there
+fn beneath(path: &Path) -> Result<(PathBuf, PathBuf), String> {
+    let resolved = resolve(path)?;
+    let root = root_of(&resolved)
+        .ok_or_else(|| format!("Access denied: {} is not within allowed directories", path.display()))?;
+    // The root matched component by component, possibly in another normalization form or case
+    let relative: PathBuf = resolved.components().skip(root.components().count()).collect();
+    Ok((root, relative))
+}
+
+/// The message for an error reaching `path` from its root
+fn failure(path: &Path, error: std::io::Error) -> String {
+    if is_symlink_error(&error) {
+        format!("Path changed while it was being opened: {}", path.display())
+    } else {
+        error.to_string()
+    }
+}
+
+/// Open a path inside the sandbox. The path is resolved and checked first,
+/// then opened one component at a time from its root without following
+/// symlinks, so a symlink swapped in after the check cannot redirect the open.
+fn open(path: &Path, access: Access) -> Result<File, String> {
+    let (root, relative) = beneath(path)?;
+    open_beneath(&root, &relative, access).map_err(|e| failure(path, e))
+}
+
+#[cfg(unix)]
+fn is_symlink_error(error: &std::io::Error) -> bool {
+    error.raw_os_error() == Some(libc::ELOOP)
+}
+
+#[cfg(not(unix))]
+fn is_symlink_error(_error: &std::io::Error) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn c_name(name: &OsStr) -> std::io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(name.as_bytes()).map_err(std::io::Error::other)
+}
+
+#[cfg(unix)]
+fn check(result: libc::c_int) -> std::io::Result<()> {
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Open `name` in `dir`, never following a symlink there
+#[cfg(unix)]
+fn openat(dir: &File, name: &OsStr, flags: libc::c_int) -> std::io::Result<File> {
+    use std::os::fd::AsRawFd;
+    use std::os::fd::FromRawFd;
+
+    let name = c_name(name)?;
+    // SAFETY: `dir` is an open directory and `name` a NUL-terminated string
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags | libc::O_NOFOLLOW | libc::O_CLOEXEC, 0o666) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just opened and is owned by nothing else
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(unix)]
+fn open_flags(access: Access) -> libc::c_int {
+    match access {
+        Access::Read => libc::O_RDONLY,
+        Access::Write(WriteMode::Overwrite) => libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+        Access::Write(WriteMode::Append) => libc::O_WRONLY | libc::O_APPEND,
+        Access::Write(WriteMode::CreateNew) => libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+        Access::Write(WriteMode::CreateOrAppend) => libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND,
+        Access::Update => libc::O_RDWR | libc::O_CREAT,
+        Access::Attributes => libc::O_RDONLY | libc::O_NONBLOCK,
+    }
+}
+
+/// Open the directory `relative` below `root`, one component at a time. With
+/// `create`, missing directories are made on the way, each in its parent as
+/// held open.
+#[cfg(unix)]
+fn open_dir_beneath(root: &Path, relative: &Path, create: bool) -> std::io::Result<File> {
+    use std::os::fd::AsRawFd;
+
+    let mut dir = File::open(root)?;
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            return Err(std::io::Error::other("unexpected path component"));
+        };
+        dir = match openat(&dir, name, libc::O_RDONLY | libc::O_DIRECTORY) {
+            Err(e) if create && e.kind() == std::io::ErrorKind::NotFound => {
+                let c_name = c_name(name)?;
+                // SAFETY: `dir` is an open directory and `c_name` a NUL-terminated string
+                match check(unsafe { libc::mkdirat(dir.as_raw_fd(), c_name.as_ptr(), 0o777) }) {
+                    // Made meanwhile, which does as well
+                    Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => return Err(e),
+                    _ => {}
+                }
+                openat(&dir, name, libc::O_RDONLY | libc::O_DIRECTORY)?
+            }
+            opened => opened?,
+        };
+    }
+    Ok(dir)
+}
+
+#[cfg(unix)]
+fn open_beneath(root: &Path, relative: &Path, access: Access) -> std::io::Result<File> {
+    let Some(name) = relative.file_name() else {
+        return File::open(root);
+    };
+    let dir = open_dir_beneath(root, relative.parent().unwrap_or(Path::new("")), false)?;
+    openat(&dir, name, open_flags(access))
+}
+
+#[cfg(not(unix))]
+fn open_beneath(root: &Path, relative: &Path, access: Access) -> std::io::Result<File> {
+    let path = root.join(relative);
+    let mut options = std::fs::OpenOptions::new();
+    match access {
+        Access::Read | Access::Attributes => options.read(true),
+        Access::Write(WriteMode::Overwrite) => options.write(true).create(true).truncate(true),
+        Access::Write(WriteMode::Append) => options.append(true),
+        Access::Write(WriteMode::CreateNew) => options.write(true).create_new(true),
+        Access::Write(WriteMode::CreateOrAppend) => options.append(true).create(true),
+        Access::Update => options.read(true).write(true).create(true).truncate(false),
+    };
+    options.open(path)
+}
+
+/// `fstatat` of `name` in `dir`, not following a symlink there
+#[cfg(unix)]
+fn stat_at(dir: &File, name: &OsStr) -> std::io::Result<libc::stat> {
+    use std::os::fd::AsRawFd;
+
+    let name = c_name(name)?;
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: `dir` is an open directory, `name` a NUL-terminated string and `stat` room for the result
+    check(unsafe { libc::fstatat(dir.as_raw_fd(), name.as_ptr(), stat.as_mut_ptr(), libc::AT_SYMLINK_NOFOLLOW) })?;
+    // SAFETY: `fstatat` succeeded, so it filled `stat` in
+    Ok(unsafe { stat.assume_init() })
+}
+
+#[cfg(unix)]
+fn is_symlink(stat: &libc::stat) -> bool {
+    stat.st_mode & libc::S_IFMT == libc::S_IFLNK
+}
+
+#[cfg(unix)]
+fn stat_of(stat: &libc::stat) -> Stat {
+    let kind = match stat.st_mode & libc::S_IFMT {
+        libc::S_IFDIR => FileKind::Directory,
+        libc::S_IFREG => FileKind::File,
+        _ => FileKind::Other,
+    };
+    let modified = u64::try_from(stat.st_mtime)
+        .ok()
+        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::new(secs, stat.st_mtime_nsec as u32)));
+    Stat {
+        kind,
+        len: stat.st_size as u64,
+        modified,
+        readonly: stat.st_mode & 0o222 == 0,
+    }
+}
+
+fn metadata_stat(metadata: &std::fs::Metadata) -> Stat {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_dir() {
+        FileKind::Directory
+    } else if file_type.is_file() {
+        FileKind::File
+    } else {
+        FileKind::Other
+    };
+    Stat {
+        kind,
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+        readonly: metadata.permissions().readonly(),
+    }
+}
+
+/// Names in a directory, other than `.` and `..`
+#[cfg(unix)]
+fn entries(dir: &File) -> std::io::Result<Vec<OsString>> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::ffi::OsStrExt;
+
+    // The stream takes its descriptor over, so it gets one of its own
+    // SAFETY: `dir` is an open directory
+    let fd = unsafe { libc::dup(dir.as_raw_fd()) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is an open directory owned by nothing else
+    let stream = unsafe { libc::fdopendir(fd) };
+    if stream.is_null() {
+        let error = std::io::Error::last_os_error();
+        // SAFETY: `fdopendir` failed, so `fd` is still ours to close
+        unsafe { libc::close(fd) };
+        return Err(error);
+    }
+    let mut names = Vec::new();
+    loop {
+        // SAFETY: `stream` is open until `closedir` below
+        let entry = unsafe { libc::readdir(stream) };
+        if entry.is_null() {
+            break;
+        }
+        // SAFETY: `d_name` of an entry returned by `readdir` is NUL-terminated
+        let name = unsafe { std::ffi::CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+        if name != b"." && name != b".." {
+            names.push(OsStr::from_bytes(name).to_os_string());
+        }
+    }
+    // SAFETY: `stream` came from `fdopendir` and is closed once, with `fd`
+    unsafe { libc::closedir(stream) };
+    Ok(names)
+}
+
+/// Remove `name` from `dir` with everything below it. Symlinks are removed,
+/// never followed.
+#[cfg(unix)]
+fn remove_tree(dir: &File, name: &OsStr) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let c_name = c_name(name)?;
+    let child = match openat(dir, name, libc::O_RDONLY | libc::O_DIRECTORY) {
+        Ok(child) => child,
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOTDIR) | Some(libc::ELOOP)) => {
+            // SAFETY: `dir` is an open directory and `c_name` a NUL-terminated string
+            return check(unsafe { libc::unlinkat(dir.as_raw_fd(), c_name.as_ptr(), 0) });
+        }
+        Err(e) => return Err(e),
+    };
+    for entry in entries(&child)? {
+        remove_tree(&child, &entry)?;
+    }
+    // SAFETY: as above
+    check(unsafe { libc::unlinkat(dir.as_raw_fd(), c_name.as_ptr(), libc::AT_REMOVEDIR) })
+}
+
+/// A directory inside the allowed directories, held open. Entries are made,
+/// renamed and removed in it by name, so they stay in this very directory
+/// whatever is renamed or swapped along the path it was reached by.
+pub struct Directory {
+    path: PathBuf,
+    #[cfg(unix)]
+    file: File,
+}
+
+/// Open the directory at `path` through the sandbox, creating it and its
+/// missing parents when `create` is set
+fn directory_at(path: &Path, create: bool) -> Result<Directory, String> {
+    let (root, relative) = beneath(path)?;
+    #[cfg(unix)]
+    let file = open_dir_beneath(&root, &relative, create).map_err(|e| failure(path, e))?;
+    #[cfg(not(unix))]
+    if create {
+        std::fs::create_dir_all(root.join(&relative)).map_err(|e| e.to_string())?;
+    }
+    Ok(Directory {
+        path: root.join(relative),
+        #[cfg(unix)]
+        file,
+    })
+}
+
+/// Open the directory at `path` through the sandbox
+pub fn directory(path: &Path) -> Result<Directory, String> {
+    directory_at(path, false)
+}
+
+/// The directory holding `path`, opened through the sandbox, and the name of
+/// `path` in it. The name itself is not resolved, so a symlink there is what
+/// gets renamed or removed, as with the usual calls.
+pub fn parent(path: &Path) -> Result<(Directory, OsString), String> {
+    let (Some(parent), Some(Component::Normal(name))) = (path.parent(), path.components().next_back()) else {
+        return Err(format!("Not an entry of a directory: {}", path.display()));
+    };
+    Ok((directory(parent)?, name.to_os_string()))
+}
+
+impl Directory {
+    fn failure(&self, name: &OsStr, error: std::io::Error) -> String {
+        failure(&self.path.join(name), error)
+    }
+
+    /// Open the entry `name`, failing if it is a symlink
+    pub fn open(&self, name: &OsStr, access: Access) -> Result<File, String> {
+        #[cfg(unix)]
+        let opened = openat(&self.file, name, open_flags(access));
+        #[cfg(not(unix))]
+        let opened = open_beneath(&self.path, Path::new(name), access);
+        opened.map_err(|e| self.failure(name, e))
+    }
+
+    pub fn create_dir(&self, name: &OsStr) -> Result<(), String> {
+        #[cfg(unix)]
+        let created = c_name(name).and_then(|c_name| {
+            use std::os::fd::AsRawFd;
+            // SAFETY: `self.file` is an open directory and `c_name` a NUL-terminated string
+            check(unsafe { libc::mkdirat(self.file.as_raw_fd(), c_name.as_ptr(), 0o777) })
+        });
+        #[cfg(not(unix))]
+        let created = std::fs::create_dir(self.path.join(name));
+        created.map_err(|e| self.failure(name, e))
+    }
+
+    /// Remove the file or symlink `name`
+    pub fn remove_file(&self, name: &OsStr) -> Result<(), String> {
+        #[cfg(unix)]
+        let removed = c_name(name).and_then(|c_name| {
+            use std::os::fd::AsRawFd;
+            // SAFETY: `self.file` is an open directory and `c_name` a NUL-terminated string
+            check(unsafe { libc::unlinkat(self.file.as_raw_fd(), c_name.as_ptr(), 0) })
+        });
+        #[cfg(not(unix))]
+        let removed = std::fs::remove_file(self.path.join(name));
+        removed.map_err(|e| self.failure(name, e))
+    }
+
+    /// Remove the empty directory `name`
+    pub fn remove_dir(&self, name: &OsStr) -> Result<(), String> {
+        #[cfg(unix)]
+        let removed = c_name(name).and_then(|c_name| {
+            use std::os::fd::AsRawFd;
+            // SAFETY: `self.file` is an open directory and `c_name` a NUL-terminated string
+            check(unsafe { libc::unlinkat(self.file.as_raw_fd(), c_name.as_ptr(), libc::AT_REMOVEDIR) })
+        });
+        #[cfg(not(unix))]
+        let removed = std::fs::remove_dir(self.path.join(name));
+        removed.map_err(|e| self.failure(name, e))
+    }
+
+    /// Remove `name` with everything below it, without following symlinks
+    pub fn remove_dir_all(&self, name: &OsStr) -> Result<(), String> {
+        #[cfg(unix)]
+        let removed = remove_tree(&self.file, name);
+        #[cfg(not(unix))]
+        let removed = std::fs::remove_dir_all(self.path.join(name));
+        removed.map_err(|e| self.failure(name, e))
+    }
+
+    /// Rename `name` to `to_name` in `to`, replacing what is there like `rename(2)`
+    pub fn rename(&self, name: &OsStr, to: &Directory, to_name: &OsStr) -> Result<(), String> {
+        #[cfg(unix)]
+        let renamed = c_name(name).and_then(|from| {
+            use std::os::fd::AsRawFd;
+            let target = c_name(to_name)?;
+            // SAFETY: both descriptors are open directories and both names NUL-terminated strings
+            check(unsafe { libc::renameat(self.file.as_raw_fd(), from.as_ptr(), to.file.as_raw_fd(), target.as_ptr()) })
+        });
+        #[cfg(not(unix))]
+        let renamed = std::fs::rename(self.path.join(name), to.path.join(to_name));
+        renamed.map_err(|e| self.failure(name, e))
+    }
+
+    /// Make `name` a symlink to `original`, which is stored as given
+    #[cfg(unix)]
+    pub fn symlink(&self, original: &Path, name: &OsStr) -> Result<(), String> {
+        use std::os::fd::AsRawFd;
+        let linked = c_name(original.as_os_str()).and_then(|original| {
+            let c_name = c_name(name)?;
+            // SAFETY: `self.file` is an open directory and both names NUL-terminated strings
+            check(unsafe { libc::symlinkat(original.as_ptr(), self.file.as_raw_fd(), c_name.as_ptr()) })
+        });
+        linked.map_err(|e| self.failure(name, e))
+    }
+}
+
+/// Create the directory at `path` and its missing parents through the sandbox
+pub fn create_dir_all(path: &Path) -> Result<(), String> {
+    directory_at(path, true).map(|_| ())
+}
+
+/// Rename `from` to `to` through the sandbox, each in its directory as held open
+pub fn rename(from: &Path, to: &Path) -> Result<(), String> {
+    let (from_dir, from_name) = parent(from)?;
+    let (to_dir, to_name) = parent(to)?;
+    from_dir.rename(&from_name, &to_dir, &to_name)
+}
+
+/// Remove the file or symlink at `path` through the sandbox
+pub fn remove_file(path: &Path) -> Result<(), String> {
+    let (dir, name) = parent(path)?;
+    dir.remove_file(&name)
+}
+
+/// Remove the empty directory at `path` through the sandbox
+pub fn remove_dir(path: &Path) -> Result<(), String> {
+    let (dir, name) = parent(path)?;
+    dir.remove_dir(&name)
+}
+
+/// Remove `path` with everything below it through the sandbox
+pub fn remove_dir_all(path: &Path) -> Result<(), String> {
+    let (dir, name) = parent(path)?;
+    dir.remove_dir_all(&name)
+}
+
+/// Open a file through the sandbox to read or change its attributes, such as
+/// its extended attributes, rather than its contents
+pub fn open_attributes(path: &Path) -> Result<File, String> {
+    open(path, Access::Attributes)
+}
+
+/// Set the permissions of `path` through the sandbox, on a handle of the file
+/// where the platform allows rather than by name
+pub fn set_permissions(path: &Path, permissions: std::fs::Permissions) -> Result<(), String> {
+    #[cfg(unix)]
+    let changed = open(path, Access::Attributes)?.set_permissions(permissions);
+    #[cfg(not(unix))]
+    let changed = {
+        let (root, relative) = beneath(path)?;
+        std::fs::set_permissions(root.join(relative), permissions)
+    };
+    changed.map_err(|e| failure(path, e))
+}
+
+/// Change the owner and group of `path` through the sandbox, leaving out what is `None`
+#[cfg(unix)]
+pub fn set_owner(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<(), String> {
+    std::os::unix::fs::fchown(open(path, Access::Attributes)?, uid, gid).map_err(|e| failure(path, e))
+}
+
+/// What `path` names, looked up through the sandbox. Symlinks count as what
+/// they point to, as in a `stat`, as long as that is inside the allowed
+/// directories. On Unix the entry is looked up in its directory as held open.
+pub fn stat(path: &Path) -> Result<Stat, String> {
+    let (root, relative) = beneath(path)?;
+    #[cfg(unix)]
+    if let Some(name) = relative.file_name() {
+        let dir = open_dir_beneath(&root, relative.parent().unwrap_or(Path::new("")), false)
+            .map_err(|e| failure(path, e))?;
+        let stat = stat_at(&dir, name).map_err(|e| failure(path, e))?;
+        // The resolved path holds no symlinks, so this one was swapped in after the check
+        if is_symlink(&stat) {
+            return Err(format!("Path changed while it was being opened: {}", path.display()));
+        }
+        return Ok(stat_of(&stat));
+    }
+    std::fs::metadata(root.join(relative))
+        .map(|metadata| metadata_stat(&metadata))
+        .map_err(|e| e.to_string())
+}
+
+/// Entries of the directory at `path`, listed through the sandbox. A symlink
+/// counts as what it points to inside the allowed directories, and as
+/// [`FileKind::Other`] when that lies outside them.
+pub fn list(path: &Path) -> Result<Vec<DirEntry>, String> {
+    let dir = directory(path)?;
+    #[cfg(unix)]
+    let names = entries(&dir.file).map_err(|e| failure(path, e))?;
+    #[cfg(not(unix))]
+    let names: Vec<OsString> = std::fs::read_dir(&dir.path)
+        .map_err(|e| e.to_string())?
+        .flatten()
+        .map(|entry| entry.file_name())
+        .collect();
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            #[cfg(unix)]
+            let kind = match stat_at(&dir.file, &name) {
+                Ok(stat) if is_symlink(&stat) => stat_kind(&dir.path.join(&name)),
+                Ok(stat) => stat_of(&stat).kind,
+                Err(_) => FileKind::Other,
+            };
+            #[cfg(not(unix))]
+            let kind = stat_kind(&dir.path.join(&name));
+            DirEntry {
+                name: name.to_string_lossy().into_owned(),
+                kind,
+            }
+        })
+        .collect())
+}
+
+fn stat_kind(path: &Path) -> FileKind {
+    stat(path).map(|stat| stat.kind).unwrap_or(FileKind::Other)
+}
+
+/// Open a file for reading through the sandbox, for callers that stream it
+pub fn open_read(path: &Path) -> Result<File, String> {
+    open(path, Access::Read)
+}
+
+/// Read a whole file through the sandbox
+pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    open(path, Access::Read)?.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+/// Read at most `limit` bytes from the start of a file through the sandbox
+pub fn read_prefix(path: &Path, limit: usize) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    open(path, Access::Read)?
+        .take(limit as u64)
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+/// Write a file through the sandbox. Existence checks are made by the open
+/// itself, so a file created concurrently is never clobbered by `CreateNew`.
+pub fn write_with_mode(path: &Path, bytes: &[u8], mode: WriteMode) -> Result<(), String> {
+    use std::io::Write;
+    open(path, Access::Write(mode))?.write_all(bytes).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::test_env;
+
+    #[test]
+    fn test_strip_verbatim_prefix() {
+        assert_eq!(strip_verbatim_prefix(r"\\?\C:\Users\me").as_deref(), Some(r"C:\Users\me"));
+        assert_eq!(strip_verbatim_prefix(r"\\?\UNC\server\share\dir").as_deref(), Some(r"\\server\share\dir"));
+        assert_eq!(strip_verbatim_prefix(r"C:\Users\me"), None);
+        // Only meaningful in the verbatim form
+        assert_eq!(strip_verbatim_prefix(r"\\?\C:\dir\name."), None);
+        assert_eq!(strip_verbatim_prefix(r"\\?\C:\dir\con.txt"), None);
+        assert_eq!(strip_verbatim_prefix(r"\\?\GLOBALROOT\Device"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_after_missing_component() {
+        let root = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        let outside_path = outside.path().canonicalize().unwrap();
+        std::os::unix::fs::symlink(&outside_path, root_path.join("link")).unwrap();
+
+        // Creating `new` and walking back out of it reaches the symlink, which leads outside
+        assert_eq!(resolve(&root_path.join("new/../link/x")).unwrap(), outside_path.join("x"));
+        assert_eq!(resolve(&root_path.join("new/deeper/../../link")).unwrap(), outside_path);
+        assert_eq!(resolve(&root_path.join("new/a/../b")).unwrap(), root_path.join("new/b"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_directory_held_across_swapped_symlink() {
+        let (_env, temp_dir, _) = test_env().await;
+        let outside = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/file"), "inside").unwrap();
+        std::fs::write(outside.path().join("file"), "outside").unwrap();
+
+        // Swap the directory for a symlink leading outside once it is held open
+        let (dir, name) = parent(&root.join("sub/file")).unwrap();
+        std::fs::rename(root.join("sub"), root.join("moved")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("sub")).unwrap();
+        dir.remove_file(&name).unwrap();
+        assert!(!root.join("moved/file").exists());
+        assert!(outside.path().join("file").exists());
+
+        // Reached afresh, the symlink is refused rather than followed
+        assert!(remove_file(&root.join("sub/file")).is_err());
+        assert!(rename(&root.join("sub/file"), &root.join("taken")).is_err());
+        assert!(outside.path().join("file").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stat_and_list_stay_inside() {
+        let (_env, temp_dir, _) = test_env().await;
+        let outside = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("file"), "inside").unwrap();
+        std::os::unix::fs::symlink(root.join("file"), root.join("inner")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("outer")).unwrap();
+
+        assert_eq!(stat(&root.join("inner")).unwrap().len, 6);
+        assert!(stat(&root.join("outer")).is_err());
+        let mut listed = list(&root).unwrap();
+        listed.sort_by(|a, b| a.name.cmp(&b.name));
+        let kinds: Vec<_> = listed.iter().map(|entry| (entry.name.as_str(), entry.kind)).collect();
+        assert_eq!(kinds, [("file", FileKind::File), ("inner", FileKind::File), ("outer", FileKind::Other)]);
+        assert!(list(&root.join("outer")).is_err());
+    }
+}
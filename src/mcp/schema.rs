@@ -0,0 +1,303 @@
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+/// JSON schema of the arguments of a tool taking `T`, in the form clients
+/// expect: inline, without `null` for arguments that may simply be left out
+pub fn input_schema<T: JsonSchema>() -> Value {
+    let generator = SchemaSettings::draft07()
+        .with(|settings| {
+            settings.inline_subschemas = true;
+            settings.meta_schema = None;
+        })
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<T>().to_value();
+    simplify(&mut schema);
+    if let Value::Object(root) = &mut schema {
+        // The tool itself carries the name and description
+        root.remove("title");
+        root.remove("description");
+        root.entry("properties").or_insert_with(|| json!({}));
+    }
+    schema
+}
+
+fn simplify(schema: &mut Value) {
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    if let Some(Value::String(description)) = schema.get_mut("description") {
+        *description = description.replace('\n', " ");
+    }
+    // Formats like `uint` are not JSON schema, the minimum already says it
+    if schema.get("format").and_then(Value::as_str).is_some_and(|format| format.contains("int")) {
+        schema.remove("format");
+    }
+    let required: Vec<Value> = schema.get("required").and_then(Value::as_array).cloned().unwrap_or_default();
+    if let Some(Value::Object(properties)) = schema.get_mut("properties") {
+        for (name, property) in properties.iter_mut() {
+            if !required.contains(&json!(name)) {
+                drop_null(property);
+            }
+        }
+    }
+    for (key, value) in schema.iter_mut() {
+        match key.as_str() {
+            "properties" => value.as_object_mut().into_iter().flat_map(Map::values_mut).for_each(simplify),
+            "items" | "additionalProperties" => simplify(value),
+            "oneOf" | "anyOf" | "allOf" => value.as_array_mut().into_iter().flatten().for_each(simplify),
+            _ => {}
+        }
+    }
+}
+
+/// Remove `null` from what an optional argument accepts. Leaving it out says
+/// the same, and is understood by every client.
+fn drop_null(schema: &mut Value) {
+    let Value::Object(map) = schema else {
+        return;
+    };
+    if let Some(Value::Array(types)) = map.get_mut("type") {
+        types.retain(|t| t != "null");
+        if types.len() == 1 {
+            let only = types.remove(0);
+            map.insert("type".to_string(), only);
+        }
+    }
+    if let Some(Value::Array(variants)) = map.get_mut("anyOf") {
+        variants.retain(|variant| variant != &json!({ "type": "null" }));
+        if variants.len() == 1 {
+            let Value::Object(only) = variants.remove(0) else {
+                return;
+            };
+            map.remove("anyOf");
+            for (key, value) in only {
+                map.entry(key).or_insert(value);
+            }
+        }
+    }
+}
+
+/// Check tool arguments against the schema of the tool. The error names the
+/// offending argument and what was expected of it.
+pub fn validate(schema: &Value, arguments: &Value) -> Result<(), String> {
+    check(schema, arguments, "")
+}
+
+fn check(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let Value::Object(schema) = schema else {
+        // `true` accepts anything, `false` nothing
+        return match schema {
+            Value::Bool(false) => Err(format!("{}is not allowed", at(path))),
+            _ => Ok(()),
+        };
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            return Err(format!("{}expected {}, got {}", at(path), types.join(" or "), type_name(value)));
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.iter().any(|allowed| same_name(allowed, value)) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            return Err(format!("{}expected one of {}, got {}", at(path), allowed.join(", "), value));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            return Err(format!("{}expected {}, got {}", at(path), constant, value));
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+            if number < minimum {
+                return Err(format!("{}must be at least {}, got {}", at(path), minimum, value));
+            }
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+            if number > maximum {
+                return Err(format!("{}must be at most {}, got {}", at(path), maximum, value));
+            }
+        }
+    }
+
+    if let Value::Object(object) = value {
+        for name in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+            let name = name.as_str().unwrap_or_default();
+            if object.get(name).is_none_or(Value::is_null) {
+                return Err(format!("{}missing required argument `{}`", at(path), name));
+            }
+        }
+        if let Some(Value::Object(properties)) = schema.get("properties") {
+            for (name, property) in properties {
+                // A null optional argument is the same as one left out
+                match object.get(name) {
+                    None | Some(Value::Null) => {}
+                    Some(value) => check(property, value, &join(path, name))?,
+                }
+            }
+        }
+    }
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            check(item_schema, item, &format!("{}[{}]", path, index))?;
+        }
+    }
+
+    for variant in schema.get("allOf").and_then(Value::as_array).into_iter().flatten() {
+        check(variant, value, path)?;
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(Value::Array(variants)) = schema.get(key) {
+            check_variants(variants, value, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// A value must match one of `variants`. Tagged enums, like the operations of
+/// `batch`, are told apart by a property with a constant value, so a value
+/// with a known tag is reported against its own variant.
+fn check_variants(variants: &[Value], value: &Value, path: &str) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for variant in variants {
+        match check(variant, value, path) {
+            Ok(()) => return Ok(()),
+            Err(e) => errors.push(e),
+        }
+    }
+    let tags: Vec<Option<(&String, &Value)>> = variants
+        .iter()
+        .map(|variant| {
+            let properties = variant.get("properties")?.as_object()?;
+            properties.iter().find_map(|(name, property)| Some((name, property.get("const")?)))
+        })
+        .collect();
+    for (tag, error) in tags.iter().zip(&errors) {
+        if let Some((name, tag)) = tag {
+            if value.get(name.as_str()) == Some(*tag) {
+                return Err(error.clone());
+            }
+        }
+    }
+    let tags: Vec<&(&String, &Value)> = tags.iter().flatten().collect();
+    match tags.first() {
+        Some((name, _)) => {
+            let allowed: Vec<String> = tags.iter().map(|(_, tag)| tag.to_string()).collect();
+            Err(format!("{}`{}` must be one of {}", at(path), name, allowed.join(", ")))
+        }
+        None => Err(errors.swap_remove(0)),
+    }
+}
+
+/// Whether `value` is the allowed enum value. Names match like the tools parse
+/// them, ignoring case and `-` versus `_`, so `UTF-8` is `utf-8`.
+fn same_name(allowed: &Value, value: &Value) -> bool {
+    let normalize = |name: &str| name.to_lowercase().replace(['-', '_'], "");
+    match (allowed, value) {
+        (Value::String(allowed), Value::String(value)) => normalize(allowed) == normalize(value),
+        _ => allowed == value,
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// Prefix naming the argument an error is about
+fn at(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("`{}`: ", path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::batch::BatchRequest;
+    use crate::mcp::tools::ReadFileRequest;
+
+    #[test]
+    fn test_input_schema() {
+        let schema = input_schema::<ReadFileRequest>();
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["required"], json!(["file_path"]));
+        assert_eq!(schema["properties"]["offset"]["type"], "integer");
+        assert_eq!(schema["properties"]["encoding"]["type"], "string");
+        assert!(schema["properties"]["encoding"]["enum"].as_array().unwrap().contains(&json!("utf-16le")));
+        assert!(schema.get("$schema").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = input_schema::<ReadFileRequest>();
+        assert!(validate(&schema, &json!({"file_path": "a.txt", "offset": 3, "encoding": null})).is_ok());
+        assert_eq!(
+            validate(&schema, &json!({"offset": 3})).unwrap_err(),
+            "missing required argument `file_path`"
+        );
+        assert_eq!(
+            validate(&schema, &json!({"file_path": 1})).unwrap_err(),
+            "`file_path`: expected string, got integer"
+        );
+        assert_eq!(
+            validate(&schema, &json!({"file_path": "a", "offset": -1})).unwrap_err(),
+            "`offset`: must be at least 0, got -1"
+        );
+        assert!(validate(&schema, &json!({"file_path": "a", "encoding": "UTF_8"})).is_ok());
+        assert!(validate(&schema, &json!({"file_path": "a", "encoding": "ebcdic"}))
+            .unwrap_err()
+            .starts_with("`encoding`: expected one of"));
+
+        // Operations of a batch are checked against the variant their tag names
+        let schema = input_schema::<BatchRequest>();
+        let operations = json!({"operations": [{"op": "read", "path": "a"}, {"op": "move", "source_path": "a"}]});
+        assert_eq!(
+            validate(&schema, &operations).unwrap_err(),
+            "`operations[1]`: missing required argument `target_path`"
+        );
+        let operations = json!({"operations": [{"op": "copy", "path": "a"}]});
+        assert!(validate(&schema, &operations)
+            .unwrap_err()
+            .starts_with("`operations[0]`: `op` must be one of \"read\""));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::mcp::sandbox;
+use crate::mcp::types::*;
+use crate::mcp::utilities::session_id;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+const SCRATCH_PREFIX: &str = "rs_filesystem-scratch-";
+/// File in each scratch directory that the server using it keeps locked
+const OWNER_LOCK: &str = ".owner.lock";
+/// Scratch directories without an owner lock, left by older versions, are
+/// removed once they are this old
+const STALE_SCRATCH_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+static SCRATCH_DIR: OnceLock<PathBuf> = OnceLock::new();
+/// Held open, and locked, for as long as the server runs
+static OWNER_LOCK_FILE: Mutex<Option<File>> = Mutex::new(None);
+static SCRATCH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// This session's scratch directory under the OS temp dir, created on first use.
+/// It is implicitly allowed and removed on shutdown.
+pub fn scratch_directory() -> std::io::Result<&'static Path> {
+    if let Some(dir) = SCRATCH_DIR.get() {
+        fs::create_dir_all(dir)?;
+        hold_owner_lock(dir)?;
+        return Ok(dir);
+    }
+    let dir = std::env::temp_dir().join(format!("{}{}", SCRATCH_PREFIX, session_id()));
+    fs::create_dir_all(&dir)?;
+    // Canonical, so allowed-path checks can compare against it directly
+    let dir = sandbox::simplify(dir.canonicalize()?);
+    let dir = SCRATCH_DIR.get_or_init(|| dir);
+    hold_owner_lock(dir)?;
+    Ok(dir)
+}
+
+/// Lock the owner file of the scratch directory, creating it again if the
+/// directory was removed, so other servers can tell it is in use
+fn hold_owner_lock(dir: &Path) -> std::io::Result<()> {
+    let path = dir.join(OWNER_LOCK);
+    let mut held = OWNER_LOCK_FILE.lock().unwrap();
+    if held.is_some() && path.exists() {
+        return Ok(());
+    }
+    let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path)?;
+    file.lock()?;
+    *held = Some(file);
+    Ok(())
+}
+
+/// Whether a scratch directory no longer belongs to a running server: its
+/// owner lock is free, or, without one, it has not changed for a day
+fn abandoned(dir: &Path) -> bool {
+    match File::open(dir.join(OWNER_LOCK)) {
+        // The lock goes away with the process that held it, however it ended
+        Ok(file) => file.try_lock().is_ok(),
+        Err(_) => fs::metadata(dir)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > STALE_SCRATCH_AGE),
+    }
+}
+
+/// The scratch directory if this session has created one
+pub fn existing_scratch_directory() -> Option<&'static Path> {
+    SCRATCH_DIR.get().map(PathBuf::as_path)
+}
+
+/// Create the scratch directory and sweep those of servers that are gone
+pub fn init() {
+    if let Ok(entries) = fs::read_dir(std::env::temp_dir()) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(SCRATCH_PREFIX) && abandoned(&entry.path()) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+    let _ = scratch_directory();
+}
+
+/// Remove this session's scratch directory, used on shutdown
+pub fn remove_scratch_directory() {
+    // Windows refuses to remove a file that is still open
+    OWNER_LOCK_FILE.lock().unwrap().take();
+    if let Some(dir) = existing_scratch_directory() {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+/// A fresh name inside the scratch directory
+fn scratch_entry_name(prefix: Option<&str>, suffix: Option<&str>) -> Result<String, String> {
+    let prefix = prefix.unwrap_or("tmp");
+    let suffix = suffix.unwrap_or("");
+    // Names must stay inside the scratch directory
+    for part in [prefix, suffix] {
+        if part.contains(['/', '\\']) || part.contains("..") {
+            return Err(format!("Invalid prefix or suffix: {:?}", part));
+        }
+    }
+    let n = SCRATCH_COUNTER.fetch_add(1, Ordering::SeqCst);
+    Ok(format!("{}-{}{}", prefix, n, suffix))
+}
+
+fn create_temp_file_in(dir: &Path, request: &CreateTempFileRequest) -> Result<PathBuf, String> {
+    loop {
+        let name = scratch_entry_name(request.prefix.as_deref(), request.suffix.as_deref())?;
+        let path = dir.join(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                if let Some(content) = &request.content {
+                    file.write_all(content.as_bytes()).map_err(|e| e.to_string())?;
+                }
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct CreateTempFileRequest {
+    /// Start of the file name. Defaults to tmp.
+    pub prefix: Option<String>,
+    /// End of the file name, e.g. an extension such as .json
+    pub suffix: Option<String>,
+    /// Initial content of the file
+    pub content: Option<String>,
+}
+
+pub async fn create_temp_file(request: CreateTempFileRequest) -> HandlerResult<CallToolResult> {
+    let result = scratch_directory()
+        .map_err(|e| format!("Failed to create scratch directory: {}", e))
+        .and_then(|dir| create_temp_file_in(dir, &request));
+    match result {
+        Ok(path) => Ok(CallToolResult::text(path.display().to_string())),
+        Err(e) => Ok(CallToolResult::error(format!("Error creating temporary file: {}", e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct CreateTempDirRequest {
+    /// Start of the directory name. Defaults to tmp.
+    pub prefix: Option<String>,
+}
+
+pub async fn create_temp_dir(request: CreateTempDirRequest) -> HandlerResult<CallToolResult> {
+    let result = scratch_directory()
+        .map_err(|e| format!("Failed to create scratch directory: {}", e))
+        .and_then(|dir| loop {
+            let path = dir.join(scratch_entry_name(request.prefix.as_deref(), None)?);
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(path),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        });
+    match result {
+        Ok(path) => Ok(CallToolResult::text(path.display().to_string())),
+        Err(e) => Ok(CallToolResult::error(format!("Error creating temporary directory: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_abandoned() {
+        let dir = TempDir::new().unwrap();
+        // Recently changed and without a lock file, as while a server sets it up
+        assert!(!abandoned(dir.path()));
+
+        let owner = File::create(dir.path().join(OWNER_LOCK)).unwrap();
+        owner.lock().unwrap();
+        assert!(!abandoned(dir.path()));
+        drop(owner);
+        assert!(abandoned(dir.path()));
+    }
+}
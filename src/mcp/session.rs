@@ -0,0 +1,209 @@
+use crate::mcp::types::*;
+use crate::mcp::utilities::session_id;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use rpc_router::RpcResource;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// A connected client. Over stdio there is exactly one; with `--socket` every
+/// connection is a session of its own, with its own watches, reservations,
+/// checkpoints and log level.
+pub struct Session {
+    id: String,
+    /// Lines for the client, written out by the connection in order
+    output: UnboundedSender<String>,
+    /// Index into the log levels of the least severe message sent to this client
+    log_level: AtomicUsize,
+    phase: Mutex<Phase>,
+    client: Mutex<Option<ClientInfo>>,
+    /// Cancellation flags of the requests in flight, by their JSON-encoded id
+    requests: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+/// Where a session is in the MCP handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Waiting for `initialize`
+    New,
+    /// `initialize` was answered, waiting for `notifications/initialized`
+    Initializing,
+    /// The handshake is done and every request is served
+    Ready,
+}
+
+/// What the client told about itself in `initialize`, and what was agreed on
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    /// The protocol version the server answered with
+    pub protocol_version: String,
+    pub client: Implementation,
+    pub capabilities: ClientCapabilities,
+}
+
+/// Methods a session may call before the handshake is done
+const HANDSHAKE_METHODS: &[&str] = &["initialize", "ping"];
+
+/// The session of a request, for handlers that need it. Passed as a router resource.
+#[derive(Clone, RpcResource)]
+pub struct CurrentSession(pub Arc<Session>);
+
+tokio::task_local! {
+    static CURRENT: Arc<Session>;
+}
+
+/// Sessions whose connections are open
+static SESSIONS: Mutex<Vec<Arc<Session>>> = Mutex::new(Vec::new());
+static SESSION_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl Session {
+    /// Register a session for a new connection. The first one takes the id of
+    /// the server process, so a single stdio client keeps the id it always had.
+    pub fn start(output: UnboundedSender<String>) -> Arc<Session> {
+        let n = SESSION_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let id = if n == 0 {
+            session_id().to_string()
+        } else {
+            format!("{}-{}", session_id(), n)
+        };
+        let session = Arc::new(Session {
+            id,
+            output,
+            log_level: AtomicUsize::new(0),
+            phase: Mutex::new(Phase::New),
+            client: Mutex::new(None),
+            requests: Mutex::new(HashMap::new()),
+        });
+        SESSIONS.lock().unwrap().push(Arc::clone(&session));
+        session
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Queue a line for the client. Lost when the connection already closed.
+    pub fn send(&self, line: String) {
+        let _ = self.output.send(line);
+    }
+
+    pub fn log_level(&self) -> usize {
+        self.log_level.load(Ordering::SeqCst)
+    }
+
+    pub fn set_log_level(&self, level: usize) {
+        self.log_level.store(level, Ordering::SeqCst);
+    }
+
+    pub fn phase(&self) -> Phase {
+        *self.phase.lock().unwrap()
+    }
+
+    pub fn client(&self) -> Option<ClientInfo> {
+        self.client.lock().unwrap().clone()
+    }
+
+    /// Whether `method` may be called in the current phase. Until the
+    /// handshake is done only the handshake itself and pings are served.
+    pub fn accepts(&self, method: &str) -> bool {
+        self.phase() == Phase::Ready || HANDSHAKE_METHODS.contains(&method)
+    }
+
+    /// Record the answered `initialize`. Fails when the session was already initialized.
+    pub fn initialize(&self, client: ClientInfo) -> Result<(), String> {
+        let mut phase = self.phase.lock().unwrap();
+        if *phase != Phase::New {
+            return Err("Session is already initialized".to_string());
+        }
+        *phase = Phase::Initializing;
+        *self.client.lock().unwrap() = Some(client);
+        Ok(())
+    }
+
+    /// `notifications/initialized` arrived. Ignored unless `initialize` came first.
+    pub fn mark_ready(&self) {
+        let mut phase = self.phase.lock().unwrap();
+        if *phase == Phase::Initializing {
+            *phase = Phase::Ready;
+        }
+    }
+
+    /// The cancellation flag of request `id`, registered until the request is finished
+    pub fn begin_request(&self, id: &Value) -> Arc<AtomicBool> {
+        Arc::clone(self.requests.lock().unwrap().entry(id.to_string()).or_default())
+    }
+
+    pub fn finish_request(&self, id: &Value) {
+        self.requests.lock().unwrap().remove(&id.to_string());
+    }
+
+    /// Set the cancellation flag of request `id`. Returns false when no such request is in flight.
+    pub fn cancel_request(&self, id: &Value) -> bool {
+        let requests = self.requests.lock().unwrap();
+        let Some(cancelled) = requests.get(&id.to_string()) else {
+            return false;
+        };
+        cancelled.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Release what the session holds once its connection closed
+    pub fn end(&self) {
+        SESSIONS.lock().unwrap().retain(|session| session.id != self.id);
+        crate::mcp::watch::unwatch_session(&self.id);
+        crate::mcp::reservations::release_session_reservations(&self.id);
+        crate::mcp::locks::release_session_locks(&self.id);
+        crate::mcp::checkpoint::discard_session(&self.id);
+        crate::mcp::budget::discard_session(&self.id);
+    }
+}
+
+/// Run `future`, a request of `session`, with the session as the current one
+pub async fn scope<F: Future>(session: Arc<Session>, future: F) -> F::Output {
+    CURRENT.scope(session, future).await
+}
+
+/// The session of the request being handled, if any
+pub fn current() -> Option<Arc<Session>> {
+    CURRENT.try_with(Arc::clone).ok()
+}
+
+/// Id of the session of the request being handled. Outside of a request, as
+/// in tests and background threads, that of the server process.
+pub fn current_id() -> String {
+    current().map_or_else(|| session_id().to_string(), |session| session.id().to_string())
+}
+
+/// Every session with an open connection
+pub fn all() -> Vec<Arc<Session>> {
+    SESSIONS.lock().unwrap().clone()
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct SessionInfoRequest {}
+
+pub async fn session_info(session: CurrentSession, _request: SessionInfoRequest) -> HandlerResult<CallToolResult> {
+    let CurrentSession(session) = session;
+    let client = session.client();
+    let info = json!({
+        "session_id": session.id(),
+        "phase": session.phase(),
+        "protocol_version": client.as_ref().map(|client| &client.protocol_version),
+        "client": client.as_ref().map(|client| &client.client),
+        "client_capabilities": client.as_ref().map(|client| &client.capabilities),
+        "connected_sessions": all().len(),
+    });
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&info).unwrap()))
+}
@@ -0,0 +1,274 @@
+use crate::mcp::checkpoint;
+use crate::mcp::config;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::scratch::scratch_directory;
+use crate::mcp::types::*;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::vfs;
+use serde_json::json;
+use serde_json::Value;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// Files smaller than this may shrink freely; below it a size check mostly
+/// produces noise
+const MIN_SIZE_FOR_SHRINK_CHECK: u64 = 1024;
+const DEFAULT_MAX_SHRINK_PERCENT: u64 = 50;
+
+static SHADOW_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Mutating tools go through a shadow copy first when the server was started
+/// with `--verify-writes`, which sets this variable
+pub fn verification_enabled() -> bool {
+    config::var("MCP_RS_FILESYSTEM_VERIFY_WRITES")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// How much smaller, in percent, a verified write may make an existing file
+fn max_shrink_percent() -> u64 {
+    config::var("MCP_RS_FILESYSTEM_VERIFY_MAX_SHRINK")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_SHRINK_PERCENT)
+        .min(100)
+}
+
+pub enum ShadowError {
+    /// The operation itself failed
+    Failed(String),
+    /// The operation succeeded on the shadow copy but did not pass verification,
+    /// so the real tree was left untouched
+    Rejected(Value),
+}
+
+impl ShadowError {
+    /// Tool result for this error, with `context` prefixed to plain failures
+    pub fn into_result(self, context: &str) -> CallToolResult {
+        let text = match self {
+            ShadowError::Failed(e) => format!("{}: {}", context, e),
+            ShadowError::Rejected(report) => serde_json::to_string_pretty(&report).unwrap(),
+        };
+        CallToolResult::error(text)
+    }
+
+    /// Message for places that report errors as plain strings, like batch results
+    pub fn into_message(self) -> String {
+        match self {
+            ShadowError::Failed(e) => e,
+            ShadowError::Rejected(report) => report.to_string(),
+        }
+    }
+}
+
+struct Check {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+fn check(name: &'static str, passed: bool, detail: String) -> Check {
+    Check { name, passed, detail }
+}
+
+fn report(path: &Path, checks: &[Check]) -> Result<(), ShadowError> {
+    if checks.iter().all(|c| c.passed) {
+        return Ok(());
+    }
+    Err(ShadowError::Rejected(json!({
+        "verification": "failed",
+        "path": path.display().to_string(),
+        "committed": false,
+        "checks": checks
+            .iter()
+            .map(|c| json!({ "check": c.name, "passed": c.passed, "detail": c.detail }))
+            .collect::<Vec<_>>(),
+    })))
+}
+
+/// Compare the shadow copy with the original. Checks only fail on problems the
+/// write introduced, so a file that was already broken can still be fixed.
+fn verify_content(path: &Path, before: Option<&[u8]>, after: &[u8]) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let is_json = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    if is_json {
+        let parses = |bytes: &[u8]| serde_json::from_slice::<Value>(bytes);
+        let was_valid = before.is_none_or(|before| parses(before).is_ok());
+        match parses(after) {
+            Ok(_) => checks.push(check("parses", true, "valid JSON".to_string())),
+            Err(e) => checks.push(check("parses", !was_valid, format!("invalid JSON: {}", e))),
+        }
+    }
+
+    if let Some(before) = before {
+        let was_text = !before.contains(&0);
+        let is_text = !after.contains(&0);
+        checks.push(check(
+            "text",
+            is_text || !was_text,
+            if is_text { "no NUL bytes".to_string() } else { "text file gained NUL bytes".to_string() },
+        ));
+
+        let (old, new) = (before.len() as u64, after.len() as u64);
+        let limit = max_shrink_percent();
+        let shrunk_too_much = old >= MIN_SIZE_FOR_SHRINK_CHECK && new < old - old * limit / 100;
+        checks.push(check(
+            "size_delta",
+            !shrunk_too_much,
+            format!("{} -> {} bytes (may shrink by at most {}%)", old, new, limit),
+        ));
+    }
+
+    let markers = |bytes: &[u8]| String::from_utf8_lossy(bytes).lines().any(|l| l.starts_with("<<<<<<< "));
+    if markers(after) && !before.is_some_and(markers) {
+        checks.push(check("conflict_markers", false, "write introduces merge conflict markers".to_string()));
+    }
+    checks
+}
+
+/// Perform a write to `path` that replaces the file. With verification enabled
+/// `write` runs against a shadow copy of the file in the scratch directory; the
+/// result is validated and only then copied over the real file.
+pub fn write<F>(path: &Path, write: F) -> Result<(), ShadowError>
+where
+    F: FnOnce(&Path) -> Result<(), String>,
+{
+    write_with_mode(path, WriteMode::Overwrite, write)
+}
+
+/// Like [`write`], for a `write` that uses `mode`. A verified write commits the
+/// same way: appends add only the new bytes to the end of the real file, so
+/// what others appended meanwhile stays, and `CreateNew` fails if the file
+/// appeared meanwhile.
+pub fn write_with_mode<F>(path: &Path, mode: WriteMode, write: F) -> Result<(), ShadowError>
+where
+    F: FnOnce(&Path) -> Result<(), String>,
+{
+    checkpoint::preserve(path).map_err(ShadowError::Failed)?;
+    if !verification_enabled() {
+        return write(path).map_err(ShadowError::Failed);
+    }
+
+    let shadow_dir = scratch_directory()
+        .map(|dir| dir.join("shadow"))
+        .map_err(|e| ShadowError::Failed(format!("Failed to create scratch directory: {}", e)))?;
+    // The caller's write is charged to the budget; the copies around it are not
+    let fs = vfs::backend();
+    fs.create_dir_all(&shadow_dir).map_err(ShadowError::Failed)?;
+    let n = SHADOW_COUNTER.fetch_add(1, Ordering::SeqCst);
+    // Keep the file name, and so the extension, that encoding detection and the checks look at
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let shadow = shadow_dir.join(format!("{}-{}", n, file_name));
+
+    let result = (|| {
+        let before = if fs.is_file(path) {
+            let before = fs.read(path).map_err(ShadowError::Failed)?;
+            fs.write(&shadow, &before, WriteMode::Overwrite).map_err(ShadowError::Failed)?;
+            Some(before)
+        } else {
+            None
+        };
+        write(&shadow).map_err(ShadowError::Failed)?;
+        let after = fs.read(&shadow).map_err(ShadowError::Failed)?;
+        report(path, &verify_content(path, before.as_deref(), &after))?;
+        let commit = match (mode, &before) {
+            (WriteMode::Append | WriteMode::CreateOrAppend, Some(before)) => match after.strip_prefix(&before[..]) {
+                Some(appended) => appended,
+                None => return Err(ShadowError::Failed("the append changed existing content".to_string())),
+            },
+            _ => &after[..],
+        };
+        fs.write(path, commit, mode).map_err(ShadowError::Failed)
+    })();
+    let _ = fs.remove_file(&shadow);
+    result
+}
+
+/// Policy checks for operations that remove or relocate `path`, such as moves
+/// and deletes. Does nothing unless verification is enabled.
+pub fn verify_removal(path: &Path) -> Result<(), ShadowError> {
+    if !verification_enabled() {
+        return Ok(());
+    }
+    let canonical = canonical_path(path);
+    let is_root = get_allowed_directories()
+        .iter()
+        .any(|root| canonical_path(&PathBuf::from(root)) == canonical);
+    report(
+        path,
+        &[check(
+            "not_allowed_root",
+            !is_root,
+            if is_root {
+                "an allowed directory cannot be moved or deleted".to_string()
+            } else {
+                "inside an allowed directory".to_string()
+            },
+        )],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::set_env;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+    use std::io::Write;
+
+    /// Overwrite `path` with `content` through a shadow copy
+    fn overwrite(path: &Path, content: &str) -> Result<(), ShadowError> {
+        write(path, |target| vfs::current().write(target, content.as_bytes(), WriteMode::Overwrite))
+    }
+
+    #[tokio::test]
+    async fn test_verify_writes_rejects_bad_content() {
+        let (_env, temp_dir, _) = test_env().await;
+        let _verify = set_env("MCP_RS_FILESYSTEM_VERIFY_WRITES", "1");
+
+        // Broken JSON never reaches the real file
+        let config = temp_dir.path().join("config.json");
+        fs::write(&config, "{\"a\": 1}").unwrap();
+        let Err(ShadowError::Rejected(report)) = overwrite(&config, "{\"a\": ") else {
+            panic!("broken JSON was not rejected");
+        };
+        assert_eq!(report["verification"], "failed");
+        assert_eq!(fs::read_to_string(&config).unwrap(), "{\"a\": 1}");
+
+        overwrite(&config, "{\"a\": 2}").unwrap_or_else(|e| panic!("{}", e.into_message()));
+        assert_eq!(fs::read_to_string(&config).unwrap(), "{\"a\": 2}");
+
+        // Large shrinks are refused
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&notes, "line\n".repeat(1000)).unwrap();
+        assert!(matches!(overwrite(&notes, "line\n"), Err(ShadowError::Rejected(_))));
+        assert_eq!(fs::metadata(&notes).unwrap().len(), 5000);
+
+        // An allowed directory itself cannot be moved away or deleted
+        assert!(verify_removal(temp_dir.path()).is_err());
+        assert!(verify_removal(&notes).is_ok());
+
+        // A verified append adds only its bytes, keeping what another writer appended meanwhile
+        let log = temp_dir.path().join("app.log");
+        fs::write(&log, "one\n").unwrap();
+        write_with_mode(&log, WriteMode::Append, |target| {
+            fs::OpenOptions::new().append(true).open(&log).unwrap().write_all(b"other\n").unwrap();
+            vfs::current().write(target, b"two\n", WriteMode::Append)
+        })
+        .unwrap_or_else(|e| panic!("{}", e.into_message()));
+        assert_eq!(fs::read_to_string(&log).unwrap(), "one\nother\ntwo\n");
+
+        // A verified create fails when the file appeared meanwhile
+        let created = temp_dir.path().join("created.txt");
+        let result = write_with_mode(&created, WriteMode::CreateNew, |target| {
+            fs::write(&created, "first").unwrap();
+            vfs::current().write(target, b"second", WriteMode::CreateNew)
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&created).unwrap(), "first");
+    }
+}
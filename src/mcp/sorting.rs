@@ -0,0 +1,116 @@
+use std::cmp::Ordering;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Orderings offered for directory listings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Digit runs compare by value, so `file2` sorts before `file10`
+    Natural,
+    /// Plain byte order of the names
+    Lexicographic,
+    /// Natural order that also ignores case and accents, so `Émile` sorts next to `emile`
+    Locale,
+}
+
+/// Values accepted for the `sort` parameter
+pub const SORT_ORDER_NAMES: &[&str] = &["natural", "lexicographic", "locale"];
+
+impl SortOrder {
+    /// Parse a `sort` parameter, defaulting to natural order
+    pub fn parse(name: Option<&str>) -> Result<SortOrder, String> {
+        match name.map(str::to_lowercase).as_deref() {
+            None | Some("") | Some("natural") => Ok(SortOrder::Natural),
+            Some("lexicographic") => Ok(SortOrder::Lexicographic),
+            Some("locale") => Ok(SortOrder::Locale),
+            Some(other) => Err(format!(
+                "Unsupported sort order: {}. Expected one of: {}",
+                other,
+                SORT_ORDER_NAMES.join(", ")
+            )),
+        }
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            SortOrder::Lexicographic => a.cmp(b),
+            SortOrder::Natural => natural_cmp(&a.to_lowercase(), &b.to_lowercase()).then_with(|| a.cmp(b)),
+            SortOrder::Locale => natural_cmp(&fold(a), &fold(b))
+                .then_with(|| natural_cmp(&a.to_lowercase(), &b.to_lowercase()))
+                .then_with(|| a.cmp(b)),
+        }
+    }
+}
+
+/// Lowercase and strip accents: decompose, then drop the combining marks
+fn fold(text: &str) -> String {
+    text.nfd().filter(|c| !is_combining_mark(*c)).collect::<String>().to_lowercase()
+}
+
+/// Compare two strings treating each run of ASCII digits as a number
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (digits_a, rest_a) = split_digits(a);
+                let (digits_b, rest_b) = split_digits(b);
+                let (value_a, value_b) = (digits_a.trim_start_matches('0'), digits_b.trim_start_matches('0'));
+                // Without leading zeros the longer run is the larger number
+                let ordering = value_a
+                    .len()
+                    .cmp(&value_b.len())
+                    .then_with(|| value_a.cmp(value_b))
+                    // `07` after `7`, so equal values still order consistently
+                    .then_with(|| digits_a.len().cmp(&digits_b.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                (a, b) = (rest_a, rest_b);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            }
+        }
+    }
+}
+
+fn split_digits(text: &str) -> (&str, &str) {
+    let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    text.split_at(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(order: SortOrder, names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        names.sort_by(|a, b| order.compare(a, b));
+        names
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let names = ["file10.txt", "File2.txt", "file1.txt", "éclair", "zebra", "eclair"];
+        assert_eq!(
+            sorted(SortOrder::Natural, &names),
+            ["eclair", "file1.txt", "File2.txt", "file10.txt", "zebra", "éclair"]
+        );
+        assert_eq!(
+            sorted(SortOrder::Lexicographic, &names),
+            ["File2.txt", "eclair", "file1.txt", "file10.txt", "zebra", "éclair"]
+        );
+        assert_eq!(
+            sorted(SortOrder::Locale, &names),
+            ["eclair", "éclair", "file1.txt", "File2.txt", "file10.txt", "zebra"]
+        );
+        assert!(SortOrder::parse(Some("random")).is_err());
+    }
+}
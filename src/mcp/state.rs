@@ -0,0 +1,479 @@
+use crate::mcp::config;
+use crate::mcp::dry_run;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::vfs;
+use chrono::Local;
+use dirs::data_local_dir;
+use dirs::home_dir;
+use dirs::state_dir;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use serde::de::DeserializeOwned;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Bump this whenever the layout of a persisted document changes.
+/// Documents written with another version are discarded on startup.
+pub const STATE_SCHEMA_VERSION: u32 = 1;
+
+/// Envelope written around every persisted document
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Persisted<T> {
+    pub schema_version: u32,
+    /// seconds since the unix epoch
+    pub saved_at: u64,
+    /// allowed directories at the time the document was written
+    pub roots: Vec<String>,
+    pub data: T,
+}
+
+/// Directory where the server keeps state between restarts.
+/// `MCP_RS_FILESYSTEM_STATE_DIR` overrides the platform default.
+pub fn state_directory() -> PathBuf {
+    if let Ok(dir) = config::var("MCP_RS_FILESYSTEM_STATE_DIR") {
+        return PathBuf::from(dir);
+    }
+    if cfg!(target_os = "linux") {
+        // Linux: ~/.local/state/rs_filesystem
+        state_dir()
+            .unwrap_or_else(|| {
+                home_dir()
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join(".local/state")
+            })
+            .join("rs_filesystem")
+    } else {
+        // macOS: ~/Library/Application Support/rs_filesystem
+        // Windows: %LOCALAPPDATA%\rs_filesystem
+        data_local_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("rs_filesystem")
+    }
+}
+
+/// Subdirectory holding the documents, so that a state directory shared with
+/// other programs never has their files taken for ours
+fn documents_directory() -> PathBuf {
+    state_directory().join("documents")
+}
+
+fn document_path(name: &str) -> PathBuf {
+    documents_directory().join(format!("{}.json", name))
+}
+
+/// The `.json` files directly in `dir`
+fn json_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().and_then(|e| e.to_str()) == Some("json"))
+        .collect()
+}
+
+/// The schema version of a file in this server's envelope, or `None` for a
+/// file that is not one of its documents
+fn envelope_version(path: &Path) -> Option<u64> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    schema_version_of(&value)
+}
+
+/// The schema version of a document in this server's envelope
+fn schema_version_of(value: &Value) -> Option<u64> {
+    let envelope = value.as_object()?;
+    if !["saved_at", "roots", "data"].iter().all(|key| envelope.contains_key(*key)) {
+        return None;
+    }
+    envelope.get("schema_version")?.as_u64()
+}
+
+/// Seconds since the unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Create the state directory and drop documents written under another schema version
+pub fn init() {
+    sweep(&state_directory(), &documents_directory());
+}
+
+/// Move documents an earlier version kept at the top of the state directory
+/// into the documents directory, and remove those of another schema version.
+/// Files that do not carry the envelope are left alone wherever they are.
+fn sweep(state: &Path, documents: &Path) {
+    if fs::create_dir_all(documents).is_err() {
+        return;
+    }
+    let current = STATE_SCHEMA_VERSION as u64;
+    for path in json_files(state) {
+        match envelope_version(&path) {
+            Some(version) if version == current => {
+                let target = documents.join(path.file_name().unwrap_or_default());
+                if !target.exists() {
+                    let _ = fs::rename(&path, target);
+                }
+            }
+            Some(_) => {
+                let _ = fs::remove_file(&path);
+            }
+            None => {}
+        }
+    }
+    for path in json_files(documents) {
+        if envelope_version(&path).is_some_and(|version| version != current) {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// Persist a named document. The write goes through a temporary file so a
+/// crash never leaves a half-written document behind.
+pub fn save_document<T: Serialize>(name: &str, data: &T) -> std::io::Result<()> {
+    let envelope = Persisted {
+        schema_version: STATE_SCHEMA_VERSION,
+        saved_at: unix_now(),
+        roots: get_allowed_directories(),
+        data,
+    };
+    let path = document_path(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec(&envelope)?)?;
+    fs::rename(&temp_path, &path)
+}
+
+/// Load a named document. Documents written under another schema version, or
+/// that cannot be read as `T`, are treated as missing. Whether the data is
+/// still current is for the caller to tell from `roots` and `saved_at`.
+pub fn load_document<T: DeserializeOwned>(name: &str) -> Option<Persisted<T>> {
+    let text = fs::read_to_string(document_path(name)).ok()?;
+    let persisted: Persisted<T> = serde_json::from_str(&text).ok()?;
+    (persisted.schema_version == STATE_SCHEMA_VERSION).then_some(persisted)
+}
+
+/// Update a document that is shared by every server process on this machine.
+/// The update runs while holding an exclusive lock on the document, and the
+/// allowed directories at save time are not checked. The document is only
+/// written back when the update changed it.
+pub fn update_shared_document<T, R>(name: &str, update: impl FnOnce(&mut T) -> R) -> std::io::Result<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let dir = documents_directory();
+    fs::create_dir_all(&dir)?;
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(format!("{}.lock", name)))?;
+    lock.lock()?;
+
+    let mut data = fs::read_to_string(document_path(name))
+        .ok()
+        .and_then(|text| serde_json::from_str::<Persisted<T>>(&text).ok())
+        .filter(|persisted| persisted.schema_version == STATE_SCHEMA_VERSION)
+        .map(|persisted| persisted.data)
+        .unwrap_or_default();
+    let before = serde_json::to_value(&data)?;
+    let result = update(&mut data);
+    if serde_json::to_value(&data)? != before {
+        save_document(name, &data)?;
+    }
+
+    lock.unlock()?;
+    Ok(result)
+}
+
+// --------- export / import -------
+
+/// Marker identifying a state bundle file
+const BUNDLE_FORMAT: &str = "rs_filesystem-state";
+
+/// Names of all documents currently in the state directory
+fn list_documents() -> Vec<String> {
+    let mut names: Vec<String> = json_files(&documents_directory())
+        .iter()
+        .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Every persisted document as the text of a bundle file, and how many there are
+fn bundle() -> Result<(String, usize), String> {
+    let mut documents = Map::new();
+    for name in list_documents() {
+        let text = fs::read_to_string(document_path(&name)).map_err(|e| e.to_string())?;
+        let value: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", name, e))?;
+        documents.insert(name, value);
+    }
+    let count = documents.len();
+    let bundle = json!({
+        "format": BUNDLE_FORMAT,
+        "schema_version": STATE_SCHEMA_VERSION,
+        "exported_at": Local::now().to_rfc3339(),
+        "roots": get_allowed_directories(),
+        "documents": documents,
+    });
+    Ok((serde_json::to_string_pretty(&bundle).unwrap(), count))
+}
+
+/// Write every persisted document into a single bundle file on the local disk.
+/// The tool writes its bundle through the request's backend instead.
+pub fn export_state(bundle_path: &Path) -> Result<usize, String> {
+    let (text, count) = bundle()?;
+    fs::write(bundle_path, text).map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+/// Move every path in `value` that lies under `from` to the same place under
+/// `to`. Whole components must match, so `/work` leaves `/workshop` alone.
+fn rebase_paths(value: &mut Value, from: &Path, to: &Path) {
+    match value {
+        Value::String(text) => {
+            if let Ok(rest) = Path::new(text.as_str()).strip_prefix(from) {
+                let rebased = if rest.as_os_str().is_empty() { to.to_path_buf() } else { to.join(rest) };
+                *text = rebased.to_string_lossy().into_owned();
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| rebase_paths(item, from, to)),
+        Value::Object(map) => map.values_mut().for_each(|item| rebase_paths(item, from, to)),
+        _ => {}
+    }
+}
+
+/// Documents the text of a bundle file restores, each with the path it goes to.
+/// With `rebase_roots`, paths under the exporting machine's allowed directories
+/// are rewritten to the local allowed directories, matched by position.
+fn read_bundle(text: &str, overwrite: bool, rebase_roots: bool) -> Result<Vec<(PathBuf, Value)>, String> {
+    let bundle: Value = serde_json::from_str(text).map_err(|e| format!("Invalid state bundle: {}", e))?;
+    if bundle.get("format").and_then(Value::as_str) != Some(BUNDLE_FORMAT) {
+        return Err("Invalid state bundle: missing format marker".to_string());
+    }
+    if bundle.get("schema_version").and_then(Value::as_u64) != Some(STATE_SCHEMA_VERSION as u64) {
+        return Err(format!(
+            "State bundle schema version {} is not supported (expected {})",
+            bundle.get("schema_version").unwrap_or(&Value::Null),
+            STATE_SCHEMA_VERSION
+        ));
+    }
+
+    let exported_roots: Vec<String> = bundle
+        .get("roots")
+        .and_then(|roots| serde_json::from_value(roots.clone()).ok())
+        .unwrap_or_default();
+    let local_roots = get_allowed_directories();
+    if rebase_roots && exported_roots.len() != local_roots.len() {
+        return Err(format!(
+            "Cannot rebase roots: the bundle has {} allowed directories but {} are configured here",
+            exported_roots.len(),
+            local_roots.len()
+        ));
+    }
+
+    let documents = bundle.get("documents").and_then(Value::as_object).cloned().unwrap_or_default();
+    let mut imports = Vec::new();
+    for (name, mut document) in documents {
+        // Document names become file names, so keep them to a single plain component
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(format!("Invalid document name in state bundle: {}", name));
+        }
+        if schema_version_of(&document) != Some(STATE_SCHEMA_VERSION as u64) {
+            return Err(format!("Invalid document in state bundle: {} is not a state document", name));
+        }
+        let path = document_path(&name);
+        if path.exists() && !overwrite {
+            continue;
+        }
+        if rebase_roots {
+            for (from, to) in exported_roots.iter().zip(&local_roots) {
+                rebase_paths(&mut document, Path::new(from), Path::new(to));
+            }
+        }
+        imports.push((path, document));
+    }
+    Ok(imports)
+}
+
+/// Restore documents from a bundle file on the local disk, returning how many
+/// were imported. See `read_bundle` for what `rebase_roots` does.
+pub fn import_state(bundle_path: &Path, overwrite: bool, rebase_roots: bool) -> Result<usize, String> {
+    let text = fs::read_to_string(bundle_path).map_err(|e| e.to_string())?;
+    write_documents(&read_bundle(&text, overwrite, rebase_roots)?)
+}
+
+/// Write the documents of a bundle. Every document was checked by `read_bundle`
+/// before the first one is written, so a bad bundle changes nothing.
+fn write_documents(imports: &[(PathBuf, Value)]) -> Result<usize, String> {
+    fs::create_dir_all(documents_directory()).map_err(|e| e.to_string())?;
+    for (path, document) in imports {
+        fs::write(path, serde_json::to_vec(document).unwrap()).map_err(|e| e.to_string())?;
+    }
+    Ok(imports.len())
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ExportStateRequest {
+    /// Path of the bundle file to write
+    pub path: String,
+    /// Report the bundle's size and document count without writing it. Always the case when the server runs with
+    /// --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+pub async fn export_state_tool(request: ExportStateRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+
+    if dry_run::requested(request.dry_run) {
+        let change = bundle().and_then(|(text, count)| {
+            let mut change = dry_run::bytes_change(path, text.as_bytes(), false)?;
+            change["documents"] = json!(count);
+            Ok(change)
+        });
+        return match change {
+            Ok(change) => dry_run::result(vec![change]),
+            Err(e) => Ok(CallToolResult::error(format!("Failed to export state: {}", e))),
+        };
+    }
+
+    let exported = bundle().and_then(|(text, count)| {
+        vfs::current().write(path, text.as_bytes(), WriteMode::Overwrite)?;
+        Ok(count)
+    });
+    match exported {
+        Ok(count) => Ok(CallToolResult::text(format!("Exported {} state document(s) to {}", count, path.display()))),
+        Err(e) => Ok(CallToolResult::error(format!("Failed to export state: {}", e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ImportStateRequest {
+    /// Path of the bundle file to read
+    pub path: String,
+    /// Replace state documents that already exist. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub overwrite: Option<bool>,
+    /// Rewrite paths under the exporting machine's allowed directories to the local ones, matched by
+    /// position. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub rebase_roots: Option<bool>,
+    /// List the state documents that would be written without importing them. Always the case when the server
+    /// runs with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+pub async fn import_state_tool(request: ImportStateRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+
+    let (overwrite, rebase_roots) = (request.overwrite.unwrap_or(true), request.rebase_roots.unwrap_or(false));
+    // The bundle is read through the request's backend, the documents are the server's own
+    let imports = vfs::current()
+        .read(path)
+        .and_then(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string()))
+        .and_then(|text| read_bundle(&text, overwrite, rebase_roots));
+    if dry_run::requested(request.dry_run) {
+        return match imports {
+            Ok(imports) => dry_run::result(
+                imports
+                    .iter()
+                    .map(|(path, document)| {
+                        json!({
+                            "action": if path.exists() { "modify" } else { "create" },
+                            "path": path.display().to_string(),
+                            "size_after": serde_json::to_vec(document).unwrap().len(),
+                        })
+                    })
+                    .collect(),
+            ),
+            Err(e) => Ok(CallToolResult::error(format!("Failed to import state: {}", e))),
+        };
+    }
+
+    match imports.and_then(|imports| write_documents(&imports)) {
+        Ok(count) => Ok(CallToolResult::text(format!("Imported {} state document(s) from {}", count, path.display()))),
+        Err(e) => Ok(CallToolResult::error(format!("Failed to import state: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sweep_leaves_foreign_files() {
+        let state = TempDir::new().unwrap();
+        let documents = state.path().join("documents");
+        let envelope = |version: u32| {
+            json!({ "schema_version": version, "saved_at": 0, "roots": [], "data": {} }).to_string()
+        };
+        fs::write(state.path().join("package.json"), r#"{"name": "unrelated"}"#).unwrap();
+        fs::write(state.path().join("notes.json"), "not json at all").unwrap();
+        fs::write(state.path().join("reservations.json"), envelope(STATE_SCHEMA_VERSION)).unwrap();
+        fs::write(state.path().join("old.json"), envelope(STATE_SCHEMA_VERSION + 1)).unwrap();
+        fs::create_dir(&documents).unwrap();
+        fs::write(documents.join("stale.json"), envelope(STATE_SCHEMA_VERSION + 1)).unwrap();
+        fs::write(documents.join("stray.json"), "{}").unwrap();
+
+        sweep(state.path(), &documents);
+        assert!(state.path().join("package.json").exists());
+        assert!(state.path().join("notes.json").exists());
+        assert!(!state.path().join("reservations.json").exists());
+        assert!(documents.join("reservations.json").exists());
+        assert!(!state.path().join("old.json").exists());
+        assert!(!documents.join("stale.json").exists());
+        assert!(documents.join("stray.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_load_document() {
+        let (_env, _temp_dir, root) = crate::mcp::testing::test_env().await;
+        save_document("test-load", &vec![1, 2, 3]).unwrap();
+        let loaded = load_document::<Vec<u32>>("test-load").unwrap();
+        assert_eq!(loaded.data, vec![1, 2, 3]);
+        assert_eq!(loaded.roots, vec![root]);
+        // A document of another shape is not taken for this one
+        assert!(load_document::<String>("test-load").is_none());
+        assert!(load_document::<Vec<u32>>("test-missing").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rebase_paths() {
+        let mut document = json!({
+            "roots": ["/work"],
+            "paths": ["/work/src/main.rs", "/workshop/notes.txt", "relative/work"],
+        });
+        rebase_paths(&mut document, Path::new("/work"), Path::new("/home/me/work"));
+        assert_eq!(document["roots"], json!(["/home/me/work"]));
+        assert_eq!(document["paths"], json!(["/home/me/work/src/main.rs", "/workshop/notes.txt", "relative/work"]));
+    }
+}
@@ -0,0 +1,698 @@
+use crate::mcp::dry_run;
+use crate::mcp::encoding::read_text_file;
+use crate::mcp::encoding::write_text_preserving;
+use crate::mcp::shadow;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::validate_write_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::path::Path;
+use toml_edit::DocumentMut;
+use toml_edit::Item;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// The requested format, or the one the file extension names
+    fn parse(name: Option<&str>, path: &Path) -> Result<Format, String> {
+        let name = match name {
+            Some(name) => name.to_lowercase(),
+            None => path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase(),
+        };
+        match name.as_str() {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            _ => Err(format!(
+                "Cannot tell the format of {}. Pass format as json, yaml or toml",
+                path.display()
+            )),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+        }
+    }
+}
+
+/// One step of a selector
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// An object key, or an array index when it is a number
+    Name(String),
+    /// An array index written as `[N]`
+    Index(usize),
+    /// The position after the last array element, `-` in a JSON Pointer
+    End,
+}
+
+/// Parse a JSON Pointer (`/servers/0/port`) or a jq-like path
+/// (`.servers[0].port`, `.["key.with.dots"]`). An empty selector or `.`
+/// selects the whole document.
+fn parse_selector(selector: &str) -> Result<Vec<Segment>, String> {
+    let selector = selector.trim();
+    if selector.is_empty() || selector == "." {
+        return Ok(Vec::new());
+    }
+    if let Some(pointer) = selector.strip_prefix('/') {
+        return Ok(pointer
+            .split('/')
+            .map(|token| match token {
+                "-" => Segment::End,
+                token => Segment::Name(token.replace("~1", "/").replace("~0", "~")),
+            })
+            .collect());
+    }
+
+    let invalid = || format!("Invalid selector: {}", selector);
+    let mut segments = Vec::new();
+    let mut chars = selector.chars().peekable();
+    if chars.peek() == Some(&'.') {
+        chars.next();
+    }
+    while let Some(&c) = chars.peek() {
+        match c {
+            '.' => {
+                chars.next();
+            }
+            '[' => {
+                chars.next();
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    let mut key = String::new();
+                    loop {
+                        match chars.next().ok_or_else(invalid)? {
+                            '\\' => key.push(chars.next().ok_or_else(invalid)?),
+                            '"' => break,
+                            c => key.push(c),
+                        }
+                    }
+                    segments.push(Segment::Name(key));
+                } else {
+                    let mut index = String::new();
+                    while let Some(c) = chars.next_if(|c| *c != ']') {
+                        index.push(c);
+                    }
+                    segments.push(match index.trim() {
+                        "" | "-" => Segment::End,
+                        index => Segment::Index(index.parse().map_err(|_| invalid())?),
+                    });
+                }
+                if chars.next() != Some(']') {
+                    return Err(invalid());
+                }
+            }
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = chars.next_if(|c| *c != '.' && *c != '[') {
+                    key.push(c);
+                }
+                segments.push(Segment::Name(key));
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn describe(segment: &Segment) -> String {
+    match segment {
+        Segment::Name(name) => name.clone(),
+        Segment::Index(index) => index.to_string(),
+        Segment::End => "-".to_string(),
+    }
+}
+
+/// Array position a segment refers to
+fn array_index(segment: &Segment, len: usize, allow_end: bool) -> Result<usize, String> {
+    let index = match segment {
+        Segment::Index(index) => *index,
+        Segment::Name(name) => name
+            .parse()
+            .map_err(|_| format!("Expected an array index, found {:?}", name))?,
+        Segment::End if allow_end => return Ok(len),
+        Segment::End => return Err("- only selects a position to append at".to_string()),
+    };
+    let limit = if allow_end { len } else { len.saturating_sub(1) };
+    if index > limit || (!allow_end && len == 0) {
+        return Err(format!("Index {} is out of range for an array of {} elements", index, len));
+    }
+    Ok(index)
+}
+
+fn select<'a>(root: &'a Value, segments: &[Segment]) -> Result<&'a Value, String> {
+    let mut current = root;
+    for (depth, segment) in segments.iter().enumerate() {
+        let missing = || {
+            format!(
+                "Nothing at {}",
+                segments[..=depth].iter().map(describe).collect::<Vec<_>>().join("/")
+            )
+        };
+        current = match (current, segment) {
+            (Value::Object(map), Segment::Name(name)) => map.get(name).ok_or_else(missing)?,
+            (Value::Array(items), segment) => &items[array_index(segment, items.len(), false).map_err(|_| missing())?],
+            _ => return Err(missing()),
+        };
+    }
+    Ok(current)
+}
+
+/// Set the value at `segments`, creating missing objects on the way.
+/// Returns the value that was replaced, if any.
+fn set_json(root: &mut Value, segments: &[Segment], value: Value) -> Result<Option<Value>, String> {
+    let Some((last, parents)) = segments.split_last() else {
+        return Ok(Some(std::mem::replace(root, value)));
+    };
+    let mut current = root;
+    for segment in parents {
+        current = match current {
+            Value::Object(map) => match segment {
+                Segment::Name(name) => map.entry(name.clone()).or_insert_with(|| json!({})),
+                _ => return Err(format!("Cannot index an object with {}", describe(segment))),
+            },
+            Value::Array(items) => {
+                let index = array_index(segment, items.len(), false)?;
+                &mut items[index]
+            }
+            _ => return Err(format!("Cannot descend into a scalar at {}", describe(segment))),
+        };
+    }
+    match current {
+        Value::Object(map) => match last {
+            Segment::Name(name) => Ok(map.insert(name.clone(), value)),
+            _ => Err(format!("Cannot index an object with {}", describe(last))),
+        },
+        Value::Array(items) => {
+            let index = array_index(last, items.len(), true)?;
+            if index == items.len() {
+                items.push(value);
+                Ok(None)
+            } else {
+                Ok(Some(std::mem::replace(&mut items[index], value)))
+            }
+        }
+        _ => Err(format!("Cannot set {} on a scalar", describe(last))),
+    }
+}
+
+fn remove_json(root: &mut Value, segments: &[Segment]) -> Result<Value, String> {
+    let Some((last, parents)) = segments.split_last() else {
+        return Err("Cannot remove the whole document".to_string());
+    };
+    let mut current = root;
+    for segment in parents {
+        current = match current {
+            Value::Object(map) => match segment {
+                Segment::Name(name) => map.get_mut(name).ok_or_else(|| format!("Nothing at {}", name))?,
+                _ => return Err(format!("Cannot index an object with {}", describe(segment))),
+            },
+            Value::Array(items) => {
+                let index = array_index(segment, items.len(), false)?;
+                &mut items[index]
+            }
+            _ => return Err(format!("Cannot descend into a scalar at {}", describe(segment))),
+        };
+    }
+    match (current, last) {
+        // shift_remove keeps the order of the remaining keys
+        (Value::Object(map), Segment::Name(name)) => {
+            map.shift_remove(name).ok_or_else(|| format!("Nothing at {}", name))
+        }
+        (Value::Array(items), segment) => {
+            let index = array_index(segment, items.len(), false)?;
+            Ok(items.remove(index))
+        }
+        _ => Err(format!("Nothing at {}", describe(last))),
+    }
+}
+
+fn toml_value_to_json(value: &toml_edit::Value) -> Value {
+    match value {
+        toml_edit::Value::String(s) => json!(s.value()),
+        toml_edit::Value::Integer(i) => json!(i.value()),
+        toml_edit::Value::Float(f) => json!(f.value()),
+        toml_edit::Value::Boolean(b) => json!(b.value()),
+        toml_edit::Value::Datetime(d) => json!(d.value().to_string()),
+        toml_edit::Value::Array(array) => Value::Array(array.iter().map(toml_value_to_json).collect()),
+        toml_edit::Value::InlineTable(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_string(), toml_value_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn toml_to_json(item: &Item) -> Value {
+    match item {
+        Item::None => Value::Null,
+        Item::Value(value) => toml_value_to_json(value),
+        Item::Table(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, item)| (key.to_string(), toml_to_json(item)))
+                .collect(),
+        ),
+        Item::ArrayOfTables(tables) => Value::Array(
+            tables
+                .iter()
+                .map(|table| toml_to_json(&Item::Table(table.clone())))
+                .collect(),
+        ),
+    }
+}
+
+fn json_to_toml(value: &Value) -> Result<toml_edit::Value, String> {
+    Ok(match value {
+        Value::Null => return Err("TOML has no null value; use the remove operation instead".to_string()),
+        Value::Bool(b) => (*b).into(),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().ok_or_else(|| format!("Number out of range for TOML: {}", n))?.into(),
+        },
+        Value::String(s) => s.as_str().into(),
+        Value::Array(items) => {
+            let mut array = toml_edit::Array::new();
+            for item in items {
+                array.push(json_to_toml(item)?);
+            }
+            toml_edit::Value::Array(array)
+        }
+        Value::Object(map) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (key, value) in map {
+                table.insert(key, json_to_toml(value)?);
+            }
+            toml_edit::Value::InlineTable(table)
+        }
+    })
+}
+
+/// Where a selector leads inside a TOML document. Tables hold items, inline
+/// tables and arrays hold plain values, so both kinds are needed.
+enum TomlNode<'a> {
+    Table(&'a mut toml_edit::Table),
+    InlineTable(&'a mut toml_edit::InlineTable),
+    Array(&'a mut toml_edit::Array),
+    Tables(&'a mut toml_edit::ArrayOfTables),
+    Scalar,
+}
+
+impl<'a> TomlNode<'a> {
+    fn of_item(item: &'a mut Item) -> TomlNode<'a> {
+        match item {
+            Item::Table(table) => TomlNode::Table(table),
+            Item::ArrayOfTables(tables) => TomlNode::Tables(tables),
+            Item::Value(value) => TomlNode::of_value(value),
+            Item::None => TomlNode::Scalar,
+        }
+    }
+
+    fn of_value(value: &'a mut toml_edit::Value) -> TomlNode<'a> {
+        match value {
+            toml_edit::Value::InlineTable(table) => TomlNode::InlineTable(table),
+            toml_edit::Value::Array(array) => TomlNode::Array(array),
+            _ => TomlNode::Scalar,
+        }
+    }
+
+    /// The child at `segment`, creating a table for a missing key when `create` is set
+    fn child(self, segment: &Segment, create: bool) -> Result<TomlNode<'a>, String> {
+        let missing = || format!("Nothing at {}", describe(segment));
+        match (self, segment) {
+            (TomlNode::Table(table), Segment::Name(name)) => {
+                if !table.contains_key(name) {
+                    if !create {
+                        return Err(missing());
+                    }
+                    let mut new_table = toml_edit::Table::new();
+                    new_table.set_implicit(true);
+                    table.insert(name, Item::Table(new_table));
+                }
+                Ok(TomlNode::of_item(table.get_mut(name).unwrap()))
+            }
+            (TomlNode::InlineTable(table), Segment::Name(name)) => {
+                if !table.contains_key(name) {
+                    if !create {
+                        return Err(missing());
+                    }
+                    table.insert(name, toml_edit::Value::InlineTable(toml_edit::InlineTable::new()));
+                }
+                Ok(TomlNode::of_value(table.get_mut(name).unwrap()))
+            }
+            (TomlNode::Array(array), segment) => {
+                let index = array_index(segment, array.len(), false)?;
+                Ok(TomlNode::of_value(array.get_mut(index).unwrap()))
+            }
+            (TomlNode::Tables(tables), segment) => {
+                let index = array_index(segment, tables.len(), false)?;
+                Ok(TomlNode::Table(tables.get_mut(index).unwrap()))
+            }
+            _ => Err(missing()),
+        }
+    }
+}
+
+/// Set or remove a value in a TOML document in place, so comments and layout
+/// elsewhere in the file survive
+fn patch_toml(document: &mut DocumentMut, segments: &[Segment], value: Option<&Value>) -> Result<(), String> {
+    let Some((last, parents)) = segments.split_last() else {
+        return Err("Cannot replace or remove the whole TOML document".to_string());
+    };
+    let mut node = TomlNode::Table(document.as_table_mut());
+    for segment in parents {
+        node = node.child(segment, value.is_some())?;
+    }
+    let Some(value) = value else {
+        let removed = match (node, last) {
+            (TomlNode::Table(table), Segment::Name(name)) => table.remove(name).is_some(),
+            (TomlNode::InlineTable(table), Segment::Name(name)) => table.remove(name).is_some(),
+            (TomlNode::Array(array), segment) => {
+                array.remove(array_index(segment, array.len(), false)?);
+                true
+            }
+            (TomlNode::Tables(tables), segment) => {
+                tables.remove(array_index(segment, tables.len(), false)?);
+                true
+            }
+            _ => false,
+        };
+        return if removed { Ok(()) } else { Err(format!("Nothing at {}", describe(last))) };
+    };
+
+    let mut new_value = json_to_toml(value)?;
+    match (node, last) {
+        (TomlNode::Table(table), Segment::Name(name)) => match (table.get_mut(name), new_value) {
+            // Keep the comments and spacing around a replaced value
+            (Some(Item::Value(old)), mut new_value) => {
+                let decor = old.decor().clone();
+                *new_value.decor_mut() = decor;
+                *old = new_value;
+            }
+            (_, toml_edit::Value::InlineTable(inline)) => {
+                table.insert(name, Item::Table(inline.into_table()));
+            }
+            (_, new_value) => {
+                table.insert(name, Item::Value(new_value));
+            }
+        },
+        (TomlNode::InlineTable(table), Segment::Name(name)) => {
+            if let Some(old) = table.get_mut(name) {
+                *new_value.decor_mut() = old.decor().clone();
+            }
+            table.insert(name, new_value);
+        }
+        (TomlNode::Array(array), segment) => {
+            let index = array_index(segment, array.len(), true)?;
+            if index == array.len() {
+                array.push(new_value);
+            } else {
+                array.replace(index, new_value);
+            }
+        }
+        (TomlNode::Tables(tables), segment) => {
+            let toml_edit::Value::InlineTable(inline) = new_value else {
+                return Err("An array of tables only holds tables".to_string());
+            };
+            let index = array_index(segment, tables.len(), true)?;
+            if index == tables.len() {
+                tables.push(inline.into_table());
+            } else {
+                *tables.get_mut(index).unwrap() = inline.into_table();
+            }
+        }
+        _ => return Err(format!("Cannot set {} here", describe(last))),
+    }
+    Ok(())
+}
+
+fn parse_document(text: &str, format: Format) -> Result<Value, String> {
+    match format {
+        Format::Json => serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {}", e)),
+        Format::Yaml => serde_norway::from_str(text).map_err(|e| format!("Invalid YAML: {}", e)),
+        Format::Toml => {
+            let document: DocumentMut = text.parse().map_err(|e| format!("Invalid TOML: {}", e))?;
+            Ok(toml_to_json(document.as_item()))
+        }
+    }
+}
+
+/// Serialize JSON in the style of the original text: its indentation, or on
+/// one line if it was compact, and with its trailing newline
+fn format_json(value: &Value, original: &str) -> String {
+    let indent = original
+        .lines()
+        .skip(1)
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .find(|indent| !indent.is_empty());
+    let mut text = match indent {
+        Some(indent) => {
+            let mut out = Vec::new();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+            value.serialize(&mut serializer).unwrap();
+            String::from_utf8(out).unwrap()
+        }
+        None if original.trim().lines().count() <= 1 && !original.trim().is_empty() => value.to_string(),
+        None => serde_json::to_string_pretty(value).unwrap(),
+    };
+    if original.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReadStructuredRequest {
+    /// File to read
+    pub path: String,
+    /// JSON Pointer or jq-like path. Defaults to the whole document
+    pub selector: Option<String>,
+    /// File format. Defaults to the file extension
+    #[schemars(extend("enum" = ["json", "yaml", "toml"]))]
+    pub format: Option<String>,
+}
+
+pub async fn read_structured(request: ReadStructuredRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let selector = request.selector.unwrap_or_default();
+    let parsed = Format::parse(request.format.as_deref(), path)
+        .and_then(|format| parse_selector(&selector).map(|segments| (format, segments)));
+    let (format, segments) = match parsed {
+        Ok(parsed) => parsed,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let document = match read_text_file(path, None).and_then(|decoded| parse_document(&decoded.text, format)) {
+        Ok(document) => document,
+        Err(msg) => return Ok(CallToolResult::error(format!("Error reading {}: {}", path.display(), msg))),
+    };
+    match select(&document, &segments) {
+        Ok(value) => {
+            let type_name = match value {
+                Value::Null => "null",
+                Value::Bool(_) => "boolean",
+                Value::Number(_) => "number",
+                Value::String(_) => "string",
+                Value::Array(_) => "array",
+                Value::Object(_) => "object",
+            };
+            let result = json!({
+                "path": path.display().to_string(),
+                "format": format.name(),
+                "selector": selector,
+                "type": type_name,
+                "value": value,
+            });
+            Ok(CallToolResult::text(serde_json::to_string_pretty(&result).unwrap()))
+        }
+        Err(msg) => Ok(CallToolResult::error(msg)),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct PatchStructuredRequest {
+    /// File to change
+    pub path: String,
+    /// What to do
+    #[schemars(extend("enum" = ["set", "remove"]))]
+    pub operation: String,
+    /// JSON Pointer or jq-like path of the value
+    pub selector: String,
+    /// New value for set, as JSON
+    pub value: Option<Value>,
+    /// File format. Defaults to the file extension
+    #[schemars(extend("enum" = ["json", "yaml", "toml"]))]
+    pub format: Option<String>,
+    /// Return the diff without changing the file. Always the case when the server runs with --dry-run. Defaults
+    /// to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+pub async fn patch_structured(request: PatchStructuredRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_write_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let value = match (request.operation.as_str(), request.value) {
+        ("set", Some(value)) => Some(value),
+        ("set", None) => return Ok(CallToolResult::error("The set operation needs a value".to_string())),
+        ("remove", _) => None,
+        (other, _) => {
+            return Ok(CallToolResult::error(format!("Unknown operation {:?}, expected set or remove", other)))
+        }
+    };
+    let parsed = Format::parse(request.format.as_deref(), path)
+        .and_then(|format| parse_selector(&request.selector).map(|segments| (format, segments)));
+    let (format, segments) = match parsed {
+        Ok(parsed) => parsed,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let original = match read_text_file(path, None) {
+        Ok(decoded) => decoded.text,
+        Err(msg) => return Ok(CallToolResult::error(format!("Error reading {}: {}", path.display(), msg))),
+    };
+
+    let patched = match format {
+        Format::Toml => original
+            .parse::<DocumentMut>()
+            .map_err(|e| format!("Invalid TOML: {}", e))
+            .and_then(|mut document| {
+                patch_toml(&mut document, &segments, value.as_ref())?;
+                Ok(document.to_string())
+            }),
+        Format::Json | Format::Yaml => parse_document(&original, format).and_then(|mut document| {
+            match value {
+                Some(value) => set_json(&mut document, &segments, value).map(|_| ())?,
+                None => remove_json(&mut document, &segments).map(|_| ())?,
+            }
+            Ok(match format {
+                Format::Json => format_json(&document, &original),
+                _ => serde_norway::to_string(&document).map_err(|e| e.to_string())?,
+            })
+        }),
+    };
+    let patched = match patched {
+        Ok(patched) => patched,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    if dry_run::requested(request.dry_run) {
+        return match dry_run::preserving_change(path, &patched, None, None) {
+            Ok(change) => dry_run::result(vec![change]),
+            Err(msg) => Ok(CallToolResult::error(format!("Error reading {}: {}", path.display(), msg))),
+        };
+    }
+    if let Err(e) = shadow::write(path, |target| write_text_preserving(target, &patched, None, None)) {
+        return Ok(e.into_result("Failed to write file"));
+    }
+    let mut result = json!({
+        "path": path.display().to_string(),
+        "format": format.name(),
+        "operation": request.operation,
+        "selector": request.selector,
+    });
+    if format == Format::Yaml {
+        result["note"] = json!("YAML is rewritten from its parsed form, so comments and custom formatting are not kept");
+    }
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&result).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    #[test]
+    fn test_parse_selector() {
+        let expected = vec![
+            Segment::Name("servers".to_string()),
+            Segment::Index(0),
+            Segment::Name("a.b".to_string()),
+        ];
+        assert_eq!(parse_selector(r#".servers[0]["a.b"]"#).unwrap(), expected);
+        assert_eq!(
+            parse_selector("/servers/0/a~1b/-").unwrap(),
+            vec![
+                Segment::Name("servers".to_string()),
+                Segment::Name("0".to_string()),
+                Segment::Name("a/b".to_string()),
+                Segment::End,
+            ]
+        );
+        assert_eq!(parse_selector("").unwrap(), vec![]);
+        assert!(parse_selector(".a[x]").is_err());
+    }
+
+    #[test]
+    fn test_patch_toml_keeps_comments() {
+        let text = "# settings\n[server]\nport = 80 # default port\nhost = \"localhost\"\n";
+        let mut document: DocumentMut = text.parse().unwrap();
+        patch_toml(&mut document, &parse_selector(".server.port").unwrap(), Some(&json!(8080))).unwrap();
+        patch_toml(&mut document, &parse_selector(".server.host").unwrap(), None).unwrap();
+        patch_toml(&mut document, &parse_selector(".logging.level").unwrap(), Some(&json!("debug"))).unwrap();
+        assert_eq!(
+            document.to_string(),
+            "# settings\n[server]\nport = 8080 # default port\n\n[logging]\nlevel = \"debug\"\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_and_patch_structured() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let json_path = temp_dir.path().join("package.json");
+        fs::write(&json_path, "{\n    \"name\": \"demo\",\n    \"scripts\": {\"test\": \"jest\"},\n    \"tags\": []\n}\n").unwrap();
+        let patch = |path: &Path, operation: &str, selector: &str, value: Option<serde_json::Value>| PatchStructuredRequest {
+            path: path.to_string_lossy().into_owned(),
+            operation: operation.to_string(),
+            selector: selector.to_string(),
+            value,
+            format: None,
+            dry_run: None,
+        };
+        assert!(!patch_structured(patch(&json_path, "set", ".scripts.build", Some(json!("tsc")))).await.unwrap().is_error);
+        assert!(!patch_structured(patch(&json_path, "set", "/tags/-", Some(json!("cli")))).await.unwrap().is_error);
+        assert!(!patch_structured(patch(&json_path, "remove", ".name", None)).await.unwrap().is_error);
+        assert_eq!(
+            fs::read_to_string(&json_path).unwrap(),
+            "{\n    \"scripts\": {\n        \"test\": \"jest\",\n        \"build\": \"tsc\"\n    },\n    \"tags\": [\n        \"cli\"\n    ]\n}\n"
+        );
+        assert!(patch_structured(patch(&json_path, "remove", ".missing", None)).await.unwrap().is_error);
+
+        let yaml_path = temp_dir.path().join("compose.yml");
+        fs::write(&yaml_path, "services:\n  web:\n    image: nginx\n    ports: [\"80:80\"]\n").unwrap();
+        assert!(!patch_structured(patch(&yaml_path, "set", ".services.web.image", Some(json!("caddy")))).await.unwrap().is_error);
+        let result = read_structured(ReadStructuredRequest {
+            path: yaml_path.to_string_lossy().into_owned(),
+            selector: Some(".services.web".to_string()),
+            format: None,
+        })
+        .await
+        .unwrap();
+        let read = result_json(&result);
+        assert_eq!(read["value"], json!({"image": "caddy", "ports": ["80:80"]}));
+        assert_eq!(read["type"], "object");
+    }
+}
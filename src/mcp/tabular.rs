@@ -0,0 +1,456 @@
+use crate::mcp::encoding::read_text_file;
+use crate::mcp::limits::effective_limit;
+use crate::mcp::limits::max_result_entries;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// Delimiters tried when the file extension does not say
+const CANDIDATE_DELIMITERS: &[char] = &[',', '\t', ';', '|'];
+const DEFAULT_ROW_LIMIT: usize = 100;
+
+/// Records of delimiter separated text, one at a time. Quoted fields may
+/// contain the delimiter, doubled quotes and line breaks.
+pub struct Records<'a> {
+    chars: Peekable<Chars<'a>>,
+    delimiter: char,
+}
+
+pub fn records(text: &str, delimiter: char) -> Records<'_> {
+    // A byte order mark would otherwise become part of the first column name
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    Records {
+        chars: text.chars().peekable(),
+        delimiter,
+    }
+}
+
+impl Iterator for Records<'_> {
+    type Item = Vec<String>;
+
+    fn next(&mut self) -> Option<Vec<String>> {
+        self.chars.peek()?;
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        while let Some(c) = self.chars.next() {
+            match c {
+                '"' if in_quotes && self.chars.peek() == Some(&'"') => {
+                    self.chars.next();
+                    field.push('"');
+                }
+                '"' if in_quotes => in_quotes = false,
+                '"' if field.is_empty() => in_quotes = true,
+                c if c == self.delimiter && !in_quotes => record.push(std::mem::take(&mut field)),
+                '\r' if !in_quotes && self.chars.peek() == Some(&'\n') => {}
+                '\n' if !in_quotes => break,
+                c => field.push(c),
+            }
+        }
+        record.push(field);
+        Some(record)
+    }
+}
+
+/// The delimiter a file uses: from its extension, or the candidate that
+/// occurs most often in its first line
+pub fn detect_delimiter(path: &Path, text: &str) -> char {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+        Some("csv") => ',',
+        Some("tsv") | Some("tab") => '\t',
+        _ => {
+            let first_line = text.lines().next().unwrap_or_default();
+            CANDIDATE_DELIMITERS
+                .iter()
+                .copied()
+                .max_by_key(|d| first_line.matches(*d).count())
+                .unwrap_or(',')
+        }
+    }
+}
+
+/// What the values seen so far in a column could all be
+struct ColumnStats {
+    name: String,
+    non_empty: usize,
+    empty: usize,
+    integer: bool,
+    float: bool,
+    boolean: bool,
+    date: bool,
+    datetime: bool,
+    min: Option<f64>,
+    max: Option<f64>,
+    example: Option<String>,
+}
+
+fn is_date(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() == 10
+        && bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && bytes.iter().enumerate().all(|(i, b)| i == 4 || i == 7 || b.is_ascii_digit())
+}
+
+fn is_datetime(value: &str) -> bool {
+    if !value.get(..10).is_some_and(is_date) || !matches!(value.as_bytes().get(10), Some(b'T' | b' ')) {
+        return false;
+    }
+    let value = value.replacen(' ', "T", 1);
+    chrono::DateTime::parse_from_rfc3339(&value).is_ok()
+        || chrono::NaiveDateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M:%S%.f").is_ok()
+}
+
+impl ColumnStats {
+    fn new(name: String) -> ColumnStats {
+        ColumnStats {
+            name,
+            non_empty: 0,
+            empty: 0,
+            integer: true,
+            float: true,
+            boolean: true,
+            date: true,
+            datetime: true,
+            min: None,
+            max: None,
+            example: None,
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        let value = value.trim();
+        if value.is_empty() {
+            self.empty += 1;
+            return;
+        }
+        self.non_empty += 1;
+        if self.example.is_none() {
+            self.example = Some(value.to_string());
+        }
+        self.integer &= value.parse::<i64>().is_ok();
+        let number = value.parse::<f64>().ok().filter(|n| n.is_finite());
+        self.float &= number.is_some();
+        if let Some(number) = number {
+            self.min = Some(self.min.map_or(number, |min| min.min(number)));
+            self.max = Some(self.max.map_or(number, |max| max.max(number)));
+        }
+        self.boolean &= matches!(value.to_lowercase().as_str(), "true" | "false");
+        self.date &= is_date(value);
+        self.datetime &= is_datetime(value);
+    }
+
+    fn type_name(&self) -> &'static str {
+        if self.non_empty == 0 {
+            "empty"
+        } else if self.integer {
+            "integer"
+        } else if self.float {
+            "float"
+        } else if self.boolean {
+            "boolean"
+        } else if self.date {
+            "date"
+        } else if self.datetime {
+            "datetime"
+        } else {
+            "string"
+        }
+    }
+
+    fn report(&self) -> Value {
+        let type_name = self.type_name();
+        let mut report = json!({
+            "name": self.name,
+            "type": type_name,
+            "non_empty": self.non_empty,
+            "empty": self.empty,
+            "example": self.example,
+        });
+        if matches!(type_name, "integer" | "float") {
+            report["min"] = json!(self.min);
+            report["max"] = json!(self.max);
+        }
+        report
+    }
+}
+
+/// Text of a table file and the delimiter to split it with
+fn load(path_arg: &str, delimiter: Option<&str>) -> Result<(String, char), String> {
+    let path = &resolve_path(Path::new(path_arg));
+    validate_path_or_error(path)?;
+    let text = read_text_file(path, None)
+        .map_err(|e| format!("Error reading {}: {}", path.display(), e))?
+        .text;
+    let delimiter = match delimiter {
+        None => detect_delimiter(path, &text),
+        Some("\\t") | Some("tab") => '\t',
+        Some(d) if d.chars().count() == 1 => d.chars().next().unwrap(),
+        Some(d) => return Err(format!("Delimiter must be a single character, got {:?}", d)),
+    };
+    Ok((text, delimiter))
+}
+
+fn header_names(header: Option<Vec<String>>, width: usize) -> Vec<String> {
+    let mut names = header.unwrap_or_default();
+    for index in names.len()..width {
+        names.push(format!("column_{}", index + 1));
+    }
+    names
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct InspectCsvRequest {
+    /// Path of the file
+    pub path: String,
+    /// Field separator, a single character or "tab". Detected from the extension or the first line when
+    /// omitted.
+    pub delimiter: Option<String>,
+    /// Whether the first row holds the column names. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub has_header: Option<bool>,
+}
+
+pub async fn inspect_csv(request: InspectCsvRequest) -> HandlerResult<CallToolResult> {
+    let (text, delimiter) = match load(&request.path, request.delimiter.as_deref()) {
+        Ok(loaded) => loaded,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let mut rows = records(&text, delimiter);
+    let header = if request.has_header.unwrap_or(true) { rows.next() } else { None };
+    let mut columns: Vec<ColumnStats> = header
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .map(ColumnStats::new)
+        .collect();
+    let width = columns.len();
+    let mut row_count = 0;
+    let mut ragged_rows = 0;
+    for row in rows {
+        row_count += 1;
+        if header.is_some() && row.len() != width {
+            ragged_rows += 1;
+        }
+        for (index, value) in row.iter().enumerate() {
+            if index >= columns.len() {
+                let name = header_names(None, index + 1).pop().unwrap();
+                columns.push(ColumnStats::new(name));
+            }
+            columns[index].add(value);
+        }
+    }
+
+    let summary = json!({
+        "path": request.path,
+        "delimiter": delimiter.to_string(),
+        "has_header": header.is_some(),
+        "row_count": row_count,
+        "column_count": columns.len(),
+        "ragged_rows": ragged_rows,
+        "columns": columns.iter().map(ColumnStats::report).collect::<Vec<_>>(),
+    });
+    Ok(CallToolResult::structured(summary))
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReadCsvRowsRequest {
+    /// Path of the file
+    pub path: String,
+    /// Data row to start at, counting from 0 after the header. Defaults to 0.
+    #[schemars(extend("default" = 0))]
+    pub offset: Option<usize>,
+    /// Maximum number of rows to return. Defaults to 100.
+    #[schemars(extend("default" = 100))]
+    pub limit: Option<usize>,
+    /// Columns to return, by name or by 0-based position. Defaults to all columns.
+    #[schemars(extend("items" = { "type": ["string", "integer"] }))]
+    pub columns: Option<Vec<Value>>,
+    /// Field separator, a single character or "tab". Detected from the extension or the first line when
+    /// omitted.
+    pub delimiter: Option<String>,
+    /// Whether the first row holds the column names. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub has_header: Option<bool>,
+}
+
+pub async fn read_csv_rows(request: ReadCsvRowsRequest) -> HandlerResult<CallToolResult> {
+    let (text, delimiter) = match load(&request.path, request.delimiter.as_deref()) {
+        Ok(loaded) => loaded,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let mut rows = records(&text, delimiter);
+    let header = if request.has_header.unwrap_or(true) { rows.next() } else { None };
+    let width = header.as_ref().map_or(0, Vec::len);
+    let names = header_names(header, width);
+
+    let selected: Vec<usize> = match &request.columns {
+        None => (0..names.len()).collect(),
+        Some(columns) => {
+            let mut selected = Vec::new();
+            for column in columns {
+                let index = match column {
+                    Value::Number(n) => n.as_u64().map(|n| n as usize),
+                    Value::String(name) => names.iter().position(|n| n == name).or_else(|| name.parse().ok()),
+                    _ => None,
+                };
+                match index {
+                    Some(index) => selected.push(index),
+                    None => {
+                        return Ok(CallToolResult::error(format!(
+                            "Unknown column: {}. Columns are: {}",
+                            column,
+                            names.join(", ")
+                        )))
+                    }
+                }
+            }
+            selected
+        }
+    };
+
+    let offset = request.offset.unwrap_or(0);
+    let limit = effective_limit(max_result_entries(), Some(request.limit.unwrap_or(DEFAULT_ROW_LIMIT)));
+    let mut total = 0;
+    let mut returned = Vec::new();
+    for row in rows {
+        if total >= offset && returned.len() < limit {
+            // Without a header every column of the row is returned
+            let values: Vec<String> = if request.columns.is_none() && names.is_empty() {
+                row
+            } else {
+                selected.iter().map(|index| row.get(*index).cloned().unwrap_or_default()).collect()
+            };
+            returned.push(values);
+        }
+        total += 1;
+    }
+
+    let selected_names: Vec<String> = selected
+        .iter()
+        .map(|index| names.get(*index).cloned().unwrap_or_else(|| format!("column_{}", index + 1)))
+        .collect();
+    let mut result = json!({
+        "columns": selected_names,
+        "rows": returned,
+        "offset": offset,
+        "returned_rows": returned.len(),
+        "total_rows": total,
+    });
+    if offset + returned.len() < total {
+        result["next_offset"] = json!(offset + returned.len());
+    }
+    Ok(CallToolResult::structured(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    #[test]
+    fn test_records() {
+        let parsed: Vec<Vec<String>> =
+            records("name,note\r\nann,\"says \"\"hi\"\", twice\"\nbob,\"two\nlines\"\n", ',').collect();
+        assert_eq!(
+            parsed,
+            vec![
+                vec!["name", "note"],
+                vec!["ann", "says \"hi\", twice"],
+                vec!["bob", "two\nlines"],
+            ]
+        );
+        assert_eq!(records("a\nb\nc", ',').count(), 3);
+        assert_eq!(detect_delimiter(Path::new("data.txt"), "a;b;c\n1;2;3"), ';');
+    }
+
+    #[test]
+    fn test_column_types() {
+        let infer = |values: &[&str]| {
+            let mut column = ColumnStats::new("c".to_string());
+            values.iter().for_each(|value| column.add(value));
+            column.type_name()
+        };
+        assert_eq!(infer(&["1", "-2", ""]), "integer");
+        assert_eq!(infer(&["1", "2.5"]), "float");
+        assert_eq!(infer(&["true", "FALSE"]), "boolean");
+        assert_eq!(infer(&["2024-01-31", "2023-12-01"]), "date");
+        assert_eq!(infer(&["2024-01-31T10:00:00Z", "2024-02-01 08:30:00"]), "datetime");
+        assert_eq!(infer(&["1", "apple"]), "string");
+        assert_eq!(infer(&["", " "]), "empty");
+    }
+
+    #[tokio::test]
+    async fn test_inspect_csv_and_read_rows() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let path = temp_dir.path().join("orders.csv").to_string_lossy().into_owned();
+        fs::write(
+            &path,
+            "id,customer,total,shipped\n1,\"Smith, Ann\",12.5,2024-03-01\n2,bob,7,\n3,cy,20,2024-03-04\n",
+        )
+        .unwrap();
+
+        let result = inspect_csv(InspectCsvRequest {
+            path: path.clone(),
+            delimiter: None,
+            has_header: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let summary = result_json(&result);
+        assert_eq!(summary["row_count"], 3);
+        assert_eq!(summary["ragged_rows"], 0);
+        let types: Vec<&str> = summary["columns"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|column| column["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(types, vec!["integer", "string", "float", "date"]);
+        assert_eq!(summary["columns"][2]["max"], 20.0);
+        assert_eq!(summary["columns"][3]["empty"], 1);
+
+        let result = read_csv_rows(ReadCsvRowsRequest {
+            path: path.clone(),
+            offset: Some(1),
+            limit: Some(1),
+            columns: Some(vec![json!("customer"), json!(0)]),
+            delimiter: None,
+            has_header: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let rows = result_json(&result);
+        assert_eq!(rows["columns"], json!(["customer", "id"]));
+        assert_eq!(rows["rows"], json!([["bob", "2"]]));
+        assert_eq!(rows["total_rows"], 3);
+        assert_eq!(rows["next_offset"], 2);
+
+        let result = read_csv_rows(ReadCsvRowsRequest {
+            path,
+            offset: None,
+            limit: None,
+            columns: Some(vec![json!("missing")]),
+            delimiter: None,
+            has_header: None,
+        })
+        .await
+        .unwrap();
+        assert!(result.is_error);
+    }
+}
@@ -0,0 +1,96 @@
+//! Fixtures for the tests of the tools. They share the environment of the
+//! process, the allowed directories above all, so they never run at the same time.
+
+use crate::mcp::types::CallToolResult;
+use crate::mcp::types::CallToolResultContent;
+use serde_json::Value;
+use std::env;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::sync::OnceLock;
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+use tokio::sync::MutexGuard;
+
+static ENV_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Keeps persisted state (reservations etc.) out of the real state directory
+static STATE_DIR: OnceLock<TempDir> = OnceLock::new();
+
+/// The environment, held by one test. Dropping it clears the allowed directories.
+pub struct EnvGuard {
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+}
+
+/// Take the environment for a test that sets up the allowed directories itself
+pub async fn lock_env() -> EnvGuard {
+    let lock = ENV_LOCK.lock().await;
+    let state_dir = STATE_DIR.get_or_init(|| TempDir::new().unwrap());
+    env::set_var("MCP_RS_FILESYSTEM_STATE_DIR", state_dir.path());
+    EnvGuard { _lock: lock }
+}
+
+/// Take the environment for a test and allow it a new directory, returned
+/// along with its canonical path
+pub async fn test_env() -> (EnvGuard, TempDir, String) {
+    let guard = lock_env().await;
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().canonicalize().unwrap().to_str().unwrap().to_string();
+    env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &path);
+    (guard, dir, path)
+}
+
+/// A variable of the environment changed by a test, put back as it was when dropped
+pub struct EnvOverride {
+    name: String,
+    previous: Option<OsString>,
+}
+
+impl Drop for EnvOverride {
+    fn drop(&mut self) {
+        match &self.previous {
+            Some(value) => env::set_var(&self.name, value),
+            None => env::remove_var(&self.name),
+        }
+    }
+}
+
+/// Set a variable until the returned guard is dropped. Take the environment
+/// with [`lock_env`] or [`test_env`] first.
+pub fn set_env(name: &str, value: impl AsRef<OsStr>) -> EnvOverride {
+    let previous = env::var_os(name);
+    env::set_var(name, value);
+    EnvOverride {
+        name: name.to_string(),
+        previous,
+    }
+}
+
+/// Unset a variable until the returned guard is dropped
+pub fn remove_env(name: &str) -> EnvOverride {
+    let previous = env::var_os(name);
+    env::remove_var(name);
+    EnvOverride {
+        name: name.to_string(),
+        previous,
+    }
+}
+
+/// The text of the first content of a tool result
+pub fn result_text(result: &CallToolResult) -> &str {
+    match &result.content[0] {
+        CallToolResultContent::Text { text } => text,
+        other => panic!("expected text, got {:?}", other),
+    }
+}
+
+/// The text of the first content of a tool result, parsed as JSON
+pub fn result_json(result: &CallToolResult) -> Value {
+    serde_json::from_str(result_text(result)).unwrap()
+}
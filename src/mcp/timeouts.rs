@@ -0,0 +1,274 @@
+use crate::mcp::budget;
+use crate::mcp::config;
+use crate::mcp::session;
+use crate::mcp::tools;
+use crate::mcp::trace;
+use crate::mcp::types::*;
+use crate::mcp::vfs;
+use serde_json::json;
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a tool call may run, unless configured otherwise
+const DEFAULT_TOOL_TIMEOUT_MS: u64 = 30_000;
+
+/// Tools that run longer by design. `tail_file` follows a file for up to a
+/// minute; `copy_file` may move gigabytes, and resumes where a timeout stopped it.
+const BUILTIN_TOOL_TIMEOUTS_MS: &[(&str, u64)] = &[("tail_file", 65_000), ("copy_file", 600_000)];
+
+/// Whether a call of `tool` that runs out of time or is cancelled is aborted,
+/// rather than left to finish on its own. Mutating tools change things in several steps, and
+/// stopping one between two steps would leave a change half made, so the call
+/// finishes in the background instead. `copy_file` is aborted all the same: it
+/// writes to a partial file that the next call resumes.
+pub fn aborts_on_timeout(tool: &str) -> bool {
+    tool == "copy_file" || !tools::is_mutating(tool)
+}
+
+/// Timeouts of single tools, from `MCP_RS_FILESYSTEM_TOOL_TIMEOUTS` as set by
+/// `--tool-timeouts`: `name=ms` pairs separated by commas
+fn configured_overrides() -> Vec<(String, u64)> {
+    config::var("MCP_RS_FILESYSTEM_TOOL_TIMEOUTS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|pair| {
+            let (name, ms) = pair.split_once('=')?;
+            Some((name.trim().to_string(), ms.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Check the syntax of `--tool-timeouts`
+pub fn check_overrides(overrides: &str) -> Result<(), String> {
+    for pair in overrides.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let valid = pair
+            .split_once('=')
+            .is_some_and(|(name, ms)| !name.trim().is_empty() && ms.trim().parse::<u64>().is_ok());
+        if !valid {
+            return Err(format!("Invalid tool timeout {:?}, expected name=milliseconds", pair.trim()));
+        }
+    }
+    Ok(())
+}
+
+/// How long a call of `tool` may run, `None` for no limit. A timeout of 0
+/// turns the limit off. Per-tool settings win over the server-wide
+/// `MCP_RS_FILESYSTEM_TOOL_TIMEOUT_MS`, set by `--tool-timeout`.
+pub fn tool_timeout(tool: &str) -> Option<Duration> {
+    let configured = configured_overrides().into_iter().find(|(name, _)| name == tool).map(|(_, ms)| ms);
+    let builtin = BUILTIN_TOOL_TIMEOUTS_MS.iter().find(|(name, _)| *name == tool).map(|(_, ms)| *ms);
+    let server_wide = || {
+        config::var("MCP_RS_FILESYSTEM_TOOL_TIMEOUT_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_TOOL_TIMEOUT_MS)
+    };
+    let ms = configured.or(builtin).unwrap_or_else(server_wide);
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+tokio::task_local! {
+    static CANCELLED: Arc<AtomicBool>;
+}
+
+/// Flag set once nobody waits for the answer of the call being handled, so
+/// long walks can stop early. `None` outside a request.
+pub fn cancel_flag() -> Option<Arc<AtomicBool>> {
+    CANCELLED.try_with(Arc::clone).ok()
+}
+
+/// Handle a request with `cancelled` as its cancellation flag
+pub async fn with_cancel_flag<F: Future>(cancelled: Arc<AtomicBool>, future: F) -> F::Output {
+    CANCELLED.scope(cancelled, future).await
+}
+
+/// `future` with the session, backend, trace, budget usage and cancellation
+/// flag of the call being handled, to run apart from it
+pub(crate) fn in_call<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let future = vfs::scope(vfs::backend(), future);
+    let (request, current, cancelled) = (trace::current(), session::current(), cancel_flag());
+    let usage = budget::current_call();
+    async move {
+        let future = async move {
+            match usage {
+                Some(usage) => budget::in_tool_call(usage, future).await,
+                None => future.await,
+            }
+        };
+        let future = async move {
+            match request {
+                Some(request) => trace::scope(request, future).await,
+                None => future.await,
+            }
+        };
+        let future = async move {
+            match current {
+                Some(current) => session::scope(current, future).await,
+                None => future.await,
+            }
+        };
+        match cancelled {
+            Some(cancelled) => CANCELLED.scope(cancelled, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// How often a running call looks whether the client cancelled it
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Why nobody waits for a call any longer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// It ran out of time
+    TimedOut,
+    /// The client cancelled its request with `notifications/cancelled`
+    Cancelled,
+}
+
+/// Resolves once `cancelled` is set
+async fn cancellation(cancelled: &AtomicBool) {
+    while !cancelled.load(Ordering::Relaxed) {
+        tokio::time::sleep(CANCEL_POLL_INTERVAL).await;
+    }
+}
+
+/// Run `future`, a tool call, as a task of its own that nobody waits for once
+/// `limit` has passed or the client cancelled the request. With `abort` the
+/// task is aborted then and its cancellation flag set, which stops the walks
+/// of work handed to [`run_blocking`]; without it the task runs to its end.
+/// A `limit` of `None` leaves the call as much time as it takes.
+pub async fn run_with_timeout<F>(limit: Option<Duration>, abort: bool, future: F) -> Result<F::Output, Stopped>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    // The flag of the request, which `notifications/cancelled` sets
+    let cancelled = cancel_flag().unwrap_or_default();
+    let mut task = tokio::spawn(in_call(CANCELLED.scope(Arc::clone(&cancelled), future)));
+    let out_of_time = async {
+        match limit {
+            Some(limit) => tokio::time::sleep(limit).await,
+            None => std::future::pending().await,
+        }
+    };
+    let stopped = tokio::select! {
+        finished = &mut task => match finished {
+            Ok(output) => return Ok(output),
+            // A panicking handler fails the connection as it did before
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        },
+        _ = out_of_time => Stopped::TimedOut,
+        _ = cancellation(&cancelled) => Stopped::Cancelled,
+    };
+    if abort {
+        cancelled.store(true, Ordering::Relaxed);
+        task.abort();
+    }
+    Err(stopped)
+}
+
+/// Run `future`, the part of a tool call that blocks on walks and reads, on a
+/// thread where it does not hold up other requests, keeping what the call runs with
+pub async fn run_blocking<F>(future: F) -> F::Output
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let future = in_call(future);
+    let runtime = tokio::runtime::Handle::current();
+    match tokio::task::spawn_blocking(move || runtime.block_on(future)).await {
+        Ok(output) => output,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// Tool result reporting a call that ran out of time. A mutating tool keeps
+/// running, so its changes may still appear after this answer.
+pub fn timed_out_result(tool: &str, timeout: Duration) -> CallToolResult {
+    let still_running = !aborts_on_timeout(tool);
+    let note = if still_running {
+        "The call keeps running and may still complete its changes; check the files before retrying"
+    } else {
+        "The call was aborted"
+    };
+    let report = json!({
+        "error": "timeout",
+        "tool": tool,
+        "timeout_ms": timeout.as_millis(),
+        "still_running": still_running,
+        "note": note,
+    });
+    CallToolResult::error(serde_json::to_string_pretty(&report).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_text;
+
+    #[test]
+    fn test_check_overrides() {
+        assert!(check_overrides("find_file=5000, tail_file=0").is_ok());
+        assert!(check_overrides("find_file").is_err());
+        assert!(check_overrides("find_file=soon").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_with_timeout() {
+        assert_eq!(run_with_timeout(Some(Duration::from_secs(5)), true, async { 7 }).await, Ok(7));
+        assert_eq!(run_with_timeout(None, true, async { 7 }).await, Ok(7));
+        let started = std::time::Instant::now();
+        let slow = tokio::time::sleep(Duration::from_secs(30));
+        assert_eq!(run_with_timeout(Some(Duration::from_millis(50)), true, slow).await, Err(Stopped::TimedOut));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_call_is_aborted() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancelled);
+        let cancel = async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            flag.store(true, Ordering::Relaxed);
+        };
+        let started = std::time::Instant::now();
+        let slow = tokio::time::sleep(Duration::from_secs(30));
+        let (stopped, _) = tokio::join!(with_cancel_flag(cancelled, run_with_timeout(None, true, slow)), cancel);
+        assert_eq!(stopped, Err(Stopped::Cancelled));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_aborted_call_is_cancelled() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slow = async move {
+            // Work handed to a blocking thread sees the flag of its call
+            let _ = sender.send(run_blocking(async { cancel_flag() }).await);
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        };
+        assert!(run_with_timeout(Some(Duration::from_millis(200)), true, slow).await.is_err());
+        let cancelled = receiver.recv().unwrap().expect("the call has a cancellation flag");
+        assert!(cancelled.load(Ordering::Relaxed));
+        assert!(cancel_flag().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mutating_call_finishes_after_timeout() {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let slow = async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let _ = sender.send("done");
+        };
+        assert!(run_with_timeout(Some(Duration::from_millis(20)), false, slow).await.is_err());
+        assert_eq!(receiver.await, Ok("done"));
+
+        let result = timed_out_result("overwrite_file", Duration::from_millis(20));
+        let text = result_text(&result);
+        assert!(text.contains("\"still_running\": true"), "{}", text);
+        assert!(aborts_on_timeout("grep_search"));
+    }
+}
@@ -1,18 +1,156 @@
+use crate::mcp::batch::batch;
+use crate::mcp::batch::BatchRequest;
+use crate::mcp::checkpoint;
+use crate::mcp::checkpoint::create_checkpoint;
+use crate::mcp::checkpoint::list_checkpoints;
+use crate::mcp::checkpoint::rollback_to_checkpoint;
+use crate::mcp::checkpoint::CreateCheckpointRequest;
+use crate::mcp::checkpoint::ListCheckpointsRequest;
+use crate::mcp::checkpoint::RollbackToCheckpointRequest;
+use crate::mcp::compare::diff_directories;
+use crate::mcp::compare::DiffDirectoriesRequest;
+use crate::mcp::config;
+use crate::mcp::conversion::decode_to_file;
+use crate::mcp::conversion::encode_file;
+use crate::mcp::conversion::DecodeToFileRequest;
+use crate::mcp::conversion::EncodeFileRequest;
+use crate::mcp::copy::copy_file;
+use crate::mcp::copy::CopyFileRequest;
+use crate::mcp::counting::word_count;
+use crate::mcp::counting::WordCountRequest;
+use crate::mcp::dry_run;
+use crate::mcp::encoding::read_text_file;
+use crate::mcp::encoding::write_text_file;
+use crate::mcp::encoding::write_text_with_mode;
+use crate::mcp::encoding::LineEnding;
+use crate::mcp::encoding::TextEncoding;
+use crate::mcp::encoding::ENCODING_NAMES;
+use crate::mcp::encoding::LINE_ENDING_NAMES;
+use crate::mcp::find::find_file;
+use crate::mcp::find::FindFileRequest;
+use crate::mcp::git::GitStatusRequest;
+use crate::mcp::git::{directory_annotations, git_status};
+use crate::mcp::hashing::chunk_signature;
+use crate::mcp::hashing::find_duplicates;
+use crate::mcp::hashing::ChunkSignatureRequest;
+use crate::mcp::hashing::FindDuplicatesRequest;
+use crate::mcp::ignore_rules::is_ignored;
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::images::image_type;
+use crate::mcp::images::read_image;
+use crate::mcp::index::index_status;
+use crate::mcp::index::IndexStatusRequest;
+use crate::mcp::limits::{effective_limit, max_read_bytes, max_result_entries, text_window, truncation_metadata};
+use crate::mcp::lines::delete_lines;
+use crate::mcp::lines::insert_lines;
+use crate::mcp::lines::read_lines;
+use crate::mcp::lines::replace_lines;
+use crate::mcp::lines::DeleteLinesRequest;
+use crate::mcp::lines::InsertLinesRequest;
+use crate::mcp::lines::ReadLinesRequest;
+use crate::mcp::lines::ReplaceLinesRequest;
+use crate::mcp::locks::lock_file;
+use crate::mcp::locks::unlock_file;
+use crate::mcp::locks::LockFileRequest;
+use crate::mcp::locks::UnlockFileRequest;
+use crate::mcp::metrics::server_stats;
+use crate::mcp::metrics::ServerStatsRequest;
+use crate::mcp::mime;
+use crate::mcp::permissions::get_permissions;
+use crate::mcp::permissions::set_permissions;
+use crate::mcp::permissions::GetPermissionsRequest;
+use crate::mcp::permissions::SetPermissionsRequest;
+use crate::mcp::preview::preview_file;
+use crate::mcp::preview::PreviewFileRequest;
+use crate::mcp::recent::recent_changes;
+use crate::mcp::recent::RecentChangesRequest;
+use crate::mcp::replace::replace_in_files;
+use crate::mcp::replace::ReplaceInFilesRequest;
+use crate::mcp::reservations::list_reservations;
+use crate::mcp::reservations::release_paths;
+use crate::mcp::reservations::reserve_paths;
+use crate::mcp::reservations::ListReservationsRequest;
+use crate::mcp::reservations::ReleasePathsRequest;
+use crate::mcp::reservations::ReservePathsRequest;
+use crate::mcp::sandbox;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::sandbox::WRITE_MODE_NAMES;
+use crate::mcp::schema::input_schema;
+use crate::mcp::scratch::create_temp_dir;
+use crate::mcp::scratch::create_temp_file;
+use crate::mcp::scratch::CreateTempDirRequest;
+use crate::mcp::scratch::CreateTempFileRequest;
+use crate::mcp::session;
+use crate::mcp::session::session_info;
+use crate::mcp::session::SessionInfoRequest;
+use crate::mcp::shadow;
+use crate::mcp::sorting::SortOrder;
+use crate::mcp::sorting::SORT_ORDER_NAMES;
+use crate::mcp::state::export_state_tool;
+use crate::mcp::state::import_state_tool;
+use crate::mcp::state::ExportStateRequest;
+use crate::mcp::state::ImportStateRequest;
+use crate::mcp::structured::patch_structured;
+use crate::mcp::structured::read_structured;
+use crate::mcp::structured::PatchStructuredRequest;
+use crate::mcp::structured::ReadStructuredRequest;
+use crate::mcp::tabular::inspect_csv;
+use crate::mcp::tabular::read_csv_rows;
+use crate::mcp::tabular::InspectCsvRequest;
+use crate::mcp::tabular::ReadCsvRowsRequest;
+use crate::mcp::timeouts;
 use crate::mcp::types::*;
-use maplit::hashmap;
-use rpc_router::RouterBuilder;
-use rpc_router::HandlerResult;
+use crate::mcp::unicode::find_unicode_issues;
+use crate::mcp::unicode::nfc;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::unicode::strip_accents;
+use crate::mcp::unicode::FindUnicodeIssuesRequest;
+use crate::mcp::usage::disk_usage;
+use crate::mcp::usage::estimate_operation;
+use crate::mcp::usage::DiskUsageRequest;
+use crate::mcp::usage::EstimateOperationRequest;
+use crate::mcp::utilities::notify;
+use crate::mcp::utilities::notify_progress;
+use crate::mcp::utilities::{validate_path_or_error, is_path_allowed};
+use crate::mcp::utilities::{validate_write_path_or_error, validate_write_paths_or_error};
+use crate::mcp::vfs;
+use crate::mcp::vfs::FileKind;
+use crate::mcp::watch::PollChangesRequest;
+use crate::mcp::watch::UnwatchPathRequest;
+use crate::mcp::watch::WatchPathRequest;
+use crate::mcp::watch::{poll_changes, unwatch_path, watch_path};
+use crate::mcp::xattrs::list_xattrs;
+use crate::mcp::xattrs::read_xattr;
+use crate::mcp::xattrs::write_xattr;
+use crate::mcp::xattrs::ListXattrsRequest;
+use crate::mcp::xattrs::ReadXattrRequest;
+use crate::mcp::xattrs::WriteXattrRequest;
+use crate::mcp::STRUCTURED_OUTPUT_PROTOCOL_VERSION;
+use chrono::DateTime;
+use chrono::Local;
+use git2::{Repository, Signature};
 use rpc_router::Handler;
+use rpc_router::HandlerResult;
+use rpc_router::RouterBuilder;
 use rpc_router::RpcParams;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
-use std::fs;
-use std::path::Path;
-use git2::{Repository, Signature};
-use crate::mcp::utilities::{validate_path_or_error, validate_paths_or_error, is_path_allowed};
-use chrono::Local;
 use serde_json::json;
-use crate::notify;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::LazyLock;
+use std::sync::RwLock;
+use std::time::Duration;
+use unicode_normalization::UnicodeNormalization;
 
 /// register all tools to the router
 pub fn register_tools(router_builder: RouterBuilder) -> RouterBuilder {
@@ -28,293 +166,834 @@ pub fn register_tools(router_builder: RouterBuilder) -> RouterBuilder {
         .append_dyn("create_directory", create_directory.into_dyn())
         .append_dyn("overwrite_file", overwrite_file.into_dyn())
         .append_dyn("grep_search", grep_search.into_dyn())
+        .append_dyn("tail_file", tail_file.into_dyn())
+        .append_dyn("batch", batch.into_dyn())
+        .append_dyn("export_state", export_state_tool.into_dyn())
+        .append_dyn("import_state", import_state_tool.into_dyn())
+        .append_dyn("reserve_paths", reserve_paths.into_dyn())
+        .append_dyn("release_paths", release_paths.into_dyn())
+        .append_dyn("list_reservations", list_reservations.into_dyn())
+        .append_dyn("chunk_signature", chunk_signature.into_dyn())
+        .append_dyn("disk_usage", disk_usage.into_dyn())
+        .append_dyn("find_duplicates", find_duplicates.into_dyn())
+        .append_dyn("get_permissions", get_permissions.into_dyn())
+        .append_dyn("set_permissions", set_permissions.into_dyn())
+        .append_dyn("find_unicode_issues", find_unicode_issues.into_dyn())
+        .append_dyn("estimate_operation", estimate_operation.into_dyn())
+        .append_dyn("create_temp_file", create_temp_file.into_dyn())
+        .append_dyn("create_temp_dir", create_temp_dir.into_dyn())
+        .append_dyn("watch_path", watch_path.into_dyn())
+        .append_dyn("poll_changes", poll_changes.into_dyn())
+        .append_dyn("unwatch_path", unwatch_path.into_dyn())
+        .append_dyn("git_status", git_status.into_dyn())
+        .append_dyn("create_checkpoint", create_checkpoint.into_dyn())
+        .append_dyn("list_checkpoints", list_checkpoints.into_dyn())
+        .append_dyn("rollback_to_checkpoint", rollback_to_checkpoint.into_dyn())
+        .append_dyn("find_file", find_file.into_dyn())
+        .append_dyn("index_status", index_status.into_dyn())
+        .append_dyn("preview_file", preview_file.into_dyn())
+        .append_dyn("read_structured", read_structured.into_dyn())
+        .append_dyn("patch_structured", patch_structured.into_dyn())
+        .append_dyn("inspect_csv", inspect_csv.into_dyn())
+        .append_dyn("read_csv_rows", read_csv_rows.into_dyn())
+        .append_dyn("read_lines", read_lines.into_dyn())
+        .append_dyn("insert_lines", insert_lines.into_dyn())
+        .append_dyn("replace_lines", replace_lines.into_dyn())
+        .append_dyn("delete_lines", delete_lines.into_dyn())
+        .append_dyn("session_info", session_info.into_dyn())
+        .append_dyn("diff_directories", diff_directories.into_dyn())
+        .append_dyn("server_stats", server_stats.into_dyn())
+        .append_dyn("list_xattrs", list_xattrs.into_dyn())
+        .append_dyn("read_xattr", read_xattr.into_dyn())
+        .append_dyn("write_xattr", write_xattr.into_dyn())
+        .append_dyn("copy_file", copy_file.into_dyn())
+        .append_dyn("replace_in_files", replace_in_files.into_dyn())
+        .append_dyn("word_count", word_count.into_dyn())
+        .append_dyn("lock_file", lock_file.into_dyn())
+        .append_dyn("unlock_file", unlock_file.into_dyn())
+        .append_dyn("encode_file", encode_file.into_dyn())
+        .append_dyn("decode_to_file", decode_to_file.into_dyn())
+        .append_dyn("recent_changes", recent_changes.into_dyn())
 }
 
-pub async fn tools_list(_request: Option<ListToolsRequest>) -> HandlerResult<ListToolsResult> {
+/// Whether tool results carry `structuredContent` next to their text, and
+/// tools an `outputSchema`: only for clients that negotiated a protocol version
+/// that has them, and unless `MCP_RS_FILESYSTEM_TOOL_OUTPUT`, set by
+/// `--tool-output`, is `text`
+pub fn structured_output() -> bool {
+    // Versions are dates, so they compare as strings
+    let negotiated = session::current()
+        .and_then(|session| session.client())
+        .is_none_or(|client| client.protocol_version.as_str() >= STRUCTURED_OUTPUT_PROTOCOL_VERSION);
+    negotiated && config::var("MCP_RS_FILESYSTEM_TOOL_OUTPUT").map_or(true, |format| format != "text")
+}
+
+/// Tools added by an application embedding the server, after the built-in ones
+static CUSTOM_TOOLS: RwLock<Vec<Tool>> = RwLock::new(Vec::new());
+
+/// Offer a tool of an embedding application next to the built-in ones. Its
+/// handler is appended to the router separately.
+pub fn register_tool(tool: Tool) -> Result<(), String> {
+    let mut custom = CUSTOM_TOOLS.write().unwrap();
+    if is_builtin_tool(&tool.name) || custom.iter().any(|other| other.name == tool.name) {
+        return Err(format!("A tool named {} already exists", tool.name));
+    }
+    custom.push(tool);
+    Ok(())
+}
+
+fn custom_tool(name: &str) -> Option<Tool> {
+    CUSTOM_TOOLS.read().unwrap().iter().find(|tool| tool.name == name).cloned()
+}
+
+/// Every tool this server offers
+fn tool_definitions() -> Vec<Tool> {
+    let mut tools = builtin_tool_definitions();
+    tools.extend(CUSTOM_TOOLS.read().unwrap().iter().cloned());
+    tools
+}
+
+/// The tools of this server itself
+fn builtin_tool_definitions() -> Vec<Tool> {
     //let tools: Vec<Tool> = serde_json::from_str(include_str!("./templates/tools.json")).unwrap();
-    let response = ListToolsResult {
-        tools: vec![
-            Tool {
-                name: "get_current_time_in_city".to_string(),
-                description: Some("Get the current time in the city".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "city".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("city name".to_owned()),
-                            enum_values: None,
-                        }
-                    },
-                    required: vec!["city".to_string()],
-                },
-            },
-            Tool {
-                name: "get_local_time".to_string(),
-                description: Some("Get the current local time".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap!{},
-                    required: vec![],
-                },
-            },
-
-            Tool {
-                name: "file_edit".to_string(),
-                description: Some("Replace exact text content in a file with optional git commit. Returns error if content not found or if there are multiple matches.".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "file_path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_string()),
-                            description: Some("Path to the file to edit".to_string()),
-                            enum_values: None,
-                        },
-                        "old_content".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_string()),
-                            description: Some("Exact content to replace (must match uniquely)".to_string()),
-                            enum_values: None,
-                        },
-                        "new_content".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_string()),
-                            description: Some("Content to insert instead".to_string()),
-                            enum_values: None,
+    vec![
+        Tool {
+            name: "get_current_time_in_city".to_string(),
+            description: Some("Get the current time in the city".to_string()),
+            input_schema: input_schema::<CurrentTimeRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "get_local_time".to_string(),
+            description: Some("Get the current local time".to_string()),
+            input_schema: input_schema::<GetLocalTimeRequest>(),
+            output_schema: None,
+        },
+
+        Tool {
+            name: "file_edit".to_string(),
+            description: Some("Replace exact text content in a file with optional git commit. Returns error if content not found or if there are multiple matches. The file's encoding and line endings are preserved.".to_string()),
+            input_schema: input_schema::<FileEditRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "read_file".to_string(),
+            description: Some("Read the contents of a file. Large files are returned in parts: a partial read ends with a JSON item holding truncated, total_size and next_offset. PNG, JPEG and WebP files are returned as images.".to_string()),
+            input_schema: input_schema::<ReadFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "list_directory".to_string(),
+            description: Some("List contents of a directory. Long listings are returned in pages: a partial listing ends with a JSON item holding truncated, total_entries and next_offset. Entries matching the server's ignore patterns (.git, node_modules and target by default) are left out, see default_ignores and ignore_patterns.".to_string()),
+            input_schema: input_schema::<ListDirectoryRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "entries": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "name": { "type": "string" },
+                                "type": { "type": "string", "enum": ["file", "directory"] },
+                                "git_status": { "type": ["string", "null"] },
+                            },
+                            "required": ["name", "type"],
                         },
-                        "commit_message".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_string()),
-                            description: Some("Message describing the purpose of this edit".to_string()),
-                            enum_values: None,
-                        }
                     },
-                    required: vec![
-                        "file_path".to_string(),
-                        "old_content".to_string(),
-                        "new_content".to_string(),
-                        "commit_message".to_string()
-                    ],
+                    "offset": { "type": "integer" },
+                    "total": { "type": "integer" },
+                    "ignored": { "type": "integer" },
                 },
-            },
-            Tool {
-                name: "read_file".to_string(),
-                description: Some("Read the contents of a file".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "file_path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Path to the file to read".to_owned()),
-                            enum_values: None,
-                        }
-                    },
-                    required: vec!["file_path".to_string()],
+                "required": ["path", "entries", "offset", "total"],
+            })),
+        },
+        Tool {
+            name: "move_or_rename".to_string(),
+            description: Some("Move or rename a file or directory".to_string()),
+            input_schema: input_schema::<MoveOrRenameRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "get_file_info".to_string(),
+            description: Some("Get metadata about a file".to_string()),
+            input_schema: input_schema::<GetFileInfoRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "size": { "type": "integer" },
+                    "type": { "type": "string", "enum": ["file", "directory", "other"] },
+                    "modified": { "type": ["string", "null"], "format": "date-time" },
+                    "readonly": { "type": "boolean" },
                 },
-            },
-            Tool {
-                name: "list_directory".to_string(),
-                description: Some("List contents of a directory".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Path to directory to list".to_owned()),
-                            enum_values: None,
-                        }
+                "required": ["path", "size", "type", "readonly"],
+            })),
+        },
+        Tool {
+            name: "create_directory".to_string(),
+            description: Some("Create a new directory".to_string()),
+            input_schema: input_schema::<CreateDirectoryRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "overwrite_file".to_string(),
+            description: Some("Write the contents of a file, replacing it or, depending on mode, appending to it or only creating it".to_string()),
+            input_schema: input_schema::<OverwriteFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "grep_search".to_string(),
+            description: Some("Search for a pattern in files or directories. For recursive searches, the path must be a directory. For non-recursive searches, the path must exist. Text stored in NFC or NFD matches the same pattern, and ignore_accents makes 'cafe' match 'café'.".to_string()),
+            input_schema: input_schema::<GrepSearchRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "matches": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "path": { "type": "string" },
+                                "line": { "type": "integer" },
+                                "text": { "type": "string" },
+                            },
+                            "required": ["path", "line", "text"],
+                        },
                     },
-                    required: vec!["path".to_string()],
                 },
-            },
-            Tool {
-                name: "move_or_rename".to_string(),
-                description: Some("Move or rename a file or directory".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "source_path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Source path to move/rename from".to_owned()),
-                            enum_values: None,
-                        },
-                        "target_path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Target path to move/rename to".to_owned()),
-                            enum_values: None,
+                "required": ["matches"],
+            })),
+        },
+        Tool {
+            name: "tail_file".to_string(),
+            description: Some("Return the last lines of a file, optionally following it for a bounded time. New lines seen while following are streamed as progress notifications when a progress token is supplied.".to_string()),
+            input_schema: input_schema::<TailFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "batch".to_string(),
+            description: Some("Run several filesystem operations in one call, in order. By default the batch is atomic: if one operation fails, the completed ones are rolled back. Returns a result per operation; read operations include the file content.".to_string()),
+            input_schema: input_schema::<BatchRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "export_state".to_string(),
+            description: Some("Bundle the server's persisted state into a single file that can be imported on another machine or shared with teammates".to_string()),
+            input_schema: input_schema::<ExportStateRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "import_state".to_string(),
+            description: Some("Restore server state from a bundle written by export_state".to_string()),
+            input_schema: input_schema::<ImportStateRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "reserve_paths".to_string(),
+            description: Some("Reserve files or directory trees for this session with a time-limited lease. While reserved, other sessions cannot modify them. Either all paths are reserved or none are. Reserving a path again renews its lease.".to_string()),
+            input_schema: input_schema::<ReservePathsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "release_paths".to_string(),
+            description: Some("Release reservations held by this session".to_string()),
+            input_schema: input_schema::<ReleasePathsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "list_reservations".to_string(),
+            description: Some("List active path reservations of all sessions".to_string()),
+            input_schema: input_schema::<ListReservationsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "chunk_signature".to_string(),
+            description: Some("Split a file into content-defined chunks (FastCDC) and return the SHA-256 of each chunk and of the whole file. Comparing signatures shows which regions of a large file changed; a small insertion only changes the chunks around it.".to_string()),
+            input_schema: input_schema::<ChunkSignatureRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "disk_usage".to_string(),
+            description: Some("Compute the recursive size, file count and directory count of a directory along with its largest files. Respects .gitignore by default and stops early (reporting truncated: true) when the entry or time budget runs out.".to_string()),
+            input_schema: input_schema::<DiskUsageRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "find_duplicates".to_string(),
+            description: Some("Find files with identical content under a directory. Files are grouped by size first and only same-sized files are hashed (SHA-256). Returns the duplicate sets ordered by how many bytes they waste.".to_string()),
+            input_schema: input_schema::<FindDuplicatesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "get_permissions".to_string(),
+            description: Some("Get the permission bits (octal and rwx form), owner uid/gid and read-only attribute of a file or directory".to_string()),
+            input_schema: input_schema::<GetPermissionsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "set_permissions".to_string(),
+            description: Some("Change the permissions of a file or directory, e.g. mark a script executable with mode \"+x\". Only available when the server runs with --allow-permission-changes. Setuid and setgid bits are never set.".to_string()),
+            input_schema: input_schema::<SetPermissionsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "find_unicode_issues".to_string(),
+            description: Some("Scan a directory for file names that are not NFC normalized, contain invisible or bidirectional control characters, or collide with a sibling that differs only in Unicode normalization".to_string()),
+            input_schema: input_schema::<FindUnicodeIssuesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "estimate_operation".to_string(),
+            description: Some("Estimate the impact of a planned recursive copy, delete or sync before running it. Returns file and directory counts, total bytes, the largest files and an estimated duration. The duration comes from disk throughput measured by reading the largest source files and by writing and removing probe files in the server's scratch directory, which may be on another device than the target; nothing is written to the allowed directories. For sync, only files whose size or modification time differ from the target are counted.".to_string()),
+            input_schema: input_schema::<EstimateOperationRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "create_temp_file".to_string(),
+            description: Some("Create a new file in this session's scratch directory and return its path. The scratch directory lives under the OS temp dir, is always accessible to the other tools and is deleted when the server shuts down, so it is a safe place to stage work outside the project directories.".to_string()),
+            input_schema: input_schema::<CreateTempFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "create_temp_dir".to_string(),
+            description: Some("Create a new directory in this session's scratch directory and return its path. The scratch directory is always accessible to the other tools and is deleted when the server shuts down.".to_string()),
+            input_schema: input_schema::<CreateTempDirRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "watch_path".to_string(),
+            description: Some("Start watching a file or directory for changes. Returns a watch_id; retrieve buffered events with poll_changes and stop with unwatch_path. Uses native notifications, or polling on network mounts and WSL drives where those are unavailable.".to_string()),
+            input_schema: input_schema::<WatchPathRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "poll_changes".to_string(),
+            description: Some("Return and clear the change events buffered for a watch created with watch_path. Each event has a kind (create, modify, metadata, rename, remove, other), the affected paths and a timestamp.".to_string()),
+            input_schema: input_schema::<PollChangesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "unwatch_path".to_string(),
+            description: Some("Stop a watch created with watch_path and discard its buffered events".to_string()),
+            input_schema: input_schema::<UnwatchPathRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "git_status".to_string(),
+            description: Some("Working-tree state of the git repository containing a path: branch, head commit, upstream ahead/behind counts, and every changed file with its staged and unstaged change (added, modified, deleted, renamed, untracked, conflicted). Only files inside the given path and the allowed directories are reported.".to_string()),
+            input_schema: input_schema::<GitStatusRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "create_checkpoint".to_string(),
+            description: Some("Mark a point to which the files changed afterwards can be restored. From then on, the original content of every file or directory is saved before the first edit, write, move, delete or mkdir that touches it, so a whole multi-file editing session can be reverted with rollback_to_checkpoint. Checkpoints last for the session.".to_string()),
+            input_schema: input_schema::<CreateCheckpointRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "list_checkpoints".to_string(),
+            description: Some("List this session's checkpoints, oldest first, with the paths changed since each.".to_string()),
+            input_schema: input_schema::<ListCheckpointsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "rollback_to_checkpoint".to_string(),
+            description: Some("Restore every file and directory changed since a checkpoint to its state at that checkpoint. Files created since are removed. Later checkpoints are discarded; the checkpoint itself is kept and records changes again. Git commits made in the meantime are not reverted.".to_string()),
+            input_schema: input_schema::<RollbackToCheckpointRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "find_file".to_string(),
+            description: Some("Find files by approximate name, like fzf. The query characters must appear in order in the file name or its path, e.g. 'tlsrs' finds 'tools.rs'. Matches at word boundaries, consecutive characters and matches in the file name itself rank higher. Returns the best matches with their scores, best first. Matching ignores case unless the query contains an uppercase letter or case_sensitive is set, treats the NFC and NFD forms of a name as equal, and can ignore accents. Searches all allowed directories unless a path is given.".to_string()),
+            input_schema: input_schema::<FindFileRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "matches": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "path": { "type": "string" },
+                                "type": { "type": "string", "enum": ["file", "directory"] },
+                                "score": { "type": "integer" },
+                                "size": { "type": ["integer", "null"] },
+                                "modified": { "type": ["string", "null"], "format": "date-time" },
+                            },
+                            "required": ["path", "type", "score"],
                         },
-                        "commit_message".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Message describing the purpose of this move/rename".to_owned()),
-                            enum_values: None,
-                        }
                     },
-                    required: vec!["source_path".to_string(), "target_path".to_string(), "commit_message".to_string()],
+                    "total_matches": { "type": "integer" },
+                    "scanned": { "type": "integer" },
+                    "indexed": { "type": "boolean" },
+                    "truncated": { "type": "boolean" },
+                    "truncated_by": { "type": ["string", "null"] },
                 },
-            },
-            Tool {
-                name: "get_file_info".to_string(),
-                description: Some("Get metadata about a file".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Path to file to get info about".to_owned()),
-                            enum_values: None,
-                        }
-                    },
-                    required: vec!["path".to_string()],
+                "required": ["query", "matches", "total_matches", "truncated"],
+            })),
+        },
+        Tool {
+            name: "index_status".to_string(),
+            description: Some("Show the state of the background file index enabled with --index: for each allowed directory whether the index is ready, how many entries it holds, how changes are detected and when it was last updated. find_file answers from the index when it is ready and the default ignore and hidden options are used.".to_string()),
+            input_schema: input_schema::<IndexStatusRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "preview_file".to_string(),
+            description: Some("Describe a file without returning its raw content: its detected MIME type, size and modification time, plus a bounded preview suited to the format. CSV and TSV files show their columns and first rows, JSON files their top-level type and keys with the type of each value, images their format and dimensions, other text files their first lines, and binary files their leading signature bytes.".to_string()),
+            input_schema: input_schema::<PreviewFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "read_structured".to_string(),
+            description: Some("Read a value from a JSON, YAML or TOML file. The selector is a JSON Pointer such as '/servers/0/port' or a jq-like path such as '.servers[0].port' or '.[\"key.with.dots\"]'; without one the whole document is returned. The value is returned as JSON together with its type.".to_string()),
+            input_schema: input_schema::<ReadStructuredRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "patch_structured".to_string(),
+            description: Some("Set or remove a single value in a JSON, YAML or TOML file without rewriting it by hand. The selector is a JSON Pointer or jq-like path as for read_structured; set creates missing objects on the way, and '-' or '[]' as the last step appends to an array. TOML files keep their comments and layout, JSON files keep their key order and indentation. YAML files are rewritten from their parsed form, so their comments are lost.".to_string()),
+            input_schema: input_schema::<PatchStructuredRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "inspect_csv".to_string(),
+            description: Some("Describe a CSV or TSV file without reading all of it into the conversation: the column names, the type inferred for each column (integer, float, boolean, date, datetime, string or empty), how many values are empty, an example value, the range of numeric columns and the number of data rows. Rows whose field count differs from the header are counted as ragged_rows. Quoted fields may contain delimiters and line breaks.".to_string()),
+            input_schema: input_schema::<InspectCsvRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "delimiter": { "type": "string" },
+                    "has_header": { "type": "boolean" },
+                    "row_count": { "type": "integer" },
+                    "column_count": { "type": "integer" },
+                    "ragged_rows": { "type": "integer" },
+                    "columns": { "type": "array", "items": { "type": "object" } },
                 },
-            },
-            Tool {
-                name: "create_directory".to_string(),
-                description: Some("Create a new directory".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Path to the new directory".to_owned()),
-                            enum_values: None,
-                        },
-                        "commit_message".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Message describing the purpose of this directory creation".to_owned()),
-                            enum_values: None,
-                        }
-                    },
-                    required: vec!["path".to_string(), "commit_message".to_string()],
+                "required": ["row_count", "column_count", "columns"],
+            })),
+        },
+        Tool {
+            name: "read_csv_rows".to_string(),
+            description: Some("Read a range of data rows from a CSV or TSV file, optionally only some of its columns. Rows are returned as arrays in the order of the returned columns. The result includes total_rows and, when more rows follow, next_offset to continue from.".to_string()),
+            input_schema: input_schema::<ReadCsvRowsRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "columns": { "type": "array", "items": { "type": "string" } },
+                    "rows": { "type": "array", "items": { "type": "array", "items": { "type": "string" } } },
+                    "offset": { "type": "integer" },
+                    "returned_rows": { "type": "integer" },
+                    "total_rows": { "type": "integer" },
+                    "next_offset": { "type": "integer" },
                 },
-            },
-            Tool {
-                name: "overwrite_file".to_string(),
-                description: Some("Overwrite the contents of a file".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Path to the file to overwrite".to_owned()),
-                            enum_values: None,
+                "required": ["columns", "rows", "offset", "returned_rows", "total_rows"],
+            })),
+        },
+        Tool {
+            name: "read_lines".to_string(),
+            description: Some("Read a range of lines from a text file, e.g. lines 100 to 150, instead of the whole file. Lines are returned as they are stored, followed by a JSON item with start_line, end_line and total_lines. When the range is larger than the read limit, next_line says where to continue.".to_string()),
+            input_schema: input_schema::<ReadLinesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "insert_lines".to_string(),
+            description: Some("Insert lines into a text file after a given line. The inserted lines take the file's line ending style and the rest of the file is left byte for byte as it was.".to_string()),
+            input_schema: input_schema::<InsertLinesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "replace_lines".to_string(),
+            description: Some("Replace a range of lines in a text file with new content, which may have more or fewer lines. The new lines take the file's line ending style and the rest of the file is left byte for byte as it was.".to_string()),
+            input_schema: input_schema::<ReplaceLinesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "delete_lines".to_string(),
+            description: Some("Delete a range of lines from a text file. The rest of the file is left byte for byte as it was.".to_string()),
+            input_schema: input_schema::<DeleteLinesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "session_info".to_string(),
+            description: Some("Describe the current client session: its id, where it is in the initialization handshake, the negotiated protocol version, and the client name, version and capabilities sent in initialize.".to_string()),
+            input_schema: input_schema::<SessionInfoRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "diff_directories".to_string(),
+            description: Some("Compare two directory trees: list the files only in A, only in B, and those that differ in size or content (SHA-256), with the number of identical files. With include_diffs, text files that differ come with a unified diff when they are small enough. Useful for verifying backups and build outputs.".to_string()),
+            input_schema: input_schema::<DiffDirectoriesRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "path_a": { "type": "string" },
+                    "path_b": { "type": "string" },
+                    "identical": { "type": "integer" },
+                    "only_in_a": { "type": "array", "items": { "type": "string" } },
+                    "only_in_b": { "type": "array", "items": { "type": "string" } },
+                    "different": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "path": { "type": "string" },
+                                "reason": { "type": "string", "enum": ["size", "content"] },
+                                "size_a": { "type": "integer" },
+                                "size_b": { "type": "integer" },
+                                "diff": { "type": "string" },
+                                "diff_skipped": { "type": "string" },
+                            },
+                            "required": ["path", "reason", "size_a", "size_b"],
                         },
-                        "content".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("New content to write".to_owned()),
-                            enum_values: None,
-                        }
                     },
-                    required: vec!["path".to_string(), "content".to_string()],
+                    "unreadable": { "type": "array" },
+                    "truncated": { "type": "boolean" },
                 },
-            },
-            Tool {
-                name: "grep_search".to_string(),
-                description: Some("Search for a pattern in files or directories. For recursive searches, the path must be a directory. For non-recursive searches, the path must exist.".to_string()),
-                input_schema: ToolInputSchema {
-                    type_name: "object".to_string(),
-                    properties: hashmap! {
-                        "pattern".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Pattern to search for".to_owned()),
-                            enum_values: None,
-                        },
-                        "path".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("string".to_owned()),
-                            description: Some("Path to search in. For recursive searches this must be a directory.".to_owned()),
-                            enum_values: None,
-                        },
-                        "recursive".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("boolean".to_owned()),
-                            description: Some("Whether to search recursively in subdirectories. Defaults to true.".to_owned()),
-                            enum_values: None,
-                        },
-                        "case_sensitive".to_string() => ToolInputSchemaProperty {
-                            type_name: Some("boolean".to_owned()),
-                            description: Some("Whether the search should be case sensitive. Defaults to true.".to_owned()),
-                            enum_values: None,
+                "required": ["path_a", "path_b", "identical", "only_in_a", "only_in_b", "different", "truncated"],
+            })),
+        },
+        Tool {
+            name: "server_stats".to_string(),
+            description: Some("Statistics of the server since it started: requests and errors by method, tool calls, failures and timeouts by tool, bytes read and written, file index hits and misses, active watches and sessions. As JSON, or in the Prometheus text format for scraping.".to_string()),
+            input_schema: input_schema::<ServerStatsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "list_xattrs".to_string(),
+            description: Some("List the extended attributes of a file or directory with their sizes: xattrs on Linux and macOS, alternate data streams on Windows. Requires the server to be started with --allow-xattrs.".to_string()),
+            input_schema: input_schema::<ListXattrsRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "read_xattr".to_string(),
+            description: Some("Read one extended attribute (alternate data stream on Windows) of a file or directory, as text or base64. Requires the server to be started with --allow-xattrs.".to_string()),
+            input_schema: input_schema::<ReadXattrRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "write_xattr".to_string(),
+            description: Some("Set or remove an extended attribute (alternate data stream on Windows) of a file or directory, such as a Finder tag, custom metadata or the macOS quarantine flag. Requires the server to be started with --allow-xattrs.".to_string()),
+            input_schema: input_schema::<WriteXattrRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "copy_file".to_string(),
+            description: Some("Copy a file in chunks without blocking the server, keeping holes of sparse files as holes and sending progress notifications when the call has a progress token. The copy is written to <target>.partial and moved into place when complete; if it stops early, as on a timeout, copying to the same target again resumes it.".to_string()),
+            input_schema: input_schema::<CopyFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "replace_in_files".to_string(),
+            description: Some("Replace text or a regular expression in every file under a directory matching a glob, such as for a project-wide rename. By default a dry run: returns the number of matches and a unified diff per file without changing anything. Check it, then repeat the call with apply: true to write the changes; if a write fails, the files already written are restored.".to_string()),
+            input_schema: input_schema::<ReplaceInFilesRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "word_count".to_string(),
+            description: Some("Count the bytes, lines, words and characters of a file, or of the files under a directory matching a glob, with an estimate of the LLM tokens they take. Use it to decide whether to read a file whole or in chunks; fits_read_limit tells whether a single read returns all of it.".to_string()),
+            input_schema: input_schema::<WordCountRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "lock_file".to_string(),
+            description: Some("Lock a file for this session with a time-limited lease, so other sessions and other agents cannot modify it until it is unlocked. The lock is an OS advisory lock (flock, LockFileEx) too, which other servers and programs honouring such locks respect. Locking a file again renews its lease. With wait_ms, waits for a lock held by someone else to be released.".to_string()),
+            input_schema: input_schema::<LockFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "unlock_file".to_string(),
+            description: Some("Unlock a file locked by this session, or every file it has locked".to_string()),
+            input_schema: input_schema::<UnlockFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "encode_file".to_string(),
+            description: Some("Read a file's raw bytes and return them as base64, base64url or hex text, so small binary assets can be inspected or copied without a shell. Large files come back in parts: pass next_offset as offset to get the next one.".to_string()),
+            input_schema: input_schema::<EncodeFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "decode_to_file".to_string(),
+            description: Some("Decode base64, base64url or hex text and write the resulting bytes to a file, the reverse of encode_file. Each call writes at most the server's read limit; append larger files in parts.".to_string()),
+            input_schema: input_schema::<DecodeToFileRequest>(),
+            output_schema: None,
+        },
+        Tool {
+            name: "recent_changes".to_string(),
+            description: Some("List the most recently modified files under a directory, newest first, to see what changed since you last looked. Pass since, e.g. the as_of of an earlier call, to only get files modified after it; total_matches counts all of them. Answers from the background index when it is enabled with --index and the default ignore and hidden options are used, otherwise walks the tree within max_entries and max_duration_ms. Looks in all allowed directories unless a path is given.".to_string()),
+            input_schema: input_schema::<RecentChangesRequest>(),
+            output_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "changes": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "path": { "type": "string" },
+                                "type": { "type": "string", "enum": ["file", "directory"] },
+                                "size": { "type": ["integer", "null"] },
+                                "modified": { "type": "string", "format": "date-time" },
+                            },
+                            "required": ["path", "type", "modified"],
                         },
                     },
-                    required: vec!["pattern".to_string(), "path".to_string()],
+                    "total_matches": { "type": "integer" },
+                    "since": { "type": ["string", "null"], "format": "date-time" },
+                    "as_of": { "type": "string", "format": "date-time" },
+                    "scanned": { "type": "integer" },
+                    "indexed": { "type": "boolean" },
+                    "truncated": { "type": "boolean" },
+                    "truncated_by": { "type": ["string", "null"] },
                 },
-            }
-        ],
+                "required": ["changes", "total_matches", "as_of", "truncated"],
+            })),
+        },
+    ]
+}
+
+/// Input schemas by tool name, for checking calls before they are dispatched
+static INPUT_SCHEMAS: LazyLock<HashMap<String, Value>> =
+    LazyLock::new(|| builtin_tool_definitions().into_iter().map(|tool| (tool.name, tool.input_schema)).collect());
+
+/// Schema of the arguments of a tool, `None` for tools this server does not
+/// have or does not offer
+pub fn input_schema_of(name: &str) -> Option<Value> {
+    if tool_disabled(name) {
+        return None;
+    }
+    INPUT_SCHEMAS.get(name).cloned().or_else(|| custom_tool(name).map(|tool| tool.input_schema))
+}
+
+fn is_builtin_tool(name: &str) -> bool {
+    INPUT_SCHEMAS.contains_key(name)
+}
+
+/// What the server needs to know of a built-in tool besides its definition
+#[derive(Clone, Copy)]
+struct ToolTraits {
+    /// See [`is_mutating`]
+    mutating: bool,
+    /// See [`is_local_only`]
+    local_only: bool,
+}
+
+const READS: ToolTraits = ToolTraits {
+    mutating: false,
+    local_only: false,
+};
+const READS_DISK: ToolTraits = ToolTraits {
+    mutating: false,
+    local_only: true,
+};
+const CHANGES: ToolTraits = ToolTraits {
+    mutating: true,
+    local_only: false,
+};
+const CHANGES_DISK: ToolTraits = ToolTraits {
+    mutating: true,
+    local_only: true,
+};
+
+/// Every built-in tool with its traits, in registration order. Timeouts and
+/// backends go by this one list, and every tool taking `dry_run` is mutating.
+const TOOL_REGISTRY: &[(&str, ToolTraits)] = &[
+    ("get_current_time_in_city", READS),
+    ("get_local_time", READS),
+    ("file_edit", CHANGES),
+    ("read_file", READS),
+    ("list_directory", READS),
+    ("move_or_rename", CHANGES_DISK),
+    ("get_file_info", READS),
+    ("create_directory", CHANGES),
+    ("overwrite_file", CHANGES),
+    ("grep_search", READS_DISK),
+    ("tail_file", READS_DISK),
+    ("batch", CHANGES_DISK),
+    ("export_state", CHANGES),
+    ("import_state", CHANGES),
+    ("reserve_paths", CHANGES),
+    ("release_paths", CHANGES),
+    ("list_reservations", READS),
+    ("chunk_signature", READS_DISK),
+    ("disk_usage", READS_DISK),
+    ("find_duplicates", READS_DISK),
+    ("get_permissions", READS_DISK),
+    ("set_permissions", CHANGES_DISK),
+    ("find_unicode_issues", READS_DISK),
+    ("estimate_operation", READS_DISK),
+    ("create_temp_file", CHANGES_DISK),
+    ("create_temp_dir", CHANGES_DISK),
+    ("watch_path", CHANGES_DISK),
+    ("poll_changes", READS_DISK),
+    ("unwatch_path", CHANGES_DISK),
+    ("git_status", READS_DISK),
+    ("create_checkpoint", CHANGES_DISK),
+    ("list_checkpoints", READS),
+    ("rollback_to_checkpoint", CHANGES_DISK),
+    ("find_file", READS_DISK),
+    ("index_status", READS_DISK),
+    ("preview_file", READS),
+    ("read_structured", READS),
+    ("patch_structured", CHANGES),
+    ("inspect_csv", READS),
+    ("read_csv_rows", READS),
+    ("read_lines", READS),
+    ("insert_lines", CHANGES),
+    ("replace_lines", CHANGES),
+    ("delete_lines", CHANGES),
+    ("session_info", READS),
+    ("diff_directories", READS_DISK),
+    ("server_stats", READS),
+    ("list_xattrs", READS_DISK),
+    ("read_xattr", READS_DISK),
+    ("write_xattr", CHANGES_DISK),
+    ("copy_file", CHANGES_DISK),
+    ("replace_in_files", CHANGES_DISK),
+    ("word_count", READS),
+    ("lock_file", CHANGES_DISK),
+    ("unlock_file", CHANGES_DISK),
+    ("encode_file", READS),
+    ("decode_to_file", CHANGES),
+    ("recent_changes", READS_DISK),
+];
+
+fn traits_of(name: &str) -> Option<ToolTraits> {
+    TOOL_REGISTRY.iter().find(|(tool, _)| *tool == name).map(|(_, traits)| *traits)
+}
+
+/// Whether `name` changes files or the state of the server: locks,
+/// reservations, watches, checkpoints and temporary files included
+pub fn is_mutating(name: &str) -> bool {
+    traits_of(name).is_some_and(|traits| traits.mutating)
+}
+
+/// Whether `name` reaches the local disk on its own, walking trees, running
+/// other programs, keeping OS handles or copying files aside, and so only runs on it
+pub fn is_local_only(name: &str) -> bool {
+    traits_of(name).is_some_and(|traits| traits.local_only)
+}
+
+/// Whether this server has a tool of this name, offered or not
+pub fn is_tool(name: &str) -> bool {
+    is_builtin_tool(name) || custom_tool(name).is_some()
+}
+
+/// `MCP_RS_FILESYSTEM_DISABLED_TOOLS`, set by `--disabled-tools` or the
+/// configuration file: tools, separated by commas, left out of `tools/list`
+/// and refused as unknown when called
+fn tool_disabled(name: &str) -> bool {
+    config::var("MCP_RS_FILESYSTEM_DISABLED_TOOLS")
+        .is_ok_and(|disabled| disabled.split(',').any(|tool| tool.trim() == name))
+}
+
+/// Names of the tools offered, in `tools/list` order
+pub fn enabled_tools() -> Vec<String> {
+    tool_definitions()
+        .into_iter()
+        .map(|tool| tool.name)
+        .filter(|name| !tool_disabled(name))
+        .collect()
+}
+
+pub async fn tools_list(_request: Option<ListToolsRequest>) -> HandlerResult<ListToolsResult> {
+    let mut tools = tool_definitions();
+    tools.retain(|tool| !tool_disabled(&tool.name));
+    if !structured_output() {
+        for tool in &mut tools {
+            tool.output_schema = None;
+        }
+    }
+    Ok(ListToolsResult {
+        tools,
         next_cursor: None,
-    };
-    Ok(response)
+    })
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct CurrentTimeRequest {
+    /// city name
     pub city: Option<String>,
 }
 
 pub async fn current_time(_request: CurrentTimeRequest) -> HandlerResult<CallToolResult> {
     let result = format!("Now: {}!", Local::now().to_rfc2822());
-    Ok(CallToolResult {
-        content: vec![CallToolResultContent::Text { text: result }],
-        is_error: false,
-    })
+    Ok(CallToolResult::text(result))
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct GetLocalTimeRequest {}
 
 pub async fn get_local_time(_request: GetLocalTimeRequest) -> HandlerResult<CallToolResult> {
     let result = format!("Local time: {}", Local::now().to_rfc2822());
-    Ok(CallToolResult {
-        content: vec![CallToolResultContent::Text { text: result }],
-        is_error: false,
-    })
+    Ok(CallToolResult::text(result))
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct FileEditRequest {
+    /// Path to the file to edit
     pub file_path: String,
+    /// Exact content to replace (must match uniquely)
     pub old_content: String,
+    /// Content to insert instead
     pub new_content: String,
+    /// Message describing the purpose of this edit
     pub commit_message: String,
+    /// Return the diff the edit would make without writing the file or committing. Always the case when the
+    /// server runs with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
 }
 
 pub async fn file_edit(request: FileEditRequest) -> HandlerResult<CallToolResult> {
     // Validate path is within allowed directories
-    let path = Path::new(&request.file_path);
-    if let Err(msg) = validate_path_or_error(path) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: msg }],
-            is_error: true,
-        });
+    let path = &resolve_path(Path::new(&request.file_path));
+    if let Err(msg) = validate_write_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
     }
 
-    // Read the file
-    let content = match fs::read_to_string(path) {
-        Ok(content) => content,
-        Err(e) => return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Error reading file: {}", e) 
-            }],
-            is_error: true,
-        }),
+    // Read the file, keeping track of its encoding and line endings
+    let decoded = match read_text_file(path, None) {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading file: {}", e))),
+    };
+
+    // Match on LF line endings so edits apply to CRLF files too, and restore them on write
+    let crlf = decoded.line_ending == Some(LineEnding::CrLf);
+    let (content, old_content, new_content) = if crlf {
+        (
+            decoded.text.replace("\r\n", "\n"),
+            request.old_content.replace("\r\n", "\n"),
+            request.new_content.replace("\r\n", "\n"),
+        )
+    } else {
+        (decoded.text, request.old_content.clone(), request.new_content.clone())
     };
 
     // Count matches of old_content
-    let matches = content.matches(&request.old_content).count();
+    let matches = content.matches(&old_content).count();
     if matches == 0 || matches > 1 {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Found {} matches of content - must match exactly once", matches) 
-            }],
-            is_error: true,
-        });
+        return Ok(CallToolResult::error(format!("Found {} matches of content - must match exactly once", matches)));
     }
 
     // Replace content
-    let new_content = content.replace(&request.old_content, &request.new_content);
+    let new_content = content.replace(&old_content, &new_content);
+
+    let line_ending = if crlf { Some(LineEnding::CrLf) } else { None };
+    if dry_run::requested(request.dry_run) {
+        return match dry_run::text_change(path, &new_content, decoded.encoding, line_ending) {
+            Ok(change) => dry_run::result(vec![change]),
+            Err(e) => Ok(CallToolResult::error(format!("Error reading file: {}", e))),
+        };
+    }
 
     // Write back to file
-    if let Err(e) = fs::write(path, new_content) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Error writing file: {}", e) 
-            }],
-            is_error: true,
-        });
+    if let Err(e) = shadow::write(path, |target| write_text_file(target, &new_content, decoded.encoding, line_ending)) {
+        return Ok(e.into_result("Error writing file"));
     }
 
     // Handle git commit if requested
@@ -326,28 +1005,44 @@ pub async fn file_edit(request: FileEditRequest) -> HandlerResult<CallToolResult
         }
     }
 
-    Ok(CallToolResult {
-        content: vec![CallToolResultContent::Text { text: message }],
-        is_error: false,
-    })
+    Ok(CallToolResult::text(message))
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct CreateDirectoryRequest {
+    /// Path to the new directory
     pub path: String,
+    /// Message describing the purpose of this directory creation
     pub commit_message: String,
+    /// List the directories that would be created without creating them. Always the case when the server runs
+    /// with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
 }
 
 pub async fn create_directory(request: CreateDirectoryRequest) -> HandlerResult<CallToolResult> {
-    let path = Path::new(&request.path);
-    if let Err(msg) = validate_path_or_error(path) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: msg }],
-            is_error: true,
-        });
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_write_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+
+    if dry_run::requested(request.dry_run) {
+        // The directory and those of its parents that do not exist yet, outermost first
+        let fs = vfs::current();
+        let mut missing: Vec<&Path> = path.ancestors().take_while(|dir| !fs.exists(dir)).collect();
+        missing.reverse();
+        let changes = missing
+            .iter()
+            .map(|dir| json!({ "action": "create_directory", "path": dir.display().to_string() }))
+            .collect();
+        return dry_run::result(changes);
+    }
+
+    if let Err(e) = checkpoint::preserve(path) {
+        return Ok(CallToolResult::error(e));
     }
 
-    match fs::create_dir_all(path) {
+    match vfs::current().create_dir_all(path) {
         Ok(_) => {
             let mut message = format!("Created directory: {}", path.display());
             
@@ -359,137 +1054,309 @@ pub async fn create_directory(request: CreateDirectoryRequest) -> HandlerResult<
                 }
             }
             
-            Ok(CallToolResult {
-                content: vec![CallToolResultContent::Text { text: message }],
-                is_error: false,
-            })
+            Ok(CallToolResult::text(message))
         },
-        Err(e) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Failed to create directory: {}", e) 
-            }],
-            is_error: true,
-        }),
+        Err(e) => Ok(CallToolResult::error(format!("Failed to create directory: {}", e))),
     }
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct OverwriteFileRequest {
+    /// Path to the file to overwrite
     pub path: String,
+    /// New content to write
     pub content: String,
+    /// Text encoding to write. Defaults to auto, which keeps the encoding of an existing file and uses UTF-8
+    /// for new files.
+    #[schemars(extend("enum" = ENCODING_NAMES, "default" = "auto"))]
+    pub encoding: Option<String>,
+    /// Line endings to write. Defaults to preserve, which keeps the line endings of an existing file.
+    #[schemars(extend("enum" = LINE_ENDING_NAMES, "default" = "preserve"))]
+    pub line_ending: Option<String>,
+    /// overwrite (default) replaces the file. append adds to the end of an existing file, create_new fails if
+    /// the file already exists, and create_or_append appends, creating the file if needed. Appended text
+    /// continues in the file's encoding and line ending style.
+    #[schemars(extend("enum" = WRITE_MODE_NAMES, "default" = "overwrite"))]
+    pub mode: Option<String>,
+    /// Return the diff and the sizes before and after without writing the file. Always the case when the server
+    /// runs with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
 }
 
 pub async fn overwrite_file(request: OverwriteFileRequest) -> HandlerResult<CallToolResult> {
-    let path = Path::new(&request.path);
-    if let Err(msg) = validate_path_or_error(path) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: msg }],
-            is_error: true,
-        });
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_write_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
     }
 
-    match std::fs::write(path, &request.content) {
-        Ok(_) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("File written successfully: {}", path.display()) 
-            }],
-            is_error: false,
-        }),
-        Err(e) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Failed to write file: {}", e) 
-            }],
-            is_error: true,
-        }),
+    let format = TextEncoding::parse(request.encoding.as_deref().unwrap_or("auto")).and_then(|encoding| {
+        LineEnding::parse(request.line_ending.as_deref().unwrap_or("preserve")).map(|line_ending| (encoding, line_ending))
+    });
+    let format = format.and_then(|(encoding, line_ending)| {
+        WriteMode::parse(request.mode.as_deref()).map(|mode| (encoding, line_ending, mode))
+    });
+    let (encoding, line_ending, mode) = match format {
+        Ok(format) => format,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    // The open itself enforces these; checking first gives a clearer message, and
+    // covers verified writes, which first write to a fresh shadow copy
+    let exists = vfs::current().exists(path);
+    let precondition = match mode {
+        WriteMode::CreateNew if exists => Some(format!("File already exists: {}", path.display())),
+        WriteMode::Append if !exists => Some(format!("File does not exist: {}", path.display())),
+        _ => None,
+    };
+    if let Some(text) = precondition {
+        return Ok(CallToolResult::error(text));
+    }
+
+    if dry_run::requested(request.dry_run) {
+        let change = if mode.appends() {
+            dry_run::append_change(path, &request.content, encoding, line_ending)
+        } else {
+            dry_run::preserving_change(path, &request.content, encoding, line_ending)
+        };
+        return match change {
+            Ok(change) => dry_run::result(vec![change]),
+            Err(text) => Ok(CallToolResult::error(text)),
+        };
+    }
+
+    // Keep the encoding and line endings of an existing file unless told otherwise
+    match shadow::write_with_mode(path, mode, |target| {
+        write_text_with_mode(target, &request.content, mode, encoding, line_ending)
+    }) {
+        Ok(_) => {
+            let done = match mode {
+                WriteMode::Overwrite => "File written successfully",
+                WriteMode::CreateNew => "File created",
+                WriteMode::Append | WriteMode::CreateOrAppend if exists => "Content appended",
+                WriteMode::Append | WriteMode::CreateOrAppend => "File created",
+            };
+            Ok(CallToolResult::text(format!("{}: {}", done, path.display())))
+        }
+        Err(e) => Ok(e.into_result("Failed to write file")),
     }
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct ReadFileRequest {
+    /// Path to the file to read
     pub file_path: String,
+    /// Text encoding of the file. Defaults to auto, which detects UTF-8, UTF-16 and Latin-1.
+    #[schemars(extend("enum" = ENCODING_NAMES, "default" = "auto"))]
+    pub encoding: Option<String>,
+    /// Byte offset into the text to start from, e.g. next_offset of a truncated read
+    pub offset: Option<usize>,
+    /// Return at most this many bytes. The server caps reads at its own limit (1 MiB by default).
+    pub max_bytes: Option<usize>,
+    /// PNG, JPEG and WebP files are returned as images. Scale them down so neither side is longer than this many
+    /// pixels.
+    #[schemars(range(min = 1))]
+    pub max_dimension: Option<u32>,
 }
 
 pub async fn read_file(request: ReadFileRequest) -> HandlerResult<CallToolResult> {
-    let path = Path::new(&request.file_path);
+    let path = &resolve_path(Path::new(&request.file_path));
     if let Err(msg) = validate_path_or_error(path) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: msg }],
-            is_error: true,
+        return Ok(CallToolResult::error(msg));
+    }
+
+    if let Some(mime_type) = image_type(path) {
+        return Ok(match read_image(path, mime_type, request.max_dimension) {
+            Ok(content) => CallToolResult::content(content),
+            Err(msg) => CallToolResult::error(msg),
         });
     }
 
-    match fs::read_to_string(path) {
-        Ok(content) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: content }],
-            is_error: false,
-        }),
-        Err(e) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Error reading file: {}", e) 
-            }],
-            is_error: true,
-        }),
+    let encoding = match TextEncoding::parse(request.encoding.as_deref().unwrap_or("auto")) {
+        Ok(encoding) => encoding,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    let decoded = match read_text_file(path, encoding) {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading file: {}", e))),
+    };
+
+    let total = decoded.text.len();
+    let offset = request.offset.unwrap_or(0);
+    if offset > total {
+        return Ok(CallToolResult::error(format!("Offset {} is past the end of the file ({} bytes)", offset, total)));
     }
+    let limit = effective_limit(max_read_bytes(), request.max_bytes);
+    let (start, end) = text_window(&decoded.text, offset, limit);
+    if start == 0 && end == total {
+        return Ok(CallToolResult::text(decoded.text));
+    }
+    // Partial reads say where they stopped so the rest can be fetched
+    Ok(CallToolResult::content(vec![
+        CallToolResultContent::Text { text: decoded.text[start..end].to_string() },
+        truncation_metadata("bytes", total, start, end - start),
+    ]))
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct ListDirectoryRequest {
+    /// Path to directory to list
     pub path: String,
+    /// Ordering of the entries: natural (default) sorts file2 before file10, lexicographic uses plain byte
+    /// order, locale also ignores case and accents
+    #[schemars(extend("enum" = SORT_ORDER_NAMES, "default" = "natural"))]
+    pub sort: Option<String>,
+    /// Index of the first entry to return, e.g. next_offset of a truncated listing
+    pub offset: Option<usize>,
+    /// Return at most this many entries. The server caps listings at its own limit (1000 by default).
+    pub max_entries: Option<usize>,
+    /// Inside a git repository, suffix entries with their state: [tracked], [modified], [added], [untracked],
+    /// [ignored] or [conflicted]
+    pub git_status: Option<bool>,
+    /// Leave out entries excluded by .gitignore, .ignore and git excludes. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub respect_gitignore: Option<bool>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
 }
 
 pub async fn list_directory(request: ListDirectoryRequest) -> HandlerResult<CallToolResult> {
-    let path = Path::new(&request.path);
+    let path = &resolve_path(Path::new(&request.path));
     if let Err(msg) = validate_path_or_error(path) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: msg }],
-            is_error: true,
-        });
+        return Ok(CallToolResult::error(msg));
     }
 
-    match fs::read_dir(path) {
-        Ok(dir) => {
-            let mut content = String::new();
-            for entry in dir {
-                if let Ok(entry) = entry {
-                    // Also validate each entry is within allowed directories
-                    if is_path_allowed(&entry.path()) {
-                        content.push_str(&format!("{}\n", entry.file_name().to_string_lossy()));
-                    }
+    let sort = match SortOrder::parse(request.sort.as_deref()) {
+        Ok(sort) => sort,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    let rules = match IgnoreRules::new(request.respect_gitignore.unwrap_or(false), true).with_overrides(&request.ignore) {
+        Ok(rules) => rules,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    let fs = vfs::current();
+    let listing = fs.list(path).map(|present| {
+        let mut listed = 0;
+        let mut entries = Vec::new();
+        if !fs.is_local() {
+            // Without ignore files to read, only the patterns apply
+            let matcher = rules.matcher(path);
+            for entry in &present {
+                let is_dir = entry.kind == FileKind::Directory;
+                if !is_ignored(&matcher, &path.join(&entry.name), is_dir) {
+                    listed += 1;
+                    entries.push((entry.name.clone(), is_dir));
                 }
             }
+            return (present.len(), listed, entries);
+        }
+        for entry in rules.walker(path).max_depth(Some(1)).build().flatten().filter(|entry| entry.depth() == 1) {
+            listed += 1;
+            // Also validate each entry is within allowed directories
+            if is_path_allowed(entry.path()) {
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                entries.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+            }
+        }
+        (present.len(), listed, entries)
+    });
+    match listing {
+        Ok((present, listed, mut entries)) => {
+            let ignored = present.saturating_sub(listed);
+            entries.sort_by(|(a, _), (b, _)| sort.compare(a, b));
+            let total = entries.len();
+            let offset = request.offset.unwrap_or(0).min(total);
+            let limit = effective_limit(max_result_entries(), request.max_entries);
+            let page = &entries[offset..total.min(offset.saturating_add(limit))];
+            let annotations = if request.git_status.unwrap_or(false) {
+                directory_annotations(path)
+            } else {
+                None
+            };
+            let label = |name: &String| annotations.as_ref().and_then(|labels| labels.get(name));
+            let content: String = page
+                .iter()
+                .map(|(name, _)| match label(name) {
+                    Some(label) => format!("{} [{}]\n", name, label),
+                    None => format!("{}\n", name),
+                })
+                .collect();
+            let structured = json!({
+                "path": path.display().to_string(),
+                "entries": page
+                    .iter()
+                    .map(|(name, is_dir)| json!({
+                        "name": name,
+                        "type": if *is_dir { "directory" } else { "file" },
+                        "git_status": label(name),
+                    }))
+                    .collect::<Vec<_>>(),
+                "offset": offset,
+                "total": total,
+                "ignored": ignored,
+            });
+            let mut content = vec![CallToolResultContent::Text { text: content }];
+            if page.len() < total {
+                content.push(truncation_metadata("entries", total, offset, page.len()));
+            }
+            if ignored > 0 {
+                content.push(CallToolResultContent::Text {
+                    text: format!("{} entries left out by the ignore rules", ignored),
+                });
+            }
             Ok(CallToolResult {
-                content: vec![CallToolResultContent::Text { text: content }],
+                content,
                 is_error: false,
+                structured_content: Some(structured),
             })
         },
-        Err(e) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Error listing directory: {}", e) 
-            }],
-            is_error: true,
-        }),
+        Err(e) => Ok(CallToolResult::error(format!("Error listing directory: {}", e))),
     }
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct MoveOrRenameRequest {
+    /// Source path to move/rename from
     pub source_path: String,
+    /// Target path to move/rename to
     pub target_path: String,
+    /// Message describing the purpose of this move/rename
     pub commit_message: String,
+    /// Report the move without making it. Always the case when the server runs with --dry-run. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
 }
 
 pub async fn move_or_rename(request: MoveOrRenameRequest) -> HandlerResult<CallToolResult> {
-    let source_path = Path::new(&request.source_path);
-    let target_path = Path::new(&request.target_path);
+    let source_path = &resolve_path(Path::new(&request.source_path));
+    let target_path = &resolve_path(Path::new(&request.target_path));
     
-    if let Err(msg) = validate_paths_or_error(source_path, target_path) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: msg }],
-            is_error: true,
-        });
+    if let Err(msg) = validate_write_paths_or_error(source_path, target_path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if let Err(e) = shadow::verify_removal(source_path) {
+        return Ok(e.into_result("Failed to move or rename"));
+    }
+    if dry_run::requested(request.dry_run) {
+        let fs = vfs::current();
+        if let Err(e) = fs.stat(source_path) {
+            return Ok(CallToolResult::error(format!("Failed to move or rename: {}", e)));
+        }
+        return dry_run::result(vec![json!({
+            "action": "move",
+            "from": source_path.display().to_string(),
+            "to": target_path.display().to_string(),
+            "replaces_existing": fs.exists(target_path),
+        })]);
+    }
+    if let Err(e) = checkpoint::preserve(source_path).and_then(|_| checkpoint::preserve(target_path)) {
+        return Ok(CallToolResult::error(e));
     }
 
-    match fs::rename(source_path, target_path) {
+    match sandbox::rename(source_path, target_path) {
         Ok(_) => {
             let mut message = format!("Moved or renamed successfully: {} to {}", source_path.display(), target_path.display());
             
@@ -501,66 +1368,91 @@ pub async fn move_or_rename(request: MoveOrRenameRequest) -> HandlerResult<CallT
                 }
             }
             
-            Ok(CallToolResult {
-                content: vec![CallToolResultContent::Text { text: message }],
-                is_error: false,
-            })
+            Ok(CallToolResult::text(message))
         },
-        Err(e) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Failed to move or rename: {}", e) 
-            }],
-            is_error: true,
-        }),
+        Err(e) => Ok(CallToolResult::error(format!("Failed to move or rename: {}", e))),
     }
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct GetFileInfoRequest {
+    /// Path to file to get info about
     pub path: String,
 }
 
 pub async fn get_file_info(request: GetFileInfoRequest) -> HandlerResult<CallToolResult> {
-    let path = Path::new(&request.path);
+    let path = &resolve_path(Path::new(&request.path));
     if let Err(msg) = validate_path_or_error(path) {
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { text: msg }],
-            is_error: true,
-        });
+        return Ok(CallToolResult::error(msg));
     }
 
-    match fs::metadata(path) {
-        Ok(metadata) => {
+    match vfs::current().stat(path) {
+        Ok(stat) => {
             let mut content = String::new();
-            content.push_str(&format!("File size: {}\n", metadata.len()));
-            content.push_str(&format!("File type: {:?}\n", metadata.file_type()));
-            if let Ok(modified) = metadata.modified() {
+            content.push_str(&format!("File size: {}\n", stat.len));
+            content.push_str(&format!("File type: {:?}\n", stat.kind));
+            if let Some(modified) = stat.modified {
                 content.push_str(&format!("Last modified: {:?}\n", modified));
             }
+            let file_type = match stat.kind {
+                FileKind::Directory => "directory",
+                FileKind::File => "file",
+                FileKind::Other => "other",
+            };
+            let structured = json!({
+                "path": path.display().to_string(),
+                "size": stat.len,
+                "type": file_type,
+                "modified": stat.modified.map(|time| DateTime::<Local>::from(time).to_rfc3339()),
+                "readonly": stat.readonly,
+            });
             Ok(CallToolResult {
                 content: vec![CallToolResultContent::Text { text: content }],
                 is_error: false,
+                structured_content: Some(structured),
             })
         },
-        Err(e) => Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text { 
-                text: format!("Error getting file info: {}", e) 
-            }],
-            is_error: true,
-        }),
+        Err(e) => Ok(CallToolResult::error(format!("Error getting file info: {}", e))),
     }
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
 pub struct GrepSearchRequest {
+    /// Pattern to search for
     pub pattern: String,
+    /// Path to search in. For recursive searches this must be a directory.
     pub path: String,
+    /// Whether to search recursively in subdirectories. Defaults to true.
     #[serde(default = "default_recursive", deserialize_with = "deserialize_bool_from_string_or_bool")]
+    #[schemars(extend("type" = ["boolean", "string"]))]
     pub recursive: Option<bool>,
+    /// Whether the search should be case sensitive. Defaults to true.
     #[serde(default = "default_case_sensitive", deserialize_with = "deserialize_bool_from_string_or_bool")]
+    #[schemars(extend("type" = ["boolean", "string"]))]
     pub case_sensitive: Option<bool>,
+    /// In recursive searches, skip files excluded by .gitignore, .ignore and git excludes. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// In recursive searches, include hidden files and directories. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    /// Match letters regardless of accents, so 'resume' finds 'résumé'. Binary files and files larger
+    /// than the read limit are skipped. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub ignore_accents: Option<bool>,
+    /// Match text stored in either Unicode normalization form (NFC or NFD). Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub normalize_unicode: Option<bool>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
 }
 
+/// Files given to one run of grep
+const GREP_FILES_PER_RUN: usize = 500;
+
+/// Text piped into one run of grep when searching without accents
+const GREP_BYTES_PER_RUN: usize = 16 * 1024 * 1024;
+
 fn default_recursive() -> Option<bool> {
     Some(true)
 }
@@ -613,22 +1505,106 @@ where
     deserializer.deserialize_any(BoolOrStringVisitor)
 }
 
+/// A `file:line:text` line of `grep -n -H` output as a match. The first
+/// `:<digits>:` ends the file name, which may itself contain colons.
+fn parse_grep_line(line: &str) -> Option<Value> {
+    let mut search = 0;
+    while let Some(found) = line[search..].find(':') {
+        let colon = search + found;
+        let rest = &line[colon + 1..];
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if digits > 0 && rest[digits..].starts_with(':') {
+            return Some(json!({
+                "path": &line[..colon],
+                "line": rest[..digits].parse::<u64>().ok()?,
+                "text": &rest[digits + 1..],
+            }));
+        }
+        search = colon + 1;
+    }
+    None
+}
+
+/// Text of a file for a search without accents, or why it is left out
+fn searchable_text(path: &Path) -> Result<String, String> {
+    let fs = vfs::current();
+    let limit = max_read_bytes();
+    if fs.stat(path)?.len > limit as u64 {
+        return Err(format!("skipped, larger than the read limit of {} bytes", limit));
+    }
+    let bytes = fs.read(path)?;
+    if !mime::looks_like_text(&bytes[..bytes.len().min(mime::SNIFF_BYTES)]) {
+        return Err("skipped, binary file".to_string());
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Runs `grep` once over the contents of `files` with accents removed, one
+/// after the other, and reports matches with the file and text as they are
+/// on disk. Stripping accents keeps line breaks in place, so every line grep
+/// reads is a line of one of the files.
+fn grep_without_accents(
+    mut grep: std::process::Command,
+    files: &[(PathBuf, String)],
+) -> std::io::Result<std::process::Output> {
+    let mut stripped = String::new();
+    // The line of the input each file starts at, with the file's lines
+    let mut starts = Vec::new();
+    let mut first = 1;
+    for (path, text) in files {
+        let lines: Vec<&str> = text.lines().collect();
+        let next = first + lines.len();
+        starts.push((first, path, lines));
+        first = next;
+        stripped.push_str(&strip_accents(text));
+        if !text.is_empty() && !text.ends_with('\n') {
+            stripped.push('\n');
+        }
+    }
+    let mut child = grep
+        .arg("--label=-")
+        .arg("--")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = std::thread::spawn(move || stdin.write_all(stripped.as_bytes()));
+    let mut output = child.wait_with_output()?;
+    let _ = writer.join();
+
+    let mut restored = String::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let original_line = parse_grep_line(line).and_then(|found| {
+            let number = usize::try_from(found["line"].as_u64()?).ok()?;
+            let file = starts.partition_point(|(first, _, _)| *first <= number).checked_sub(1)?;
+            let (first, path, lines) = &starts[file];
+            let text = lines.get(number - first)?;
+            Some(format!("{}:{}:{}", path.display(), number - first + 1, text))
+        });
+        restored.push_str(&original_line.unwrap_or_else(|| line.to_string()));
+        restored.push('\n');
+    }
+    output.stdout = restored.into_bytes();
+    Ok(output)
+}
+
 pub async fn grep_search(request: GrepSearchRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(grep_search_blocking(request)).await
+}
+
+async fn grep_search_blocking(request: GrepSearchRequest) -> HandlerResult<CallToolResult> {
     // First check if grep is available
-    if let Err(_) = std::process::Command::new("grep").arg("--version").output() {
+    if std::process::Command::new("grep").arg("--version").output().is_err() {
         notify("logging/message", Some(json!({
             "message": "grep command not found on system",
             "level": "error"
         })));
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text {
-                text: "grep command not found on system".to_string(),
-            }],
-            is_error: true,
-        });
+        return Ok(CallToolResult::error("grep command not found on system".to_string()));
     }
 
-    let path = Path::new(&request.path);
+    let path = &resolve_path(Path::new(&request.path));
     
     // Validate the search path is allowed
     if let Err(e) = validate_path_or_error(path) {
@@ -636,12 +1612,7 @@ pub async fn grep_search(request: GrepSearchRequest) -> HandlerResult<CallToolRe
             "message": format!("Path validation error: {}", e),
             "level": "error"
         })));
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: true,
-        });
+        return Ok(CallToolResult::error(e.to_string()));
     }
 
     // For recursive searches, the path must be a directory and must exist
@@ -652,100 +1623,290 @@ pub async fn grep_search(request: GrepSearchRequest) -> HandlerResult<CallToolRe
                 "message": "Path must be a directory for recursive search",
                 "level": "error"
             })));
-            return Ok(CallToolResult {
-                content: vec![CallToolResultContent::Text {
-                    text: "Path must be a directory for recursive search".to_string(),
-                }],
-                is_error: true,
-            });
+            return Ok(CallToolResult::error("Path must be a directory for recursive search".to_string()));
         }
     } else if !path.exists() {
         notify("logging/message", Some(json!({
             "message": "Path does not exist",
             "level": "error"
         })));
-        return Ok(CallToolResult {
-            content: vec![CallToolResultContent::Text {
-                text: "Path does not exist".to_string(),
-            }],
-            is_error: true,
-        });
+        return Ok(CallToolResult::error("Path does not exist".to_string()));
     }
 
     let case_sensitive = request.case_sensitive.unwrap_or(true);
 
-    let mut cmd = std::process::Command::new("grep");
-    cmd.arg("-n") // Show line numbers
-       .arg("-H"); // Always show filename
-    
-    if !case_sensitive {
-        cmd.arg("-i");
-    }
-    
-    if recursive {
-        cmd.arg("-r");
-    }
-    
-    cmd.arg(&request.pattern)
-        .arg(path);
+    // Recursive searches go through the shared ignore rules, and grep is given the files that are left
+    let targets = if recursive {
+        let rules = IgnoreRules::new(request.respect_gitignore.unwrap_or(true), request.include_hidden.unwrap_or(false))
+            .with_overrides(&request.ignore);
+        match rules {
+            Ok(rules) => rules
+                .walker(path)
+                .build()
+                .flatten()
+                .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+                .map(|entry| entry.into_path())
+                .collect(),
+            Err(msg) => return Ok(CallToolResult::error(msg)),
+        }
+    } else {
+        vec![path.clone()]
+    };
+
+    // Without accents, the pattern is matched against text that has none either
+    let ignore_accents = request.ignore_accents.unwrap_or(false);
+    let pattern = if ignore_accents {
+        strip_accents(&request.pattern)
+    } else {
+        request.pattern.clone()
+    };
+    // Match text stored in either Unicode normalization form
+    let variants = if request.normalize_unicode.unwrap_or(true) && !ignore_accents {
+        vec![nfc(&pattern), pattern.nfd().collect::<String>()]
+    } else {
+        Vec::new()
+    };
+    let grep = || {
+        let mut cmd = std::process::Command::new("grep");
+        cmd.arg("-n") // Show line numbers
+           .arg("-H"); // Always show filename
+        if !case_sensitive {
+            cmd.arg("-i");
+        }
+        cmd.arg("-e").arg(&pattern);
+        for variant in &variants {
+            if *variant != pattern {
+                cmd.arg("-e").arg(variant);
+            }
+        }
+        cmd
+    };
 
     notify("logging/message", Some(json!({
-        "message": format!("Running grep command: {:?}", cmd),
+        "message": format!("Running grep command: {:?} on {} file(s)", grep(), targets.len()),
         "level": "debug"
     })));
 
-    match cmd.output() {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            
-            notify("logging/message", Some(json!({
-                "message": format!("grep stdout: {}", stdout),
-                "level": "debug"
-            })));
-            
-            if !stderr.is_empty() {
-                notify("logging/message", Some(json!({
-                    "message": format!("grep stderr: {}", stderr),
-                    "level": "debug"
-                })));
+    // Batches keep the command line within the limits of the system
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let mut skipped = String::new();
+    let mut matched = false;
+    let mut collect = |output: std::io::Result<std::process::Output>| -> std::io::Result<()> {
+        let output = output?;
+        matched |= output.status.success();
+        stdout.push_str(&String::from_utf8_lossy(&output.stdout));
+        stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+        Ok(())
+    };
+    let result = if ignore_accents {
+        // Texts without accents are piped in, many files to a run
+        (|| {
+            let mut batch = Vec::new();
+            let mut batch_bytes = 0;
+            for target in &targets {
+                match searchable_text(target) {
+                    Ok(text) => {
+                        batch_bytes += text.len();
+                        batch.push((target.clone(), text));
+                    }
+                    Err(reason) => skipped.push_str(&format!("grep: {}: {}\n", target.display(), reason)),
+                }
+                if batch.len() >= GREP_FILES_PER_RUN || batch_bytes >= GREP_BYTES_PER_RUN {
+                    collect(grep_without_accents(grep(), &std::mem::take(&mut batch)))?;
+                    batch_bytes = 0;
+                }
             }
-            
-            if output.status.success() {
-                Ok(CallToolResult {
-                    content: vec![CallToolResultContent::Text {
-                        text: stdout.into_owned(),
-                    }],
-                    is_error: false,
-                })
-            } else {
-                notify("logging/message", Some(json!({
-                    "message": format!("grep error: {}", stderr),
-                    "level": "error"
-                })));
-                Ok(CallToolResult {
-                    content: vec![CallToolResultContent::Text {
-                        text: format!("Grep error: {}", stderr),
-                    }],
-                    is_error: true,
-                })
+            if batch.is_empty() {
+                return Ok(());
             }
-        }
-        Err(e) => {
-            notify("logging/message", Some(json!({
-                "message": format!("Failed to execute grep: {}", e),
-                "level": "error"
-            })));
-            Ok(CallToolResult {
-                content: vec![CallToolResultContent::Text {
-                    text: format!("Failed to execute grep: {}", e),
-                }],
-                is_error: true,
-            })
-        },
-    }
-}
-
+            collect(grep_without_accents(grep(), &batch))
+        })()
+    } else {
+        targets.chunks(GREP_FILES_PER_RUN).try_for_each(|batch| collect(grep().arg("--").args(batch).output()))
+    };
+    if let Err(e) = result {
+        notify("logging/message", Some(json!({
+            "message": format!("Failed to execute grep: {}", e),
+            "level": "error"
+        })));
+        return Ok(CallToolResult::error(format!("Failed to execute grep: {}", e)));
+    }
+    stderr.push_str(&skipped);
+
+    notify("logging/message", Some(json!({
+        "message": format!("grep stdout: {}", stdout),
+        "level": "debug"
+    })));
+
+    if !stderr.is_empty() {
+        notify("logging/message", Some(json!({
+            "message": format!("grep stderr: {}", stderr),
+            "level": "debug"
+        })));
+    }
+
+    if matched {
+        let matches: Vec<Value> = stdout.lines().filter_map(parse_grep_line).collect();
+        Ok(CallToolResult {
+            content: vec![CallToolResultContent::Text { text: stdout }],
+            is_error: false,
+            structured_content: Some(json!({ "matches": matches })),
+        })
+    } else {
+        notify("logging/message", Some(json!({
+            "message": format!("grep error: {}", stderr),
+            "level": "error"
+        })));
+        Ok(CallToolResult::error(format!("Grep error: {}", stderr)))
+    }
+}
+
+const TAIL_DEFAULT_LINES: usize = 10;
+const TAIL_DEFAULT_FOLLOW_MS: u64 = 10_000;
+const TAIL_MAX_FOLLOW_MS: u64 = 60_000;
+const TAIL_DEFAULT_MAX_NEW_LINES: usize = 100;
+const TAIL_POLL_INTERVAL_MS: u64 = 250;
+const TAIL_READ_BLOCK: u64 = 8192;
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct TailFileRequest {
+    /// Path to the file to tail
+    pub path: String,
+    /// Number of lines to return from the end of the file. Defaults to 10.
+    #[schemars(extend("default" = 10))]
+    pub lines: Option<usize>,
+    /// Keep watching the file for appended lines. Defaults to false.
+    #[serde(default, deserialize_with = "deserialize_bool_from_string_or_bool")]
+    #[schemars(extend("type" = ["boolean", "string"], "default" = false))]
+    pub follow: Option<bool>,
+    /// How long to follow the file in milliseconds. Defaults to 10000, capped at 60000.
+    #[schemars(extend("default" = 10000))]
+    pub follow_duration_ms: Option<u64>,
+    /// Stop following after this many new lines. Defaults to 100.
+    #[schemars(extend("default" = 100))]
+    pub max_new_lines: Option<usize>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub meta: Option<MetaParams>,
+}
+
+pub async fn tail_file(request: TailFileRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+
+    let (tail, mut position) = match read_last_lines(path, request.lines.unwrap_or(TAIL_DEFAULT_LINES)) {
+        Ok(result) => result,
+        Err(e) => return Ok(CallToolResult::error(format!("Error reading file: {}", e))),
+    };
+
+    let mut content = String::new();
+    for line in &tail {
+        content.push_str(&format!("{}\n", line));
+    }
+
+    if !request.follow.unwrap_or(false) {
+        return Ok(CallToolResult::text(content));
+    }
+
+    let follow_ms = request.follow_duration_ms.unwrap_or(TAIL_DEFAULT_FOLLOW_MS).min(TAIL_MAX_FOLLOW_MS);
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(follow_ms);
+    let max_new_lines = request.max_new_lines.unwrap_or(TAIL_DEFAULT_MAX_NEW_LINES);
+    let progress_token = request.meta.as_ref().map(|meta| meta.progress_token.clone());
+
+    let mut new_lines: Vec<String> = Vec::new();
+    let mut pending: Vec<u8> = Vec::new();
+    while new_lines.len() < max_new_lines && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(TAIL_POLL_INTERVAL_MS)).await;
+
+        let len = match vfs::current().stat(path) {
+            Ok(stat) => stat.len,
+            Err(_) => continue,
+        };
+        if len < position {
+            // The file was truncated or rotated, start over from the beginning
+            position = 0;
+            pending.clear();
+        }
+        if len == position {
+            continue;
+        }
+        match read_range(path, position, len) {
+            Ok(bytes) => {
+                position += bytes.len() as u64;
+                pending.extend_from_slice(&bytes);
+            }
+            Err(_) => continue,
+        }
+
+        // Only complete lines are reported, a trailing partial line waits for the next poll
+        while let Some(newline) = pending.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = pending.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&raw).trim_end_matches(['\n', '\r']).to_string();
+            if let Some(token) = &progress_token {
+                notify_progress(token, new_lines.len() as i32 + 1, Some(max_new_lines as i32), Some(line.clone()));
+            }
+            new_lines.push(line);
+            if new_lines.len() >= max_new_lines {
+                break;
+            }
+        }
+    }
+
+    content.push_str(&format!("--- {} new line(s) while following ---\n", new_lines.len()));
+    for line in &new_lines {
+        content.push_str(&format!("{}\n", line));
+    }
+
+    Ok(CallToolResult::text(content))
+}
+
+/// Read the last `count` lines of a file by scanning backwards from the end,
+/// returning them together with the file length they were read up to.
+fn read_last_lines(path: &Path, count: usize) -> std::io::Result<(Vec<String>, u64)> {
+    let mut file = vfs::current().open(path).map_err(std::io::Error::other)?;
+    let len = file.seek(SeekFrom::End(0))?;
+
+    // One extra line break is needed to see the start of the first requested line,
+    // plus one more when the file ends with a newline
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut newlines = 0;
+    let mut start = len;
+    while start > 0 && newlines <= count + 1 {
+        let block_start = start.saturating_sub(TAIL_READ_BLOCK);
+        let mut block = vec![0; (start - block_start) as usize];
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block)?;
+        newlines += block.iter().filter(|b| **b == b'\n').count();
+        block.extend_from_slice(&buffer);
+        buffer = block;
+        start = block_start;
+    }
+
+    let text = String::from_utf8_lossy(&buffer);
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    if text.is_empty() {
+        return Ok((Vec::new(), len));
+    }
+    let lines: Vec<&str> = text.split('\n').collect();
+    let skip = lines.len().saturating_sub(count);
+    let tail = lines[skip..]
+        .iter()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect();
+    Ok((tail, len))
+}
+
+fn read_range(path: &Path, start: u64, end: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = vfs::current().open(path).map_err(std::io::Error::other)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.take(end - start).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
 fn find_git_repo(path: &Path) -> Option<String> {
     let mut current = path.to_path_buf();
     while let Some(parent) = current.parent() {
@@ -790,27 +1951,24 @@ fn commit_to_git(repo_path: &str, file_path: &Path, message: &str) -> Result<(),
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env;
     use std::fs;
     use tempfile::TempDir;
     use serde_json::json;
+    use base64::Engine;
+    use crate::mcp::testing::lock_env;
+    use crate::mcp::testing::remove_env;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::result_text;
+    use crate::mcp::testing::set_env;
+    use crate::mcp::testing::test_env;
     use crate::mcp::utilities::notify;
 
-    fn setup_test_env() -> (TempDir, String) {
-        let _temp_dir = TempDir::new().unwrap();
-        let canonical_path = _temp_dir.path().canonicalize().unwrap();
-        let _temp_path = canonical_path.to_str().unwrap().to_string();
-        (_temp_dir, _temp_path)
-    }
-
-    fn setup_git_repo() -> (TempDir, String) {
-        let (_temp_dir, _temp_path) = setup_test_env();
-        
+    fn setup_git_repo(dir: &Path) -> String {
         // Initialize git repo using the temp directory path
-        let repo = git2::Repository::init(_temp_dir.path()).unwrap();
+        let repo = git2::Repository::init(dir).unwrap();
         
         // Create test file in the temp directory
-        let test_file_path = _temp_dir.path().join("test.txt");
+        let test_file_path = dir.join("test.txt");
         fs::write(&test_file_path, "initial content\n").unwrap();
         
         // Make initial commit
@@ -824,16 +1982,17 @@ mod tests {
         
         // Canonicalize the file path after creating it
         let canonical_file_path = test_file_path.canonicalize().unwrap();
-        (_temp_dir, canonical_file_path.to_str().unwrap().to_string())
+        canonical_file_path.to_str().unwrap().to_string()
     }
 
     #[tokio::test]
     async fn test_file_edit_with_git() {
-        let (_temp_dir, file_path) = setup_git_repo();
+        let (_env, _temp_dir, _temp_path) = test_env().await;
+        let file_path = setup_git_repo(_temp_dir.path());
         
-        // Set up allowed directories
+        // Set up allowed directories, put back when the test ends
         #[cfg(target_os = "macos")]
-        {
+        let _directories = {
             let temp_path = _temp_dir.path().canonicalize().unwrap().to_str().unwrap().to_string();
             let (var_path, private_var_path) = if temp_path.starts_with("/private/var") {
                 (temp_path.strip_prefix("/private").unwrap().to_string(), temp_path.clone())
@@ -857,20 +2016,15 @@ mod tests {
                 "message": format!("Setting allowed directories: {}", allowed_dirs),
                 "level": "debug"
             })));
-            env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", allowed_dirs);
-        }
-        
-        #[cfg(not(target_os = "macos"))]
-        {
-            let temp_path = _temp_dir.path().canonicalize().unwrap().to_str().unwrap().to_string();
-            env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &temp_path);
-        }
+            set_env("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", allowed_dirs)
+        };
 
         let request = FileEditRequest {
             file_path: file_path.clone(),
             old_content: "initial content\n".to_string(),
             new_content: "modified content".to_string(),
             commit_message: "Test commit".to_string(),
+            dry_run: None,
         };
 
         let result = file_edit(request).await.unwrap();
@@ -894,11 +2048,11 @@ mod tests {
 
     #[tokio::test]
     async fn test_file_edit_without_git() {
-        let (_temp_dir, _temp_path) = setup_test_env();
+        let (_env, _temp_dir, _temp_path) = test_env().await;
         
-        // Set up allowed directories
+        // Set up allowed directories, put back when the test ends
         #[cfg(target_os = "macos")]
-        {
+        let _directories = {
             let temp_path = _temp_dir.path().canonicalize().unwrap().to_str().unwrap().to_string();
             let (var_path, private_var_path) = if temp_path.starts_with("/private/var") {
                 (temp_path.strip_prefix("/private").unwrap().to_string(), temp_path.clone())
@@ -913,22 +2067,16 @@ mod tests {
                 "level": "debug"
             })));
             
-            env::set_var(
-                "MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES",
-                format!("{}:{}", var_path, private_var_path)
-            );
-            
             notify("logging/message", Some(json!({
                 "message": format!("Setting allowed directories: {}", format!("{}:{}", var_path, private_var_path)),
                 "level": "debug"
             })));
-        }
-        
-        #[cfg(not(target_os = "macos"))]
-        {
-            let temp_path = _temp_dir.path().canonicalize().unwrap().to_str().unwrap().to_string();
-            env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &temp_path);
-        }
+            
+            set_env(
+                "MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES",
+                format!("{}:{}", var_path, private_var_path)
+            )
+        };
         
         // Create test file in the temp directory
         let test_file = _temp_dir.path().join("test.txt");
@@ -947,7 +2095,7 @@ mod tests {
         })));
         
         notify("logging/message", Some(json!({
-            "message": format!("Allowed directories: {}", env::var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES").unwrap_or_default()),
+            "message": format!("Allowed directories: {}", config::var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES").unwrap_or_default()),
             "level": "debug"
         })));
         
@@ -956,6 +2104,7 @@ mod tests {
             old_content: "initial content".to_string(),
             new_content: "modified content".to_string(),
             commit_message: "".to_string(),
+            dry_run: None,
         };
 
         let result = file_edit(request).await.unwrap();
@@ -970,15 +2119,12 @@ mod tests {
         // Verify file content
         let content = fs::read_to_string(&canonical_file_path).unwrap();
         assert_eq!(content, "modified content");
-        
-        // Clean up
-        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
     }
 
     #[tokio::test]
     async fn test_grep_search() {
         // First check if grep is available
-        if let Err(_) = std::process::Command::new("grep").arg("--version").output() {
+        if std::process::Command::new("grep").arg("--version").output().is_err() {
             notify("logging/message", Some(json!({
                 "message": "Skipping grep_search test: grep command not available",
                 "level": "info"
@@ -986,23 +2132,15 @@ mod tests {
             return;
         }
 
-        let (temp_dir, temp_path) = setup_test_env();
+        let (_env, temp_dir, temp_path) = test_env().await;
         
         notify("logging/message", Some(json!({
             "message": format!("Test directory: {}", temp_path),
             "level": "debug"
         })));
         
-        // Set up allowed directories
-        #[cfg(target_os = "macos")]
-        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &temp_path);
-        #[cfg(target_os = "linux")]
-        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &temp_path);
-        #[cfg(target_os = "windows")]
-        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &temp_path);
-        
         notify("logging/message", Some(json!({
-            "message": format!("Allowed directories: {}", env::var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES").unwrap_or_default()),
+            "message": format!("Allowed directories: {}", config::var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES").unwrap_or_default()),
             "level": "debug"
         })));
         
@@ -1022,30 +2160,1190 @@ mod tests {
             path: temp_path.clone(),
             recursive: Some(true),
             case_sensitive: Some(true),
+            respect_gitignore: None,
+            include_hidden: None,
+            ignore_accents: None,
+            normalize_unicode: None,
+            ignore: Default::default(),
         };
         
         let result = grep_search(request).await.unwrap();
         if result.is_error {
-            if let CallToolResultContent::Text { text } = &result.content[0] {
-                notify("logging/message", Some(json!({
-                    "message": format!("Error content: {}", text),
-                    "level": "error"
-                })));
-            }
+            let text = result_text(&result);
+            notify("logging/message", Some(json!({
+                "message": format!("Error content: {}", text),
+                "level": "error"
+            })));
         }
         assert!(!result.is_error, "Grep search failed");
         assert_eq!(result.content.len(), 1);
-        if let CallToolResultContent::Text { text } = &result.content[0] {
-            notify("logging/message", Some(json!({
-                "message": format!("Grep output: {}", text),
-                "level": "debug"
-            })));
-            assert!(text.contains("test1.txt"), "Output should contain test1.txt");
-            assert!(text.contains("test2.txt"), "Output should contain test2.txt");
-            assert!(text.contains("test3.txt"), "Output should contain test3.txt");
+        let text = result_text(&result);
+        notify("logging/message", Some(json!({
+            "message": format!("Grep output: {}", text),
+            "level": "debug"
+        })));
+        assert!(text.contains("test1.txt"), "Output should contain test1.txt");
+        assert!(text.contains("test2.txt"), "Output should contain test2.txt");
+        assert!(text.contains("test3.txt"), "Output should contain test3.txt");
+    }
+
+    #[tokio::test]
+    async fn test_grep_search_ignores_accents() {
+        if std::process::Command::new("grep").arg("--version").output().is_err() {
+            return;
+        }
+        let (_env, temp_dir, temp_path) = test_env().await;
+        fs::write(temp_dir.path().join("menu.txt"), "soup\nCrème brûlée\ncafe\u{301} au lait\n").unwrap();
+        fs::write(temp_dir.path().join("order.txt"), "first\ntwo crèmes").unwrap();
+
+        let search = |pattern: &str, ignore_accents: Option<bool>| GrepSearchRequest {
+            pattern: pattern.to_string(),
+            path: temp_path.clone(),
+            recursive: Some(true),
+            case_sensitive: Some(true),
+            respect_gitignore: None,
+            include_hidden: None,
+            ignore_accents,
+            normalize_unicode: None,
+            ignore: Default::default(),
+        };
+        let lines = |result: CallToolResult| -> Vec<(u64, String)> {
+            match result.structured_content {
+                Some(found) => found["matches"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|m| (m["line"].as_u64().unwrap(), m["text"].as_str().unwrap().to_string()))
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+
+        assert!(lines(grep_search(search("Creme", None)).await.unwrap()).is_empty());
+        // Matches are reported with the text as written in the file
+        let found = lines(grep_search(search("Creme brulee", Some(true))).await.unwrap());
+        assert_eq!(found, vec![(2, "Crème brûlée".to_string())]);
+        // A decomposed é in the file matches a composed one in the pattern
+        assert_eq!(lines(grep_search(search("caf\u{e9}", None)).await.unwrap()).len(), 1);
+        assert_eq!(lines(grep_search(search("café", Some(true))).await.unwrap()).len(), 1);
+        // Files searched in one run keep their own line numbers
+        let found = lines(grep_search(search("cremes", Some(true))).await.unwrap());
+        assert_eq!(found, vec![(2, "two crèmes".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_tail_file() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let log_path = temp_dir.path().join("server.log");
+        let lines: Vec<String> = (1..=50).map(|i| format!("line {}", i)).collect();
+        fs::write(&log_path, format!("{}\n", lines.join("\n"))).unwrap();
+
+        let request = TailFileRequest {
+            path: log_path.to_str().unwrap().to_string(),
+            lines: Some(3),
+            follow: None,
+            follow_duration_ms: None,
+            max_new_lines: None,
+            meta: None,
+        };
+        let result = tail_file(request).await.unwrap();
+        assert!(!result.is_error, "tail_file failed: {:?}", result.content);
+        let text = result_text(&result);
+        assert_eq!(text, "line 48\nline 49\nline 50\n");
+
+        // Append while following and make sure the new lines are picked up
+        let append_path = log_path.clone();
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let mut file = fs::OpenOptions::new().append(true).open(&append_path).unwrap();
+            std::io::Write::write_all(&mut file, b"appended 1\nappended 2\n").unwrap();
+        });
+        let request = TailFileRequest {
+            path: log_path.to_str().unwrap().to_string(),
+            lines: Some(1),
+            follow: Some(true),
+            follow_duration_ms: Some(5_000),
+            max_new_lines: Some(2),
+            meta: None,
+        };
+        let result = tail_file(request).await.unwrap();
+        writer.await.unwrap();
+        assert!(!result.is_error, "tail_file failed: {:?}", result.content);
+        let text = result_text(&result);
+        assert!(text.starts_with("line 50\n"));
+        assert!(text.contains("--- 2 new line(s) while following ---"));
+        assert!(text.ends_with("appended 1\nappended 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_encoding_and_line_endings_preserved() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        // A CRLF file keeps its line endings when edited and when overwritten with LF content
+        let crlf_path = temp_dir.path().join("windows.txt");
+        fs::write(&crlf_path, "first\r\nsecond\r\n").unwrap();
+        let request = FileEditRequest {
+            file_path: crlf_path.to_str().unwrap().to_string(),
+            old_content: "first\nsecond".to_string(),
+            new_content: "first\nchanged".to_string(),
+            commit_message: "".to_string(),
+            dry_run: None,
+        };
+        let result = file_edit(request).await.unwrap();
+        assert!(!result.is_error, "file_edit failed: {:?}", result.content);
+        assert_eq!(fs::read(&crlf_path).unwrap(), b"first\r\nchanged\r\n");
+
+        let request = OverwriteFileRequest {
+            path: crlf_path.to_str().unwrap().to_string(),
+            content: "one\ntwo\n".to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: None,
+            dry_run: None,
+        };
+        let result = overwrite_file(request).await.unwrap();
+        assert!(!result.is_error, "overwrite_file failed: {:?}", result.content);
+        assert_eq!(fs::read(&crlf_path).unwrap(), b"one\r\ntwo\r\n");
+
+        // UTF-16 and Latin-1 files are readable
+        let utf16_path = temp_dir.path().join("utf16.txt");
+        let request = OverwriteFileRequest {
+            path: utf16_path.to_str().unwrap().to_string(),
+            content: "héllo".to_string(),
+            encoding: Some("utf-16le".to_string()),
+            line_ending: None,
+            mode: None,
+            dry_run: None,
+        };
+        assert!(!overwrite_file(request).await.unwrap().is_error);
+        assert_eq!(&fs::read(&utf16_path).unwrap()[..2], &[0xFF, 0xFE]);
+
+        let latin1_path = temp_dir.path().join("latin1.txt");
+        fs::write(&latin1_path, [b'c', b'a', b'f', 0xE9]).unwrap();
+        for path in [&utf16_path, &latin1_path] {
+            let request = ReadFileRequest {
+                file_path: path.to_str().unwrap().to_string(),
+                encoding: None,
+                offset: None,
+                max_bytes: None,
+                max_dimension: None,
+            };
+            let result = read_file(request).await.unwrap();
+            assert!(!result.is_error, "read_file failed: {:?}", result.content);
+            let text = result_text(&result);
+            assert!(text == "héllo" || text == "café", "unexpected content: {}", text);
         }
+    }
+
+    #[tokio::test]
+    async fn test_unicode_normalization_of_paths() {
+        let (_env, temp_dir, temp_path) = test_env().await;
+
+        // Stored decomposed, as macOS does, but requested composed
+        let decomposed = temp_dir.path().join("cafe\u{301}.txt");
+        fs::write(&decomposed, "espresso").unwrap();
+        let request = ReadFileRequest {
+            file_path: temp_dir.path().join("caf\u{e9}.txt").to_str().unwrap().to_string(),
+            encoding: None,
+            offset: None,
+            max_bytes: None,
+            max_dimension: None,
+        };
+        let result = read_file(request).await.unwrap();
+        assert!(!result.is_error);
+        let text = result_text(&result);
+        assert_eq!(text, "espresso");
+
+        fs::write(temp_dir.path().join("caf\u{e9}.txt"), "latte").unwrap();
+        fs::write(temp_dir.path().join("report\u{200B}.txt"), "").unwrap();
+        let request = crate::mcp::unicode::FindUnicodeIssuesRequest {
+            path: temp_path.clone(),
+            respect_gitignore: None,
+            include_hidden: None,
+            max_entries: None,
+            ignore: Default::default(),
+        };
+        let result = find_unicode_issues(request).await.unwrap();
+        let summary = result_json(&result);
+        let issues = summary["issues"].to_string();
+        assert!(issues.contains("not NFC normalized"));
+        assert!(issues.contains("zero width space U+200B"));
+        assert_eq!(summary["normalization_collisions"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_scratch_directory_is_implicitly_allowed() {
+        let (_env, _temp_dir, temp_path) = test_env().await;
+
+        let request = crate::mcp::scratch::CreateTempFileRequest {
+            prefix: Some("notes".to_string()),
+            suffix: Some(".md".to_string()),
+            content: Some("draft".to_string()),
+        };
+        let result = create_temp_file(request).await.unwrap();
+        assert!(!result.is_error);
+        let scratch_file = result_text(&result);
+        assert!(scratch_file.ends_with(".md"));
+        assert!(!scratch_file.starts_with(&temp_path));
+
+        let request = ReadFileRequest {
+            file_path: scratch_file.to_string(),
+            encoding: None,
+            offset: None,
+            max_bytes: None,
+            max_dimension: None,
+        };
+        let result = read_file(request).await.unwrap();
+        assert!(!result.is_error);
+
+        let scratch = crate::mcp::scratch::existing_scratch_directory().unwrap();
+        let escape = scratch.join("..").join("outside.txt");
+        assert!(!is_path_allowed(&escape));
+        let request = crate::mcp::scratch::CreateTempFileRequest {
+            prefix: Some("../escape".to_string()),
+            suffix: None,
+            content: None,
+        };
+        assert!(create_temp_file(request).await.unwrap().is_error);
 
-        // Clean up
-        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+        crate::mcp::scratch::remove_scratch_directory();
+        assert!(!scratch.exists());
+    }
+
+    #[tokio::test]
+    async fn test_review_file_prompt_embeds_contents() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let file = temp_dir.path().join("main.rs");
+        fs::write(&file, "fn main() {}\n").unwrap();
+        let request = GetPromptRequest {
+            name: "review_file".to_string(),
+            arguments: Some(std::collections::HashMap::from([(
+                "path".to_string(),
+                json!(file.to_str().unwrap()),
+            )])),
+        };
+        let result = crate::mcp::prompts::prompts_get(request).await.unwrap();
+        let messages = serde_json::to_value(result.messages.unwrap()).unwrap();
+        assert_eq!(messages[0]["content"]["type"], "resource");
+        assert_eq!(messages[0]["content"]["resource"]["text"], "fn main() {}\n");
+        assert!(messages[0]["content"]["resource"]["uri"].as_str().unwrap().starts_with("file:///"));
+        assert_eq!(messages[1]["content"]["type"], "text");
+
+        // Files outside the allowed directories are refused
+        let request = GetPromptRequest {
+            name: "review_file".to_string(),
+            arguments: Some(std::collections::HashMap::from([("path".to_string(), json!("/etc/hostname"))])),
+        };
+        assert!(crate::mcp::prompts::prompts_get(request).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_workspace_summary_resource() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        fs::write(temp_dir.path().join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        fs::create_dir_all(temp_dir.path().join("src/bin")).unwrap();
+        fs::write(temp_dir.path().join("src/main.rs"), "fn main() {\n}\n").unwrap();
+        for n in 0..200 {
+            fs::write(temp_dir.path().join(format!("src/bin/tool_{}.rs", n)), "fn main() {}\n").unwrap();
+            fs::create_dir_all(temp_dir.path().join(format!("src/mod_{}", n))).unwrap();
+        }
+
+        let read = |uri: &str| crate::mcp::resources::resource_read(ReadResourceRequest {
+            uri: url::Url::parse(uri).unwrap(),
+            meta: None,
+        });
+        let summary = read("workspace://summary?budget=5000").await.unwrap().contents.remove(0).text.unwrap();
+        assert!(summary.contains("Rust (Cargo)"));
+        assert!(summary.contains("- .rs: 201 files, 202 lines"));
+        assert!(summary.contains("- Cargo.toml"));
+        assert!(summary.contains("src/"));
+
+        let small = read("workspace://summary?budget=100").await.unwrap().contents.remove(0).text.unwrap();
+        assert!(small.len() < 600);
+        assert!(small.contains("token budget"));
+    }
+
+    #[tokio::test]
+    async fn test_read_and_list_truncation() {
+        let (_env, temp_dir, temp_path) = test_env().await;
+        let _read_bytes = set_env("MCP_RS_FILESYSTEM_MAX_READ_BYTES", "10");
+        let _result_entries = set_env("MCP_RS_FILESYSTEM_MAX_RESULT_ENTRIES", "3");
+
+        let file = temp_dir.path().join("long.txt");
+        fs::write(&file, "0123456789ab\u{e9}cdef").unwrap();
+        let read = |offset: Option<usize>| ReadFileRequest {
+            file_path: file.to_str().unwrap().to_string(),
+            encoding: None,
+            offset,
+            max_bytes: None,
+            max_dimension: None,
+        };
+        let result = read_file(read(None)).await.unwrap();
+        assert_eq!(result.content.len(), 2);
+        let text = result_text(&result);
+        assert_eq!(text, "0123456789");
+        let CallToolResultContent::Text { text } = &result.content[1] else { panic!() };
+        let metadata: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(metadata["truncated"], true);
+        assert_eq!(metadata["total_size"], 18);
+        assert_eq!(metadata["next_offset"], 10);
+
+        // The rest, without splitting the two-byte character
+        let result = read_file(read(Some(10))).await.unwrap();
+        let text = result_text(&result);
+        assert_eq!(text, "ab\u{e9}cdef");
+        let CallToolResultContent::Text { text } = &result.content[1] else { panic!() };
+        let metadata: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(metadata["truncated"], false);
+        assert!(metadata.get("next_offset").is_none());
+
+        for name in ["a", "b", "c", "d", "e"] {
+            fs::write(temp_dir.path().join(name), "").unwrap();
+        }
+        let result = list_directory(ListDirectoryRequest {
+            path: temp_path.clone(),
+            sort: None,
+            offset: Some(3),
+            max_entries: None,
+            git_status: None,
+            respect_gitignore: None,
+            ignore: Default::default(),
+        })
+        .await
+        .unwrap();
+        let text = result_text(&result);
+        assert_eq!(text, "d\ne\nlong.txt\n");
+        let CallToolResultContent::Text { text } = &result.content[1] else { panic!() };
+        let metadata: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(metadata["total_entries"], 6);
+        assert_eq!(metadata["truncated"], false);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_rejects_traversal() {
+        let (_env, temp_dir, _) = test_env().await;
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+
+        // `..` after a component that does not exist
+        let escape = temp_dir.path().join("missing/../../").join(outside.path().file_name().unwrap()).join("secret.txt");
+        assert!(!is_path_allowed(&escape));
+        assert!(is_path_allowed(&temp_dir.path().join("missing/../new.txt")));
+
+        #[cfg(unix)]
+        {
+            // `..` applies to the symlink target, not to the link's own parent
+            std::os::unix::fs::symlink(outside.path(), temp_dir.path().join("link")).unwrap();
+            assert!(!is_path_allowed(&temp_dir.path().join("link/secret.txt")));
+            // Creating `new` first and then walking back up would follow the symlink out
+            assert!(!is_path_allowed(&temp_dir.path().join("new/../link/x")));
+            let result = create_directory(CreateDirectoryRequest {
+                path: temp_dir.path().join("new/../link/x").to_str().unwrap().to_string(),
+                commit_message: String::new(),
+                dry_run: None,
+            })
+            .await
+            .unwrap();
+            assert!(result.is_error);
+            assert!(!outside.path().join("x").exists());
+            assert!(!temp_dir.path().join("new").exists());
+            let nested = outside.path().join("nested");
+            fs::create_dir(&nested).unwrap();
+            std::os::unix::fs::symlink(&nested, temp_dir.path().join("deep")).unwrap();
+            assert!(!is_path_allowed(&temp_dir.path().join("deep/../secret.txt")));
+
+            // Reads go through the component-by-component open
+            fs::create_dir(temp_dir.path().join("dir")).unwrap();
+            fs::write(temp_dir.path().join("dir/file.txt"), "ok").unwrap();
+            assert_eq!(crate::mcp::sandbox::read(&temp_dir.path().join("dir/file.txt")).unwrap(), b"ok");
+        }
+
+        let result = read_file(ReadFileRequest {
+            file_path: escape.to_str().unwrap().to_string(),
+            encoding: None,
+            offset: None,
+            max_bytes: None,
+            max_dimension: None,
+        })
+        .await
+        .unwrap();
+        assert!(result.is_error);
+    }
+
+    #[tokio::test]
+    async fn test_git_status_and_annotated_listing() {
+        use crate::mcp::git::*;
+        let (_env, temp_dir, temp_path) = test_env().await;
+
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        fs::write(temp_dir.path().join("tracked.txt"), "one").unwrap();
+        fs::write(temp_dir.path().join("changed.txt"), "one").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked.txt")).unwrap();
+        index.add_path(Path::new("changed.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        fs::write(temp_dir.path().join("changed.txt"), "two").unwrap();
+        fs::write(temp_dir.path().join("new.txt"), "new").unwrap();
+
+        let result = git_status(GitStatusRequest {
+            path: temp_path.clone(),
+            include_untracked: None,
+            include_ignored: None,
+        })
+        .await
+        .unwrap();
+        let status = result_json(&result);
+        assert_eq!(status["clean"], false);
+        assert_eq!(status["counts"]["unstaged"], 1);
+        assert_eq!(status["counts"]["untracked"], 1);
+        let entries = status["entries"].as_array().unwrap();
+        assert!(entries.iter().any(|e| e["path"].as_str().unwrap().ends_with("changed.txt") && e["unstaged"] == "modified"));
+
+        let result = list_directory(ListDirectoryRequest {
+            path: temp_path.clone(),
+            sort: None,
+            offset: None,
+            max_entries: None,
+            git_status: Some(true),
+            respect_gitignore: None,
+            ignore: Default::default(),
+        })
+        .await
+        .unwrap();
+        let text = result_text(&result);
+        assert!(text.contains("changed.txt [modified]\n"));
+        assert!(text.contains("new.txt [untracked]\n"));
+        assert!(text.contains("tracked.txt [tracked]\n"));
+    }
+
+
+    #[tokio::test]
+    async fn test_preview_file_and_file_resources() {
+        use crate::mcp::preview::*;
+        use crate::mcp::resources::resource_read;
+        let (_env, temp_dir, _) = test_env().await;
+
+        let preview = |name: &str| {
+            let path = temp_dir.path().join(name).to_string_lossy().into_owned();
+            async move {
+                let result = preview_file(PreviewFileRequest {
+                    path,
+                    max_rows: Some(1),
+                    max_lines: None,
+                })
+                .await
+                .unwrap();
+                assert!(!result.is_error);
+                let text = result_text(&result);
+                serde_json::from_str::<serde_json::Value>(text).unwrap()
+            }
+        };
+
+        fs::write(temp_dir.path().join("people.csv"), "name,age\nann,31\nbob,42\n").unwrap();
+        let table = preview("people.csv").await;
+        assert_eq!(table["kind"], "table");
+        assert_eq!(table["columns"], json!(["name", "age"]));
+        assert_eq!(table["rows"], json!([["ann", "31"]]));
+        assert_eq!(table["total_rows"], 2);
+
+        fs::write(temp_dir.path().join("config.json"), r#"{"name": "demo", "tags": [1, 2]}"#).unwrap();
+        let structure = preview("config.json").await;
+        assert_eq!(structure["structure"]["keys"][1], json!({"key": "tags", "type": "array", "length": 2}));
+
+        // A PNG with a misleading extension is still recognized by its signature
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00\x02\x00\x00\x00\x01\x00\x08\x06\x00\x00\x00";
+        fs::write(temp_dir.path().join("image.txt"), png).unwrap();
+        let image = preview("image.txt").await;
+        assert_eq!(image["mime_type"], "image/png");
+        assert_eq!((image["width"].clone(), image["height"].clone()), (json!(512), json!(256)));
+
+        let read = |name: &str| ReadResourceRequest {
+            uri: url::Url::from_file_path(temp_dir.path().join(name)).unwrap(),
+            meta: None,
+        };
+        let resource = resource_read(read("image.txt")).await.unwrap();
+        assert_eq!(resource.contents[0].mime_type.as_deref(), Some("image/png"));
+        assert!(resource.contents[0].blob.is_some() && resource.contents[0].text.is_none());
+        let resource = resource_read(read("config.json")).await.unwrap();
+        assert_eq!(resource.contents[0].mime_type.as_deref(), Some("application/json"));
+        assert!(resource.contents[0].text.as_deref().unwrap().contains("demo"));
+        let outside = ReadResourceRequest {
+            uri: url::Url::parse("file:///etc/hostname").unwrap(),
+            meta: None,
+        };
+        assert!(resource_read(outside).await.is_err());
+
+        // Files are listed as resources after the fixed ones, without ignored ones, a page at a time
+        use crate::mcp::resources::resources_list;
+        fs::create_dir(temp_dir.path().join("target")).unwrap();
+        fs::write(temp_dir.path().join("target/build.log"), "").unwrap();
+        let list = resources_list(None).await.unwrap();
+        let names: Vec<&str> = list.resources[2..].iter().map(|resource| resource.name.as_str()).collect();
+        assert_eq!(names.len(), 3);
+        assert!(names[0].ends_with("config.json") && names[2].ends_with("people.csv"), "{:?}", names);
+        assert!(list.next_cursor.is_none());
+        fs::create_dir(temp_dir.path().join("many")).unwrap();
+        for i in 0..250 {
+            fs::write(temp_dir.path().join(format!("many/{:03}.txt", i)), "").unwrap();
+        }
+        let first = resources_list(None).await.unwrap();
+        assert_eq!(first.resources.len(), 200);
+        let cursor = first.next_cursor.unwrap();
+        let second = resources_list(Some(ListResourcesRequest { cursor: Some(cursor) })).await.unwrap();
+        assert_eq!(second.resources.len(), 2 + 3 + 250 - 200);
+        assert!(second.next_cursor.is_none());
+        assert!(resources_list(Some(ListResourcesRequest { cursor: Some("soon".to_string()) })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_file_modes() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let log = temp_dir.path().join("app.log");
+        let write = |content: &str, mode: &str| OverwriteFileRequest {
+            path: log.to_str().unwrap().to_string(),
+            content: content.to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: Some(mode.to_string()),
+            dry_run: None,
+        };
+
+        // append needs the file, create_or_append makes it
+        assert!(overwrite_file(write("first\n", "append")).await.unwrap().is_error);
+        assert!(!log.exists());
+        assert!(!overwrite_file(write("first\n", "create_or_append")).await.unwrap().is_error);
+        assert!(!overwrite_file(write("second\n", "append")).await.unwrap().is_error);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "first\nsecond\n");
+
+        // create_new never replaces an existing file
+        assert!(overwrite_file(write("replaced\n", "create_new")).await.unwrap().is_error);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "first\nsecond\n");
+        assert!(overwrite_file(write("x", "truncate")).await.unwrap().is_error);
+
+        // Appended text follows the file's encoding and line endings, without another byte order mark
+        let utf16 = temp_dir.path().join("utf16.txt");
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("a\r\n".encode_utf16().flat_map(u16::to_le_bytes));
+        fs::write(&utf16, &bytes).unwrap();
+        let result = overwrite_file(OverwriteFileRequest {
+            path: utf16.to_str().unwrap().to_string(),
+            content: "b\n".to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: Some("append".to_string()),
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        bytes.extend("b\r\n".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(fs::read(&utf16).unwrap(), bytes);
+
+        let fresh = temp_dir.path().join("fresh.txt");
+        let result = overwrite_file(OverwriteFileRequest {
+            path: fresh.to_str().unwrap().to_string(),
+            content: "new".to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: Some("create_new".to_string()),
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        assert_eq!(fs::read_to_string(&fresh).unwrap(), "new");
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_isolated() {
+        use crate::mcp::reservations::*;
+        use crate::mcp::session::{self, Session};
+        use crate::mcp::watch::*;
+        let (_env, temp_dir, temp_path) = test_env().await;
+
+        let (sender_a, mut output_a) = tokio::sync::mpsc::unbounded_channel();
+        let (sender_b, mut output_b) = tokio::sync::mpsc::unbounded_channel();
+        let a = Session::start(sender_a);
+        let b = Session::start(sender_b);
+        let text = |result: CallToolResult| (result_text(&result).to_string(), result.is_error);
+
+        let watch = session::scope(a.clone(), async {
+            let (info, is_error) = text(
+                watch_path(WatchPathRequest {
+                    path: temp_path.clone(),
+                    recursive: None,
+                    backend: Some("poll".to_string()),
+                    poll_interval_ms: None,
+                })
+                .await
+                .unwrap(),
+            );
+            assert!(!is_error);
+            let (_, is_error) = text(
+                reserve_paths(ReservePathsRequest {
+                    paths: vec![temp_path.clone()],
+                    lease_seconds: None,
+                    label: None,
+                })
+                .await
+                .unwrap(),
+            );
+            assert!(!is_error);
+            notify("logging/message", Some(json!({ "level": "info", "data": "for a" })));
+            serde_json::from_str::<serde_json::Value>(&info).unwrap()["watch_id"].as_str().unwrap().to_string()
+        })
+        .await;
+
+        // Another session neither sees the watch nor may write under the reservation
+        session::scope(b.clone(), async {
+            let poll = || PollChangesRequest {
+                watch_id: watch.clone(),
+                max_events: None,
+            };
+            assert!(text(poll_changes(poll()).await.unwrap()).1);
+            assert!(crate::mcp::reservations::check_not_reserved(&temp_dir.path().join("file.txt")).is_err());
+        })
+        .await;
+        assert!(output_a.try_recv().unwrap().contains("for a"));
+        assert!(output_b.try_recv().is_err());
+
+        // Ending a session gives up what it held
+        a.end();
+        session::scope(b.clone(), async {
+            assert!(crate::mcp::reservations::check_not_reserved(&temp_dir.path().join("file.txt")).is_ok());
+        })
+        .await;
+        b.end();
+    }
+
+    #[tokio::test]
+    async fn test_session_handshake() {
+        use crate::mcp::session::*;
+        use crate::mcp::utilities::{initialize, notifications_initialized};
+        let _env = lock_env().await;
+
+        let (sender, _output) = tokio::sync::mpsc::unbounded_channel();
+        let session = Session::start(sender);
+        let request = || -> InitializeRequest {
+            serde_json::from_value(json!({
+                "protocolVersion": "2024-11-05",
+                "capabilities": { "roots": { "listChanged": true } },
+                "clientInfo": { "name": "test-client", "version": "2.0" },
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(session.phase(), Phase::New);
+        assert!(session.accepts("ping"));
+        assert!(!session.accepts("tools/call"));
+
+        let current = || Some(CurrentSession(session.clone()));
+        initialize(current(), request()).await.unwrap();
+        assert_eq!(session.phase(), Phase::Initializing);
+        assert!(!session.accepts("resources/list"));
+        // A session is initialized once
+        assert!(initialize(current(), request()).await.is_err());
+
+        scope(session.clone(), async { notifications_initialized() }).await;
+        assert_eq!(session.phase(), Phase::Ready);
+        assert!(session.accepts("tools/call"));
+
+        let result = session_info(CurrentSession(session.clone()), SessionInfoRequest {}).await.unwrap();
+        let info = result_json(&result);
+        assert_eq!(info["phase"], "ready");
+        assert_eq!(info["client"]["name"], "test-client");
+        assert_eq!(info["protocol_version"], "2024-11-05");
+        assert_eq!(info["client_capabilities"]["roots"]["listChanged"], true);
+
+        // Structured output came with a later protocol version
+        let tools = scope(session.clone(), tools_list(None)).await.unwrap();
+        assert!(tools.tools.iter().all(|tool| tool.output_schema.is_none()));
+        session.end();
+        let (sender, _output) = tokio::sync::mpsc::unbounded_channel();
+        let newer = Session::start(sender);
+        let request: InitializeRequest = serde_json::from_value(json!({
+            "protocolVersion": "2025-06-18",
+            "capabilities": {},
+            "clientInfo": { "name": "test-client", "version": "2.0" },
+        }))
+        .unwrap();
+        let result = initialize(Some(CurrentSession(newer.clone())), request).await.unwrap();
+        assert_eq!(result.protocol_version, "2025-06-18");
+        assert!(scope(newer.clone(), async { structured_output() }).await);
+        newer.end();
+    }
+
+    #[tokio::test]
+    async fn test_structured_tool_output() {
+        let (_env, temp_dir, temp_path) = test_env().await;
+        let _output = remove_env("MCP_RS_FILESYSTEM_TOOL_OUTPUT");
+
+        fs::write(temp_dir.path().join("notes.txt"), "alpha\nneedle: here\n").unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+
+        let listing = list_directory(ListDirectoryRequest {
+            path: temp_path.clone(),
+            sort: None,
+            offset: None,
+            max_entries: None,
+            git_status: None,
+            respect_gitignore: None,
+            ignore: Default::default(),
+        })
+        .await
+        .unwrap();
+        let structured = listing.structured_content.unwrap();
+        assert_eq!(structured["total"], 2);
+        assert_eq!(structured["entries"][0], json!({"name": "notes.txt", "type": "file", "git_status": null}));
+        assert_eq!(structured["entries"][1]["type"], "directory");
+
+        let info = get_file_info(GetFileInfoRequest {
+            path: temp_dir.path().join("notes.txt").to_str().unwrap().to_string(),
+        })
+        .await
+        .unwrap();
+        let structured = info.structured_content.unwrap();
+        assert_eq!(structured["size"], 19);
+        assert_eq!(structured["type"], "file");
+        assert!(structured["modified"].is_string());
+
+        // The colon in the matched text does not confuse the parsing
+        let hits = grep_search(GrepSearchRequest {
+            pattern: "needle".to_string(),
+            path: temp_path.clone(),
+            recursive: Some(true),
+            case_sensitive: Some(true),
+            respect_gitignore: None,
+            include_hidden: None,
+            ignore_accents: None,
+            normalize_unicode: None,
+            ignore: Default::default(),
+        })
+        .await
+        .unwrap();
+        let structured = hits.structured_content.unwrap();
+        assert_eq!(structured["matches"][0]["line"], 2);
+        assert_eq!(structured["matches"][0]["text"], "needle: here");
+        assert!(structured["matches"][0]["path"].as_str().unwrap().ends_with("notes.txt"));
+
+        // Tools with structured results announce their schema, unless text output was asked for
+        let tools = tools_list(None).await.unwrap().tools;
+        let schema = |tools: &[Tool], name: &str| tools.iter().find(|tool| tool.name == name).unwrap().output_schema.is_some();
+        assert!(schema(&tools, "list_directory"));
+        assert!(!schema(&tools, "read_file"));
+        let _output = set_env("MCP_RS_FILESYSTEM_TOOL_OUTPUT", "text");
+        let tools = tools_list(None).await.unwrap().tools;
+        assert!(!schema(&tools, "list_directory"));
+    }
+
+    #[tokio::test]
+    async fn test_read_image() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let file = temp_dir.path().join("shot.png");
+        image::RgbImage::from_pixel(200, 100, image::Rgb([200, 30, 30])).save(&file).unwrap();
+        let read = |max_dimension: Option<u32>| ReadFileRequest {
+            file_path: file.to_str().unwrap().to_string(),
+            encoding: None,
+            offset: None,
+            max_bytes: None,
+            max_dimension,
+        };
+
+        let result = read_file(read(None)).await.unwrap();
+        assert!(!result.is_error);
+        let CallToolResultContent::Image { data, mime_type } = &result.content[0] else {
+            panic!("expected an image");
+        };
+        assert_eq!(mime_type, "image/png");
+        let bytes = base64::engine::general_purpose::STANDARD.decode(data).unwrap();
+        assert_eq!(bytes, fs::read(&file).unwrap());
+        let CallToolResultContent::Text { text } = &result.content[1] else {
+            panic!("expected the image size");
+        };
+        assert_eq!(text, "image/png, 200x100");
+
+        // Scaled down keeping the aspect ratio
+        let result = read_file(read(Some(50))).await.unwrap();
+        let CallToolResultContent::Image { data, .. } = &result.content[0] else {
+            panic!("expected an image");
+        };
+        let bytes = base64::engine::general_purpose::STANDARD.decode(data).unwrap();
+        let scaled = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (50, 25));
+        let CallToolResultContent::Text { text } = &result.content[1] else {
+            panic!("expected the image size");
+        };
+        assert_eq!(text, "image/png, 200x100, scaled to 50x25");
+
+        // The serialized block is what MCP clients expect
+        let value = serde_json::to_value(&result.content[0]).unwrap();
+        assert_eq!(value["type"], "image");
+        assert_eq!(value["mimeType"], "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_traversals_share_ignore_rules() {
+        let (_env, temp_dir, temp_path) = test_env().await;
+        let _patterns = remove_env("MCP_RS_FILESYSTEM_IGNORE_PATTERNS");
+
+        fs::create_dir_all(temp_dir.path().join("target/debug")).unwrap();
+        fs::write(temp_dir.path().join("target/debug/build.log"), "needle\n").unwrap();
+        fs::write(temp_dir.path().join("main.rs"), "needle\n").unwrap();
+
+        // Overrides arrive flattened among the other arguments
+        let request = |arguments: Value| serde_json::from_value::<ListDirectoryRequest>(arguments).unwrap();
+        let listing = list_directory(request(json!({"path": temp_path}))).await.unwrap();
+        let structured = listing.structured_content.unwrap();
+        assert_eq!(structured["total"], 1);
+        assert_eq!(structured["ignored"], 1);
+        let listing = list_directory(request(json!({"path": temp_path, "default_ignores": false}))).await.unwrap();
+        assert_eq!(listing.structured_content.unwrap()["total"], 2);
+        let listing = list_directory(request(json!({"path": temp_path, "ignore_patterns": ["*.rs"]}))).await.unwrap();
+        assert_eq!(listing.structured_content.unwrap()["total"], 0);
+
+        let grep = |ignore: IgnoreOverrides| GrepSearchRequest {
+            pattern: "needle".to_string(),
+            path: temp_path.clone(),
+            recursive: Some(true),
+            case_sensitive: Some(true),
+            respect_gitignore: None,
+            include_hidden: None,
+            ignore_accents: None,
+            normalize_unicode: None,
+            ignore,
+        };
+        let hits = grep_search(grep(Default::default())).await.unwrap();
+        assert_eq!(hits.structured_content.unwrap()["matches"].as_array().unwrap().len(), 1);
+        let brought_back = IgnoreOverrides {
+            default_ignores: None,
+            ignore_patterns: Some(vec!["!target".to_string()]),
+        };
+        let hits = grep_search(grep(brought_back)).await.unwrap();
+        assert_eq!(hits.structured_content.unwrap()["matches"].as_array().unwrap().len(), 2);
+
+        // The server's patterns are configurable
+        let _patterns = set_env("MCP_RS_FILESYSTEM_IGNORE_PATTERNS", "");
+        let hits = grep_search(grep(Default::default())).await.unwrap();
+        assert_eq!(hits.structured_content.unwrap()["matches"].as_array().unwrap().len(), 2);
+    }
+
+
+
+    #[tokio::test]
+    async fn test_path_expansion() {
+        let (_env, temp_dir, temp_path) = test_env().await;
+        fs::write(temp_dir.path().join("note.txt"), "expanded").unwrap();
+        let _home = set_env("HOME", &temp_path);
+        let _test_dir = set_env("RS_FILESYSTEM_TEST_DIR", &temp_path);
+
+        let read = |file_path: &str| {
+            read_file(ReadFileRequest {
+                file_path: file_path.to_string(),
+                encoding: None,
+                offset: None,
+                max_bytes: None,
+                max_dimension: None,
+            })
+        };
+        let text = |result: CallToolResult| (result.is_error, result_text(&result).to_string());
+
+        let _expansion = remove_env("MCP_RS_FILESYSTEM_PATH_EXPANSION");
+        assert_eq!(text(read("~/note.txt").await.unwrap()), (false, "expanded".to_string()));
+        // Variables only expand when asked for
+        assert!(read("$RS_FILESYSTEM_TEST_DIR/note.txt").await.unwrap().is_error);
+        let _expansion = set_env("MCP_RS_FILESYSTEM_PATH_EXPANSION", "all");
+        assert_eq!(text(read("${RS_FILESYSTEM_TEST_DIR}/note.txt").await.unwrap()), (false, "expanded".to_string()));
+        let _expansion = set_env("MCP_RS_FILESYSTEM_PATH_EXPANSION", "off");
+        assert!(read("~/note.txt").await.unwrap().is_error);
+    }
+
+
+    #[tokio::test]
+    async fn test_protected_paths() {
+        let (_env, temp_dir, _) = test_env().await;
+        let _protected = set_env("MCP_RS_FILESYSTEM_PROTECTED_PATHS", "**/.git/**, *.pem");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("keys")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join("keys/server.pem"), "secret").unwrap();
+        fs::write(root.join(".git/config"), "[core]").unwrap();
+        fs::write(root.join("notes.txt"), "notes").unwrap();
+
+        let overwrite = |path: &Path| OverwriteFileRequest {
+            path: path.to_str().unwrap().to_string(),
+            content: "changed".to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: None,
+            dry_run: None,
+        };
+        let result = overwrite_file(overwrite(&root.join("keys/server.pem"))).await.unwrap();
+        assert!(result.is_error);
+        let error = result_json(&result);
+        assert_eq!(error["error"], "protected_path");
+        assert_eq!(error["rule"], "*.pem");
+        assert!(overwrite_file(overwrite(&root.join(".git/config"))).await.unwrap().is_error);
+        assert!(!overwrite_file(overwrite(&root.join("notes.txt"))).await.unwrap().is_error);
+        assert_eq!(fs::read_to_string(root.join("keys/server.pem")).unwrap(), "secret");
+
+        // Moving the directory would take the protected file along
+        let moved = move_or_rename(MoveOrRenameRequest {
+            source_path: root.join("keys").to_string_lossy().into_owned(),
+            target_path: root.join("old-keys").to_string_lossy().into_owned(),
+            commit_message: String::new(),
+            dry_run: None,
+        })
+        .await
+        .unwrap();
+        assert!(moved.is_error);
+        assert!(root.join("keys/server.pem").exists());
+    }
+
+
+
+    #[tokio::test]
+    async fn test_memory_backend() {
+        use crate::mcp::resources::resource_read;
+        use crate::mcp::resources::resources_list;
+        use crate::mcp::vfs::FileSystem;
+        use crate::mcp::vfs::MemoryFs;
+        let (_env, temp_dir, _) = test_env().await;
+        // Allowed, but nothing is there on disk
+        let root = temp_dir.path().join("virtual");
+        let _directories = set_env("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &root);
+        let memory = std::sync::Arc::new(MemoryFs::new());
+        memory.create_dir_all(&root).unwrap();
+        let path = |name: &str| root.join(name).to_string_lossy().into_owned();
+        let text = |result: &CallToolResult| result_text(result).to_string();
+
+        vfs::scope(memory.clone(), async {
+            let created = create_directory(CreateDirectoryRequest {
+                path: path("docs"),
+                commit_message: String::new(),
+                dry_run: None,
+            })
+            .await
+            .unwrap();
+            assert!(!created.is_error, "{}", text(&created));
+            let written = overwrite_file(OverwriteFileRequest {
+                path: path("docs/notes.txt"),
+                content: "first line\n".to_string(),
+                encoding: None,
+                line_ending: None,
+                mode: None,
+                dry_run: None,
+            })
+            .await
+            .unwrap();
+            assert!(!written.is_error, "{}", text(&written));
+
+            let read = read_file(ReadFileRequest {
+                file_path: path("docs/notes.txt"),
+                encoding: None,
+                offset: None,
+                max_bytes: None,
+                max_dimension: None,
+            })
+            .await
+            .unwrap();
+            assert_eq!(text(&read), "first line\n");
+
+            let listed = list_directory(ListDirectoryRequest {
+                path: path("docs"),
+                sort: None,
+                offset: None,
+                max_entries: None,
+                git_status: None,
+                respect_gitignore: None,
+                ignore: Default::default(),
+            })
+            .await
+            .unwrap();
+            assert_eq!(text(&listed), "notes.txt\n");
+
+            let info = get_file_info(GetFileInfoRequest { path: path("docs/notes.txt") }).await.unwrap();
+            let info = info.structured_content.unwrap();
+            assert_eq!((info["type"].clone(), info["size"].clone()), (json!("file"), json!(11)));
+
+            let resource = resource_read(ReadResourceRequest {
+                uri: url::Url::from_file_path(root.join("docs/notes.txt")).unwrap(),
+                meta: None,
+            })
+            .await
+            .unwrap();
+            assert_eq!(resource.contents[0].text.as_deref(), Some("first line\n"));
+
+            // Outside the allowed directories, whatever the backend
+            let outside = read_file(ReadFileRequest {
+                file_path: temp_dir.path().join("elsewhere.txt").to_string_lossy().into_owned(),
+                encoding: None,
+                offset: None,
+                max_bytes: None,
+                max_dimension: None,
+            })
+            .await
+            .unwrap();
+            assert!(outside.is_error);
+
+            // The state bundle goes through the backend too
+            let exported = export_state_tool(ExportStateRequest {
+                path: path("state.json"),
+                dry_run: None,
+            })
+            .await
+            .unwrap();
+            assert!(!exported.is_error, "{}", text(&exported));
+            let imported = import_state_tool(ImportStateRequest {
+                path: path("state.json"),
+                overwrite: Some(false),
+                rebase_roots: None,
+                dry_run: None,
+            })
+            .await
+            .unwrap();
+            assert!(!imported.is_error, "{}", text(&imported));
+
+            // The workspace summary walks the disk, so it is neither listed nor read
+            let resources = resources_list(None).await.unwrap();
+            assert!(resources.resources.iter().all(|resource| resource.uri.scheme() != "workspace"));
+            let summary = resource_read(ReadResourceRequest {
+                uri: url::Url::parse("workspace://summary").unwrap(),
+                meta: None,
+            })
+            .await;
+            assert!(summary.is_err());
+        })
+        .await;
+
+        assert_eq!(memory.read(&root.join("docs/notes.txt")).unwrap(), b"first line\n");
+        assert!(memory.exists(&root.join("state.json")));
+        assert!(!root.exists());
+    }
+
+    #[tokio::test]
+    async fn test_replay_transcript() {
+        let _env = lock_env().await;
+        let _directories = set_env("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", crate::replay::REPLAY_ROOT);
+        let transcript = include_str!("../../tests/transcripts/basic.jsonl");
+        assert_eq!(crate::replay::check(transcript).await, Ok(11));
+
+        // A changed answer is reported with its line
+        let changed = transcript.replace("buy milk\\n\"}],\"isError\":false", "buy bread\\n\"}],\"isError\":false");
+        let report = crate::replay::check(&changed).await.unwrap_err();
+        assert!(report.starts_with("1 of 11 steps differ\nLine 6:"), "{}", report);
+        let updated = crate::replay::update(&changed).await.unwrap();
+        assert_eq!(updated, transcript);
+    }
+
+    #[tokio::test]
+    async fn test_custom_tool() {
+        let _env = lock_env().await;
+        let tool = |name: &str| Tool {
+            name: name.to_string(),
+            description: Some("The local time, under another name".to_string()),
+            input_schema: input_schema::<GetLocalTimeRequest>(),
+            output_schema: None,
+        };
+        register_tool(tool("custom_local_time")).unwrap();
+        assert!(register_tool(tool("custom_local_time")).is_err());
+        assert!(register_tool(tool("read_file")).is_err());
+        assert!(is_tool("custom_local_time"));
+        assert!(input_schema_of("custom_local_time").is_some());
+        let listed = tools_list(None).await.unwrap().tools;
+        assert_eq!(listed.last().unwrap().name, "custom_local_time");
+
+        // Calls reach the handler the embedding application appended
+        let router = crate::server::rpc_router_builder()
+            .append_dyn("custom_local_time", get_local_time.into_dyn())
+            .build();
+        let call = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "custom_local_time", "arguments": {} },
+        });
+        let response = crate::server::handle_line(&router, &call.to_string()).await.unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["result"]["isError"], false, "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let (_env, temp_dir, _) = test_env().await;
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&notes, "one\ntwo\n").unwrap();
+        let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+
+        let result = file_edit(FileEditRequest {
+            file_path: path("notes.txt"),
+            old_content: "two".to_string(),
+            new_content: "three".to_string(),
+            commit_message: String::new(),
+            dry_run: Some(true),
+        })
+        .await
+        .unwrap();
+        let report = result.structured_content.unwrap();
+        assert_eq!(report["dry_run"], true);
+        let change = &report["changes"][0];
+        assert_eq!(change["action"], "modify");
+        assert_eq!(change["size_before"], 8);
+        assert_eq!(change["size_after"], 10);
+        let diff = change["diff"].as_str().unwrap();
+        assert!(diff.contains("-two\n") && diff.contains("+three\n"), "{}", diff);
+
+        let result = overwrite_file(OverwriteFileRequest {
+            path: path("notes.txt"),
+            content: "four\n".to_string(),
+            encoding: None,
+            line_ending: None,
+            mode: Some("append".to_string()),
+            dry_run: Some(true),
+        })
+        .await
+        .unwrap();
+        assert_eq!(result.structured_content.unwrap()["changes"][0]["size_after"], 13);
+
+        let result = create_directory(CreateDirectoryRequest {
+            path: path("a/b"),
+            commit_message: String::new(),
+            dry_run: Some(true),
+        })
+        .await
+        .unwrap();
+        let changes = result.structured_content.unwrap()["changes"].clone();
+        assert_eq!(changes.as_array().unwrap().len(), 2);
+        assert_eq!(changes[1]["path"], path("a/b"));
+
+        assert_eq!(fs::read_to_string(&notes).unwrap(), "one\ntwo\n");
+        assert!(!temp_dir.path().join("a").exists());
+    }
+
+    #[test]
+    fn test_tool_registry() {
+        let registered: Vec<&str> = TOOL_REGISTRY.iter().map(|(name, _)| *name).collect();
+        let defined: Vec<String> = builtin_tool_definitions().into_iter().map(|tool| tool.name).collect();
+        assert_eq!(registered, defined);
+
+        // The dry-run set is part of the mutating one
+        for name in &defined {
+            if INPUT_SCHEMAS[name]["properties"].get("dry_run").is_some() {
+                assert!(is_mutating(name), "{} takes dry_run but is not mutating", name);
+            }
+        }
+        for name in ["create_temp_file", "lock_file", "reserve_paths", "create_checkpoint", "watch_path"] {
+            assert!(is_mutating(name) && !crate::mcp::timeouts::aborts_on_timeout(name), "{}", name);
+        }
+        assert!(is_local_only("grep_search") && !is_local_only("read_file"));
+        assert!(!is_mutating("read_file") && !is_mutating("unknown_tool"));
     }
 }
@@ -0,0 +1,185 @@
+use crate::mcp::config;
+use crate::mcp::session;
+use chrono::DateTime;
+use chrono::Local;
+use serde_json::json;
+use serde_json::Value;
+use std::fs::File;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Instant;
+use std::time::SystemTime;
+
+/// Paths kept per request. A tool walking a tree checks many more, and those
+/// past the first few say little about where the time went.
+const MAX_PATHS: usize = 32;
+
+/// Where traces are written: the log file of the JSON-RPC lines, so a trace
+/// sits between the request and the response it describes
+static LOG: OnceLock<Arc<Mutex<File>>> = OnceLock::new();
+
+static COUNTER: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CURRENT: Arc<Trace>;
+}
+
+/// What is known about a request while it is handled
+pub struct Trace {
+    correlation_id: String,
+    started: SystemTime,
+    clock: Instant,
+    paths: Mutex<Vec<String>>,
+    /// How many more paths were checked than kept
+    more_paths: AtomicU64,
+    /// Set when the outcome cannot be told from the response alone
+    outcome: Mutex<Option<&'static str>>,
+}
+
+impl Trace {
+    /// Start tracing a request, with an id unique across the sessions of the server
+    pub fn start() -> Arc<Trace> {
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        Arc::new(Trace {
+            correlation_id: format!("{}-r{}", session::current_id(), n),
+            started: SystemTime::now(),
+            clock: Instant::now(),
+            paths: Mutex::new(Vec::new()),
+            more_paths: AtomicU64::new(0),
+            outcome: Mutex::new(None),
+        })
+    }
+
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+}
+
+/// Write traces to `log` from now on
+pub fn init(log: Arc<Mutex<File>>) {
+    let _ = LOG.set(log);
+}
+
+/// `MCP_RS_FILESYSTEM_RESPONSE_TIMING`, set by `--response-timing`
+pub fn response_timing() -> bool {
+    config::var("MCP_RS_FILESYSTEM_RESPONSE_TIMING")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Run `future` as part of the request `trace` describes
+pub async fn scope<F: Future>(trace: Arc<Trace>, future: F) -> F::Output {
+    CURRENT.scope(trace, future).await
+}
+
+/// The trace of the request being handled, if any
+pub fn current() -> Option<Arc<Trace>> {
+    CURRENT.try_with(Arc::clone).ok()
+}
+
+/// Note a path the request resolved and checked
+pub fn record_path(path: &Path) {
+    let Some(trace) = current() else {
+        return;
+    };
+    let path = path.display().to_string();
+    let mut paths = trace.paths.lock().unwrap();
+    if paths.contains(&path) {
+        return;
+    }
+    if paths.len() < MAX_PATHS {
+        paths.push(path);
+    } else {
+        trace.more_paths.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Note an outcome the response does not show, such as a timeout
+pub fn set_outcome(outcome: &'static str) {
+    if let Some(trace) = current() {
+        *trace.outcome.lock().unwrap() = Some(outcome);
+    }
+}
+
+/// Milliseconds, to the microsecond
+fn millis(trace: &Trace) -> f64 {
+    (trace.clock.elapsed().as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+/// Log what `trace` found about the request with `id` and, with
+/// `--response-timing`, add its correlation id and duration to the `_meta` of
+/// the result. `tool` is the tool a `tools/call` called.
+pub fn finish(trace: &Trace, id: &Value, method: &str, tool: Option<&str>, response: &mut Option<Value>) {
+    let duration_ms = millis(trace);
+    let error_code = response.as_ref().and_then(|response| response["error"]["code"].as_i64());
+    let outcome = trace.outcome.lock().unwrap().unwrap_or(match response {
+        None => "no_response",
+        Some(_) if error_code.is_some() => "error",
+        Some(response) if response["result"]["isError"] == true => "tool_error",
+        Some(_) => "ok",
+    });
+    if let Some(log) = LOG.get() {
+        let mut entry = json!({
+            "type": "trace",
+            "correlation_id": trace.correlation_id(),
+            "session": session::current_id(),
+            "id": id,
+            "method": method,
+            "started": DateTime::<Local>::from(trace.started).to_rfc3339(),
+            "duration_ms": duration_ms,
+            "outcome": outcome,
+            "paths": *trace.paths.lock().unwrap(),
+        });
+        if let Some(tool) = tool {
+            entry["tool"] = json!(tool);
+        }
+        if let Some(code) = error_code {
+            entry["error_code"] = json!(code);
+        }
+        let more_paths = trace.more_paths.load(Ordering::Relaxed);
+        if more_paths > 0 {
+            entry["more_paths"] = json!(more_paths);
+        }
+        let _ = writeln!(log.lock().unwrap(), "{}", entry);
+    }
+
+    if !response_timing() {
+        return;
+    }
+    if let Some(result) = response.as_mut().and_then(|response| response.get_mut("result")?.as_object_mut()) {
+        let meta = result.entry("_meta").or_insert_with(|| json!({}));
+        if let Value::Object(meta) = meta {
+            meta.insert("correlationId".to_string(), json!(trace.correlation_id()));
+            meta.insert("durationMs".to_string(), json!(duration_ms));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trace_paths() {
+        let trace = Trace::start();
+        scope(Arc::clone(&trace), async {
+            for i in 0..MAX_PATHS + 3 {
+                record_path(Path::new(&format!("/data/{}", i)));
+            }
+            record_path(Path::new("/data/0"));
+            set_outcome("timeout");
+        })
+        .await;
+        record_path(Path::new("/outside/any/request"));
+        assert_eq!(trace.paths.lock().unwrap().len(), MAX_PATHS);
+        assert_eq!(trace.more_paths.load(Ordering::Relaxed), 3);
+        assert_eq!(*trace.outcome.lock().unwrap(), Some("timeout"));
+        assert!(trace.correlation_id().starts_with(&format!("{}-r", session::current_id())));
+    }
+}
@@ -10,6 +10,7 @@ use url::Url;
 pub struct InitializeRequest {
     #[serde(rename = "protocolVersion")]
     pub protocol_version: String,
+    #[serde(default)]
     pub capabilities: ClientCapabilities,
     #[serde(rename = "clientInfo")]
     pub client_info: Implementation,
@@ -61,6 +62,9 @@ pub struct ClientCapabilities {
     pub roots: Option<RootCapabilities>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sampling: Option<Value>,
+    /// Capabilities this server does not know, such as those of later protocol versions
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -120,15 +124,7 @@ pub struct ReadResourceRequest {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ReadResourceResult {
-    pub contents: Vec<TextResourceContents>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct TextResourceContents {
-    pub uri: Url,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mime_type: Option<String>,
-    pub text: String,
+    pub contents: Vec<ResourceContent>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -137,7 +133,9 @@ pub struct ResourceContent {
     pub uri: Url, // The URI of the resource
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mime_type: Option<String>, // Optional MIME type
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>, // For text resources
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub blob: Option<String>, // For binary resources (base64 encoded)
 }
 
@@ -194,44 +192,31 @@ pub struct PromptMessage {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub struct PromptMessageContent {
-    #[serde(rename = "type")]
-    pub type_name: String,
-    pub text: String,
+#[serde(tag = "type")]
+pub enum PromptMessageContent {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "resource")]
+    Resource { resource: ResourceContent },
 }
 
 // --------- tool -------
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Tool {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
-    pub input_schema: ToolInputSchema,
-}
-
-#[derive(Deserialize, Serialize)]
-pub struct ToolInputSchema {
-    #[serde(rename = "type")]
-    pub type_name: String,
-    pub properties: HashMap<String, ToolInputSchemaProperty>,
-    pub required: Vec<String>,
-}
-
-#[derive(Deserialize, Serialize)]
-pub struct ToolInputSchemaProperty {
+    /// JSON schema of the arguments, generated from the request struct of the tool
+    pub input_schema: Value,
+    /// JSON schema of the `structuredContent` of the tool's results
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "type")]
-    pub type_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "enum")]
-    pub enum_values: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub description: Option<String>,
+    pub output_schema: Option<Value>,
 }
 
 #[derive(Deserialize, Serialize, RpcParams)]
+#[allow(dead_code)]
 pub struct CallToolRequest {
     pub params: ToolCallRequestParams,
     #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
@@ -243,6 +228,8 @@ pub struct ToolCallRequestParams {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub arguments: Option<Value>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
 }
 
 #[derive(Deserialize, Serialize, RpcParams)]
@@ -250,6 +237,46 @@ pub struct ToolCallRequestParams {
 pub struct CallToolResult {
     pub content: Vec<CallToolResultContent>,
     pub is_error: bool,
+    /// The result as JSON matching the tool's output schema. The text content
+    /// carries the same result for clients that do not read this.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub structured_content: Option<Value>,
+}
+
+impl CallToolResult {
+    /// Successful result made of `content`
+    pub fn content(content: Vec<CallToolResultContent>) -> CallToolResult {
+        CallToolResult {
+            content,
+            is_error: false,
+            structured_content: None,
+        }
+    }
+
+    /// Successful result holding `text`
+    pub fn text(text: String) -> CallToolResult {
+        CallToolResult::content(vec![CallToolResultContent::Text { text }])
+    }
+
+    /// Failed result holding `text`, which tells what went wrong
+    pub fn error(text: String) -> CallToolResult {
+        CallToolResult {
+            is_error: true,
+            ..CallToolResult::text(text)
+        }
+    }
+
+    /// Successful result carrying `value` both as structured content and as
+    /// its pretty-printed text
+    pub fn structured(value: Value) -> CallToolResult {
+        CallToolResult {
+            content: vec![CallToolResultContent::Text {
+                text: serde_json::to_string_pretty(&value).unwrap(),
+            }],
+            is_error: false,
+            structured_content: Some(value),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -258,7 +285,11 @@ pub enum CallToolResultContent {
     #[serde(rename = "text")]
     Text { text: String },
     #[serde(rename = "image")]
-    Image { data: String, mime_type: String },
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
     #[serde(rename = "resource")]
     Resource { resource: ResourceContent },
 }
@@ -281,27 +312,33 @@ pub struct ListToolsResult {
 pub struct EmptyResult {}
 
 #[derive(Deserialize, Serialize, RpcParams)]
+#[allow(dead_code)]
 pub struct PingRequest {}
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelledNotification {
-    pub request_id: String,
+    /// Id of the request to cancel, a string or a number
+    pub request_id: Value,
     pub reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MetaParams {
-    pub progress_token: String,
+    /// progress tokens may be either a string or a number
+    pub progress_token: Value,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Progress {
-    pub progress_token: String,
+    pub progress_token: Value,
     pub progress: i32,
-    pub total: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, RpcParams)]
@@ -313,6 +350,7 @@ pub struct SetLevelRequest {
 pub struct LoggingResponse {}
 
 #[derive(Debug, Deserialize, Serialize, RpcParams)]
+#[allow(dead_code)]
 pub struct LoggingMessageNotification {
     pub level: String,
     pub logger: String,
@@ -345,6 +383,8 @@ pub enum ErrorCode {
     MethodNotFound = -32601,
     InvalidParams = -32602,
     InternalError = -32603,
+    // Request before the initialization handshake finished
+    ServerNotInitialized = -32002,
 }
 
 // ----- json-rpc -----
@@ -366,6 +406,7 @@ impl JsonRpcResponse {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[allow(dead_code)]
 pub struct JsonRpcNotification {
     pub jsonrpc: String,
     pub method: String,
@@ -0,0 +1,228 @@
+use crate::mcp::expansion::expand_path;
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::vfs;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::is_nfc;
+use unicode_normalization::UnicodeNormalization;
+
+const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// NFC form of a string
+pub fn nfc(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// `text` without accents and other combining marks, e.g. `Crème brûlée` for
+/// `Creme brulee`, in NFC. Letters that are not written with a mark, such as
+/// `ø` or `ß`, stay as they are.
+pub fn strip_accents(text: &str) -> String {
+    text.nfd().filter(|c| !is_combining_mark(*c)).nfc().collect()
+}
+
+/// Path with every component in NFC, for normalization-insensitive comparisons
+pub fn nfc_path(path: &Path) -> PathBuf {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => OsString::from(nfc(name)),
+                None => name.to_os_string(),
+            },
+            other => other.as_os_str().to_os_string(),
+        })
+        .collect()
+}
+
+/// Map a path onto the names actually stored on disk. A component that does
+/// not exist as given is matched against its siblings after NFC normalization,
+/// so an NFC path from the client finds a file that macOS stored in NFD.
+/// Components with no match are kept as given. `~` and environment variables
+/// are expanded first, as configured.
+pub fn resolve_path(path: &Path) -> PathBuf {
+    let path = &expand_path(path);
+    // Another backend holds its own files, which the disk knows nothing of
+    let backend = vfs::current();
+    let local = backend.is_local();
+    let mut resolved = PathBuf::new();
+    let mut missing = false;
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            resolved.push(component);
+            continue;
+        };
+        let candidate = resolved.join(name);
+        let exists = if local { candidate.symlink_metadata().is_ok() } else { backend.exists(&candidate) };
+        if missing || exists {
+            resolved = candidate;
+            continue;
+        }
+        let wanted = name.to_str().map(nfc);
+        let on_disk = wanted.as_ref().and_then(|wanted| {
+            let parent = if resolved.as_os_str().is_empty() { Path::new(".") } else { &resolved };
+            let names: Vec<OsString> = if local {
+                fs::read_dir(parent).ok()?.flatten().map(|entry| entry.file_name()).collect()
+            } else {
+                backend.list(parent).ok()?.into_iter().map(|entry| OsString::from(entry.name)).collect()
+            };
+            names.into_iter().find(|entry_name| entry_name.to_str().map(nfc).as_ref() == Some(wanted))
+        });
+        match on_disk {
+            Some(entry_name) => resolved.push(entry_name),
+            None => {
+                // Nothing below a missing component can exist either
+                missing = true;
+                resolved = candidate;
+            }
+        }
+    }
+    resolved
+}
+
+/// Characters that render as nothing or reorder text, which makes names look
+/// identical while comparing differently
+fn invisible_character(c: char) -> Option<&'static str> {
+    match c {
+        '\u{200B}' => Some("zero width space"),
+        '\u{200C}' => Some("zero width non-joiner"),
+        '\u{200D}' => Some("zero width joiner"),
+        '\u{2060}' => Some("word joiner"),
+        '\u{FEFF}' => Some("zero width no-break space"),
+        '\u{00AD}' => Some("soft hyphen"),
+        '\u{200E}' | '\u{200F}' | '\u{061C}' => Some("bidirectional mark"),
+        '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' => Some("bidirectional control"),
+        '\u{00A0}' | '\u{2007}' | '\u{202F}' => Some("non-breaking space"),
+        c if c.is_control() => Some("control character"),
+        _ => None,
+    }
+}
+
+/// Problems with a single file name
+fn name_issues(name: &str) -> Vec<String> {
+    let mut issues = Vec::new();
+    if !is_nfc(name) {
+        issues.push("not NFC normalized (decomposed form)".to_string());
+    }
+    for c in name.chars() {
+        if let Some(kind) = invisible_character(c) {
+            let issue = format!("contains {} U+{:04X}", kind, c as u32);
+            if !issues.contains(&issue) {
+                issues.push(issue);
+            }
+        }
+    }
+    if name != name.trim() {
+        issues.push("leading or trailing whitespace".to_string());
+    }
+    issues
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct FindUnicodeIssuesRequest {
+    /// Directory to scan
+    pub path: String,
+    /// Skip files excluded by .gitignore, .ignore and git excludes. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// Include hidden files and directories. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    /// Stop after this many entries. Defaults to 100000.
+    #[schemars(extend("default" = 100000))]
+    pub max_entries: Option<usize>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+pub async fn find_unicode_issues(request: FindUnicodeIssuesRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(find_unicode_issues_blocking(request)).await
+}
+
+async fn find_unicode_issues_blocking(request: FindUnicodeIssuesRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if !path.is_dir() {
+        return Ok(CallToolResult::error(format!("Not a directory: {}", path.display())));
+    }
+
+    let respect_gitignore = request.respect_gitignore.unwrap_or(true);
+    let max_entries = request.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES).max(1);
+    let rules = IgnoreRules::new(respect_gitignore, request.include_hidden.unwrap_or(false)).with_overrides(&request.ignore);
+    let walker = match rules {
+        Ok(rules) => rules.walker(path).build(),
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+
+    let mut findings = Vec::new();
+    // Names per directory keyed by their NFC form, to spot entries that only differ in normalization
+    let mut siblings: HashMap<(PathBuf, String), Vec<String>> = HashMap::new();
+    let mut scanned = 0;
+    let mut truncated = false;
+    for entry in walker.flatten() {
+        if entry.depth() == 0 {
+            continue;
+        }
+        if scanned >= max_entries {
+            truncated = true;
+            break;
+        }
+        scanned += 1;
+
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            findings.push(json!({
+                "path": entry.path().display().to_string(),
+                "issues": ["name is not valid UTF-8"],
+            }));
+            continue;
+        };
+        let parent = entry.path().parent().map(Path::to_path_buf).unwrap_or_default();
+        siblings.entry((parent, nfc(name))).or_default().push(name.to_string());
+        let issues = name_issues(name);
+        if !issues.is_empty() {
+            findings.push(json!({
+                "path": entry.path().display().to_string(),
+                "issues": issues,
+            }));
+        }
+    }
+
+    let mut collisions: Vec<_> = siblings
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|((parent, _), mut names)| {
+            names.sort();
+            json!({
+                "directory": parent.display().to_string(),
+                "names": names,
+                "issue": "names differ only in Unicode normalization",
+            })
+        })
+        .collect();
+    collisions.sort_by_key(|c| c["directory"].as_str().unwrap_or_default().to_string());
+
+    let summary = json!({
+        "path": path.display().to_string(),
+        "entries_scanned": scanned,
+        "truncated": truncated,
+        "issues": findings,
+        "normalization_collisions": collisions,
+    });
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&summary).unwrap()))
+}
@@ -0,0 +1,518 @@
+use crate::mcp::ignore_rules::IgnoreOverrides;
+use crate::mcp::ignore_rules::IgnoreRules;
+use crate::mcp::scratch::scratch_directory;
+use crate::mcp::timeouts;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+const DEFAULT_TOP_N: usize = 10;
+const MAX_TOP_N: usize = 1000;
+const DEFAULT_MAX_ENTRIES: usize = 100_000;
+const MAX_MAX_ENTRIES: usize = 1_000_000;
+const DEFAULT_MAX_DURATION_MS: u64 = 5_000;
+const MAX_MAX_DURATION_MS: u64 = 60_000;
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct DiskUsageRequest {
+    /// Directory to measure
+    pub path: String,
+    /// Number of largest files to report. Defaults to 10, at most 1000.
+    #[schemars(extend("default" = 10))]
+    pub top_n: Option<usize>,
+    /// Skip files excluded by .gitignore, .ignore and git excludes. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub respect_gitignore: Option<bool>,
+    /// Include hidden files and directories. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub include_hidden: Option<bool>,
+    /// Stop after visiting this many entries. Defaults to 100000.
+    #[schemars(extend("default" = 100000))]
+    pub max_entries: Option<usize>,
+    /// Stop after this many milliseconds. Defaults to 5000, at most 60000.
+    #[schemars(extend("default" = 5000))]
+    pub max_duration_ms: Option<u64>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+/// Totals gathered while walking a directory tree
+#[derive(Default)]
+struct Usage {
+    total_bytes: u64,
+    file_count: u64,
+    dir_count: u64,
+    skipped: u64,
+    /// min-heap holding the largest files seen so far
+    largest: BinaryHeap<Reverse<(u64, PathBuf)>>,
+}
+
+impl Usage {
+    fn add_file(&mut self, path: PathBuf, size: u64, top_n: usize) {
+        self.total_bytes += size;
+        self.file_count += 1;
+        if top_n == 0 {
+            return;
+        }
+        if self.largest.len() < top_n {
+            self.largest.push(Reverse((size, path)));
+        } else if self.largest.peek().is_some_and(|Reverse((smallest, _))| size > *smallest) {
+            self.largest.pop();
+            self.largest.push(Reverse((size, path)));
+        }
+    }
+}
+
+pub async fn disk_usage(request: DiskUsageRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(disk_usage_blocking(request)).await
+}
+
+async fn disk_usage_blocking(request: DiskUsageRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if !path.is_dir() {
+        return Ok(CallToolResult::error(format!("Not a directory: {}", path.display())));
+    }
+
+    let top_n = request.top_n.unwrap_or(DEFAULT_TOP_N).min(MAX_TOP_N);
+    let max_entries = request.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES).clamp(1, MAX_MAX_ENTRIES);
+    let max_duration = Duration::from_millis(
+        request
+            .max_duration_ms
+            .unwrap_or(DEFAULT_MAX_DURATION_MS)
+            .clamp(1, MAX_MAX_DURATION_MS),
+    );
+    let respect_gitignore = request.respect_gitignore.unwrap_or(true);
+
+    let rules = match IgnoreRules::new(respect_gitignore, request.include_hidden.unwrap_or(false))
+        .with_overrides(&request.ignore)
+    {
+        Ok(rules) => rules,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    let walker = rules.walker(path).build();
+
+    let started = Instant::now();
+    let mut usage = Usage::default();
+    let mut truncated: Option<&str> = None;
+    for (visited, entry) in walker.enumerate() {
+        if visited >= max_entries {
+            truncated = Some("max_entries");
+            break;
+        }
+        if started.elapsed() >= max_duration {
+            truncated = Some("max_duration_ms");
+            break;
+        }
+        let Ok(entry) = entry else {
+            usage.skipped += 1;
+            continue;
+        };
+        // The root itself is not counted as a subdirectory
+        if entry.depth() == 0 {
+            continue;
+        }
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => usage.dir_count += 1,
+            Ok(metadata) if metadata.is_file() => {
+                usage.add_file(entry.into_path(), metadata.len(), top_n)
+            }
+            // Symlinks and special files take no space of their own worth reporting
+            Ok(_) => {}
+            Err(_) => usage.skipped += 1,
+        }
+    }
+
+    let largest: Vec<_> = usage
+        .largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((size, path))| json!({ "path": path.display().to_string(), "size": size }))
+        .collect();
+    let summary = json!({
+        "path": path.display().to_string(),
+        "total_bytes": usage.total_bytes,
+        "file_count": usage.file_count,
+        "dir_count": usage.dir_count,
+        "skipped": usage.skipped,
+        "respect_gitignore": respect_gitignore,
+        "truncated": truncated.is_some(),
+        "truncated_by": truncated,
+        "elapsed_ms": started.elapsed().as_millis() as u64,
+        "largest_files": largest,
+    });
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&summary).unwrap()))
+}
+
+// --------- impact estimates -------
+
+/// How much data is read to measure throughput
+const THROUGHPUT_SAMPLE_BYTES: usize = 16 * 1024 * 1024;
+/// Size of the probe file written to measure write throughput
+const WRITE_PROBE_BYTES: usize = 4 * 1024 * 1024;
+const DELETE_PROBE_FILES: usize = 32;
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct EstimateOperationRequest {
+    /// The planned operation
+    #[schemars(extend("enum" = ["copy", "delete", "sync"]))]
+    pub operation: String,
+    /// File or directory the operation reads from (or deletes)
+    pub source_path: String,
+    /// Destination of a copy or sync
+    pub target_path: Option<String>,
+    /// Leave out files excluded by .gitignore and ignore files. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub respect_gitignore: Option<bool>,
+    /// Include hidden files and directories. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub include_hidden: Option<bool>,
+    /// Number of largest files to report. Defaults to 10.
+    #[schemars(extend("default" = 10))]
+    pub top_n: Option<usize>,
+    /// Stop after visiting this many entries. Defaults to 100000.
+    #[schemars(extend("default" = 100000))]
+    pub max_entries: Option<usize>,
+    /// Stop scanning after this many milliseconds. Defaults to 5000.
+    #[schemars(extend("default" = 5000))]
+    pub max_duration_ms: Option<u64>,
+    #[serde(flatten)]
+    pub ignore: IgnoreOverrides,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PlannedOperation {
+    Copy,
+    Delete,
+    Sync,
+}
+
+/// Bytes per second reading the largest files, which is what dominates a copy
+fn measure_read_throughput(files: &[PathBuf]) -> Option<f64> {
+    let started = Instant::now();
+    let mut buffer = vec![0u8; 256 * 1024];
+    let mut total = 0;
+    for path in files {
+        let Ok(mut file) = File::open(path) else {
+            continue;
+        };
+        while total < THROUGHPUT_SAMPLE_BYTES {
+            match file.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => total += read,
+            }
+        }
+        if total >= THROUGHPUT_SAMPLE_BYTES {
+            break;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    (total > 0 && elapsed > 0.0).then(|| total as f64 / elapsed)
+}
+
+/// Bytes per second writing and syncing a probe file inside `dir`
+fn measure_write_throughput(dir: &Path) -> Option<f64> {
+    let probe = dir.join(format!(".estimate-probe-{}", std::process::id()));
+    let data = vec![0x5Au8; WRITE_PROBE_BYTES];
+    let started = Instant::now();
+    let written = File::create(&probe).and_then(|mut file| {
+        file.write_all(&data)?;
+        file.sync_all()
+    });
+    let elapsed = started.elapsed().as_secs_f64();
+    let _ = fs::remove_file(&probe);
+    (written.is_ok() && elapsed > 0.0).then(|| WRITE_PROBE_BYTES as f64 / elapsed)
+}
+
+/// Seconds to create and remove one small file inside `dir`
+fn measure_delete_cost(dir: &Path) -> Option<f64> {
+    let probes: Vec<PathBuf> = (0..DELETE_PROBE_FILES)
+        .map(|n| dir.join(format!(".estimate-probe-{}-{}", std::process::id(), n)))
+        .collect();
+    for probe in &probes {
+        fs::write(probe, b"x").ok()?;
+    }
+    let started = Instant::now();
+    for probe in &probes {
+        let _ = fs::remove_file(probe);
+    }
+    Some(started.elapsed().as_secs_f64() / DELETE_PROBE_FILES as f64)
+}
+
+/// Whether `target` already holds an identical-looking copy of a source file
+fn up_to_date(source: &fs::Metadata, target: &Path) -> bool {
+    let Ok(target) = fs::metadata(target) else {
+        return false;
+    };
+    target.len() == source.len()
+        && matches!((source.modified(), target.modified()), (Ok(s), Ok(t)) if t >= s)
+}
+
+pub async fn estimate_operation(request: EstimateOperationRequest) -> HandlerResult<CallToolResult> {
+    timeouts::run_blocking(estimate_operation_blocking(request)).await
+}
+
+async fn estimate_operation_blocking(request: EstimateOperationRequest) -> HandlerResult<CallToolResult> {
+    let error = |text: String| Ok(CallToolResult::error(text));
+    let operation = match request.operation.to_lowercase().as_str() {
+        "copy" => PlannedOperation::Copy,
+        "delete" => PlannedOperation::Delete,
+        "sync" => PlannedOperation::Sync,
+        other => return error(format!("Unsupported operation: {}. Expected one of: copy, delete, sync", other)),
+    };
+    let source = &resolve_path(Path::new(&request.source_path));
+    if let Err(msg) = validate_path_or_error(source) {
+        return error(msg);
+    }
+    if !source.exists() {
+        return error(format!("Source does not exist: {}", source.display()));
+    }
+    let target = match (&request.target_path, operation) {
+        (Some(target), _) => {
+            let target = resolve_path(Path::new(target));
+            if let Err(msg) = validate_path_or_error(&target) {
+                return error(msg);
+            }
+            Some(target)
+        }
+        (None, PlannedOperation::Delete) => None,
+        (None, _) => return error("target_path is required for copy and sync".to_string()),
+    };
+
+    let top_n = request.top_n.unwrap_or(DEFAULT_TOP_N).min(MAX_TOP_N);
+    let max_entries = request.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES).clamp(1, MAX_MAX_ENTRIES);
+    let max_duration = Duration::from_millis(
+        request
+            .max_duration_ms
+            .unwrap_or(DEFAULT_MAX_DURATION_MS)
+            .clamp(1, MAX_MAX_DURATION_MS),
+    );
+    let rules = IgnoreRules::new(request.respect_gitignore.unwrap_or(false), request.include_hidden.unwrap_or(true))
+        .with_overrides(&request.ignore);
+    let walker = match rules {
+        Ok(rules) => rules.walker(source).build(),
+        Err(msg) => return error(msg),
+    };
+
+    let started = Instant::now();
+    let mut usage = Usage::default();
+    // Files a sync would skip because the target already matches
+    let mut unchanged = 0u64;
+    let mut bytes_to_transfer = 0u64;
+    let mut truncated: Option<&str> = None;
+    for (visited, entry) in walker.enumerate() {
+        if visited >= max_entries {
+            truncated = Some("max_entries");
+            break;
+        }
+        if started.elapsed() >= max_duration {
+            truncated = Some("max_duration_ms");
+            break;
+        }
+        let Ok(entry) = entry else {
+            usage.skipped += 1;
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            usage.skipped += 1;
+            continue;
+        };
+        if metadata.is_dir() {
+            if entry.depth() > 0 {
+                usage.dir_count += 1;
+            }
+            continue;
+        }
+        if !metadata.is_file() {
+            continue;
+        }
+        if operation == PlannedOperation::Sync {
+            let relative = entry.path().strip_prefix(source).unwrap_or(entry.path());
+            let destination = target.as_ref().map(|t| t.join(relative)).unwrap_or_default();
+            if up_to_date(&metadata, &destination) {
+                unchanged += 1;
+                continue;
+            }
+        }
+        bytes_to_transfer += metadata.len();
+        usage.add_file(entry.into_path(), metadata.len(), top_n.max(1));
+    }
+
+    let largest: Vec<PathBuf> = usage
+        .largest
+        .clone()
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((_, path))| path)
+        .collect();
+    // Probes go into the server's scratch directory, never into the allowed
+    // directories, so an estimate changes nothing there. That may be another
+    // device than the target, which makes the write figures a rough guide.
+    let probe_dir = scratch_directory().ok();
+
+    let mut throughput = serde_json::Map::new();
+    let estimated_seconds = match operation {
+        PlannedOperation::Delete => {
+            let per_entry = probe_dir.and_then(measure_delete_cost);
+            if let Some(per_entry) = per_entry {
+                throughput.insert("seconds_per_delete".to_string(), json!(per_entry));
+            }
+            per_entry.map(|cost| cost * (usage.file_count + usage.dir_count) as f64)
+        }
+        PlannedOperation::Copy | PlannedOperation::Sync => {
+            let read = measure_read_throughput(&largest);
+            let write = target.as_ref().and(probe_dir).and_then(measure_write_throughput);
+            throughput.insert("read_bytes_per_second".to_string(), json!(read.map(|r| r as u64)));
+            throughput.insert("write_bytes_per_second".to_string(), json!(write.map(|w| w as u64)));
+            match (read, write) {
+                _ if bytes_to_transfer == 0 => Some(0.0),
+                (Some(read), Some(write)) => {
+                    Some(bytes_to_transfer as f64 / read + bytes_to_transfer as f64 / write)
+                }
+                _ => None,
+            }
+        }
+    };
+
+    let mut summary = json!({
+        "operation": request.operation.to_lowercase(),
+        "source_path": source.display().to_string(),
+        "target_path": target.as_ref().map(|t| t.display().to_string()),
+        "file_count": usage.file_count,
+        "dir_count": usage.dir_count,
+        "total_bytes": usage.total_bytes,
+        "largest_files": usage
+            .largest
+            .into_sorted_vec()
+            .into_iter()
+            .take(top_n)
+            .map(|Reverse((size, path))| json!({ "path": path.display().to_string(), "size": size }))
+            .collect::<Vec<_>>(),
+        "throughput": throughput,
+        "estimated_seconds": estimated_seconds.map(|s| (s * 100.0).round() / 100.0),
+        "skipped": usage.skipped,
+        "truncated": truncated.is_some(),
+        "truncated_by": truncated,
+    });
+    if operation == PlannedOperation::Sync {
+        summary["files_unchanged"] = json!(unchanged);
+        summary["bytes_to_transfer"] = json!(bytes_to_transfer);
+    }
+    Ok(CallToolResult::text(serde_json::to_string_pretty(&summary).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+
+    #[tokio::test]
+    async fn test_disk_usage_respects_gitignore_and_budget() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let root = temp_dir.path().join("project");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("src/big.rs"), vec![b'x'; 3000]).unwrap();
+        fs::write(root.join("src/small.rs"), vec![b'x'; 10]).unwrap();
+        fs::write(root.join("target/huge.bin"), vec![0u8; 10_000]).unwrap();
+
+        let request = DiskUsageRequest {
+            path: root.to_str().unwrap().to_string(),
+            top_n: Some(1),
+            respect_gitignore: None,
+            include_hidden: None,
+            max_entries: None,
+            max_duration_ms: None,
+            ignore: Default::default(),
+        };
+        let result = disk_usage(request).await.unwrap();
+        assert!(!result.is_error);
+        let summary = result_json(&result);
+        assert_eq!(summary["total_bytes"], 3010);
+        assert_eq!(summary["file_count"], 2);
+        assert_eq!(summary["truncated"], false);
+        assert!(summary["largest_files"][0]["path"].as_str().unwrap().ends_with("big.rs"));
+        assert_eq!(summary["largest_files"].as_array().unwrap().len(), 1);
+
+        let request = DiskUsageRequest {
+            path: root.to_str().unwrap().to_string(),
+            top_n: None,
+            respect_gitignore: Some(false),
+            include_hidden: None,
+            max_entries: Some(2),
+            max_duration_ms: None,
+            ignore: Default::default(),
+        };
+        let result = disk_usage(request).await.unwrap();
+        let summary = result_json(&result);
+        assert_eq!(summary["truncated"], true);
+        assert_eq!(summary["truncated_by"], "max_entries");
+    }
+
+    #[tokio::test]
+    async fn test_estimate_operation() {
+        let (_env, temp_dir, _) = test_env().await;
+
+        let source = temp_dir.path().join("source");
+        let target = temp_dir.path().join("target");
+        fs::create_dir_all(source.join("nested")).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::write(source.join("a.bin"), vec![1u8; 2048]).unwrap();
+        fs::write(source.join("nested/b.bin"), vec![2u8; 1024]).unwrap();
+        // Already synced
+        fs::write(target.join("a.bin"), vec![1u8; 2048]).unwrap();
+
+        let request = |operation: &str| EstimateOperationRequest {
+            operation: operation.to_string(),
+            source_path: source.to_str().unwrap().to_string(),
+            target_path: Some(target.to_str().unwrap().to_string()),
+            respect_gitignore: None,
+            include_hidden: None,
+            top_n: None,
+            max_entries: None,
+            max_duration_ms: None,
+            ignore: Default::default(),
+        };
+        let summary = |result: CallToolResult| result_json(&result);
+
+        let copy = summary(estimate_operation(request("copy")).await.unwrap());
+        assert_eq!(copy["file_count"], 2);
+        assert_eq!(copy["dir_count"], 1);
+        assert_eq!(copy["total_bytes"], 3072);
+        assert!(copy["largest_files"][0]["path"].as_str().unwrap().ends_with("a.bin"));
+        assert!(copy["estimated_seconds"].is_number());
+
+        let sync = summary(estimate_operation(request("sync")).await.unwrap());
+        assert_eq!(sync["files_unchanged"], 1);
+        assert_eq!(sync["bytes_to_transfer"], 1024);
+
+        let delete = summary(estimate_operation(request("delete")).await.unwrap());
+        assert_eq!(delete["file_count"], 2);
+        // Probes never go into the allowed directories
+        assert_eq!(fs::read_dir(&source).unwrap().count(), 2);
+        assert_eq!(fs::read_dir(&target).unwrap().count(), 1);
+
+        assert!(estimate_operation(request("shred")).await.unwrap().is_error);
+    }
+}
@@ -1,62 +1,247 @@
+use crate::mcp::config;
+use crate::mcp::locks::check_not_locked;
+use crate::mcp::protection::check_not_protected;
+use crate::mcp::reservations::check_not_reserved;
+use crate::mcp::sandbox;
+use crate::mcp::session;
+use crate::mcp::session::ClientInfo;
+use crate::mcp::session::CurrentSession;
+use crate::mcp::session::Phase;
+use crate::mcp::session::Session;
+use crate::mcp::trace;
 use crate::mcp::types::*;
-use crate::mcp::PROTOCOL_VERSION;
+use crate::mcp::vfs;
+use crate::mcp::SUPPORTED_PROTOCOL_VERSIONS;
 use crate::mcp::SERVER_NAME;
 use crate::mcp::SERVER_VERSION;
 use rpc_router::HandlerResult;
+use rpc_router::IntoHandlerError;
 use serde_json::json;
 use serde_json::Value;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+/// Allowed directories, separated like `PATH`: by `:` on Unix and by `;` on
+/// Windows, where `:` is part of drive letters
 pub fn get_allowed_directories() -> Vec<String> {
-    std::env::var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES")
-        .unwrap_or_default()
-        .split(':')
-        .filter(|s| !s.is_empty())
-        .map(String::from)
+    let dirs = config::var_os("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES").unwrap_or_default();
+    std::env::split_paths(&dirs)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| sandbox::simplify(dir).to_string_lossy().into_owned())
         .collect()
 }
 
+/// The protocol version to answer with: the client's own when this server
+/// supports it, otherwise the newest one we speak, leaving it to the client to
+/// disconnect if it cannot use that
+pub fn negotiate_protocol_version(requested: &str) -> &'static str {
+    SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .find(|version| **version == requested)
+        .unwrap_or(&SUPPORTED_PROTOCOL_VERSIONS[0])
+}
+
+/// Whether log messages are sent to clients, `MCP_RS_FILESYSTEM_LOGGING`. On
+/// unless turned off with `--no-logging` or `logging = false`.
+pub fn logging_enabled() -> bool {
+    config::var("MCP_RS_FILESYSTEM_LOGGING")
+        .map(|value| !matches!(value.to_lowercase().as_str(), "0" | "false" | "no"))
+        .unwrap_or(true)
+}
+
+/// Capabilities backed by what is actually implemented and enabled
+fn server_capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        experimental: None,
+        prompts: Some(PromptCapabilities {
+            // Only announced while the prompts directory is being watched
+            list_changed: Some(crate::mcp::prompt_library::watching()),
+        }),
+        resources: Some(ResourceCapabilities {
+            subscribe: Some(false),
+            // Only announced while the allowed directories are being watched or
+            // can change with the configuration file
+            list_changed: Some(crate::mcp::resources::watching() || config::reloadable()),
+        }),
+        // The tools offered can only change with the configuration file
+        tools: Some(json!({ "listChanged": config::reloadable() })),
+        roots: None,
+        sampling: None,
+        logging: logging_enabled().then(|| json!({})),
+    }
+}
+
 /// handler for `initialize` request from client
-pub async fn initialize(_request: InitializeRequest) -> HandlerResult<InitializeResult> {
+pub async fn initialize(session: Option<CurrentSession>, request: InitializeRequest) -> HandlerResult<InitializeResult> {
+    let protocol_version = negotiate_protocol_version(&request.protocol_version).to_string();
+    let capabilities = server_capabilities();
+    if let Some(CurrentSession(session)) = session {
+        let client = ClientInfo {
+            protocol_version: protocol_version.clone(),
+            client: request.client_info,
+            capabilities: request.capabilities,
+        };
+        if let Err(message) = session.initialize(client) {
+            return Err(json!({ "code": ErrorCode::InvalidRequest as i32, "message": message }).into_handler_error());
+        }
+    }
     let result = InitializeResult {
-        protocol_version: PROTOCOL_VERSION.to_string(),
+        protocol_version,
         server_info: Implementation {
             name: SERVER_NAME.to_string(),
             version: SERVER_VERSION.to_string(),
         },
-        capabilities: ServerCapabilities {
-            experimental: None,
-            prompts: Some(PromptCapabilities::default()),
-            resources: None,
-            tools: Some(json!({})),
-            roots: None,
-            sampling: None,
-            logging: None,
-        },
+        capabilities,
         instructions: None,
     };
     Ok(result)
 }
 
+/// Identifier of this server session, used to tell apart the owners of state
+/// shared between server processes
+pub fn session_id() -> &'static str {
+    static SESSION_ID: OnceLock<String> = OnceLock::new();
+    SESSION_ID.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        format!("{}-{}", std::process::id(), started)
+    })
+}
+
+/// The requests of the server, counted so that shutdown can wait for them
+struct Requests {
+    /// Set once shutdown begins. New requests are refused and watchers stop from then on.
+    shutting_down: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl Requests {
+    const fn new() -> Requests {
+        Requests {
+            shutting_down: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn start(&'static self) -> Option<InFlight> {
+        // Count first, so that shutdown either sees this request or we see the flag
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        if self.shutting_down.load(Ordering::SeqCst) {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(InFlight(self))
+    }
+
+    async fn drain(&self, timeout: Duration) -> usize {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while self.in_flight.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+static REQUESTS: Requests = Requests::new();
+
+pub fn shutting_down() -> bool {
+    REQUESTS.shutting_down.load(Ordering::SeqCst)
+}
+
+/// Marks a request as in flight until dropped
+pub struct InFlight(&'static Requests);
+
+impl InFlight {
+    /// `None` once shutdown has begun
+    pub fn start() -> Option<InFlight> {
+        REQUESTS.start()
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Stop accepting requests and wait up to `timeout` for the ones in flight to
+/// finish. Returns how many were still running when the wait ended.
+pub async fn drain_requests(timeout: Duration) -> usize {
+    REQUESTS.drain(timeout).await
+}
+
 /// handler for SIGINT by client
 pub fn graceful_shutdown() {
-    // shutdown server
+    // Also stops the polling threads that watch the filesystem
+    REQUESTS.shutting_down.store(true, Ordering::SeqCst);
+    crate::mcp::watch::unwatch_all();
+    // The next run starts from the index as it is now
+    crate::mcp::index::save_all();
+    for session in session::all() {
+        session.end();
+    }
+    crate::mcp::scratch::remove_scratch_directory();
 }
 
-/// handler for `notifications/initialized` from client
-pub fn notifications_initialized() {}
+/// handler for `notifications/initialized` from client: the handshake is done
+pub fn notifications_initialized() {
+    if let Some(session) = session::current() {
+        session.mark_ready();
+    }
+}
 
-/// handler for `notifications/cancelled` from client
-pub fn notifications_cancelled(_params: CancelledNotification) {
-    // cancel request
+/// handler for `notifications/cancelled` from client: flags the request, so
+/// the walks it runs stop early. A request that already finished is ignored.
+pub fn notifications_cancelled(params: CancelledNotification) {
+    if let Some(session) = session::current() {
+        session.cancel_request(&params.request_id);
+    }
 }
 
 pub async fn ping() -> HandlerResult<EmptyResult> {
     Ok(EmptyResult {})
 }
 
-pub async fn logging_set_level(_request: SetLevelRequest) -> HandlerResult<LoggingResponse> {
-    Ok(LoggingResponse {})
+/// Syslog severities accepted by `logging/setLevel`, least severe first
+const LOG_LEVELS: &[&str] = &["debug", "info", "notice", "warning", "error", "critical", "alert", "emergency"];
+
+pub async fn logging_set_level(request: SetLevelRequest) -> HandlerResult<LoggingResponse> {
+    let level = request.level.to_lowercase();
+    match LOG_LEVELS.iter().position(|known| *known == level) {
+        Some(index) => {
+            // Each client picks the level of the messages it gets
+            if let Some(session) = session::current() {
+                session.set_log_level(index);
+            }
+            Ok(LoggingResponse {})
+        }
+        None => Err(json!({
+            "code": -32602,
+            "message": format!("Invalid log level {:?}, expected one of {}", request.level, LOG_LEVELS.join(", ")),
+        })
+        .into_handler_error()),
+    }
+}
+
+/// Whether a log message of this level passes the level set by a client
+fn log_level_enabled(level: &str, min_level: usize) -> bool {
+    LOG_LEVELS
+        .iter()
+        .position(|known| *known == level)
+        .is_none_or(|index| index >= min_level)
 }
 
 pub async fn roots_list(_request: Option<ListRootsRequest>) -> HandlerResult<ListRootsResult> {
@@ -69,64 +254,88 @@ pub async fn roots_list(_request: Option<ListRootsRequest>) -> HandlerResult<Lis
     Ok(response)
 }
 
-/// send notification to client
+/// send notification to client: the one whose request is being handled, or
+/// every connected client for notifications from background work
 #[allow(dead_code)]
 pub fn notify(method: &str, params: Option<Value>) {
-    let notification = json!({
-        "jsonrpc": "2.0",
-        "method": method,
-        "params": params,
-    });
-    println!("{}", serde_json::to_string(&notification).unwrap());
+    let sessions = match session::current() {
+        Some(session) => vec![session],
+        None => session::all(),
+    };
+    send_notification(sessions, method, params);
 }
 
-pub fn is_path_allowed(path: &Path) -> bool {
-    let allowed_dirs = get_allowed_directories();
-    if allowed_dirs.is_empty() {
-        return false; // If no directories are explicitly allowed, deny all access
-    }
+/// Receives the server's own log messages while no client can take them, as
+/// `(level, logger, message)`. Set with [`ServerBuilder::log_hook`](crate::ServerBuilder::log_hook).
+pub type LogHook = Arc<dyn Fn(&str, &str, &str) + Send + Sync>;
 
-    // Get all parent directories of the path, including itself
-    let mut check_path = path.to_path_buf();
-    loop {
-        // Try to canonicalize the current path if it exists
-        let canonical_check = if check_path.exists() {
-            match check_path.canonicalize() {
-                Ok(p) => p,
-                Err(_) => check_path.clone(),
-            }
-        } else {
-            check_path.clone()
-        };
+static LOG_HOOK: Mutex<Option<LogHook>> = Mutex::new(None);
 
-        // Check if this path or parent is allowed
-        for allowed_dir in &allowed_dirs {
-            let allowed_path = Path::new(allowed_dir);
-            let canonical_allowed = if allowed_path.exists() {
-                match allowed_path.canonicalize() {
-                    Ok(p) => p,
-                    Err(_) => continue,
-                }
-            } else {
-                continue;
-            };
-
-            if canonical_check.starts_with(&canonical_allowed) {
-                return true;
-            }
-        }
+/// Install the hook for log messages no client takes, or remove it with `None`
+pub fn set_log_hook(hook: Option<LogHook>) {
+    *LOG_HOOK.lock().unwrap() = hook;
+}
+
+/// Log a message of the server's own, such as a watch that failed to start.
+/// Clients get it as `notifications/message` once one is through its
+/// handshake; until then it goes to the log hook, and is dropped without one.
+/// On stdio a notification sent before the handshake would land among the responses.
+pub fn log_message(level: &str, logger: &str, message: &str) {
+    if session::all().iter().any(|session| session.phase() == Phase::Ready) {
+        notify("notifications/message", Some(json!({ "level": level, "logger": logger, "data": message })));
+        return;
+    }
+    let hook = LOG_HOOK.lock().unwrap().clone();
+    if let Some(hook) = hook {
+        hook(level, logger, message);
+    }
+}
 
-        // Move up to parent directory
-        match check_path.parent() {
-            Some(parent) => check_path = parent.to_path_buf(),
-            None => break,
+/// send notification to every connected client, for changes that concern them
+/// all even when one of them caused it
+pub fn notify_all(method: &str, params: Option<Value>) {
+    send_notification(session::all(), method, params);
+}
+
+fn send_notification(sessions: Vec<Arc<Session>>, method: &str, params: Option<Value>) {
+    let level = matches!(method, "notifications/message" | "logging/message")
+        .then(|| params.as_ref().and_then(|p| p["level"].as_str()).unwrap_or_default());
+    if level.is_some() && !logging_enabled() {
+        return;
+    }
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+    });
+    // With no session, as outside a connection, there is nobody to tell
+    let line = serde_json::to_string(&notification).unwrap();
+    for session in sessions {
+        if level.is_none_or(|level| log_level_enabled(level, session.log_level())) {
+            session.send(line.clone());
         }
     }
+}
 
-    false
+/// send a `notifications/progress` notification for a request that supplied a progress token
+pub fn notify_progress(progress_token: &Value, progress: i32, total: Option<i32>, message: Option<String>) {
+    let progress = Progress {
+        progress_token: progress_token.clone(),
+        progress,
+        total,
+        message,
+    };
+    notify("notifications/progress", Some(json!(progress)));
+}
+
+pub fn is_path_allowed(path: &Path) -> bool {
+    // On disk resolved physically, so `..` after a symlink leaves its target, and `..` out of a
+    // missing component looks at the disk again
+    vfs::current().contains(path)
 }
 
 pub fn validate_path_or_error(path: &Path) -> Result<(), String> {
+    trace::record_path(path);
     if !is_path_allowed(path) {
         Err(format!(
             "Access denied: {} is not within allowed directories. Use the allowed_directories resource to view permitted locations.",
@@ -139,6 +348,8 @@ pub fn validate_path_or_error(path: &Path) -> Result<(), String> {
 
 // For operations that involve two paths (like move/rename)
 pub fn validate_paths_or_error(source: &Path, target: &Path) -> Result<(), String> {
+    trace::record_path(source);
+    trace::record_path(target);
     if !is_path_allowed(source) {
         Err(format!(
             "Access denied: source path {} is not within allowed directories",
@@ -152,4 +363,183 @@ pub fn validate_paths_or_error(source: &Path, target: &Path) -> Result<(), Strin
     } else {
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Absolute, symlink-resolved form of a path that may not exist yet: the longest
+/// existing ancestor is canonicalized and the remaining components appended.
+pub fn canonical_path(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            let mut canonical = sandbox::simplify(canonical);
+            canonical.extend(missing.iter().rev());
+            return canonical;
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return absolute,
+        }
+    }
+}
+
+/// Validation for tools that modify the filesystem: on top of the allowed
+/// directories check, the path must not be protected, nor reserved or locked
+/// by another session.
+pub fn validate_write_path_or_error(path: &Path) -> Result<(), String> {
+    validate_path_or_error(path)?;
+    check_not_protected(path)?;
+    check_not_reserved(path)?;
+    check_not_locked(path)
+}
+
+// For mutating operations that involve two paths (like move/rename)
+pub fn validate_write_paths_or_error(source: &Path, target: &Path) -> Result<(), String> {
+    validate_paths_or_error(source, target)?;
+    check_not_protected(source)?;
+    check_not_protected(target)?;
+    check_not_reserved(source)?;
+    check_not_reserved(target)?;
+    check_not_locked(source)?;
+    check_not_locked(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_initialize_negotiates_protocol_version() {
+        let request = |version: &str| -> InitializeRequest {
+            serde_json::from_value(json!({
+                "protocolVersion": version,
+                "clientInfo": {"name": "test", "version": "1"},
+            }))
+            .unwrap()
+        };
+        let result = initialize(None, request("2024-11-05")).await.unwrap();
+        assert_eq!(result.protocol_version, "2024-11-05");
+        let result = initialize(None, request("1999-01-01")).await.unwrap();
+        assert_eq!(result.protocol_version, SUPPORTED_PROTOCOL_VERSIONS[0]);
+
+        let capabilities = serde_json::to_value(&result.capabilities).unwrap();
+        assert_eq!(capabilities["resources"]["subscribe"], json!(false));
+        assert!(capabilities["logging"].is_object());
+        assert!(capabilities.get("sampling").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_initialize_capabilities() {
+        let _guard = crate::mcp::testing::lock_env().await;
+        let request = |capabilities: Value| -> InitializeRequest {
+            serde_json::from_value(json!({
+                "protocolVersion": SUPPORTED_PROTOCOL_VERSIONS[0],
+                "capabilities": capabilities,
+                "clientInfo": {"name": "test", "version": "1"},
+            }))
+            .unwrap()
+        };
+        let advertised = |result: InitializeResult| serde_json::to_value(&result.capabilities).unwrap();
+
+        // A configuration file makes the tools and resources lists changeable
+        config::install(Arc::new(config::Settings::new([(
+            "MCP_RS_FILESYSTEM_CONFIG".to_string(),
+            "rs_filesystem.toml".into(),
+        )])));
+        let full = advertised(initialize(None, request(json!({ "roots": { "listChanged": true } }))).await.unwrap());
+        assert_eq!(full["tools"]["listChanged"], json!(true));
+        assert_eq!(full["resources"]["listChanged"], json!(true));
+        assert!(full["logging"].is_object());
+
+        // What the client declared makes no difference
+        let bare = advertised(initialize(None, request(json!({}))).await.unwrap());
+        assert_eq!(bare, full);
+
+        config::install(Arc::new(config::Settings::new([(
+            "MCP_RS_FILESYSTEM_LOGGING".to_string(),
+            "false".into(),
+        )])));
+        let quiet = advertised(initialize(None, request(json!({}))).await.unwrap());
+        assert!(quiet.get("logging").is_none());
+        config::install(Arc::new(config::Settings::default()));
+    }
+
+    #[tokio::test]
+    async fn test_log_message_before_handshake() {
+        let _guard = crate::mcp::testing::lock_env().await;
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&logged);
+        set_log_hook(Some(Arc::new(move |level: &str, logger: &str, message: &str| {
+            sink.lock().unwrap().push(format!("{} {}: {}", level, logger, message));
+        })));
+
+        // A client still in its handshake is sent nothing, the hook gets the message
+        let (sender, mut output) = tokio::sync::mpsc::unbounded_channel();
+        let client = Session::start(sender);
+        log_message("warning", "prompts", "Invalid prompt file");
+        assert_eq!(*logged.lock().unwrap(), ["warning prompts: Invalid prompt file"]);
+        assert!(output.try_recv().is_err());
+
+        client.initialize(ClientInfo {
+            protocol_version: SUPPORTED_PROTOCOL_VERSIONS[0].to_string(),
+            client: Implementation {
+                name: "test".to_string(),
+                version: "1".to_string(),
+            },
+            capabilities: serde_json::from_value(json!({})).unwrap(),
+        })
+        .unwrap();
+        client.mark_ready();
+        log_message("warning", "prompts", "Duplicate prompt");
+        assert!(output.try_recv().unwrap().contains("Duplicate prompt"));
+        assert_eq!(logged.lock().unwrap().len(), 1);
+        client.end();
+        set_log_hook(None);
+    }
+
+    #[tokio::test]
+    async fn test_notifications_cancelled() {
+        let (sender, _output) = tokio::sync::mpsc::unbounded_channel();
+        let client = Session::start(sender);
+        let cancelled = client.begin_request(&json!(4));
+        let cancel = |id: Value| CancelledNotification {
+            request_id: id,
+            reason: None,
+        };
+        session::scope(Arc::clone(&client), async {
+            // The id "4" names another request than 4
+            notifications_cancelled(cancel(json!("4")));
+            assert!(!cancelled.load(Ordering::Relaxed));
+            notifications_cancelled(cancel(json!(4)));
+            assert!(cancelled.load(Ordering::Relaxed));
+        })
+        .await;
+        client.finish_request(&json!(4));
+        assert!(!client.cancel_request(&json!(4)));
+        client.end();
+    }
+
+    #[tokio::test]
+    async fn test_drain_requests() {
+        static TEST_REQUESTS: Requests = Requests::new();
+        let request = TEST_REQUESTS.start().unwrap();
+
+        // A request still running outlasts the wait, and no new one starts
+        assert_eq!(TEST_REQUESTS.drain(Duration::from_millis(50)).await, 1);
+        assert!(TEST_REQUESTS.start().is_none());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(request);
+        });
+        assert_eq!(TEST_REQUESTS.drain(Duration::from_secs(5)).await, 0);
+    }
+}
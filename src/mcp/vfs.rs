@@ -0,0 +1,509 @@
+use crate::mcp::budget::charge_read;
+use crate::mcp::budget::charge_write;
+use crate::mcp::config;
+use crate::mcp::sandbox;
+use crate::mcp::sandbox::WriteMode;
+use crate::mcp::tools;
+use crate::mcp::utilities::get_allowed_directories;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+/// A file opened for reading
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Other,
+}
+
+/// What a backend knows about a path, following symlinks
+#[derive(Debug, Clone)]
+pub struct Stat {
+    pub kind: FileKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub readonly: bool,
+}
+
+impl Stat {
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Directory
+    }
+}
+
+/// An entry of a directory listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: FileKind,
+}
+
+/// Where the tools read and write files. Paths are those the client sent,
+/// after expansion; each backend decides which of them lie inside the allowed
+/// directories. Errors are messages for the client, like the rest of the tools.
+pub trait FileSystem: Send + Sync {
+    /// Whether paths name files on the local disk, so tools that walk trees
+    /// with ignore rules or hand paths to other programs see the same files
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    /// Whether `path` lies inside an allowed directory
+    fn contains(&self, path: &Path) -> bool;
+
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek>, String>;
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        self.open(path)?.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+        Ok(bytes)
+    }
+
+    /// At most `limit` bytes from the start of a file
+    fn read_prefix(&self, path: &Path, limit: usize) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        self.open(path)?
+            .take(limit as u64)
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+        Ok(bytes)
+    }
+
+    /// Write a file. Existence checks for `mode` are part of the write, so a
+    /// file created concurrently is never clobbered by `CreateNew`.
+    fn write(&self, path: &Path, bytes: &[u8], mode: WriteMode) -> Result<(), String>;
+
+    /// Entries of a directory, in no particular order
+    fn list(&self, path: &Path) -> Result<Vec<DirEntry>, String>;
+
+    fn stat(&self, path: &Path) -> Result<Stat, String>;
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), String>;
+
+    fn remove_file(&self, path: &Path) -> Result<(), String>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.stat(path).is_ok_and(|stat| stat.is_file())
+    }
+}
+
+/// The local disk, reached through the sandbox so symlinks cannot lead out of
+/// the allowed directories
+pub struct LocalFs;
+
+impl FileSystem for LocalFs {
+    fn is_local(&self) -> bool {
+        true
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        sandbox::is_contained(path)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek>, String> {
+        Ok(Box::new(sandbox::open_read(path)?))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, String> {
+        sandbox::read(path)
+    }
+
+    fn read_prefix(&self, path: &Path, limit: usize) -> Result<Vec<u8>, String> {
+        sandbox::read_prefix(path, limit)
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8], mode: WriteMode) -> Result<(), String> {
+        sandbox::write_with_mode(path, bytes, mode)
+    }
+
+    fn list(&self, path: &Path) -> Result<Vec<DirEntry>, String> {
+        sandbox::list(path)
+    }
+
+    fn stat(&self, path: &Path) -> Result<Stat, String> {
+        sandbox::stat(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), String> {
+        sandbox::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), String> {
+        sandbox::remove_file(path)
+    }
+}
+
+enum Node {
+    File { bytes: Vec<u8>, modified: SystemTime },
+    Directory { modified: SystemTime },
+}
+
+/// Files kept in memory, for tests and for running the server without a disk.
+/// There are no symlinks, so paths are resolved by their spelling alone.
+#[derive(Default)]
+pub struct MemoryFs {
+    nodes: Mutex<BTreeMap<PathBuf, Node>>,
+}
+
+/// `path` without `.` and `..`, or `None` when it is relative. As on a disk,
+/// `..` at the root stays there.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() {
+        return None;
+    }
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::Normal(_) => normalized.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+        }
+    }
+    Some(normalized)
+}
+
+fn not_found(path: &Path) -> String {
+    format!("No such file or directory: {}", path.display())
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    fn resolve(path: &Path) -> Result<PathBuf, String> {
+        normalize(path).ok_or_else(|| format!("Not an absolute path: {}", path.display()))
+    }
+
+    /// Whether `path` is the root of the tree or a directory in it
+    fn is_directory(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> bool {
+        path.parent().is_none() || matches!(nodes.get(path), Some(Node::Directory { .. }))
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn contains(&self, path: &Path) -> bool {
+        let Some(path) = normalize(path) else {
+            return false;
+        };
+        get_allowed_directories()
+            .iter()
+            .filter_map(|dir| normalize(Path::new(dir)))
+            .any(|dir| path.starts_with(dir))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek>, String> {
+        let resolved = MemoryFs::resolve(path)?;
+        match self.nodes.lock().unwrap().get(&resolved) {
+            Some(Node::File { bytes, .. }) => Ok(Box::new(Cursor::new(bytes.clone()))),
+            Some(Node::Directory { .. }) => Err(format!("Is a directory: {}", path.display())),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8], mode: WriteMode) -> Result<(), String> {
+        let resolved = MemoryFs::resolve(path)?;
+        let mut nodes = self.nodes.lock().unwrap();
+        if !resolved.parent().is_some_and(|parent| MemoryFs::is_directory(&nodes, parent)) {
+            return Err(not_found(path));
+        }
+        let modified = SystemTime::now();
+        match (nodes.get_mut(&resolved), mode) {
+            (Some(Node::Directory { .. }), _) => Err(format!("Is a directory: {}", path.display())),
+            (Some(Node::File { .. }), WriteMode::CreateNew) => Err(format!("File exists: {}", path.display())),
+            (None, WriteMode::Append) => Err(not_found(path)),
+            (Some(Node::File { bytes: existing, modified: time }), WriteMode::Append | WriteMode::CreateOrAppend) => {
+                existing.extend_from_slice(bytes);
+                *time = modified;
+                Ok(())
+            }
+            _ => {
+                nodes.insert(resolved, Node::File { bytes: bytes.to_vec(), modified });
+                Ok(())
+            }
+        }
+    }
+
+    fn list(&self, path: &Path) -> Result<Vec<DirEntry>, String> {
+        let resolved = MemoryFs::resolve(path)?;
+        let nodes = self.nodes.lock().unwrap();
+        if !MemoryFs::is_directory(&nodes, &resolved) {
+            return Err(match nodes.get(&resolved) {
+                Some(_) => format!("Not a directory: {}", path.display()),
+                None => not_found(path),
+            });
+        }
+        Ok(nodes
+            .range(resolved.clone()..)
+            .take_while(|(child, _)| child.starts_with(&resolved))
+            .filter(|(child, _)| child.parent() == Some(resolved.as_path()))
+            .map(|(child, node)| DirEntry {
+                name: child.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                kind: match node {
+                    Node::File { .. } => FileKind::File,
+                    Node::Directory { .. } => FileKind::Directory,
+                },
+            })
+            .collect())
+    }
+
+    fn stat(&self, path: &Path) -> Result<Stat, String> {
+        let resolved = MemoryFs::resolve(path)?;
+        let (kind, len, modified) = match self.nodes.lock().unwrap().get(&resolved) {
+            Some(Node::File { bytes, modified }) => (FileKind::File, bytes.len() as u64, Some(*modified)),
+            Some(Node::Directory { modified }) => (FileKind::Directory, 0, Some(*modified)),
+            None if resolved.parent().is_none() => (FileKind::Directory, 0, None),
+            None => return Err(not_found(path)),
+        };
+        Ok(Stat {
+            kind,
+            len,
+            modified,
+            readonly: false,
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), String> {
+        let resolved = MemoryFs::resolve(path)?;
+        let mut nodes = self.nodes.lock().unwrap();
+        let modified = SystemTime::now();
+        for ancestor in resolved.ancestors().collect::<Vec<_>>().into_iter().rev().skip(1) {
+            match nodes.get(ancestor) {
+                Some(Node::Directory { .. }) => {}
+                Some(Node::File { .. }) => return Err(format!("Not a directory: {}", ancestor.display())),
+                None => {
+                    nodes.insert(ancestor.to_path_buf(), Node::Directory { modified });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), String> {
+        let resolved = MemoryFs::resolve(path)?;
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(&resolved) {
+            Some(Node::File { .. }) => {
+                nodes.remove(&resolved);
+                Ok(())
+            }
+            Some(Node::Directory { .. }) => Err(format!("Is a directory: {}", path.display())),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+/// Names accepted by `--backend`
+pub const BACKEND_NAMES: &[&str] = &["local", "memory"];
+
+/// Refuse a call of `tool` that needs the local disk when the request runs on
+/// another backend, rather than let it touch files the backend does not hold
+pub fn check_tool(tool: &str) -> Result<(), String> {
+    if tools::is_local_only(tool) && !current().is_local() {
+        return Err(format!("{} works on the local disk only and is not available with this backend", tool));
+    }
+    Ok(())
+}
+
+static LOCAL: LazyLock<Arc<dyn FileSystem>> = LazyLock::new(|| Arc::new(LocalFs));
+
+/// The backend of the server, when `--backend` chose another than the local disk
+static DEFAULT: OnceLock<Arc<dyn FileSystem>> = OnceLock::new();
+
+tokio::task_local! {
+    static BACKEND: Arc<dyn FileSystem>;
+}
+
+/// Set up the backend `MCP_RS_FILESYSTEM_BACKEND`, set by `--backend`, names.
+/// A memory backend starts out with the allowed directories, empty.
+pub fn init() -> Result<(), String> {
+    let name = config::var("MCP_RS_FILESYSTEM_BACKEND").unwrap_or_default();
+    let backend: Arc<dyn FileSystem> = match name.as_str() {
+        "" | "local" => return Ok(()),
+        "memory" => {
+            let memory = MemoryFs::new();
+            for dir in get_allowed_directories() {
+                memory.create_dir_all(Path::new(&dir))?;
+            }
+            Arc::new(memory)
+        }
+        other => return Err(format!("Unknown backend: {}, expected one of {}", other, BACKEND_NAMES.join(", "))),
+    };
+    let _ = DEFAULT.set(backend);
+    Ok(())
+}
+
+/// Run `future` with `backend` in place of the server's
+pub async fn scope<F: Future>(backend: Arc<dyn FileSystem>, future: F) -> F::Output {
+    BACKEND.scope(backend, future).await
+}
+
+/// The backend of the request being handled: the one a caller chose with
+/// [`scope`], else the server's, the local disk unless `--backend` says otherwise.
+/// What goes through it is charged to the session's budget.
+pub fn current() -> Arc<dyn FileSystem> {
+    Arc::new(Metered(backend()))
+}
+
+/// The backend of [`current`] without the budget, for what the server reads and
+/// writes on its own behalf: headers sniffed for a file's type, shadow copies
+/// and the old text a dry run compares with
+pub fn backend() -> Arc<dyn FileSystem> {
+    BACKEND
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| Arc::clone(DEFAULT.get().unwrap_or(&LOCAL)))
+}
+
+/// A backend charging the bytes read and written to the session's budget,
+/// before they are read or written where the size is known up front
+struct Metered(Arc<dyn FileSystem>);
+
+/// A file of a [`Metered`] backend, charging what is read as it goes
+struct MeteredReader {
+    path: PathBuf,
+    inner: Box<dyn ReadSeek>,
+}
+
+impl Read for MeteredReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        charge_read(&self.path, read as u64).map_err(std::io::Error::other)?;
+        Ok(read)
+    }
+}
+
+impl Seek for MeteredReader {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+impl FileSystem for Metered {
+    fn is_local(&self) -> bool {
+        self.0.is_local()
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.0.contains(path)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek>, String> {
+        let inner = self.0.open(path)?;
+        Ok(Box::new(MeteredReader {
+            path: path.to_path_buf(),
+            inner,
+        }))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, String> {
+        // A missing file is left for the read to report
+        if let Ok(stat) = self.0.stat(path) {
+            charge_read(path, stat.len)?;
+        }
+        self.0.read(path)
+    }
+
+    fn read_prefix(&self, path: &Path, limit: usize) -> Result<Vec<u8>, String> {
+        if let Ok(stat) = self.0.stat(path) {
+            charge_read(path, stat.len.min(limit as u64))?;
+        }
+        self.0.read_prefix(path, limit)
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8], mode: WriteMode) -> Result<(), String> {
+        charge_write(path, bytes.len() as u64)?;
+        self.0.write(path, bytes, mode)
+    }
+
+    fn list(&self, path: &Path) -> Result<Vec<DirEntry>, String> {
+        self.0.list(path)
+    }
+
+    fn stat(&self, path: &Path) -> Result<Stat, String> {
+        self.0.stat(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), String> {
+        self.0.create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), String> {
+        self.0.remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_fs() {
+        let fs = MemoryFs::new();
+        let root = if cfg!(windows) { Path::new(r"C:\work") } else { Path::new("/work") };
+        assert!(fs.write(&root.join("a.txt"), b"a", WriteMode::Overwrite).is_err());
+        fs.create_dir_all(&root.join("src")).unwrap();
+        fs.write(&root.join("a.txt"), b"hello", WriteMode::CreateNew).unwrap();
+        assert!(fs.write(&root.join("a.txt"), b"again", WriteMode::CreateNew).is_err());
+        fs.write(&root.join("src/../a.txt"), b" world", WriteMode::Append).unwrap();
+        assert!(fs.write(&root.join("b.txt"), b"", WriteMode::Append).is_err());
+        assert_eq!(fs.read(&root.join("a.txt")).unwrap(), b"hello world");
+        assert_eq!(fs.read_prefix(&root.join("a.txt"), 4).unwrap(), b"hell");
+        assert!(fs.read(&root.join("src")).is_err());
+
+        let mut entries = fs.list(root).unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(
+            entries,
+            [
+                DirEntry { name: "a.txt".to_string(), kind: FileKind::File },
+                DirEntry { name: "src".to_string(), kind: FileKind::Directory },
+            ]
+        );
+        assert!(fs.list(&root.join("a.txt")).is_err());
+        assert_eq!(fs.stat(&root.join("a.txt")).unwrap().len, 11);
+        assert!(fs.stat(&root.join("src")).unwrap().is_dir());
+        assert!(!fs.exists(&root.join("missing")));
+        assert!(fs.create_dir_all(&root.join("a.txt/sub")).is_err());
+        assert!(fs.remove_file(&root.join("src")).is_err());
+        fs.remove_file(&root.join("a.txt")).unwrap();
+        assert!(!fs.exists(&root.join("a.txt")));
+        assert!(fs.read(Path::new("relative.txt")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_tool() {
+        assert!(check_tool("grep_search").is_ok());
+        scope(Arc::new(MemoryFs::new()), async {
+            assert!(check_tool("grep_search").is_err());
+            assert!(check_tool("copy_file").is_err());
+            assert!(check_tool("read_file").is_ok());
+        })
+        .await;
+    }
+}
@@ -0,0 +1,387 @@
+use crate::mcp::session;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::canonical_path;
+use crate::mcp::utilities::is_path_allowed;
+use crate::mcp::utilities::validate_path_or_error;
+use chrono::Local;
+use notify::event::ModifyKind;
+use notify::Event;
+use notify::EventKind;
+use notify::PollWatcher;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Events kept per watch until polled; older ones are dropped beyond this
+const MAX_BUFFERED_EVENTS: usize = 10_000;
+const DEFAULT_MAX_EVENTS: usize = 1000;
+const DEFAULT_POLL_INTERVAL_MS: u64 = 2000;
+const MIN_POLL_INTERVAL_MS: u64 = 100;
+
+/// Filesystems on which native change notifications are missing or unreliable
+const POLLED_FILESYSTEMS: &[&str] = &[
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "afpfs", "webdav", "9p", "drvfs", "vboxsf", "fuse.sshfs", "fuse.rclone",
+    "fuse.gvfsd-fuse", "afs", "ceph", "glusterfs",
+];
+
+#[derive(Default)]
+struct Buffer {
+    events: VecDeque<Value>,
+    dropped: usize,
+}
+
+struct Watch {
+    /// Session that started the watch, the only one that sees it
+    session: String,
+    path: PathBuf,
+    backend: &'static str,
+    buffer: Arc<Mutex<Buffer>>,
+    // Kept alive for as long as the watch exists, dropping it stops watching
+    _watcher: Box<dyn Watcher + Send>,
+}
+
+static WATCHES: Mutex<Option<HashMap<String, Watch>>> = Mutex::new(None);
+static WATCH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Type of the filesystem holding `path`, from the mount table
+#[cfg(target_os = "linux")]
+fn filesystem_type(path: &Path) -> Option<String> {
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            // Spaces in mount points are escaped as \040
+            let mount_point = fields.next()?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point).then(|| (mount_point.len(), fs_type.to_string()))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+}
+
+#[cfg(target_os = "macos")]
+fn filesystem_type(path: &Path) -> Option<String> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid statfs buffer
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // SAFETY: the kernel fills f_fstypename with a NUL-terminated name
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn filesystem_type(_path: &Path) -> Option<String> {
+    None
+}
+
+fn event_kind(kind: &EventKind) -> Option<&'static str> {
+    match kind {
+        EventKind::Create(_) => Some("create"),
+        EventKind::Modify(ModifyKind::Name(_)) => Some("rename"),
+        EventKind::Modify(ModifyKind::Metadata(_)) => Some("metadata"),
+        EventKind::Modify(_) => Some("modify"),
+        EventKind::Remove(_) => Some("remove"),
+        // Reads are not changes
+        EventKind::Access(_) => None,
+        EventKind::Any | EventKind::Other => Some("other"),
+    }
+}
+
+fn event_handler(buffer: Arc<Mutex<Buffer>>) -> impl Fn(notify::Result<Event>) + Send + 'static {
+    move |result: notify::Result<Event>| {
+        let entry = match result {
+            Ok(event) => {
+                let Some(kind) = event_kind(&event.kind) else {
+                    return;
+                };
+                let paths: Vec<String> = event
+                    .paths
+                    .iter()
+                    .filter(|path| is_path_allowed(path))
+                    .map(|path| path.display().to_string())
+                    .collect();
+                if paths.is_empty() {
+                    return;
+                }
+                json!({ "kind": kind, "paths": paths, "time": Local::now().to_rfc3339() })
+            }
+            Err(e) => json!({ "kind": "error", "error": e.to_string(), "time": Local::now().to_rfc3339() }),
+        };
+        let mut buffer = buffer.lock().unwrap();
+        if buffer.events.len() >= MAX_BUFFERED_EVENTS {
+            buffer.events.pop_front();
+            buffer.dropped += 1;
+        }
+        buffer.events.push_back(entry);
+    }
+}
+
+fn start_watch<F>(
+    path: &Path,
+    mode: RecursiveMode,
+    use_polling: bool,
+    interval: Duration,
+    handler: F,
+) -> notify::Result<Box<dyn Watcher + Send>>
+where
+    F: Fn(notify::Result<Event>) + Send + 'static,
+{
+    let mut watcher: Box<dyn Watcher + Send> = if use_polling {
+        let config = notify::Config::default().with_poll_interval(interval);
+        Box::new(PollWatcher::new(handler, config)?)
+    } else {
+        Box::new(RecommendedWatcher::new(handler, notify::Config::default())?)
+    };
+    watcher.watch(path, mode)?;
+    Ok(watcher)
+}
+
+/// Watch a directory tree on behalf of another subsystem, picking the backend
+/// as the `auto` setting of `watch_path` does. Returns the watcher, which stops
+/// watching when dropped, and whether it polls.
+pub fn watch_tree<F>(path: &Path, handler: F) -> notify::Result<(Box<dyn Watcher + Send>, bool)>
+where
+    F: Fn(notify::Result<Event>) + Clone + Send + 'static,
+{
+    let interval = Duration::from_millis(DEFAULT_POLL_INTERVAL_MS);
+    let use_polling = filesystem_type(path).is_some_and(|fs_type| POLLED_FILESYSTEMS.contains(&fs_type.as_str()));
+    match start_watch(path, RecursiveMode::Recursive, use_polling, interval, handler.clone()) {
+        Ok(watcher) => Ok((watcher, use_polling)),
+        Err(_) if !use_polling => {
+            start_watch(path, RecursiveMode::Recursive, true, interval, handler).map(|watcher| (watcher, true))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Number of paths being watched, over all sessions
+pub fn active_watches() -> usize {
+    WATCHES.lock().unwrap().as_ref().map_or(0, HashMap::len)
+}
+
+/// Stop every watch, used on shutdown
+pub fn unwatch_all() {
+    WATCHES.lock().unwrap().take();
+}
+
+/// Stop the watches of a session that ended
+pub fn unwatch_session(session: &str) {
+    if let Some(watches) = WATCHES.lock().unwrap().as_mut() {
+        watches.retain(|_, watch| watch.session != session);
+    }
+}
+
+fn json_result(value: &Value) -> HandlerResult<CallToolResult> {
+    Ok(CallToolResult::text(serde_json::to_string_pretty(value).unwrap()))
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct WatchPathRequest {
+    /// File or directory to watch
+    pub path: String,
+    /// Also watch subdirectories. Defaults to true.
+    #[schemars(extend("default" = true))]
+    pub recursive: Option<bool>,
+    /// auto (default) picks polling on filesystems without reliable notifications
+    #[schemars(extend("enum" = ["auto", "native", "poll"], "default" = "auto"))]
+    pub backend: Option<String>,
+    /// Scan interval of the poll backend in milliseconds. Defaults to 2000.
+    #[schemars(extend("default" = 2000))]
+    pub poll_interval_ms: Option<u64>,
+}
+
+pub async fn watch_path(request: WatchPathRequest) -> HandlerResult<CallToolResult> {
+    let path = &resolve_path(Path::new(&request.path));
+    if let Err(msg) = validate_path_or_error(path) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if !path.exists() {
+        return Ok(CallToolResult::error(format!("Path does not exist: {}", path.display())));
+    }
+    let path = canonical_path(path);
+    let mode = if request.recursive.unwrap_or(true) {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    let interval = Duration::from_millis(
+        request.poll_interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS).max(MIN_POLL_INTERVAL_MS),
+    );
+
+    let fs_type = filesystem_type(&path);
+    let (use_polling, mut reason) = match request.backend.as_deref().unwrap_or("auto") {
+        "native" => (false, "requested".to_string()),
+        "poll" => (true, "requested".to_string()),
+        "auto" => match fs_type.as_deref().filter(|fs_type| POLLED_FILESYSTEMS.contains(fs_type)) {
+            Some(fs_type) => (true, format!("{} filesystem has no reliable change notifications", fs_type)),
+            None => (false, "native notifications available".to_string()),
+        },
+        other => {
+            return Ok(CallToolResult::error(format!("Unknown backend {:?}, expected auto, native or poll", other)))
+        }
+    };
+
+    let buffer = Arc::new(Mutex::new(Buffer::default()));
+    let mut started = start_watch(&path, mode, use_polling, interval, event_handler(Arc::clone(&buffer))).map(|watcher| (watcher, use_polling));
+    // Fall back to polling when native notifications cannot be set up, e.g. inotify limits
+    if let Err(e) = &started {
+        if !use_polling && request.backend.as_deref() != Some("native") {
+            reason = format!("native watcher failed: {}", e);
+            started = start_watch(&path, mode, true, interval, event_handler(Arc::clone(&buffer))).map(|watcher| (watcher, true));
+        }
+    }
+    let (watcher, polling) = match started {
+        Ok(started) => started,
+        Err(e) => return Ok(CallToolResult::error(format!("Failed to watch {}: {}", path.display(), e))),
+    };
+
+    let id = format!("watch-{}", WATCH_COUNTER.fetch_add(1, Ordering::SeqCst) + 1);
+    let backend = if polling { "poll" } else { "native" };
+    let mut info = json!({
+        "watch_id": id,
+        "path": path.display().to_string(),
+        "recursive": matches!(mode, RecursiveMode::Recursive),
+        "backend": backend,
+        "reason": reason,
+        "filesystem": fs_type,
+    });
+    if polling {
+        info["poll_interval_ms"] = json!(interval.as_millis());
+    }
+    WATCHES.lock().unwrap().get_or_insert_with(HashMap::new).insert(
+        id,
+        Watch {
+            session: session::current_id(),
+            path,
+            backend,
+            buffer,
+            _watcher: watcher,
+        },
+    );
+    json_result(&info)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct PollChangesRequest {
+    /// Id returned by watch_path
+    pub watch_id: String,
+    /// Return at most this many events, the rest stay buffered. Defaults to 1000.
+    #[schemars(extend("default" = 1000))]
+    pub max_events: Option<usize>,
+}
+
+pub async fn poll_changes(request: PollChangesRequest) -> HandlerResult<CallToolResult> {
+    let watches = WATCHES.lock().unwrap();
+    let session = session::current_id();
+    let Some(watch) = watches
+        .as_ref()
+        .and_then(|watches| watches.get(&request.watch_id))
+        .filter(|watch| watch.session == session)
+    else {
+        return Ok(CallToolResult::error(format!("Unknown watch: {}", request.watch_id)));
+    };
+    let mut buffer = watch.buffer.lock().unwrap();
+    let count = request.max_events.unwrap_or(DEFAULT_MAX_EVENTS).min(buffer.events.len());
+    let events: Vec<Value> = buffer.events.drain(..count).collect();
+    let dropped = std::mem::take(&mut buffer.dropped);
+    json_result(&json!({
+        "watch_id": request.watch_id,
+        "path": watch.path.display().to_string(),
+        "backend": watch.backend,
+        "events": events,
+        "remaining": buffer.events.len(),
+        "dropped": dropped,
+    }))
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct UnwatchPathRequest {
+    /// Id returned by watch_path
+    pub watch_id: String,
+}
+
+pub async fn unwatch_path(request: UnwatchPathRequest) -> HandlerResult<CallToolResult> {
+    let session = session::current_id();
+    let removed = WATCHES.lock().unwrap().as_mut().and_then(|watches| {
+        let owned = watches.get(&request.watch_id).is_some_and(|watch| watch.session == session);
+        owned.then(|| watches.remove(&request.watch_id)).flatten()
+    });
+    match removed {
+        Some(watch) => Ok(CallToolResult::text(format!("Stopped watching {}", watch.path.display()))),
+        None => Ok(CallToolResult::error(format!("Unknown watch: {}", request.watch_id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_watch_path_poll_backend() {
+        let (_env, temp_dir, temp_path) = test_env().await;
+
+        let result = watch_path(WatchPathRequest {
+            path: temp_path.clone(),
+            recursive: None,
+            backend: Some("poll".to_string()),
+            poll_interval_ms: Some(100),
+        })
+        .await
+        .unwrap();
+        assert!(!result.is_error);
+        let info = result_json(&result);
+        assert_eq!(info["backend"], "poll");
+        let watch_id = info["watch_id"].as_str().unwrap().to_string();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        fs::write(temp_dir.path().join("new.txt"), "hello").unwrap();
+
+        // The poll backend notices the file within a few scans
+        let mut events = Vec::new();
+        for _ in 0..30 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let result = poll_changes(PollChangesRequest {
+                watch_id: watch_id.clone(),
+                max_events: None,
+            })
+            .await
+            .unwrap();
+            let changes = result_json(&result);
+            events.extend(changes["events"].as_array().unwrap().clone());
+            if !events.is_empty() {
+                break;
+            }
+        }
+        assert!(events.iter().any(|e| e["kind"] == "create" && e["paths"][0].as_str().unwrap().ends_with("new.txt")));
+
+        let request = || UnwatchPathRequest { watch_id: watch_id.clone() };
+        assert!(!unwatch_path(request()).await.unwrap().is_error);
+        assert!(unwatch_path(request()).await.unwrap().is_error);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::mcp::config;
+use crate::mcp::ignore_rules::build_walker;
+use crate::mcp::utilities::get_allowed_directories;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+pub const SUMMARY_URI: &str = "workspace://summary";
+/// Default size of the summary, in tokens of roughly four characters
+const DEFAULT_TOKEN_BUDGET: usize = 2000;
+const MAX_TOKEN_BUDGET: usize = 50_000;
+const CHARS_PER_TOKEN: usize = 4;
+/// Upper bound on entries walked per root, so huge trees stay cheap to summarize
+const MAX_ENTRIES: usize = 20_000;
+/// Files larger than this are counted but their lines are not
+const MAX_LINE_COUNT_BYTES: u64 = 1024 * 1024;
+const TREE_DEPTH: usize = 2;
+const RECENT_FILES: usize = 10;
+const TOP_EXTENSIONS: usize = 10;
+
+/// Files whose presence identifies a kind of project
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "Rust (Cargo)"),
+    ("package.json", "JavaScript/TypeScript (npm)"),
+    ("pyproject.toml", "Python"),
+    ("setup.py", "Python"),
+    ("requirements.txt", "Python"),
+    ("go.mod", "Go"),
+    ("pom.xml", "Java (Maven)"),
+    ("build.gradle", "Java/Kotlin (Gradle)"),
+    ("build.gradle.kts", "Java/Kotlin (Gradle)"),
+    ("Gemfile", "Ruby"),
+    ("composer.json", "PHP (Composer)"),
+    ("mix.exs", "Elixir"),
+    ("CMakeLists.txt", "C/C++ (CMake)"),
+    ("Package.swift", "Swift"),
+    ("deno.json", "Deno"),
+];
+
+/// Configuration and documentation files worth pointing out at the top of a root
+const KEY_FILES: &[&str] = &[
+    "README.md",
+    "README",
+    "README.rst",
+    "CONTRIBUTING.md",
+    "LICENSE",
+    "Cargo.toml",
+    "package.json",
+    "tsconfig.json",
+    "pyproject.toml",
+    "setup.cfg",
+    "go.mod",
+    "Makefile",
+    "Dockerfile",
+    "docker-compose.yml",
+    "compose.yaml",
+    ".gitignore",
+    ".editorconfig",
+    ".env.example",
+    "CLAUDE.md",
+    "AGENTS.md",
+];
+
+/// The token budget, from the `budget` query parameter of the resource URI or
+/// `MCP_RS_FILESYSTEM_SUMMARY_TOKENS`
+pub fn token_budget(uri: &url::Url) -> usize {
+    uri.query_pairs()
+        .find(|(key, _)| key == "budget")
+        .and_then(|(_, value)| value.parse().ok())
+        .or_else(|| {
+            config::var("MCP_RS_FILESYSTEM_SUMMARY_TOKENS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+        })
+        .unwrap_or(DEFAULT_TOKEN_BUDGET)
+        .clamp(100, MAX_TOKEN_BUDGET)
+}
+
+#[derive(Default)]
+struct ExtensionStats {
+    files: u64,
+    bytes: u64,
+    lines: u64,
+}
+
+/// What a walk of one allowed directory found
+#[derive(Default)]
+struct RootScan {
+    file_count: u64,
+    dir_count: u64,
+    total_bytes: u64,
+    truncated: bool,
+    by_extension: HashMap<String, ExtensionStats>,
+    /// relative directory -> number of files directly inside
+    tree: BTreeMap<PathBuf, u64>,
+    recent: Vec<(SystemTime, PathBuf)>,
+}
+
+fn scan(root: &Path) -> RootScan {
+    let mut scan = RootScan::default();
+    for (visited, entry) in build_walker(root, true, false).flatten().enumerate() {
+        if visited >= MAX_ENTRIES {
+            scan.truncated = true;
+            break;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path()).to_path_buf();
+        if metadata.is_dir() {
+            if entry.depth() > 0 {
+                scan.dir_count += 1;
+            }
+            if entry.depth() <= TREE_DEPTH {
+                scan.tree.entry(relative).or_default();
+            }
+            continue;
+        }
+        if !metadata.is_file() {
+            continue;
+        }
+
+        scan.file_count += 1;
+        scan.total_bytes += metadata.len();
+        if let Some(parent) = relative.parent().filter(|_| entry.depth() <= TREE_DEPTH + 1) {
+            *scan.tree.entry(parent.to_path_buf()).or_default() += 1;
+        }
+
+        let extension = entry
+            .path()
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .map(|e| format!(".{}", e))
+            .unwrap_or_else(|| "(no extension)".to_string());
+        let stats = scan.by_extension.entry(extension).or_default();
+        stats.files += 1;
+        stats.bytes += metadata.len();
+        if metadata.len() <= MAX_LINE_COUNT_BYTES {
+            if let Ok(bytes) = fs::read(entry.path()) {
+                // Binary files have no meaningful line count
+                if !bytes.contains(&0) {
+                    stats.lines += bytes.iter().filter(|b| **b == b'\n').count() as u64;
+                }
+            }
+        }
+
+        if let Ok(modified) = metadata.modified() {
+            scan.recent.push((modified, relative));
+            if scan.recent.len() > RECENT_FILES * 4 {
+                scan.recent.sort_by_key(|(modified, _)| Reverse(*modified));
+                scan.recent.truncate(RECENT_FILES);
+            }
+        }
+    }
+    scan.recent.sort_by_key(|(modified, _)| Reverse(*modified));
+    scan.recent.truncate(RECENT_FILES);
+    scan
+}
+
+fn age(modified: SystemTime) -> String {
+    let seconds = SystemTime::now().duration_since(modified).map(|d| d.as_secs()).unwrap_or(0);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86_399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86_400),
+    }
+}
+
+/// A titled block of lines; lines are dropped from the end when over budget
+struct Section {
+    title: String,
+    lines: Vec<String>,
+}
+
+fn summarize_root(root: &str) -> Vec<Section> {
+    let path = Path::new(root);
+    let scan = scan(path);
+
+    let mut project_types: Vec<&str> = PROJECT_MARKERS
+        .iter()
+        .filter(|(marker, _)| path.join(marker).exists())
+        .map(|(_, kind)| *kind)
+        .collect();
+    project_types.dedup();
+    if path.join(".git").exists() {
+        project_types.push("git repository");
+    }
+
+    let mut extensions: Vec<_> = scan.by_extension.iter().collect();
+    extensions.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(b.0)));
+    let mut overview = vec![
+        format!(
+            "Project type: {}",
+            if project_types.is_empty() { "unknown".to_string() } else { project_types.join(", ") }
+        ),
+        format!(
+            "{} files, {} directories, {} bytes{}",
+            scan.file_count,
+            scan.dir_count,
+            scan.total_bytes,
+            if scan.truncated { " (scan truncated)" } else { "" }
+        ),
+    ];
+    overview.extend(extensions.iter().take(TOP_EXTENSIONS).map(|(extension, stats)| {
+        format!("- {}: {} files, {} lines, {} bytes", extension, stats.files, stats.lines, stats.bytes)
+    }));
+
+    let key_files: Vec<String> = KEY_FILES
+        .iter()
+        .filter_map(|name| {
+            let metadata = fs::metadata(path.join(name)).ok()?;
+            Some(format!("- {} ({} bytes)", name, metadata.len()))
+        })
+        .chain(
+            path.join(".github/workflows")
+                .is_dir()
+                .then(|| "- .github/workflows/ (CI)".to_string()),
+        )
+        .collect();
+
+    let recent: Vec<String> = scan
+        .recent
+        .iter()
+        .map(|(modified, file)| format!("- {} ({})", file.display(), age(*modified)))
+        .collect();
+
+    let tree: Vec<String> = scan
+        .tree
+        .iter()
+        .filter(|(dir, _)| !dir.as_os_str().is_empty())
+        .map(|(dir, files)| {
+            let depth = dir.components().count();
+            let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            format!("{}{}/ ({} files)", "  ".repeat(depth - 1), name, files)
+        })
+        .collect();
+
+    vec![
+        Section { title: format!("## {}", root), lines: overview },
+        Section { title: "### Key files".to_string(), lines: key_files },
+        Section { title: "### Recently modified".to_string(), lines: recent },
+        Section { title: format!("### Directory tree (depth {})", TREE_DEPTH), lines: tree },
+    ]
+}
+
+/// Markdown overview of every allowed directory, kept within `token_budget`
+pub fn workspace_summary(token_budget: usize) -> String {
+    let budget = token_budget * CHARS_PER_TOKEN;
+    let mut output = String::from("# Workspace summary\n");
+    let roots = get_allowed_directories();
+    if roots.is_empty() {
+        output.push_str("\nNo allowed directories are configured.\n");
+        return output;
+    }
+
+    // Fill sections in priority order: overviews of every root first, then the
+    // remaining sections of each root while the budget lasts
+    let sections: Vec<Vec<Section>> = roots.iter().map(|root| summarize_root(root)).collect();
+    let mut rendered: Vec<Vec<String>> = vec![Vec::new(); sections.len()];
+    let mut used = output.len();
+    let rounds = sections.iter().map(Vec::len).max().unwrap_or(0);
+    let mut omitted = false;
+    for round in 0..rounds {
+        for (index, root_sections) in sections.iter().enumerate() {
+            let Some(section) = root_sections.get(round) else {
+                continue;
+            };
+            if section.lines.is_empty() {
+                continue;
+            }
+            let header = format!("\n{}\n", section.title);
+            if used + header.len() > budget {
+                omitted = true;
+                continue;
+            }
+            let mut block = header;
+            let mut shown = 0;
+            for line in &section.lines {
+                if used + block.len() + line.len() + 1 > budget {
+                    break;
+                }
+                block.push_str(line);
+                block.push('\n');
+                shown += 1;
+            }
+            if shown < section.lines.len() {
+                block.push_str(&format!("... {} more\n", section.lines.len() - shown));
+                omitted = true;
+            }
+            used += block.len();
+            rendered[index].push(block);
+        }
+    }
+    for blocks in rendered {
+        output.extend(blocks);
+    }
+    if omitted {
+        output.push_str("\n(Summary shortened to fit the token budget. Read workspace://summary?budget=N for more.)\n");
+    }
+    output
+}
@@ -0,0 +1,462 @@
+use crate::mcp::budget::charge_read;
+use crate::mcp::budget::charge_write;
+use crate::mcp::config;
+use crate::mcp::dry_run;
+use crate::mcp::limits::max_read_bytes;
+use crate::mcp::types::*;
+use crate::mcp::unicode::resolve_path;
+use crate::mcp::utilities::validate_path_or_error;
+use crate::mcp::utilities::validate_write_path_or_error;
+use base64::Engine;
+use rpc_router::HandlerResult;
+use rpc_router::RpcParams;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::path::Path;
+
+/// Extended attributes are off unless the server was started with
+/// `--allow-xattrs`, which sets this variable
+pub fn xattrs_allowed() -> bool {
+    config::var("MCP_RS_FILESYSTEM_ALLOW_XATTRS")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Extended attributes through the xattr calls of Linux, macOS and the BSDs,
+/// made on a handle opened through the sandbox rather than by name
+#[cfg(unix)]
+mod platform {
+    use crate::mcp::sandbox;
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
+    use xattr::FileExt;
+
+    fn open(path: &Path) -> io::Result<File> {
+        sandbox::open_attributes(path).map_err(io::Error::other)
+    }
+
+    pub fn list(path: &Path) -> io::Result<Vec<(String, u64)>> {
+        let file = open(path)?;
+        let mut attributes = Vec::new();
+        for name in file.list_xattr()? {
+            let size = file.get_xattr(&name)?.map_or(0, |value| value.len() as u64);
+            attributes.push((name.to_string_lossy().into_owned(), size));
+        }
+        Ok(attributes)
+    }
+
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        open(path)?.get_xattr(name)
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        open(path)?.set_xattr(name, value)
+    }
+
+    pub fn remove(path: &Path, name: &str) -> io::Result<()> {
+        open(path)?.remove_xattr(name)
+    }
+}
+
+/// Alternate data streams of NTFS, the Windows counterpart of extended
+/// attributes, addressed as `path:name`
+#[cfg(windows)]
+mod platform {
+    use std::ffi::OsString;
+    use std::fs;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use windows_sys::Win32::Foundation::ERROR_HANDLE_EOF;
+    use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+    use windows_sys::Win32::Storage::FileSystem::FindClose;
+    use windows_sys::Win32::Storage::FileSystem::FindFirstStreamW;
+    use windows_sys::Win32::Storage::FileSystem::FindNextStreamW;
+    use windows_sys::Win32::Storage::FileSystem::FindStreamInfoStandard;
+    use windows_sys::Win32::Storage::FileSystem::WIN32_FIND_STREAM_DATA;
+
+    fn stream_path(path: &Path, name: &str) -> PathBuf {
+        let mut stream = OsString::from(path.as_os_str());
+        stream.push(":");
+        stream.push(name);
+        PathBuf::from(stream)
+    }
+
+    /// Name of a stream as `FindFirstStreamW` reports it, `:name:$DATA`. The
+    /// unnamed stream, the file's contents, has none.
+    fn stream_name(data: &WIN32_FIND_STREAM_DATA) -> Option<String> {
+        let len = data.cStreamName.iter().position(|c| *c == 0).unwrap_or(data.cStreamName.len());
+        let full = String::from_utf16_lossy(&data.cStreamName[..len]);
+        let name = full.strip_prefix(':')?.strip_suffix(":$DATA")?;
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    pub fn list(path: &Path) -> io::Result<Vec<(String, u64)>> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+        // SAFETY: `wide` is NUL-terminated and `data` is the buffer the standard info level fills
+        let handle = unsafe {
+            FindFirstStreamW(wide.as_ptr(), FindStreamInfoStandard, &mut data as *mut _ as *mut _, 0)
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let error = io::Error::last_os_error();
+            // No streams at all, as for most directories
+            if error.raw_os_error() == Some(ERROR_HANDLE_EOF as i32) {
+                return Ok(Vec::new());
+            }
+            return Err(error);
+        }
+        let mut attributes = Vec::new();
+        loop {
+            if let Some(name) = stream_name(&data) {
+                attributes.push((name, data.StreamSize as u64));
+            }
+            // SAFETY: `handle` is an open stream search and `data` a valid buffer
+            if unsafe { FindNextStreamW(handle, &mut data as *mut _ as *mut _) } == 0 {
+                break;
+            }
+        }
+        let error = io::Error::last_os_error();
+        // SAFETY: `handle` came from FindFirstStreamW and is closed once
+        unsafe { FindClose(handle) };
+        match error.raw_os_error() {
+            Some(code) if code == ERROR_HANDLE_EOF as i32 => Ok(attributes),
+            _ => Err(error),
+        }
+    }
+
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(stream_path(path, name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        fs::write(stream_path(path, name), value)
+    }
+
+    pub fn remove(path: &Path, name: &str) -> io::Result<()> {
+        fs::remove_file(stream_path(path, name))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, "Extended attributes are not supported on this platform")
+    }
+
+    pub fn list(_path: &Path) -> io::Result<Vec<(String, u64)>> {
+        Err(unsupported())
+    }
+
+    pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn remove(_path: &Path, _name: &str) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+/// Reject names the platform would read as something else: a NUL ends the
+/// name early, and on Windows a colon or separator names another stream type or path
+fn check_name(name: &str) -> Result<(), String> {
+    let forbidden: &[char] = if cfg!(windows) { &['\0', ':', '\\', '/'] } else { &['\0'] };
+    if name.is_empty() || name.contains(forbidden) {
+        return Err(format!("Invalid attribute name: {:?}", name));
+    }
+    Ok(())
+}
+
+fn json_result(value: &Value) -> HandlerResult<CallToolResult> {
+    Ok(CallToolResult::text(serde_json::to_string_pretty(value).unwrap()))
+}
+
+/// The checks every xattr tool makes before touching `path`
+fn checked_path(path: &str, write: bool) -> Result<std::path::PathBuf, String> {
+    if !xattrs_allowed() {
+        return Err("Extended attributes are disabled. Start the server with --allow-xattrs to enable them".to_string());
+    }
+    let path = resolve_path(Path::new(path));
+    if write {
+        validate_write_path_or_error(&path)?;
+    } else {
+        validate_path_or_error(&path)?;
+    }
+    if !path.exists() {
+        return Err(format!("Path not found: {}", path.display()));
+    }
+    Ok(path)
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ListXattrsRequest {
+    /// File or directory to list the extended attributes (alternate data streams on Windows) of
+    pub path: String,
+}
+
+pub async fn list_xattrs(request: ListXattrsRequest) -> HandlerResult<CallToolResult> {
+    let path = match checked_path(&request.path, false) {
+        Ok(path) => path,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    match platform::list(&path) {
+        Ok(attributes) => {
+            let attributes: Vec<Value> =
+                attributes.into_iter().map(|(name, size)| json!({ "name": name, "size": size })).collect();
+            json_result(&json!({ "path": path.display().to_string(), "attributes": attributes }))
+        }
+        Err(e) => Ok(CallToolResult::error(format!("Error listing attributes of {}: {}", path.display(), e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct ReadXattrRequest {
+    /// File or directory holding the attribute
+    pub path: String,
+    /// Attribute name, such as user.comment on Linux, com.apple.quarantine on macOS or Zone.Identifier on Windows
+    pub name: String,
+    /// How to return the value: auto (default) for text when it is valid UTF-8 and base64 otherwise, text, or
+    /// base64
+    #[schemars(extend("enum" = ["auto", "text", "base64"], "default" = "auto"))]
+    pub encoding: Option<String>,
+}
+
+pub async fn read_xattr(request: ReadXattrRequest) -> HandlerResult<CallToolResult> {
+    let path = match checked_path(&request.path, false) {
+        Ok(path) => path,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    if let Err(msg) = check_name(&request.name) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let value = match platform::get(&path, &request.name) {
+        Ok(Some(value)) => value,
+        Ok(None) => return Ok(CallToolResult::error(format!("{} has no attribute {}", path.display(), request.name))),
+        Err(e) => {
+            return Ok(CallToolResult::error(format!(
+                "Error reading attribute {} of {}: {}",
+                request.name,
+                path.display(),
+                e
+            )))
+        }
+    };
+    if value.len() > max_read_bytes() {
+        return Ok(CallToolResult::error(format!(
+            "Attribute {} is {} bytes, more than the read limit of {} bytes",
+            request.name,
+            value.len(),
+            max_read_bytes()
+        )));
+    }
+    if let Err(msg) = charge_read(&path, value.len() as u64) {
+        return Ok(CallToolResult::error(msg));
+    }
+    let size = value.len();
+    let (encoding, value) = match (request.encoding.as_deref().unwrap_or("auto"), String::from_utf8(value)) {
+        ("auto" | "text", Ok(text)) => ("text", text),
+        ("text", Err(_)) => {
+            return Ok(CallToolResult::error(format!(
+                "Attribute {} is not valid UTF-8, read it as base64",
+                request.name
+            )))
+        }
+        ("auto" | "base64", value) => {
+            let bytes = value.map_or_else(|e| e.into_bytes(), String::into_bytes);
+            ("base64", base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+        (other, _) => {
+            return Ok(CallToolResult::error(format!("Unknown encoding: {}, expected auto, text or base64", other)))
+        }
+    };
+    json_result(&json!({
+        "path": path.display().to_string(),
+        "name": request.name,
+        "size": size,
+        "encoding": encoding,
+        "value": value,
+    }))
+}
+
+#[derive(Deserialize, Serialize, RpcParams, JsonSchema)]
+pub struct WriteXattrRequest {
+    /// File or directory to set the attribute on
+    pub path: String,
+    /// Attribute name. On Linux, unprivileged processes can only set names in the user. namespace.
+    pub name: String,
+    /// New value of the attribute, replacing any old one. Required unless remove is true.
+    pub value: Option<String>,
+    /// How value is given: text (default) or base64 for binary values
+    #[schemars(extend("enum" = ["text", "base64"], "default" = "text"))]
+    pub encoding: Option<String>,
+    /// Remove the attribute instead of setting it, such as com.apple.quarantine. Defaults to false.
+    #[schemars(extend("default" = false))]
+    pub remove: Option<bool>,
+    /// Report the change without making it. Always the case when the server runs with --dry-run. Defaults to
+    /// false.
+    #[schemars(extend("default" = false))]
+    pub dry_run: Option<bool>,
+}
+
+pub async fn write_xattr(request: WriteXattrRequest) -> HandlerResult<CallToolResult> {
+    let path = match checked_path(&request.path, true) {
+        Ok(path) => path,
+        Err(msg) => return Ok(CallToolResult::error(msg)),
+    };
+    if let Err(msg) = check_name(&request.name) {
+        return Ok(CallToolResult::error(msg));
+    }
+    if request.remove.unwrap_or(false) {
+        if request.value.is_some() {
+            return Ok(CallToolResult::error("Give either value or remove, not both".to_string()));
+        }
+        if dry_run::requested(request.dry_run) {
+            return dry_run::result(vec![json!({
+                "action": "remove_xattr",
+                "path": path.display().to_string(),
+                "name": request.name,
+            })]);
+        }
+        return match platform::remove(&path, &request.name) {
+            Ok(()) => json_result(&json!({ "path": path.display().to_string(), "name": request.name, "removed": true })),
+            Err(e) => {
+                Ok(CallToolResult::error(format!(
+                    "Error removing attribute {} of {}: {}",
+                    request.name,
+                    path.display(),
+                    e
+                )))
+            }
+        };
+    }
+    let Some(value) = &request.value else {
+        return Ok(CallToolResult::error("value is required unless remove is true".to_string()));
+    };
+    let value = match request.encoding.as_deref().unwrap_or("text") {
+        "text" => value.as_bytes().to_vec(),
+        "base64" => match base64::engine::general_purpose::STANDARD.decode(value) {
+            Ok(bytes) => bytes,
+            Err(e) => return Ok(CallToolResult::error(format!("Invalid base64 value: {}", e))),
+        },
+        other => return Ok(CallToolResult::error(format!("Unknown encoding: {}, expected text or base64", other))),
+    };
+    if dry_run::requested(request.dry_run) {
+        return dry_run::result(vec![json!({
+            "action": "set_xattr",
+            "path": path.display().to_string(),
+            "name": request.name,
+            "size": value.len(),
+        })]);
+    }
+    if let Err(msg) = charge_write(&path, value.len() as u64) {
+        return Ok(CallToolResult::error(msg));
+    }
+    match platform::set(&path, &request.name, &value) {
+        Ok(()) => json_result(&json!({
+            "path": path.display().to_string(),
+            "name": request.name,
+            "size": value.len(),
+        })),
+        Err(e) => {
+            Ok(CallToolResult::error(format!("Error writing attribute {} of {}: {}", request.name, path.display(), e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::testing::remove_env;
+    use crate::mcp::testing::result_json;
+    use crate::mcp::testing::result_text;
+    use crate::mcp::testing::set_env;
+    use crate::mcp::testing::test_env;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Whether the filesystem of `dir` lets this process set extended attributes
+    fn supported_in(dir: &Path) -> bool {
+        let probe = dir.join(".xattr-probe");
+        let supported = fs::write(&probe, "").is_ok() && platform::set(&probe, "user.probe", b"1").is_ok();
+        let _ = fs::remove_file(&probe);
+        supported
+    }
+
+    #[tokio::test]
+    async fn test_xattrs() {
+        let (_env, temp_dir, _) = test_env().await;
+        let file = temp_dir.path().join("tagged.txt");
+        fs::write(&file, "content").unwrap();
+        let path = file.to_str().unwrap().to_string();
+
+        // Off unless the server allows them
+        let _allowed = remove_env("MCP_RS_FILESYSTEM_ALLOW_XATTRS");
+        let result = list_xattrs(ListXattrsRequest { path: path.clone() }).await.unwrap();
+        assert!(result.is_error);
+        if !supported_in(temp_dir.path()) {
+            return;
+        }
+        let _allowed = set_env("MCP_RS_FILESYSTEM_ALLOW_XATTRS", "1");
+
+        let write = |name: &str, value: Option<&str>, encoding: Option<&str>, remove: Option<bool>| WriteXattrRequest {
+            path: path.clone(),
+            name: name.to_string(),
+            value: value.map(String::from),
+            encoding: encoding.map(String::from),
+            remove,
+            dry_run: None,
+        };
+        assert!(!write_xattr(write("user.comment", Some("reviewed"), None, None)).await.unwrap().is_error);
+        assert!(!write_xattr(write("user.blob", Some("AP8="), Some("base64"), None)).await.unwrap().is_error);
+
+        let result = list_xattrs(ListXattrsRequest { path: path.clone() }).await.unwrap();
+        let listing = result_json(&result);
+        let mut names: Vec<&str> =
+            listing["attributes"].as_array().unwrap().iter().map(|a| a["name"].as_str().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, ["user.blob", "user.comment"]);
+
+        let read = |name: &str| ReadXattrRequest {
+            path: path.clone(),
+            name: name.to_string(),
+            encoding: None,
+        };
+        let result = read_xattr(read("user.comment")).await.unwrap();
+        let text = result_text(&result);
+        let value: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!((value["encoding"].as_str(), value["value"].as_str()), (Some("text"), Some("reviewed")));
+        // Bytes that are not UTF-8 come back as base64
+        let result = read_xattr(read("user.blob")).await.unwrap();
+        let value = result_json(&result);
+        assert_eq!((value["encoding"].as_str(), value["value"].as_str()), (Some("base64"), Some("AP8=")));
+
+        assert!(!write_xattr(write("user.comment", None, None, Some(true))).await.unwrap().is_error);
+        assert!(read_xattr(read("user.comment")).await.unwrap().is_error);
+        assert!(write_xattr(write("user.comment", None, None, None)).await.unwrap().is_error);
+
+        // Files outside the allowed directories are refused
+        let outside = TempDir::new().unwrap();
+        let result = list_xattrs(ListXattrsRequest {
+            path: outside.path().to_str().unwrap().to_string(),
+        })
+        .await
+        .unwrap();
+        assert!(result.is_error);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::mcp::session;
+use crate::mcp::session::Session;
+use crate::mcp::utilities::get_allowed_directories;
+use crate::mcp::vfs;
+use crate::mcp::vfs::FileSystem;
+use crate::mcp::vfs::MemoryFs;
+use crate::server::handle_line;
+use crate::server::rpc_router_builder;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Allowed directory of a replay when none is configured. It only exists in memory.
+pub const REPLAY_ROOT: &str = if cfg!(windows) { r"C:\replay" } else { "/replay" };
+
+/// Stands for any value in an expected message, for ids, times and versions
+/// that differ from run to run
+const ANY: &str = "<any>";
+
+/// One line of a transcript: a message from the client and what the server is
+/// expected to send for it, notifications first and the response last
+#[derive(Debug)]
+struct Step {
+    line: usize,
+    send: Value,
+    expect: Vec<Value>,
+}
+
+fn parse(transcript: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (i, text) in transcript.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let line = i + 1;
+        let mut step: Value = serde_json::from_str(text).map_err(|e| format!("Line {}: {}", line, e))?;
+        let Some(send) = step.get_mut("send").map(Value::take) else {
+            return Err(format!("Line {}: expected an object with send and expect", line));
+        };
+        let expect = match step.get_mut("expect").map(Value::take) {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(expect)) => expect,
+            Some(_) => return Err(format!("Line {}: expect must be an array of messages", line)),
+        };
+        steps.push(Step { line, send, expect });
+    }
+    Ok(steps)
+}
+
+/// Whether `actual` is `expected`, where `"<any>"` matches anything
+fn matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::String(any), _) if any == ANY => true,
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len() && expected.iter().zip(actual).all(|(e, a)| matches(e, a))
+        }
+        (Value::Object(expected), Value::Object(actual)) => {
+            expected.len() == actual.len()
+                && expected.iter().all(|(key, e)| actual.get(key).is_some_and(|a| matches(e, a)))
+        }
+        _ => expected == actual,
+    }
+}
+
+fn all_match(expected: &[Value], actual: &[Value]) -> bool {
+    expected.len() == actual.len() && expected.iter().zip(actual).all(|(e, a)| matches(e, a))
+}
+
+/// Send every step through the router as one client session, against an empty
+/// in-memory backend holding only the allowed directories. Returns what the
+/// server sent for each step.
+async fn play(steps: &[Step]) -> Result<Vec<Vec<Value>>, String> {
+    let memory = MemoryFs::new();
+    for dir in get_allowed_directories() {
+        memory.create_dir_all(Path::new(&dir))?;
+    }
+    let router = rpc_router_builder().build();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    let session = Session::start(sender);
+    let outputs = vfs::scope(
+        Arc::new(memory),
+        session::scope(Arc::clone(&session), async {
+            let mut outputs = Vec::new();
+            for step in steps {
+                let response = handle_line(&router, &step.send.to_string()).await;
+                // Notifications sent while handling the step went out before its response
+                let mut sent = Vec::new();
+                while let Ok(line) = receiver.try_recv() {
+                    sent.push(line);
+                }
+                sent.extend(response);
+                let sent: Result<Vec<Value>, _> = sent.iter().map(|line| serde_json::from_str(line)).collect();
+                outputs.push(sent.map_err(|e| format!("Line {}: the server sent invalid JSON: {}", step.line, e))?);
+            }
+            Ok(outputs)
+        }),
+    )
+    .await;
+    session.end();
+    outputs
+}
+
+/// Replay a transcript and compare what the server sent with what it expects.
+/// Returns how many steps were replayed, or a report of those that differ.
+pub async fn check(transcript: &str) -> Result<usize, String> {
+    let steps = parse(transcript)?;
+    let outputs = play(&steps).await?;
+    let mut report = Vec::new();
+    for (step, actual) in steps.iter().zip(&outputs) {
+        if !all_match(&step.expect, actual) {
+            report.push(format!(
+                "Line {}: sent {}\n  expected {}\n  actual   {}",
+                step.line,
+                step.send,
+                Value::Array(step.expect.clone()),
+                Value::Array(actual.clone())
+            ));
+        }
+    }
+    if report.is_empty() {
+        Ok(steps.len())
+    } else {
+        Err(format!("{} of {} steps differ\n{}", report.len(), steps.len(), report.join("\n")))
+    }
+}
+
+/// Replay a transcript and return it with the expectations that no longer
+/// match replaced by what the server sent. Those that still match are kept,
+/// with their wildcards.
+pub async fn update(transcript: &str) -> Result<String, String> {
+    let steps = parse(transcript)?;
+    let outputs = play(&steps).await?;
+    let mut updated = String::new();
+    for (step, actual) in steps.iter().zip(outputs) {
+        let expect = if all_match(&step.expect, &actual) {
+            Value::Array(step.expect.clone())
+        } else {
+            Value::Array(actual)
+        };
+        // Written by hand so send comes before expect, as it happens
+        updated.push_str(&format!("{{\"send\":{},\"expect\":{}}}\n", step.send, expect));
+    }
+    Ok(updated)
+}
+
+/// `--replay`: check the transcript in `path`, or with `update` rewrite its
+/// expectations. Returns whether the replay succeeded.
+pub async fn run(path: &Path, update_transcript: bool) -> bool {
+    let transcript = match std::fs::read_to_string(path) {
+        Ok(transcript) => transcript,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            return false;
+        }
+    };
+    if update_transcript {
+        let written = update(&transcript)
+            .await
+            .and_then(|updated| std::fs::write(path, updated).map_err(|e| format!("Failed to write {}: {}", path.display(), e)));
+        return match written {
+            Ok(()) => {
+                println!("Updated {}", path.display());
+                true
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                false
+            }
+        };
+    }
+    match check(&transcript).await {
+        Ok(steps) => {
+            println!("{}: {} steps match", path.display(), steps);
+            true
+        }
+        Err(report) => {
+            eprintln!("{}: {}", path.display(), report);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_matches() {
+        let expected = json!({"id": 1, "result": {"version": "<any>", "tools": [1, 2]}});
+        assert!(matches(&expected, &json!({"id": 1, "result": {"version": "0.1.0", "tools": [1, 2]}})));
+        assert!(matches(&expected, &json!({"id": 1, "result": {"version": {"major": 0}, "tools": [1, 2]}})));
+        assert!(!matches(&expected, &json!({"id": 2, "result": {"version": "0.1.0", "tools": [1, 2]}})));
+        assert!(!matches(&expected, &json!({"id": 1, "result": {"version": "0.1.0", "tools": [1]}})));
+        assert!(!matches(&expected, &json!({"id": 1, "result": {"version": "0.1.0", "tools": [1, 2], "more": true}})));
+
+        let steps = parse("{\"send\":{\"method\":\"ping\"}}\n\n{\"send\":1,\"expect\":[2]}\n").unwrap();
+        assert_eq!(steps.len(), 2);
+        assert!(steps[0].expect.is_empty());
+        assert_eq!(steps[1].line, 3);
+        assert!(parse("{\"expect\":[]}").unwrap_err().starts_with("Line 1"));
+        assert!(parse("{\"send\":1,\"expect\":2}").is_err());
+    }
+}
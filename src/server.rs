@@ -0,0 +1,870 @@
+//! The MCP server: its router, the transports it serves clients on and
+//! [`ServerBuilder`], which configures it for the binary or for applications
+//! that embed it
+
+use crate::mcp::budget;
+use crate::mcp::config;
+use crate::mcp::config::config_reload;
+use crate::mcp::config::Settings;
+use crate::mcp::expansion::PathExpansion;
+use crate::mcp::ignore_rules;
+use crate::mcp::index;
+use crate::mcp::metrics;
+use crate::mcp::prompt_library;
+use crate::mcp::prompts::prompts_get;
+use crate::mcp::prompts::prompts_list;
+use crate::mcp::protection;
+use crate::mcp::protocol;
+use crate::mcp::resources;
+use crate::mcp::resources::allowed_directories;
+use crate::mcp::resources::resource_read;
+use crate::mcp::resources::resources_list;
+use crate::mcp::schema;
+use crate::mcp::scratch;
+use crate::mcp::secrets;
+use crate::mcp::session;
+use crate::mcp::session::CurrentSession;
+use crate::mcp::session::Phase;
+use crate::mcp::session::Session;
+use crate::mcp::state;
+use crate::mcp::timeouts;
+use crate::mcp::timeouts::Stopped;
+use crate::mcp::tools;
+use crate::mcp::tools::input_schema_of;
+use crate::mcp::tools::is_tool;
+use crate::mcp::tools::register_tools;
+use crate::mcp::tools::structured_output;
+use crate::mcp::trace;
+use crate::mcp::trace::Trace;
+use crate::mcp::types::CallToolResult;
+use crate::mcp::types::CancelledNotification;
+use crate::mcp::types::ErrorCode;
+use crate::mcp::types::JsonRpcError;
+use crate::mcp::types::JsonRpcResponse;
+use crate::mcp::types::Tool;
+use crate::mcp::types::ToolCallRequestParams;
+use crate::mcp::utilities::*;
+use crate::mcp::vfs;
+use dirs::data_local_dir;
+use dirs::home_dir;
+use dirs::state_dir;
+use rpc_router::Error;
+use rpc_router::Handler;
+use rpc_router::Request;
+use rpc_router::Resources;
+use rpc_router::Router;
+use rpc_router::RouterBuilder;
+use serde_json::json;
+use serde_json::Value;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::signal;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+/// How long shutdown waits for in-flight requests before exiting anyway
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Appends the handler of a tool added with [`ServerBuilder::tool`]
+type Registration = Box<dyn FnOnce(RouterBuilder) -> RouterBuilder + Send>;
+
+/// Every method and tool of the server
+pub(crate) fn rpc_router_builder() -> RouterBuilder {
+    let builder = RouterBuilder::default()
+        // append resources here
+        .append_dyn("initialize", initialize.into_dyn())
+        .append_dyn("ping", ping.into_dyn())
+        .append_dyn("logging/setLevel", logging_set_level.into_dyn())
+        .append_dyn("config/reload", config_reload.into_dyn())
+        .append_dyn("roots/list", roots_list.into_dyn())
+        .append_dyn("prompts/list", prompts_list.into_dyn())
+        .append_dyn("prompts/get", prompts_get.into_dyn())
+        .append_dyn("resources/list", resources_list.into_dyn())
+        .append_dyn("resources/read", resource_read.into_dyn())
+        .append_dyn("resources/allowed_directories", allowed_directories.into_dyn());
+    register_tools(builder)
+}
+
+fn get_log_directory() -> PathBuf {
+    if cfg!(target_os = "macos") {
+        // macOS: ~/Library/Logs/Claude
+        home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("Library/Logs/Claude")
+    } else if cfg!(target_os = "windows") {
+        // Windows: %LOCALAPPDATA%\Claude\logs
+        data_local_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("Claude")
+            .join("logs")
+    } else {
+        // Linux: ~/.local/state/claude/logs
+        state_dir()
+            .unwrap_or_else(|| {
+                home_dir()
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join(".local/state")
+            })
+            .join("claude")
+            .join("logs")
+    }
+}
+
+/// Where clients reach the server
+#[derive(Debug, Clone, Default)]
+pub enum Transport {
+    /// A single client on stdin and stdout, until it closes stdin
+    #[default]
+    Stdio,
+    /// Clients connecting to a Unix domain socket, or a named pipe such as
+    /// `\\.\pipe\rs_filesystem` on Windows, each served as its own session
+    Socket(PathBuf),
+}
+
+/// Configures a [`Server`]. Settings are the variables the command line flags
+/// stand for. The built server keeps them to itself rather than in the
+/// environment, and they take precedence over it and the configuration file.
+///
+/// ```no_run
+/// # async fn serve() -> Result<(), String> {
+/// let server = rs_filesystem::Server::builder()
+///     .allowed_directories(["/srv/projects"])
+///     .disabled_tools(["set_permissions"])
+///     .build()?;
+/// server.run().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ServerBuilder {
+    allowed_directories: Vec<PathBuf>,
+    config_file: Option<PathBuf>,
+    disabled_tools: Vec<String>,
+    settings: Vec<(String, OsString)>,
+    transport: Transport,
+    log_file: Option<PathBuf>,
+    log_hook: Option<LogHook>,
+    tools: Vec<Tool>,
+    registrations: Vec<Registration>,
+}
+
+impl ServerBuilder {
+    /// Directories the tools may work in, replacing `MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES`
+    pub fn allowed_directories<I, P>(mut self, dirs: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.allowed_directories = dirs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// TOML file with settings that can be changed while the server runs, as `--config`
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Tools to leave out of `tools/list` and refuse, as `--disabled-tools`
+    pub fn disabled_tools<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.disabled_tools = names.into_iter().map(Into::into).filter(|name: &String| !name.is_empty()).collect();
+        self
+    }
+
+    fn flag(self, name: &str, on: bool) -> Self {
+        if on {
+            self.setting(name, "1")
+        } else {
+            self
+        }
+    }
+
+    /// Let `set_permissions` change permission bits and ownership. Off unless
+    /// the environment or the configuration file turns it on.
+    pub fn allow_permission_changes(self, allow: bool) -> Self {
+        self.flag("MCP_RS_FILESYSTEM_ALLOW_PERMISSION_CHANGES", allow)
+    }
+
+    /// Return secrets in file contents as they are instead of masking them.
+    /// Off unless the environment or the configuration file turns it on.
+    pub fn allow_secrets(self, allow: bool) -> Self {
+        self.flag("MCP_RS_FILESYSTEM_ALLOW_SECRETS", allow)
+    }
+
+    /// Enable the extended attribute tools. Off unless the environment or the
+    /// configuration file turns them on.
+    pub fn allow_xattrs(self, allow: bool) -> Self {
+        self.flag("MCP_RS_FILESYSTEM_ALLOW_XATTRS", allow)
+    }
+
+    /// Have every mutating tool report what it would change instead of
+    /// changing it, as `--dry-run`
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        self.flag("MCP_RS_FILESYSTEM_DRY_RUN", dry_run)
+    }
+
+    /// Patterns of paths tools may read but never change, as `--protected-paths`
+    pub fn protected_paths<I, S>(self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns: Vec<String> = patterns.into_iter().map(|p| p.as_ref().to_string()).collect();
+        self.setting("MCP_RS_FILESYSTEM_PROTECTED_PATHS", patterns.join(","))
+    }
+
+    /// Milliseconds a tool call may run, 0 for no limit, as `--tool-timeout`
+    pub fn tool_timeout(self, ms: u64) -> Self {
+        self.setting("MCP_RS_FILESYSTEM_TOOL_TIMEOUT_MS", ms.to_string())
+    }
+
+    /// Any other setting, by the variable it is read from, e.g.
+    /// `MCP_RS_FILESYSTEM_INDEX` or `MCP_RS_FILESYSTEM_MAX_READ_BYTES`
+    pub fn setting(mut self, name: &str, value: impl Into<OsString>) -> Self {
+        self.settings.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// How clients reach the server. Defaults to stdio.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// File that requests, responses and traces are logged to. Defaults to
+    /// `MCP_LOG_FILE_PATH`, or `rs_filesystem.logs.jsonl` in the Claude logs directory.
+    pub fn log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    /// Where the server's own log messages go while no client is through its
+    /// handshake to take them as notifications, e.g. a watch that failed to
+    /// start. Called with the level, the logger and the message. Without a
+    /// hook they are dropped.
+    pub fn log_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, &str, &str) + Send + Sync + 'static,
+    {
+        self.log_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Add a tool of the embedding application. `definition` is listed by
+    /// `tools/list` and its input schema checks calls, and `register` appends
+    /// the handler under the same name, e.g.
+    /// `|router| router.append_dyn("my_tool", my_tool.into_dyn())`.
+    pub fn tool<F>(mut self, definition: Tool, register: F) -> Self
+    where
+        F: FnOnce(RouterBuilder) -> RouterBuilder + Send + 'static,
+    {
+        self.tools.push(definition);
+        self.registrations.push(Box::new(register));
+        self
+    }
+
+    /// Apply the settings to this process and check them, without starting a
+    /// server. Enough for commands that only list tools, prompts or resources.
+    pub fn configure(&self) -> Result<(), String> {
+        self.apply().map(|_| ())
+    }
+
+    /// The settings of the builder, by the variable each stands for
+    fn settings(&self) -> Result<Settings, String> {
+        let mut settings = self.settings.clone();
+        if let Some(path) = &self.config_file {
+            settings.push(("MCP_RS_FILESYSTEM_CONFIG".to_string(), path.into()));
+        }
+        if !self.allowed_directories.is_empty() {
+            let dirs = env::join_paths(&self.allowed_directories).map_err(|e| format!("Allowed directories: {}", e))?;
+            settings.push(("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES".to_string(), dirs));
+        }
+        if !self.disabled_tools.is_empty() {
+            let custom = |name: &str| self.tools.iter().any(|tool| tool.name == name);
+            if let Some(unknown) = self.disabled_tools.iter().find(|name| !is_tool(name) && !custom(name)) {
+                return Err(format!("Unknown tool in --disabled-tools: {}", unknown));
+            }
+            settings.push(("MCP_RS_FILESYSTEM_DISABLED_TOOLS".to_string(), self.disabled_tools.join(",").into()));
+        }
+        Ok(Settings::new(settings))
+    }
+
+    /// Put the settings in place for this process and check them
+    fn apply(&self) -> Result<Arc<Settings>, String> {
+        let settings = Arc::new(self.settings()?);
+        config::install(Arc::clone(&settings));
+        set_log_hook(self.log_hook.clone());
+        config::init()?;
+        vfs::init()?;
+        if let Ok(mode) = config::var("MCP_RS_FILESYSTEM_PATH_EXPANSION") {
+            PathExpansion::parse(&mode)?;
+        }
+        ignore_rules::check_patterns(&ignore_rules::server_patterns())?;
+        protection::check_patterns(&protection::protected_patterns())?;
+        if let Ok(overrides) = config::var("MCP_RS_FILESYSTEM_TOOL_TIMEOUTS") {
+            timeouts::check_overrides(&overrides)?;
+        }
+        Ok(settings)
+    }
+
+    /// Configure the process and build the server, with the tools added to
+    /// the built-in ones
+    pub fn build(self) -> Result<Server, String> {
+        let settings = self.apply()?;
+        for tool in self.tools {
+            tools::register_tool(tool)?;
+        }
+        let router = self
+            .registrations
+            .into_iter()
+            .fold(rpc_router_builder(), |builder, register| register(builder))
+            .build();
+        let log_path = self.log_file.unwrap_or_else(|| {
+            env::var("MCP_LOG_FILE_PATH").map(PathBuf::from).unwrap_or_else(|_| {
+                get_log_directory().join("rs_filesystem.logs.jsonl")
+            })
+        });
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .map_err(|e| format!("Failed to open log file {}: {}", log_path.display(), e))?;
+        Ok(Server {
+            router,
+            settings,
+            transport: self.transport,
+            log: Arc::new(Mutex::new(log)),
+        })
+    }
+}
+
+/// An MCP filesystem server, built with [`Server::builder`]
+pub struct Server {
+    router: Router,
+    /// What the builder was given, in effect while the server runs
+    settings: Arc<Settings>,
+    transport: Transport,
+    log: Arc<Mutex<File>>,
+}
+
+impl Server {
+    /// Start configuring a server
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// The router with every method and tool of the server
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    /// Serve one client on a pair of streams of newline-delimited JSON-RPC,
+    /// until it closes its end. For applications that talk to the server
+    /// in-process; [`Server::run`] serves the configured transport.
+    pub async fn serve_connection<R, W>(&self, reader: R, writer: W)
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        config::install(Arc::clone(&self.settings));
+        serve_connection(self.router.clone(), reader, writer, Arc::clone(&self.log)).await;
+    }
+
+    /// Start the background services and serve the transport until the
+    /// client closes stdin or the process is told to stop. SIGINT and SIGTERM
+    /// (Ctrl+C on Windows) drain the requests in flight and exit the process.
+    pub async fn run(self) {
+        config::install(Arc::clone(&self.settings));
+        // Restore state persisted by a previous run
+        state::init();
+        // Per-session scratch area, removed again on shutdown
+        scratch::init();
+        // User-defined prompts, reloaded when their files change
+        prompt_library::init();
+        // Background file index, when enabled
+        index::init();
+        // Resource list changes, when enabled
+        resources::init();
+
+        let logging_file = self.log;
+        // Request traces go to the same file, next to the lines they describe
+        trace::init(Arc::clone(&logging_file));
+
+        // Reload the configuration file on SIGHUP
+        #[cfg(unix)]
+        if config::reloadable() {
+            tokio::spawn(async {
+                let mut sighup = signal::unix::signal(signal::unix::SignalKind::hangup())
+                    .expect("Failed to set up SIGHUP handler");
+                while sighup.recv().await.is_some() {
+                    if let Err(e) = config::reload() {
+                        notify("notifications/message", Some(json!({
+                            "level": "error",
+                            "logger": "config",
+                            "data": e,
+                        })));
+                    }
+                }
+            });
+        }
+
+        // Clone necessary variables for the shutdown task
+        let shutdown_log = Arc::clone(&logging_file);
+        let shutdown_socket = match &self.transport {
+            Transport::Socket(socket) => Some(socket.clone()),
+            Transport::Stdio => None,
+        };
+        let shutdown_handle = tokio::spawn(async move {
+            // Create a shutdown signal future
+            #[cfg(unix)]
+            let shutdown = async {
+                // Listen for SIGINT and SIGTERM on Unix
+                let mut sigint = signal::unix::signal(signal::unix::SignalKind::interrupt())
+                    .expect("Failed to set up SIGINT handler");
+                let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+                    .expect("Failed to set up SIGTERM handler");
+
+                tokio::select! {
+                    _ = sigint.recv() => {},
+                    _ = sigterm.recv() => {},
+                }
+            };
+
+            #[cfg(windows)]
+            let shutdown = async {
+                // Listen for Ctrl+C on Windows
+                signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+            };
+
+            shutdown.await;
+            let unfinished = drain_requests(SHUTDOWN_TIMEOUT).await;
+            graceful_shutdown();
+            if let Some(socket) = &shutdown_socket {
+                let _ = std::fs::remove_file(socket);
+            }
+            if let Ok(mut file) = shutdown_log.lock() {
+                if unfinished > 0 {
+                    let _ = writeln!(file, "Shutdown timed out with {} request(s) in flight", unfinished);
+                }
+                let _ = file.sync_all();
+            }
+            std::process::exit(0);
+        });
+
+        // Process JSON-RPC from MCP client
+        let router = self.router;
+        let rpc_log = Arc::clone(&logging_file);
+        let rpc_handle = match self.transport {
+            // Every connection is a client of its own; runs until shutdown
+            Transport::Socket(socket) => tokio::spawn(async move {
+                if let Err(e) = serve_socket(&socket, router, rpc_log).await {
+                    log_message("error", "transport", &format!("Failed to listen on {}: {}", socket.display(), e));
+                }
+            }),
+            // A single client on stdin and stdout, until it closes stdin
+            Transport::Stdio => tokio::spawn(serve_connection(router, tokio::io::stdin(), tokio::io::stdout(), rpc_log)),
+        };
+
+        // Wait for either the RPC handling or shutdown to complete
+        tokio::select! {
+            _ = rpc_handle => {},
+            _ = shutdown_handle => {},
+        }
+        // stdin was closed by the client
+        graceful_shutdown();
+        if let Ok(file) = logging_file.lock() {
+            let _ = file.sync_all();
+        };
+    }
+}
+
+/// Serve one client: JSON-RPC messages are read line by line from `reader`,
+/// and responses and notifications written as lines to `writer`, until the
+/// client closes its end
+async fn serve_connection<R, W>(router: Router, reader: R, writer: W, rpc_log: Arc<Mutex<std::fs::File>>)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    let session = Session::start(sender);
+    // Responses and notifications from any task reach the client in the order sent
+    let writer_handle = tokio::spawn(async move {
+        let mut writer = writer;
+        while let Some(line) = receiver.recv().await {
+            let written = writer.write_all(format!("{}\n", line).as_bytes()).await;
+            if written.is_err() || writer.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let connection = Arc::clone(&session);
+    session::scope(Arc::clone(&session), async move {
+        let mut reader = tokio::io::BufReader::new(reader).lines();
+        let mut requests = JoinSet::new();
+        while let Ok(Some(line)) = reader.next_line().await {
+            writeln!(rpc_log.lock().unwrap(), "{}", line).unwrap();
+            let ids = request_ids(&line);
+            if ids.is_empty() {
+                // Notifications are handled in the order they arrive, so the
+                // end of the handshake comes before the requests sent after it
+                answer_line(&router, &line, &connection, &rpc_log).await;
+                continue;
+            }
+            // Requests run side by side, so a slow one holds up neither the
+            // others nor the notification that cancels it. They are
+            // registered here, for a cancellation that arrives before they start.
+            for id in &ids {
+                connection.begin_request(id);
+            }
+            let (router, connection, rpc_log) = (router.clone(), Arc::clone(&connection), Arc::clone(&rpc_log));
+            requests.spawn(timeouts::in_call(async move {
+                answer_line(&router, &line, &connection, &rpc_log).await;
+            }));
+            while let Some(finished) = requests.try_join_next() {
+                resume_panic(finished);
+            }
+        }
+        // The requests still running are answered before the session ends
+        while let Some(finished) = requests.join_next().await {
+            resume_panic(finished);
+        }
+    })
+    .await;
+
+    // Dropping the last handle on the session closes the channel, which ends the writer
+    session.end();
+    drop(session);
+    let _ = writer_handle.await;
+}
+
+/// Handle one line from the client and send the answer, if any, on its connection
+async fn answer_line(router: &Router, line: &str, connection: &Session, rpc_log: &Mutex<std::fs::File>) {
+    if let Some(response_json) = handle_line(router, line).await {
+        writeln!(rpc_log.lock().unwrap(), "{}\n", response_json).unwrap();
+        connection.send(response_json);
+    }
+}
+
+/// Ids of the requests in a line from a client, empty when it only holds notifications
+fn request_ids(line: &str) -> Vec<Value> {
+    let messages = match protocol::parse_line(line) {
+        Ok(Value::Array(messages)) => messages,
+        Ok(message) => vec![message],
+        Err(_) => Vec::new(),
+    };
+    messages
+        .into_iter()
+        .filter_map(|message| message.as_object().and_then(|object| object.get("id")).cloned())
+        .collect()
+}
+
+/// A panicking request fails the connection, as it did when requests ran one at a time
+fn resume_panic(finished: Result<(), tokio::task::JoinError>) {
+    if let Err(e) = finished {
+        if e.is_panic() {
+            std::panic::resume_unwind(e.into_panic());
+        }
+    }
+}
+
+/// Accept clients on a Unix domain socket, each served as its own session
+#[cfg(unix)]
+async fn serve_socket(path: &Path, router: Router, rpc_log: Arc<Mutex<std::fs::File>>) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    // A socket file left behind by a server that did not shut down cleanly
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (reader, writer) = stream.into_split();
+        tokio::spawn(serve_connection(router.clone(), reader, writer, Arc::clone(&rpc_log)));
+    }
+}
+
+/// Accept clients on a named pipe, e.g. `\\.\pipe\rs_filesystem`, each served
+/// as its own session
+#[cfg(windows)]
+async fn serve_socket(path: &Path, router: Router, rpc_log: Arc<Mutex<std::fs::File>>) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let mut server = ServerOptions::new().first_pipe_instance(true).create(path)?;
+    loop {
+        server.connect().await?;
+        // The next client connects to a fresh instance of the pipe
+        let connected = std::mem::replace(&mut server, ServerOptions::new().create(path)?);
+        let (reader, writer) = tokio::io::split(connected);
+        tokio::spawn(serve_connection(router.clone(), reader, writer, Arc::clone(&rpc_log)));
+    }
+}
+
+/// Dispatch one line from a client, returning the line to answer with if any
+pub(crate) async fn handle_line(router: &Router, line: &str) -> Option<String> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    let response = match protocol::parse_line(line) {
+        // Batch: dispatch every element and answer with an array of responses
+        Ok(Value::Array(messages)) => handle_batch(router, messages).await,
+        Ok(message) => handle_message(router, message).await,
+        Err(error) => Some(json!(error)),
+    };
+    response.map(|response| serde_json::to_string(&response).unwrap())
+}
+
+/// Dispatch a JSON-RPC batch. Returns `None` when the batch only held
+/// notifications, since no response is sent for those.
+async fn handle_batch(router: &Router, messages: Vec<Value>) -> Option<Value> {
+    if messages.is_empty() {
+        let error = JsonRpcError::new(Value::Null, ErrorCode::InvalidRequest as i32, "Invalid Request: empty batch");
+        return Some(json!(error));
+    }
+    let mut responses = Vec::new();
+    for message in messages {
+        if let Some(response) = handle_message(router, message).await {
+            responses.push(response);
+        }
+    }
+    if responses.is_empty() {
+        None
+    } else {
+        Some(Value::Array(responses))
+    }
+}
+
+/// Dispatch a single JSON-RPC message, returning the response to send if any
+async fn handle_message(router: &Router, json_value: Value) -> Option<Value> {
+    // Messages without a method are malformed, and counted under an empty one
+    let method = json_value["method"].as_str().unwrap_or_default().to_string();
+    metrics::record_request(&method);
+    // Notifications get no response, so only requests are traced
+    if protocol::is_notification(&json_value) {
+        return respond(router, json_value).await;
+    }
+    let id = json_value["id"].clone();
+    let tool = (method == "tools/call").then(|| json_value["params"]["name"].as_str().map(String::from)).flatten();
+    let request = Trace::start();
+    // The flag `notifications/cancelled` sets for this request
+    let session = session::current();
+    let cancelled = session.as_ref().map(|session| session.begin_request(&id)).unwrap_or_default();
+    let handled = timeouts::with_cancel_flag(cancelled, respond(router, json_value));
+    let mut response = trace::scope(Arc::clone(&request), handled).await;
+    if let Some(session) = &session {
+        session.finish_request(&id);
+    }
+    trace::finish(&request, &id, &method, tool.as_deref(), &mut response);
+    if response.as_ref().is_some_and(|response| response.get("error").is_some()) {
+        metrics::record_error(&method);
+    }
+    response
+}
+
+async fn respond(router: &Router, json_value: Value) -> Option<Value> {
+    if let Err(error) = protocol::check_message(&json_value) {
+        return reject(&json_value, error);
+    }
+    // Notifications, no response required
+    if protocol::is_notification(&json_value) {
+        let method = json_value["method"].as_str().unwrap_or_default();
+        if method == "notifications/initialized" {
+            notifications_initialized();
+        } else if method == "notifications/cancelled" {
+            match protocol::params::<CancelledNotification>(&Value::Null, method, json_value.get("params").cloned()) {
+                Ok(cancel_params) => notifications_cancelled(cancel_params),
+                Err(error) => return reject(&json_value, error),
+            }
+        }
+        return None;
+    }
+
+    let id = json_value["id"].clone();
+    let mut rpc_request = match Request::from_value(json_value) {
+        Ok(rpc_request) => rpc_request,
+        Err(e) => {
+            let message = format!("Invalid Request: {:?}", e);
+            return Some(json!(JsonRpcError::new(id, ErrorCode::InvalidRequest as i32, &message)));
+        }
+    };
+    // Normal JSON-RPC message, and response expected
+    let id = rpc_request.id.clone();
+    let Some(_in_flight) = InFlight::start() else {
+        let error = JsonRpcError::new(id, -32000, "Server is shutting down");
+        return Some(json!(error));
+    };
+    if let Some(session) = session::current().filter(|session| !session.accepts(&rpc_request.method)) {
+        let message = match session.phase() {
+            Phase::New => format!("Server not initialized: send initialize before {}", rpc_request.method),
+            _ => format!("Server not initialized: send notifications/initialized before {}", rpc_request.method),
+        };
+        let error = JsonRpcError::new(id, ErrorCode::ServerNotInitialized as i32, &message);
+        return Some(json!(error));
+    }
+    if rpc_request.method == "tools/call" {
+        let params = match protocol::params::<ToolCallRequestParams>(&id, "tools/call", rpc_request.params) {
+            Ok(params) => params,
+            Err(error) => return Some(json!(error)),
+        };
+        let Some(schema) = input_schema_of(&params.name) else {
+            let message = format!("Unknown tool: {}", params.name);
+            return Some(json!(JsonRpcError::new(id, ErrorCode::InvalidParams as i32, &message)));
+        };
+        // Bad arguments are reported against the tool's schema, before the handler sees them
+        let arguments = params.arguments.clone().unwrap_or_else(|| json!({}));
+        if let Err(e) = schema::validate(&schema, &arguments) {
+            let message = format!("Invalid arguments for tool {}: {}", params.name, e);
+            return Some(json!(JsonRpcError::new(id, ErrorCode::InvalidParams as i32, &message)));
+        }
+        if let Err(text) = vfs::check_tool(&params.name) {
+            let result = CallToolResult::error(text);
+            return Some(json!(JsonRpcResponse::new(id, json!(result))));
+        }
+        // Forward `_meta` (e.g. the progress token) to the tool handler
+        let mut arguments = params.arguments;
+        if let Some(meta) = params.meta {
+            if let Value::Object(map) = arguments.get_or_insert_with(|| json!({})) {
+                map.insert("_meta".to_string(), meta);
+            }
+        }
+        rpc_request = Request {
+            id: id.clone(),
+            method: params.name,
+            params: arguments,
+        };
+        // Budgets are enforced on tool calls only
+        let usage = match budget::begin_tool_call() {
+            Ok(usage) => usage,
+            Err(report) => return Some(json!(JsonRpcResponse::new(id, json!(budget::exceeded_result(&report))))),
+        };
+        let tool = rpc_request.method.clone();
+        let (limit, aborts) = (timeouts::tool_timeout(&tool), timeouts::aborts_on_timeout(&tool));
+        let call = {
+            let router = router.clone();
+            async move { dispatch(&router, rpc_request).await }
+        };
+        let stopped = budget::in_tool_call(Arc::clone(&usage), timeouts::run_with_timeout(limit, aborts, call));
+        let (mut response, timed_out) = match stopped.await {
+            Ok(response) => (response, false),
+            Err(Stopped::TimedOut) => {
+                trace::set_outcome("timeout");
+                let result = timeouts::timed_out_result(&tool, limit.unwrap_or_default());
+                (Some(json!(JsonRpcResponse::new(id.clone(), json!(result)))), true)
+            }
+            // The client gave up on the call and expects no answer
+            Err(Stopped::Cancelled) => {
+                trace::set_outcome("cancelled");
+                (None, false)
+            }
+        };
+        let failed = response
+            .as_ref()
+            .is_some_and(|response| response.get("error").is_some() || response["result"]["isError"] == true);
+        metrics::record_tool_call(&tool, failed, timed_out);
+        if !secrets::secrets_allowed() {
+            if let Some(result) = response.as_mut().and_then(|response| response.get_mut("result")) {
+                secrets::redact_tool_result(result);
+            }
+        }
+        if !structured_output() {
+            if let Some(result) = response.as_mut().and_then(|response| response.get_mut("result")?.as_object_mut()) {
+                result.remove("structuredContent");
+            }
+        }
+        if let Some(report) = budget::end_tool_call(&usage) {
+            if let Some(result) = response.as_mut().and_then(|response| response.get_mut("result")) {
+                budget::add_exceeded_report(result, &report);
+            }
+        }
+        return response;
+    }
+    if matches!(rpc_request.method.as_str(), "resources/read" | "prompts/get") && !secrets::secrets_allowed() {
+        let prompt = rpc_request.method == "prompts/get";
+        let mut response = dispatch(router, rpc_request).await;
+        if let Some(result) = response.as_mut().and_then(|response| response.get_mut("result")) {
+            if prompt {
+                secrets::redact_prompt_result(result);
+            } else {
+                secrets::redact_resource_result(result);
+            }
+        }
+        return response;
+    }
+    dispatch(router, rpc_request).await
+}
+
+/// Answer a malformed message with its error. Notifications get no response,
+/// so for them the error is logged to the client instead.
+fn reject(message: &Value, error: JsonRpcError) -> Option<Value> {
+    if protocol::is_notification(message) {
+        notify("notifications/message", Some(json!({
+            "level": "error",
+            "logger": "protocol",
+            "data": error.error.message,
+        })));
+        return None;
+    }
+    Some(json!(error))
+}
+
+/// Call the handler for a request and turn its outcome into a JSON-RPC response
+async fn dispatch(router: &Router, rpc_request: Request) -> Option<Value> {
+    let id = rpc_request.id.clone();
+    // Handlers that need the session of the request take it as a resource
+    let resources = match session::current() {
+        Some(session) => Resources::builder().append(CurrentSession(session)).build(),
+        None => Resources::default(),
+    };
+    let method = rpc_request.method.clone();
+    match router.call_with_resources(rpc_request, resources).await {
+        Ok(call_response) => {
+            if call_response.value.is_null() {
+                None
+            } else {
+                Some(json!(JsonRpcResponse::new(id, call_response.value)))
+            }
+        }
+        Err(error) => match &error.error {
+            // Error from JSON-RPC call
+            Error::Handler(handler) => handler.get::<Value>().map(|error_value| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "error": error_value,
+                    "id": id
+                })
+            }),
+            Error::MethodUnknown => {
+                let message = format!("Method not found: {}", method);
+                Some(json!(JsonRpcError::new(id, ErrorCode::MethodNotFound as i32, &message)))
+            }
+            Error::ParamsParsing(e) => {
+                let message = format!("Invalid params for {}: {}", method, e);
+                Some(json!(JsonRpcError::new(id, ErrorCode::InvalidParams as i32, &message)))
+            }
+            Error::ParamsMissingButRequested => {
+                let message = format!("Invalid params for {}: missing params", method);
+                Some(json!(JsonRpcError::new(id, ErrorCode::InvalidParams as i32, &message)))
+            }
+            _ => {
+                let message = format!("Internal error: {}", error.error);
+                Some(json!(JsonRpcError::new(id, ErrorCode::InternalError as i32, &message)))
+            }
+        },
+    }
+}